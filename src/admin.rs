@@ -0,0 +1,110 @@
+//! Admin console of the server
+//!
+//! Once the game has started, the lines typed in the terminal of the server are read by a thread
+//! of their own and handled between two turns: `say <text>` broadcasts an announcement to all the
+//! players, and `motd <text>` replaces the message of the day shown to the players who join later.
+
+use std::io::BufRead;
+use std::sync::mpsc::{ channel, Receiver };
+use std::thread;
+use crate::bold;
+
+/// help shown for the lines which are not understood
+pub const ADMIN_HELP: &str = "Admin commands (handled at the start of the next turn):\n\
+                              \x20 say <text>: send an announcement to all the players\n\
+                              \x20 motd <text>: set the message of the day (‘motd’ alone clears it)\n";
+
+/// Command typed in the admin console
+#[derive(Debug, PartialEq, Eq)]
+pub enum AdminCommand {
+
+    /// broadcast an announcement
+    Say(String),
+
+    /// replace the message of the day (an empty one to clear it)
+    Motd(String),
+
+    /// show the list of commands
+    Help
+}
+
+/// Read a line typed in the admin console
+///
+/// Return `None` for an empty line.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::admin::{ parse_admin_command, AdminCommand };
+///
+/// assert_eq!(Some(AdminCommand::Say("Server restart at 18:00".to_string())),
+///            parse_admin_command("say Server restart at 18:00\n"));
+/// assert_eq!(Some(AdminCommand::Motd(String::new())), parse_admin_command("motd"));
+/// assert_eq!(Some(AdminCommand::Help), parse_admin_command("say"));
+/// assert_eq!(Some(AdminCommand::Help), parse_admin_command("shout hello"));
+/// assert_eq!(None, parse_admin_command("  "));
+/// ```
+pub fn parse_admin_command(line: &str) -> Option<AdminCommand> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+    let (command, text) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let text = text.trim().to_string();
+    Some(match command {
+        "say" if !text.is_empty() => AdminCommand::Say(text),
+        "motd" => AdminCommand::Motd(text),
+        _ => AdminCommand::Help
+    })
+}
+
+/// Admin console, reading the terminal of the server in its own thread
+pub struct AdminConsole {
+    receiver: Receiver<String>
+}
+
+impl AdminConsole {
+
+    /// Start reading the lines typed in the terminal
+    pub fn start() -> AdminConsole {
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        AdminConsole { receiver }
+    }
+
+    /// Commands typed since the last call, without waiting for new ones
+    pub fn commands(&self) -> Vec<AdminCommand> {
+        self.receiver.try_iter().filter_map(|line| parse_admin_command(&line)).collect()
+    }
+}
+
+/// Read the message of the day from a file, with its trailing white space removed
+///
+/// Return an empty message if the file does not exist or can not be read.
+pub fn load_motd(fname: &str) -> String {
+    std::fs::read_to_string(fname).map(|s| s.trim_end().to_string()).unwrap_or_default()
+}
+
+/// Message shown to a player who joins, with the message of the day (nothing if it is empty)
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::admin::motd_message;
+///
+/// assert_eq!("\x1b[1mMessage of the day:\x1b[0m\nWelcome!\n\n", motd_message("Welcome!"));
+/// assert_eq!("", motd_message(""));
+/// ```
+pub fn motd_message(motd: &str) -> String {
+    if motd.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n{}\n\n", bold("Message of the day:"), motd)
+    }
+}
@@ -0,0 +1,211 @@
+//! Headless game between bots, timing the main parts of the engine
+//!
+//! The game is fully determined by its seed, so the timings of different versions can be compared.
+
+use std::fmt;
+use std::time::{ Duration, Instant };
+use rand::{ SeedableRng, rngs::StdRng, seq::SliceRandom };
+use crate::*;
+use crate::bot::{ BotWeights, MeldCache };
+use crate::evaluation::evaluate_hand;
+use crate::rules::RuleSet;
+use crate::save::{ SaveBackend, LocalFile };
+
+/// configuration of the benchmark games
+const BENCH_CONFIG: Config = Config {
+    n_decks: 2,
+    n_jokers: 4,
+    n_cards_to_start: 13,
+    custom_rule_jokers: false,
+    n_players: 4,
+    initial_meld_points: 0
+};
+
+/// number of hands kept in the cache of the solver
+const BENCH_CACHE_CAPACITY: usize = 10_000;
+
+/// Result of a benchmark game
+#[derive(Debug, Clone, Default)]
+pub struct BenchReport {
+    pub seed: u64,
+    pub n_turns: usize,
+
+    /// index of the winner, if the game has not ended in a draw
+    pub winner: Option<usize>,
+
+    /// time spent checking the sequences played
+    pub validation: Duration,
+
+    /// time spent by the bots looking for sequences to play
+    pub solver: Duration,
+
+    /// number of searches for sequences answered by the cache of the solver, and not found in it
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+
+    /// time spent converting and encoding the game to save it
+    pub serialization: Duration,
+
+    /// time spent writing the save file
+    pub io: Duration,
+
+    /// size of the last save, and of the same game with the compact encoding
+    pub save_size: usize,
+    pub compact_save_size: usize,
+
+    pub total: Duration
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "seed={}", self.seed)?;
+        writeln!(f, "turns={}", self.n_turns)?;
+        match self.winner {
+            Some(i) => writeln!(f, "winner={}", i + 1)?,
+            None => writeln!(f, "winner=none")?
+        };
+        writeln!(f, "validation_us={}", self.validation.as_micros())?;
+        writeln!(f, "solver_us={}", self.solver.as_micros())?;
+        writeln!(f, "solver_cache_hits={}", self.cache_hits)?;
+        writeln!(f, "solver_cache_misses={}", self.cache_misses)?;
+        writeln!(f, "serialization_us={}", self.serialization.as_micros())?;
+        writeln!(f, "io_us={}", self.io.as_micros())?;
+        writeln!(f, "save_bytes={}", self.save_size)?;
+        writeln!(f, "compact_save_bytes={}", self.compact_save_size)?;
+        writeln!(f, "total_us={}", self.total.as_micros())
+    }
+}
+
+// deck shuffled with a given seed
+fn seeded_deck(config: &Config, rng: &mut StdRng) -> Sequence {
+    let mut bytes = Vec::<u8>::new();
+    for _ in 0..config.n_decks {
+        bytes.extend(1..=52);
+    }
+    bytes.extend(std::iter::repeat(0).take(config.n_jokers as usize));
+    bytes.shuffle(rng);
+    Sequence::from_bytes(&bytes)
+}
+
+/// play a game between bots with the given seed, saving it after each turn to `save_name`
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::bench::run_bench_game;
+///
+/// let save_name = std::env::temp_dir().join("machiavelli_doctest_bench.sav");
+/// let report = run_bench_game(42, save_name.to_str().unwrap()).unwrap();
+///
+/// assert_eq!(report.n_turns, run_bench_game(42, save_name.to_str().unwrap()).unwrap().n_turns);
+/// std::fs::remove_file(save_name).unwrap();
+/// ```
+pub fn run_bench_game(seed: u64, save_name: &str) -> std::io::Result<BenchReport> {
+    run_bench_game_with_weights(seed, save_name, &[])
+}
+
+/// play a game between bots with the given seed and weights, saving it after each turn to 
+/// `save_name`
+///
+/// The player `i` uses the weights `weights[i]`, or the default ones if there are not enough of
+/// them. Comparing the winners over many seeds tells which weights are the strongest.
+pub fn run_bench_game_with_weights(seed: u64, save_name: &str, weights: &[BotWeights]) 
+    -> std::io::Result<BenchReport> 
+{
+    let start = Instant::now();
+    let mut report = BenchReport { seed, ..BenchReport::default() };
+    let config = BENCH_CONFIG;
+    let player_names: Vec<String> = (1..=config.n_players).map(|i| format!("Bot {}", i)).collect();
+    let opened = vec![false; config.n_players as usize];
+
+    // deal the cards
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut deck = seeded_deck(&config, &mut rng);
+    let mut hands = deck.deal(config.n_players as usize, config.n_cards_to_start as usize);
+    let mut table = Table::new();
+    let mut cache = MeldCache::new(BENCH_CACHE_CAPACITY);
+
+    let mut player = 0;
+    while deck.number_cards() > 0 {
+        report.n_turns += 1;
+
+        // play as many sequences as worth it, or pick a card
+        let weights = weights.get(player).copied().unwrap_or_default();
+        let mut has_played = false;
+        loop {
+            let timer = Instant::now();
+            let meld = cache.find_meld(&hands[player]);
+            report.solver += timer.elapsed();
+            let (mut meld, indices) = match meld {
+                Some(m) => m,
+                None => break
+            };
+            let timer = Instant::now();
+            let valid = meld.is_valid();
+            report.validation += timer.elapsed();
+            if !valid || weights.score(&meld.to_vec()) <= 0. {
+                break;
+            }
+            for i in indices {
+                hands[player].take_card(i);
+            }
+            table.add(meld);
+            has_played = true;
+        }
+
+        // then add cards to the sequences on the table
+        if weights.table_aggressiveness > 0. {
+            let timer = Instant::now();
+            let evaluation = evaluate_hand(&hands[player], &table, &RuleSet::default());
+            report.solver += timer.elapsed();
+            let mut sequences = table.to_vec();
+            for (card, i) in evaluation.additions.into_iter().filter(|(c, _)| weights.adds_to_table(c)) {
+
+                // an earlier addition may have changed the sequence
+                let mut longer = sequences[i - 1].clone();
+                longer.add_card(card.clone());
+                let timer = Instant::now();
+                let valid = longer.is_valid();
+                report.validation += timer.elapsed();
+                let position = hands[player].to_vec().iter().position(|c| c == &card);
+                if let (true, Some(position)) = (valid, position) {
+                    hands[player].take_card(position + 1);
+                    sequences[i - 1] = longer;
+                    has_played = true;
+                }
+            }
+            table = Table::new();
+            for sequence in sequences {
+                table.add(sequence);
+            }
+        }
+        if !has_played {
+            pick_a_card_end_of_turn(&mut hands[player], &mut deck, 0);
+        }
+        if hands[player].number_cards() == 0 {
+            report.winner = Some(player);
+            break;
+        }
+        player = (player + 1) % config.n_players as usize;
+
+        // save the game, as the server does at the beginning of each turn
+        let timer = Instant::now();
+        let bytes = game_to_bytes(0, player as u8, &table, &hands, &deck, &config, &player_names, &opened);
+        let bytes = encode::encrypt_save(&bytes, "");
+        report.serialization += timer.elapsed();
+        let timer = Instant::now();
+        LocalFile.write(save_name, &bytes)?;
+        report.io += timer.elapsed();
+    }
+
+    // compare the sizes of the two encodings
+    let player = player as u8;
+    report.save_size = game_to_bytes(0, player, &table, &hands, &deck, &config, &player_names, &opened).len();
+    report.compact_save_size = compact::compact_game_to_bytes(0, player, &table, &hands, &deck, 
+                                                              &config, &player_names).len();
+
+    report.cache_hits = cache.hits();
+    report.cache_misses = cache.misses();
+    report.total = start.elapsed();
+    Ok(report)
+}
@@ -1,56 +1,83 @@
 //! Client for the Machiavelli game
 
 use std::env;
-use std::process::exit;
 use machiavelli::lib_client::*;
+use machiavelli::terminal;
 
 fn main() {
 
+    // set-up the terminal (plain output if it does not support colours)
+    terminal::init();
+
+    // the terminal is restored when leaving `main`, even after a panic
+    let _terminal_guard = terminal::TerminalGuard::new(true);
+
     ctrlc::set_handler(|| {
-        print!("\x1b[0m\x1b[?25h"); // reset the style and show the cursor
-        print!("\x1b[2J\x1b[1;1H"); // clear the screen
-        print!("\x1b[K"); // redraw the screen
-        exit(0);
+        terminal::clear();
+        terminal::exit(0);
     }).expect("Could not set the Ctrl-C signal handler!");
 
-    // parse the command-line arguments
-    let args: Vec<String> = env::args().collect();
+    // parse the command-line arguments; with `--spectate`, the client watches the game without
+    // taking a seat, and with `--code CODE`, it only joins the game with this code
+    let mut args: Vec<String> = env::args().collect();
+    let spectator = args.iter().any(|a| a == "--spectate");
+    args.retain(|a| a != "--spectate");
+    let game_code = match args.iter().position(|a| a == "--code") {
+        Some(i) if i + 1 < args.len() => {
+            let code = args.remove(i + 1);
+            args.remove(i);
+            Some(code)
+        },
+        _ => None
+    };
 
     let single_byte_buffer: &mut [u8; 1] = &mut [0];
 
+    // state of the client; the top of the terminal is reserved for the status bar
+    let mut state = ClientState::new();
+    state.status_bar.init();
+
+    // with `turn` in this file, the situation is only sent when a turn starts, rather than after
+    // every move of the other players
+    let name_file_updates = "Config/updates_client.dat";
+    if let Ok(s) = std::fs::read_to_string(name_file_updates) {
+        state.updates = Updates::parse(&s).unwrap_or_default();
+    }
+
     // set-up the TCP stream to communicate with the server
-    let mut stream = if args.len() > 1 {
+    let (mut stream, host, name) = if args.len() > 1 {
         
         // if one command-line argument is given, use it as player name
-        connect(&args[1])
+        connect(&args[1], spectator, game_code.as_deref())
 
     } else {
         
         //otherwise, the name will be asked
-        connect("")
+        connect("", spectator, game_code.as_deref())
     };
 
     loop {
 
-        // handle the server request and quit if the server can not be reached
-        handle_server_request(single_byte_buffer, &mut stream).unwrap_or_else(|_| {
-            println!("lost connection to the server");
-            print!("\x1b[0m\x1b[?25h"); // reset the style and show the cursor
-            print!("\x1b[K"); // redraw the screen
-            exit(1);
-        });
+        // handle the server request; if the server can not be reached, try to reconnect, and quit
+        // if it fails
+        if handle_server_request(single_byte_buffer, &mut stream, &mut state).is_err() {
+            stream = reconnect(&host, &name, state.session_token.as_deref()).unwrap_or_else(|_| {
+                println!("lost connection to the server");
+                terminal::exit(1);
+            });
+        }
 
     }
 }
 
 
 // function to try to connect to the server and exit if unsuccessful
-fn connect(name: &str) -> TcpStream {
-    match say_hello(name.to_string()) {
+fn connect(name: &str, spectator: bool, game_code: Option<&str>) -> (EncryptedStream, String, String) {
+    match say_hello(name.to_string(), spectator, game_code) {
         Ok(s) => s,
         Err(e) => {
             println!("Failed to connect: {}", e);
-            exit(1);
+            terminal::exit(1);
         }
     }
 }
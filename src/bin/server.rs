@@ -1,13 +1,15 @@
 //! Server for the Machiavelli game
 
 use std::process;
-use std::fs::File;
 use std::thread;
 use std::env;
-use rand::{ thread_rng, Rng };
+use std::path::PathBuf;
+use std::time::{ SystemTime, UNIX_EPOCH };
+use rand::{ thread_rng, Rng, SeedableRng, rngs::StdRng };
 use machiavelli::lib_server::*;
 
 const SAVE_EXTENSION: &str = ".sav";
+const REPLAY_EXTENSION: &str = ".rpl";
 
 // ask the user for the port to use
 fn get_port() -> usize {
@@ -23,30 +25,222 @@ fn get_port() -> usize {
     }
 }
 
+// in non-interactive mode, exit with an error explaining what is missing instead of prompting
+fn require_interactive(non_interactive: bool, problem: &str, fix: &str) {
+    if non_interactive {
+        eprintln!("Error: {}.\n{}\n(the server runs in non-interactive mode, so it can not ask for it)", 
+                  problem, fix);
+        process::exit(2);
+    }
+}
+
+// first word of a line of a configuration file
+fn first_word_of(s: &str) -> &str {
+    s.split_whitespace().next().unwrap_or("")
+}
+
 fn main() {
     
+    // in non-interactive mode, set with `--non-interactive` or the `MACHIAVELLI_NON_INTERACTIVE`
+    // environment variable, the server never reads from stdin
+    let non_interactive = env::args().any(|a| a == "--non-interactive") 
+        || env::var("MACHIAVELLI_NON_INTERACTIVE").map(|v| !v.is_empty() && v != "0").unwrap_or(false);
+
+    // with `--telemetry`, anonymous statistics on each finished game are appended to a local file
+    // (see the `telemetry` module)
+    let telemetry = env::args().any(|a| a == "--telemetry");
+
     // get the command-line arguments
-    let mut args = env::args();
+    let mut args = env::args().filter(|a| a != "--non-interactive" && a != "--telemetry");
     args.next(); // skip the first one (name of the executable)
     
-    // clear the terminal
-    print!("\x1b[2J\x1b[1;1H");
+    // clear the terminal (the messages sent to the clients keep their colours, which the clients
+    // remove if needed)
+    terminal::init_control_only();
+
+    // the terminal is restored when leaving `main`, even after a panic in a prompt
+    let _terminal_guard = terminal::TerminalGuard::new(false);
+    clear_terminal();
     println!("Machiavelli server\n");
 
-    // port on which to listen
+    // listener passed by systemd with socket activation, or port on which to listen
+    let systemd_listener = net::listener_from_systemd();
     let name_file_port_server = "Config/port_server.dat";
-    let port = match std::fs::read_to_string(name_file_port_server) {
-        Ok(s) => match s.trim().parse::<usize>() {
-            Ok(n) => n,
-            Err(_) => get_port()
+    let port = match std::fs::read_to_string(name_file_port_server).ok()
+        .and_then(|s| s.trim().parse::<usize>().ok()) {
+        Some(n) => n,
+        None if systemd_listener.is_some() => 0,
+        None => {
+            require_interactive(non_interactive, 
+                                &format!("no valid port in {}", name_file_port_server),
+                                "Write the port number to listen to in this file.");
+            get_port()
+        }
+    };
+
+    // time to wait for the players to join before filling the empty seats with bots (forever if the
+    // file does not exist)
+    let name_file_join_timeout = "Config/join_timeout_server.dat";
+    let join_timeout = std::fs::read_to_string(name_file_join_timeout).ok()
+        .and_then(|s| first_word_of(&s).parse::<u64>().ok())
+        .map(std::time::Duration::from_secs);
+
+    // number of seats given to bots from the start (none if the file does not exist); at least one
+    // seat is left to the players
+    let name_file_n_bots = "Config/n_bots_server.dat";
+    let n_bots = std::fs::read_to_string(name_file_n_bots).ok()
+        .and_then(|s| first_word_of(&s).parse::<u8>().ok())
+        .unwrap_or(0);
+
+    // whether the replay of each finished game is written next to the save file (yes unless the
+    // file contains 0)
+    let name_file_replays = "Config/save_replays_server.dat";
+    let save_replays = std::fs::read_to_string(name_file_replays).ok()
+        .map(|s| first_word_of(&s) != "0")
+        .unwrap_or(true);
+
+    // password needed to join the game, if the file exists and its first line is not empty
+    let name_file_password = "Config/password_server.dat";
+    if let Ok(s) = std::fs::read_to_string(name_file_password) {
+        set_join_password(s.lines().next().unwrap_or("").trim());
+    }
+
+    // time without a new turn after which the game is considered stuck, in minutes (no limit if
+    // the file does not exist or contains 0), followed by `restart` to close the connection of the
+    // active player when it happens
+    let name_file_watchdog = "Config/watchdog_server.dat";
+    let watchdog_settings = std::fs::read_to_string(name_file_watchdog).ok()
+        .and_then(|s| {
+            let minutes = first_word_of(&s).parse::<u64>().ok().filter(|&m| m > 0)?;
+            Some((std::time::Duration::from_secs(60 * minutes), s.split_whitespace().any(|w| w == "restart")))
+        });
+
+    // words which may not be used in the player names
+    let name_file_filter = "Config/word_filter_server.dat";
+    if std::path::Path::new(name_file_filter).exists() {
+        match WordFilter::load(name_file_filter) {
+            Ok(filter) => set_name_filter(filter),
+            Err(_) => {
+                println!("Could not read the word filter from {}", name_file_filter);
+                process::exit(1);
+            }
+        }
+    }
+
+    // destination of the JSON reports on the finished games, a file or an `http://` URL (no
+    // report if the file does not exist)
+    let name_file_report = "Config/report_server.dat";
+    let report_target = match std::fs::read_to_string(name_file_report) {
+        Ok(s) => match report::ReportTarget::parse(&s) {
+            Some(target) => Some(target),
+            None => {
+                println!("Could not read the destination of the reports from {}", name_file_report);
+                process::exit(1);
+            }
+        },
+        Err(_) => None
+    };
+
+    // limits of the search for the hints, for small machines, if the server is built with the
+    // `serde` feature (defaults if the file does not exist)
+    #[cfg(feature = "serde")]
+    {
+        let name_file_solver = "Config/solver_server.dat";
+        if std::path::Path::new(name_file_solver).exists() {
+            match solver::SolverLimits::load(name_file_solver) {
+                Ok(limits) => solver::set_limits(limits),
+                Err(_) => {
+                    println!("Could not read the limits of the solver from {}", name_file_solver);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+
+    // message of the day, shown to the players when they join (none if the file does not exist)
+    let name_file_motd = "Config/motd_server.dat";
+    let mut motd = admin::load_motd(name_file_motd);
+
+    // optional rules
+    let name_file_rules = "Config/rules.dat";
+    let mut rules = if std::path::Path::new(name_file_rules).exists() {
+        match rules::RuleSet::load(name_file_rules) {
+            Ok(rules) => rules,
+            Err(_) => {
+                println!("Could not read the rules from {}", name_file_rules);
+                process::exit(1);
+            }
         }
-        Err(_) => get_port()
+    } else {
+        rules::RuleSet::default()
     };
 
-    // ask if a previous game should be loaded if not provided as an argument
+    // with `config save <name>`, save the settings of Config/config.dat and the rules as a preset;
+    // with `config load <name>`, start a new game with the settings and rules of a preset
+    let mut preset_config = None;
+    match preset::parse_config_command(&env::args().collect::<Vec<String>>()) {
+        Some(Ok(preset::ConfigCommand::Save(name))) => {
+            let config = match get_config_from_file("Config/config.dat") {
+                Ok(conf) => conf.0,
+                Err(_) => {
+                    println!("Could not read the config from the file!");
+                    process::exit(2);
+                }
+            };
+            match (preset::Preset { config, rules }).save(&name) {
+                Ok(()) => println!("Settings saved as the preset {}", &name),
+                Err(_) => {
+                    println!("Could not write the preset {}", &name);
+                    process::exit(1);
+                }
+            }
+            return;
+        },
+        Some(Ok(preset::ConfigCommand::Load(name))) => match preset::Preset::load(&name) {
+            Ok(p) => {
+                println!("Using the preset {}", &name);
+                rules = p.rules;
+                preset_config = Some(p.config);
+            },
+            Err(_) => {
+                println!("{}", preset::load_error_message(&name));
+                process::exit(2);
+            }
+        },
+        Some(Err(e)) => {
+            println!("{}", e);
+            process::exit(2);
+        },
+        None => ()
+    }
+    set_speed(rules.speed);
+    render::set_visibility(if rules.blind { render::Visibility::blind() } else { render::Visibility::default() });
+
+    // ask if a previous game should be loaded if not provided as an argument; with `--continue`,
+    // the most recent save in the current directory is loaded without prompts
     let load: bool;
     let load_from_command_line: bool;
+    let mut continue_from = String::new();
     match args.next() {
+        _ if preset_config.is_some() => {
+            load_from_command_line = false;
+            println!("Starting a new game");
+            load = false;
+        }
+        Some(s) if s == "--continue" => {
+            load_from_command_line = false;
+            load = true;
+            match save::newest_save(".", SAVE_EXTENSION) {
+                Some(path) => {
+                    continue_from = path.to_string_lossy().to_string();
+                    println!("Continuing the game saved in {}", &continue_from);
+                },
+                None => {
+                    println!("No save file to continue from!");
+                    process::exit(1);
+                }
+            }
+        }
         // "1" or "y" for yes, anything else for no
         Some(s) => {
             load_from_command_line = true;
@@ -62,6 +256,11 @@ fn main() {
                 _ => load = false
             };
         }
+        None if non_interactive => {
+            load_from_command_line = false;
+            println!("Starting a new game");
+            load = false;
+        }
         None => {
             load_from_command_line = false;
             println!("Load a previous game? (y/n)");
@@ -74,13 +273,16 @@ fn main() {
             n_jokers: 0,
             n_cards_to_start: 0,
             custom_rule_jokers: false,
-            n_players: 0
+            n_players: 0,
+            initial_meld_points: 0
     };
 
     // default save file without the sav extension
     let mut savefile = "machiavelli_save".to_string();
 
-    if !load {
+    if let Some(conf) = preset_config {
+        config = conf;
+    } else if !load {
 
         // get the config
         match get_config_from_file("Config/config.dat") {
@@ -90,6 +292,8 @@ fn main() {
             },
             Err(_) => {
                 println!("Could not read the config from the file!");
+                require_interactive(non_interactive, "could not read the config from Config/config.dat",
+                                    "See the Readme for the format of this file.");
                 match get_config_and_savefile() {
                     Ok(conf) => {
                         config = conf.0;
@@ -110,12 +314,36 @@ fn main() {
     let mut hands: Vec<Sequence>;
     let mut player: usize;
     let mut player_names = Vec::<String>::new();
+    let mut session_tokens = Vec::<String>::new();
+    let mut graveyard: Option<(Vec<usize>, Sequence)> = None;
+    let mut opened = Vec::<bool>::new();
     let mut rng = thread_rng();
+
+    // seed used to shuffle the deck, kept in the replay (0 for a loaded game)
+    let mut seed: u64 = 0;
+
+    // passphrase protecting the save file
+    let mut passphrase = passphrase_from_env();
+
+    // where the save files are stored
+    let save_backend = match save::backend_from_env() {
+        Ok(b) => b,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // `--continue` only finds the local save files
+    if !continue_from.is_empty() && !save_backend.is_local() {
+        println!("Only the local save files can be continued; give the name of the save file instead.");
+        process::exit(1);
+    }
     
     if load {
         
-        let mut fname = String::new(); // filename
-        let mut bytes = Vec::<u8>::new();
+        let mut fname = continue_from.clone(); // filename
+        let mut bytes: Vec<u8>;
         // if there is a next command-line argument, use it as name for the save file
         // if not, use the default name
         if load_from_command_line {
@@ -129,6 +357,8 @@ fn main() {
 
             // get the file name if not set
             if fname.is_empty() {
+                require_interactive(non_interactive, "could not read the save file",
+                                    "Give the name of an existing save file as second argument.");
                 println!("Name of the save file (nothing for the default file):");
                 match stdin().read_line(&mut fname) {
                     Ok(_) => (),
@@ -146,34 +376,44 @@ fn main() {
                 fname = savefile.clone() + SAVE_EXTENSION;
             }
 
-            // try to open the file
-            let mut file: File; 
-            match File::open(fname.clone()) {
-                Ok(f) => file = f,
-                Err(_) => {
-                    println!("Could not open the file!");
-                    fname.clear();
-                    continue;
-                }
-            };
-
-            // load the data from the file
-            match file.read_to_end(&mut bytes) {
-                Ok(_) => (),
+            // load the data from the save backend
+            match save_backend.read(&fname) {
+                Ok(b) => bytes = b,
                 Err(_) => {
                     println!("Could not read from the file!");
-                    bytes.clear();
                     fname.clear();
                     continue;
                 }
             };
             
-            // decode the sequence of bytes
-            bytes = encode::xor(&bytes, fname.as_bytes());
+            // decode the sequence of bytes; the saves written by the previous versions are 
+            // converted the next time the game is saved, and those written by the first versions,
+            // which can not be checked, are only loaded if the administrator agrees
+            let decoded = match encode::decode_save_with_migration(&bytes, &passphrase, &fname) {
+                Ok((decoded_bytes, legacy)) => {
+                    if legacy {
+                        println!("The save file has been written by a previous version");
+                    }
+                    Ok(decoded_bytes)
+                },
+                Err(e) => {
+                    println!("Could not decrypt the save file: {}.", e);
+                    let unchecked = !non_interactive && continue_from.is_empty() 
+                        && !bytes.starts_with(&encode::ENCRYPTED_SAVE_MAGIC) && {
+                        println!("It may have been written by one of the first versions, which can not be checked. Load it anyway? (‘y’ for yes)");
+                        is_yes(get_input().unwrap_or_default().trim())
+                    };
+                    if unchecked {
+                        Ok(encode::decode_unchecked_save(&bytes, &fname))
+                    } else {
+                        Err(LoadingError::Invalid)
+                    }
+                }
+            };
 
             // load the game
-            match load_game(&bytes) {
-                Ok(lg) => {
+            match decoded.and_then(|decoded_bytes| load_game(&decoded_bytes).map(|lg| (lg, decoded_bytes))) {
+                Ok((lg, decoded_bytes)) => {
                     config = lg.0;
                     starting_player = lg.1;
                     player = lg.2 as usize; 
@@ -181,9 +421,33 @@ fn main() {
                     hands = lg.4; 
                     deck = lg.5;
                     player_names = lg.6;
+                    opened = lg.7;
+                    session_tokens = session_tokens_from_bytes(&decoded_bytes, config.n_players as usize)
+                        .unwrap_or_default();
+                    graveyard = graveyard_from_bytes(&decoded_bytes, config.n_players as usize);
                 },
-                Err(_) => {
-                    println!("Error loading the save file!");
+                Err(LoadingError::Invalid) => {
+                    
+                    // the passphrase may be wrong
+                    if !continue_from.is_empty() {
+                        println!("Error loading the save file! Is MACHIAVELLI_PASSPHRASE set correctly?");
+                        process::exit(1);
+                    }
+                    require_interactive(non_interactive, &format!("could not load the save file {}", &fname),
+                                        "Check that MACHIAVELLI_PASSPHRASE is set to the passphrase of this file.");
+                    println!("Error loading the save file! Passphrase (nothing to choose another file):");
+                    bytes.clear();
+                    passphrase = get_input().unwrap_or_default().trim().to_string();
+                    if passphrase.is_empty() {
+                        fname.clear();
+                    }
+                    continue;
+                },
+                Err(e) => {
+                    println!("Error loading the save file: {}!", e);
+                    if !continue_from.is_empty() {
+                        process::exit(1);
+                    }
                     bytes.clear();
                     fname.clear();
                     continue;
@@ -196,154 +460,492 @@ fn main() {
     } else {
 
         // build the deck
-        deck = Sequence::multi_deck(config.n_decks, config.n_jokers, &mut rng);
+        seed = rng.gen();
+        deck = Sequence::multi_deck(config.n_decks, config.n_jokers, &mut StdRng::seed_from_u64(seed));
     
         // choose the starting player randomly
         starting_player = rng.gen_range(0..config.n_players);
         player = starting_player as usize;
         
         // build the hands
-        hands = vec![Sequence::new(); config.n_players as usize];
-        for i in 0..config.n_players {
-            for _ in 0..config.n_cards_to_start {
-                hands[i as usize].add_card(deck.draw_card().unwrap());
-            }
-        }
+        hands = deck.deal(config.n_players as usize, config.n_cards_to_start as usize);
+        debug_assert_eq!(Ok(()), deck_audit(hands.iter().chain(Some(&deck)).flatten(),
+                                             config.n_decks, config.n_jokers));
+
+    }
 
+    // session tokens, kept from the save file if possible so that the players can reconnect
+    // automatically after the server has been restarted
+    if session_tokens.len() != config.n_players as usize {
+        session_tokens = (0..config.n_players).map(|_| new_session_token()).collect();
     }
 
-    // current number of clients
+    // connected clients, with their names, in the order of the players
+    let mut client_slots: Vec<Option<(EncryptedStream, String)>> = 
+        (0..config.n_players).map(|_| None).collect();
+
+    // clients watching the game, who are never sent a hand nor asked for a move
+    let mut spectator_streams: Vec<PlayerConnection> = Vec::new();
+
+    // current number of clients, and number of clients to wait for
     let mut n_clients: u8 = 0;
+    let mut n_humans = config.n_players.saturating_sub(n_bots).max(1);
 
-    // vector of client threads
-    let mut client_threads = Vec::<thread::JoinHandle<(TcpStream, String, usize)>>::new();
-    
-    // vector of client streams
-    let mut client_streams = Vec::<TcpStream>::new();
-    
-    {
+    // code identifying the game, shown to the players when it starts
+    let game_code = new_game_code();
+    set_game_code(&game_code);
+
+    // set-up the tcp listener, used for the whole game; it does not block, so that the results of
+    // the threads greeting the clients can be collected while waiting for new connections
+    let listener = match systemd_listener {
+        Some(listener) => listener,
+        None => TcpListener::bind(format!("0.0.0.0:{}", port)).unwrap()
+    };
+    listener.set_nonblocking(true).unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
 
-        // set-up the tcp listener
-        let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).unwrap();
+    // connections to the players, in the order of the seats; a seat whose connection can not be 
+    // set up is left open until a player takes it
+    let mut connections: Vec<Option<PlayerConnection>> = (0..config.n_players).map(|_| None).collect();
+    if !load {
+        player_names = vec![String::new(); config.n_players as usize];
+    }
+
+    {
         
         // accept connections and process them, each in its own thread
         let names_taken = Arc::new(Mutex::new(Vec::<String>::new())); // vector of the names that are already taken
-        println!("\nserver listening to port {}", port);
-        for stream_res in listener.incoming() {
-            match stream_res {
-                Ok(stream) => {
-                    n_clients += 1;
-                    println!("New connection: {} (player {})", stream.peer_addr().unwrap(), n_clients);
-                    if load {
-                        let player_names_ = player_names.clone();
-                        let arc = names_taken.clone();
-                        client_threads.push(thread::spawn(move || {
-                            handle_client_load(stream, &player_names_, arc).unwrap()
-                        }));
-                    } else {
-                        client_threads.push(thread::spawn(move || {handle_client(stream).unwrap()}));
+        match listener.local_addr() {
+            Ok(address) => println!("\nserver listening to port {}", address.port()),
+            Err(_) => println!("\nserver listening to port {}", port)
+        }
+        println!("game code: {}", game_code);
+        loop {
+            let start_time = std::time::Instant::now();
+            while n_clients < n_humans {
+                
+                // if the time is over and at least one player has joined, start without the others
+                if n_clients > 0 && join_timeout.map(|t| start_time.elapsed() >= t).unwrap_or(false) {
+                    println!("Not all players have joined in time; the empty seats go to bots");
+                    break;
+                }
+
+                match listener.accept() {
+                    Ok((stream, address)) => {
+                        println!("New connection: {}", address);
+                        stream.set_nonblocking(false).unwrap_or(());
+                        let sender = sender.clone();
+                        if load {
+                            let player_names_ = player_names.clone();
+                            let session_tokens_ = session_tokens.clone();
+                            let arc = names_taken.clone();
+                            thread::spawn(move || {
+                                sender.send(handle_client_load(stream, &player_names_, &session_tokens_, arc))
+                                    .unwrap_or(());
+                            });
+                        } else {
+                            thread::spawn(move || { sender.send(handle_client(stream, &[])).unwrap_or(()); });
+                        }
+                    },
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => wait(),
+                    Err(e) => {
+                        println!("Error: {}", e);
                     }
-                },
-                Err(e) => {
-                    println!("Error: {}", e);
                 }
-            }
 
-            // exit the loop if enough players have joined
-            if n_clients == config.n_players {
-                break;
+                // collect the clients which have been greeted; when loading a game, a client may 
+                // replace a previous connection with the same name
+                for result in receiver.try_iter() {
+                    match result {
+                        Ok((stream, name, _)) if name.starts_with(SPECTATOR_PREFIX) => {
+                            add_spectator(&mut spectator_streams, stream, &name);
+                        },
+                        Ok((stream, name, position)) => {
+                            let slot = if load {
+                                Some(position).filter(|&i| connections[i].is_none())
+                            } else {
+                                (0..client_slots.len()).find(|&i| client_slots[i].is_none() && connections[i].is_none())
+                            };
+                            let slot = match slot {
+                                Some(slot) => slot,
+                                None => {
+                                    println!("{} has been turned away: the seat is taken", name);
+                                    stream.shutdown(Shutdown::Both).unwrap_or(());
+                                    continue;
+                                }
+                            };
+                            match client_slots[slot].replace((stream, name)) {
+                                Some((old_stream, name)) => {
+                                    println!("{} has been replaced by a new connection", name);
+                                    old_stream.shutdown(Shutdown::Both).unwrap_or(());
+                                },
+                                None => {
+                                    n_clients += 1;
+                                    println!("Player {} has joined ({}/{})", slot + 1, n_clients, 
+                                             n_humans);
+                                }
+                            }
+                            if n_clients == n_humans {
+                                break;
+                            }
+                        },
+                        Err(e) => println!("Could not greet the client: {}", e)
+                    }
+                }
             }
-        }
-        
-        // wait for all threads to finish and collect the client streams 
-        if load {
 
-            for _i in 0..config.n_players {
-                client_streams.push(TcpStream::connect(format!("0.0.0.0:{}", port)).unwrap());
-            }
-            for thread in client_threads {
-                let output = thread.join().unwrap();
-                client_streams[output.2] = output.0;
+            // set up the connections, each client being read by its own thread from now on; bots 
+            // take the empty seats, keeping the names of the players from the save file if any
+            let mut names_taken: Vec<String> = client_slots.iter().flatten().map(|slot| slot.1.clone())
+                .chain(player_names.iter().filter(|name| !name.is_empty()).cloned())
+                .collect();
+            for i in 0..config.n_players as usize {
+                if connections[i].is_some() {
+                    continue;
+                }
+                let (stream, name, is_bot) = match client_slots[i].take() {
+                    Some((stream, name)) => (stream, name, false),
+                    None => match bot::connect_bot() {
+                        Ok(stream) => {
+                            let name = if load { player_names[i].clone() } else { bot::bot_name(&names_taken) };
+                            (stream, name, true)
+                        },
+                        Err(e) => {
+                            println!("Could not start a bot for player {}: {}", i + 1, e);
+                            continue;
+                        }
+                    }
+                };
+                match PlayerConnection::new(stream, i) {
+                    Ok(mut connection) => {
+                        if is_bot {
+                            connection.set_bot();
+                        }
+                        names_taken.push(name.clone());
+                        if !load {
+                            player_names[i] = name;
+                        }
+                        connections[i] = Some(connection);
+                    },
+                    Err(e) => {
+                        println!("Could not set up the connection of player {}: {}", i + 1, e);
+                        if !is_bot {
+                            n_clients -= 1;
+                        }
+                    }
+                }
             }
 
-        } else {
-
-            for thread in client_threads {
-                let output = thread.join().unwrap();
-                client_streams.push(output.0);
-                player_names.push(output.1);
+            // wait for players to take the seats left open
+            let n_open = connections.iter().filter(|connection| connection.is_none()).count() as u8;
+            if n_open == 0 {
+                break;
             }
-
-            // check that no players have the same name; if yes, rename players
-            ensure_names_are_different(&mut player_names, &mut client_streams).unwrap();
+            println!("{} seat(s) left open; waiting for players to take them", n_open);
+            n_humans = n_clients + n_open;
         }
+    }
+    let mut client_streams: Vec<PlayerConnection> = connections.into_iter().flatten().collect();
 
+    // check that no players have the same name; if yes, rename players
+    if !load {
+        ensure_names_are_different(&mut player_names, &mut client_streams).unwrap();
     }
 
+    // give each player their session token
+    for (stream, token) in client_streams.iter_mut().zip(session_tokens.iter()) {
+        stream.set_session_token(token);
+        send_session_token(stream, token).unwrap_or(());
+        negotiate_updates(stream).unwrap_or(());
+    }
+    send_message_all_players(&mut client_streams, &format!("Game code: {}\n", game_code));
+    if !motd.is_empty() {
+        send_message_all_players(&mut client_streams, &admin::motd_message(&motd));
+    }
+    send_message_all_players(&mut client_streams, &rules.summary());
+
     // name of the save file
     let save_name = &(savefile.clone() + SAVE_EXTENSION);
     
     // name of the backup save file
-    let backup_name = &(savefile + "_bak" + SAVE_EXTENSION);
+    let backup_name = &(savefile.clone() + "_bak" + SAVE_EXTENSION);
+
+    // if the server panics, the state at the start of the turn is saved in a local file
+    save::install_emergency_save_hook(PathBuf::from(savefile.clone() + "_emergency" + SAVE_EXTENSION));
+
+    // the watchdog writes the same file if the game gets stuck
+    let watchdog = watchdog_settings.map(|(limit, restart)| {
+        watchdog::Watchdog::start(limit, restart, PathBuf::from(savefile.clone() + "_emergency" + SAVE_EXTENSION))
+    });
    
     // sort modes for the cards (0: unsorted, 1: sort by rank, 2: sort by suit)
     let mut sort_modes: Vec<u8> = vec![0; config.n_players as usize];
 
+    // players who have forfeited (in a saved game, they are those without cards)
+    let mut forfeited: Vec<bool> = hands.iter().map(|hand| hand.number_cards() == 0).collect();
+
+    // set when the players have agreed to save the game and quit
+    let mut save_and_quit = false;
+
+    // records on the melds of this game
+    let mut records = stats::GameRecords::new();
+
+    // time taken by the players during their turns
+    let mut turn_times = stats::TurnTimes::new(config.n_players as usize);
+
+    // players who have completed their first turn (all of them in a saved game)
+    let mut first_turn_done: Vec<bool> = vec![load; config.n_players as usize];
+
+    // players who have made their first meld (kept in the save file), if it must be worth a minimum
+    // number of points
+    if !load {
+        opened = vec![false; config.n_players as usize];
+    }
+    let mut disconnection_announced: Vec<bool> = vec![false; config.n_players as usize];
+
+    // players who have exchanged a card during this game (not kept in the save file)
+    let mut exchanges_done: Vec<bool> = vec![false; config.n_players as usize];
+
+    // whether the turn order has been reversed (see the `reverse_rank` rule)
+    let mut reversed = false;
+
+    // number of turns played since the beginning of the round
+    let mut turns_in_round = 0;
+
+    // house rules (see the `hooks` module), including those of the script `Config/rules.rhai` if
+    // the server is built with the `scripting` feature
+    let mut hooks = RuleHooks::from_rules(config.custom_rule_jokers, &rules);
+    #[cfg(feature = "scripting")]
+    {
+        let name_file_script = "Config/rules.rhai";
+        if std::path::Path::new(name_file_script).exists() {
+            match machiavelli::scripting::ScriptHook::from_file(name_file_script) {
+                Ok(hook) => hooks.add(hook),
+                Err(e) => {
+                    println!("Could not load the rules script: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+
+    // number of turns each sequence on the table has been left untouched, and cards waiting to be
+    // reshuffled into the deck
+    let (mut table_ages, mut discard_pool) = graveyard.unwrap_or_else(|| (vec![0; table.len()], Sequence::new()));
+
+    // computer player taking the turns of the bots
+    let mut bot_player: Box<dyn bot::BotPlayer> = Box::new(bot::GreedyBot);
+
+    // turns which modified each sequence on the table (not kept in the save file)
+    let mut history = TableHistory::new(&table);
+
+    // turns of the game, written to a file when it is over
+    let mut replay = replay::Replay::new(&config, &rules, seed, &player_names);
+    let mut game_start = std::time::Instant::now();
+
+    // scores of the match (see the `match_score` rule), kept in a file next to the save file so
+    // that a match can be resumed
+    let match_name = savefile.clone() + "_match.dat";
+    let mut match_scores = match match_play::MatchScores::load(&match_name) {
+        Ok(scores) if load && rules.match_score > 0 => scores,
+        _ => match_play::MatchScores::new(&player_names)
+    };
+
+    // commands typed in the terminal of the server (see the `admin` module), unless it runs in
+    // non-interactive mode
+    let admin_console = if non_interactive { None } else { Some(admin::AdminConsole::start()) };
+
+    // commitment to the deck, sent to the players when the cards are dealt and revealed at the end
+    // of the game (see the `fairness` module); there is none for a game loaded from a save file
+    let mut deal_secret = if load { None } else { Some(fairness::DealSecret::new(seed, &mut rng)) };
+    if let Some(secret) = deal_secret.as_ref() {
+        send_deal_commitments(&mut client_streams, secret, &config, &forfeited);
+    }
+
+    // the key of the save file is derived once, rather than at every turn
+    let save_key = encode::SaveKey::new(&passphrase);
+
     let mut play_again = true;
     let mut previous_messages: Vec<String> = vec!["".to_string(); config.n_players as usize];
     while play_again {
+
+        // before the first turn, the players with a hopeless hand may ask for a re-deal
+        if rules.redeal_vote && first_turn_done.iter().all(|&done| !done) 
+            && offer_redeal(&mut client_streams, &hands, &player_names, &forfeited) {
+            seed = rng.gen();
+            replay.seed = seed;
+            deck = Sequence::multi_deck(config.n_decks, config.n_jokers, &mut StdRng::seed_from_u64(seed));
+            hands = deal_active_players(&mut deck, config.n_cards_to_start as usize, &forfeited);
+            let secret = fairness::DealSecret::new(seed, &mut rng);
+            send_deal_commitments(&mut client_streams, &secret, &config, &forfeited);
+            deal_secret = Some(secret);
+        }
+
+        // winner of the game, if it does not end in a draw
+        let mut game_winner: Option<usize> = None;
+
         loop {
             
-            // if all the cards have been drawn, stop the game
-            if deck.number_cards() == 0 {
+            // if all the cards have been drawn, the cards set aside by the resets are reshuffled
+            // into a new deck
+            if deck.number_cards() == 0 && rules.graveyard_rounds > 0 && discard_pool.number_cards() > 0 {
                 send_message_all_players(&mut client_streams, 
-                                         "\n\x1b[1mNo more cards in the deck—it's a draw!\x1b[0m\n");
+                    &format!("\n{}\n", bold(&format!("No more cards in the deck—{} cards from the discard pool are reshuffled into a new deck", 
+                                                        discard_pool.number_cards()))));
+                reshuffle_discard_pool(&mut deck, &mut discard_pool);
+            }
+
+            // if all the cards have been drawn, stop the game, unless the players may still 
+            // change the table
+            let n_active_players = forfeited.iter().filter(|&&f| !f).count();
+            if deck.number_cards() == 0 
+                && table_settled(&table_ages, rules.graveyard_rounds * n_active_players) {
+                for streams in [&mut client_streams, &mut spectator_streams] {
+                    send_message_all_players(streams, &format!("\n{}\n", bold("No more cards in the deck—it's a draw!")));
+                }
                 break;
             }
             
+            // greet the clients which have connected during the last turn
+            while let Ok((stream, address)) = listener.accept() {
+                println!("New connection: {}", address);
+                stream.set_nonblocking(false).unwrap_or(());
+                let sender = sender.clone();
+                let session_tokens_ = session_tokens.clone();
+                thread::spawn(move || { 
+                    sender.send(handle_client(stream, &session_tokens_)).unwrap_or(()); 
+                });
+            }
+            
+            // a greeted client takes back the seat of a disconnected player with the same name or 
+            // session token; otherwise, depending on the rules, it joins the game or watches it
+            // until a seat is free
+            for (mut stream, name, _) in receiver.try_iter().flatten() {
+                if name.starts_with(SPECTATOR_PREFIX) {
+                    add_spectator(&mut spectator_streams, stream, &name);
+                    continue;
+                }
+                let returning_player = (0..config.n_players as usize).find(|&i| {
+                    !client_streams[i].is_connected() && 
+                        (player_names[i] == name || client_streams[i].matches_session_token(&name))
+                });
+                if let Some(i) = returning_player {
+                    client_streams[i].replace_stream(stream).unwrap_or(());
+                    negotiate_updates(&mut client_streams[i]).unwrap_or(());
+                    disconnection_announced[i] = false;
+                    println!("Player {} is back", i + 1);
+                    continue;
+                }
+                // a session token does not let a player join with a second seat, which would skip 
+                // the password of a private game
+                if name.starts_with(SESSION_TOKEN_PREFIX) {
+                    println!("Turning away {}: the game is full", name);
+                    turn_away_client(&mut stream, config.n_players).unwrap_or(());
+                    continue;
+                }
+                if !rules.allow_joining || config.n_players == u8::MAX {
+                    send_message_to_client(&mut stream, 
+                        "The game is full: you are watching it as a spectator, and will be offered the seat of a player who leaves.\n")
+                        .unwrap_or(());
+                    add_spectator(&mut spectator_streams, stream, &name);
+                    continue;
+                }
+                let mut connection = match PlayerConnection::new(stream, config.n_players as usize) {
+                    Ok(connection) => connection,
+                    Err(_) => continue
+                };
+                let i = add_player(&mut hands, &mut deck, &mut config);
+                let name = if player_names.contains(&name) { format!("{} ({})", name, i + 1) } else { name };
+                session_tokens.push(new_session_token());
+                connection.set_session_token(&session_tokens[i]);
+                send_session_token(&mut connection, &session_tokens[i]).unwrap_or(());
+                send_message_to_client(&mut connection, &format!("Game code: {}\n", game_code)).unwrap_or(());
+                if !motd.is_empty() {
+                    send_message_to_client(&mut connection, &admin::motd_message(&motd)).unwrap_or(());
+                }
+                send_message_to_client(&mut connection, &rules.summary()).unwrap_or(());
+                negotiate_updates(&mut connection).unwrap_or(());
+                client_streams.push(connection);
+                player_names.push(name);
+                sort_modes.push(0);
+                previous_messages.push(String::new());
+                forfeited.push(false);
+                first_turn_done.push(false);
+                opened.push(false);
+                disconnection_announced.push(false);
+                exchanges_done.push(false);
+                turn_times.add_player();
+                println!("{} joins the game as player {}", &player_names[i], i + 1);
+                send_message_all_players(&mut client_streams, 
+                                         &format!("{} joins the game!\n", &player_names[i]));
+            }
+
             // save the game
             let mut bytes = game_to_bytes(starting_player, player as u8, &table, &hands, &deck, 
-                                          &config, &player_names);
-            bytes = encode::xor(&bytes, save_name.as_bytes());
-            match File::create(save_name) {
-                Ok(mut f) => match f.write_all(&bytes) {
-                    Ok(_) => (),
-                    Err(_) => {
-                        println!("Could not write to the save file!");
-                    }
-                },
+                                          &config, &player_names, &opened);
+            append_graveyard(&mut bytes, &table_ages, &discard_pool);
+            append_session_tokens(&mut bytes, &session_tokens);
+            bytes = save_key.encrypt(&bytes);
+            match save_backend.write(save_name, &bytes) {
+                Ok(_) => (),
                 Err(_) => {
-                    println!("Could not create the save file!");
+                    println!("Could not write to the save file!");
                 }
             };
+            save::set_emergency_state(bytes);
+            if let Some(watchdog) = watchdog.as_ref() {
+                let stream = if client_streams[player].is_bot() { 
+                    None 
+                } else { 
+                    client_streams[player].try_clone_stream().ok() 
+                };
+                watchdog.turn_started(&player_names[player], stream);
+            }
             
             // backup the save file
-            match std::fs::copy(save_name, backup_name) {
+            match save_backend.copy(save_name, backup_name) {
                 Ok(_) => (),
                 Err(_) => println!("Could not create the backup file!")
             };
  
             // print the name of the current player 
+            send_status_all_players(&mut client_streams, &status_string(&player_names[player], &deck));
+            send_heartbeat_all_players(&mut client_streams);
             clear_and_send_message_all_players(&mut client_streams, 
-                                               &format!("\x1b[1m{}'s turn:{}", 
-                                                        &player_names[player], &reset_style_string()));
+                                               &format!("{}{}", bold(&format!("{}'s turn:", &player_names[player])), 
+                                                        &reset_style_string()));
         
-            // string with the number of cards each player has
-            let mut string_n_cards = format!("\nNumber of cards ({} remaining in the deck):", deck.number_cards());
-            for i in 0..(config.n_players as usize) {
-                string_n_cards += &format!("\n  {}: {}", &player_names[i], &hands[i].number_cards());
-            }
-            string_n_cards += "\n";
+            // number of cards each player has and connection status of the players
+            let hand_sizes: Vec<usize> = hands.iter().map(|h| h.number_cards()).collect();
+            let notes: Vec<&str> = client_streams.iter().map(connection_status).collect();
+            let turn_order = render::turn_order(&player_names, player, &forfeited, reversed);
 
-           
             // print the situation for each player
-            for i in 0..(config.n_players as usize) {
+            let seated: Vec<usize> = (0..(config.n_players as usize)).filter(|&i| !forfeited[i]).collect();
+            for i in seated {
+                let string_n_cards = format!("{}\n{}\n", 
+                    render::card_counts(&player_names, &hand_sizes, &notes, &forfeited, i, deck.number_cards()),
+                    &turn_order);
                 loop {
                     match send_message_to_client(&mut client_streams[i], 
                             &format!("{}{}", &string_n_cards, 
-                                &situation_to_string(&table, &hands[i], &Sequence::new(), &previous_messages[i]))
+                                &situation_to_string(&table, &hands[i], &Sequence::new(), &Sequence::new(),
+                                                    &previous_messages[i]))
                     ) {
                         Ok(_) => break,
+
+                        // the game only waits for the current player; the others can take their 
+                        // seat back between two turns
+                        Err(_) if i != player => {
+                            if !disconnection_announced[i] {
+                                disconnection_announced[i] = true;
+                                println!("Lost connection with player {}", i + 1);
+                                send_message_all_players(&mut client_streams, 
+                                    &format!("{} seems to have disconnected; the game goes on until their turn.\n",
+                                             &player_names[i]));
+                            }
+                            break;
+                        },
                         Err(_) => {
                             send_message_all_players(
                                 &mut client_streams,
@@ -351,7 +953,18 @@ fn main() {
                                          &player_names[i])
                             );
                             println!("Lost connection with player {}", i + 1);
-                            wait_for_reconnection(&mut client_streams[i], &player_names[i], port).unwrap();
+                            if let Err(e) = wait_for_reconnection(&mut client_streams[i], &player_names[i], 
+                                                                  &listener) {
+                                
+                                // the player can not come back, so they leave the game
+                                println!("Could not wait for player {} to reconnect: {}", i + 1, e);
+                                forfeit(&mut hands[i], &mut deck, rules.forfeited_cards_to_deck);
+                                forfeited[i] = true;
+                                send_message_all_players(&mut client_streams, 
+                                    &format!("{} can not reconnect and leaves the game\n", &player_names[i]));
+                                break;
+                            }
+                            disconnection_announced[i] = false;
                             println!("Player {} is back", i + 1);
                             send_message_all_players(
                                 &mut client_streams,
@@ -362,49 +975,289 @@ fn main() {
                 }
             }
 
-            // player turn
-            match start_player_turn(&mut table, &mut hands, &mut deck, 
-                              config.custom_rule_jokers, &player_names,
-                              player, config.n_players as usize, &mut client_streams,
-                              port, &mut sort_modes[player], &previous_messages)
-            {
-                Ok(o_m) => previous_messages[player] = o_m.clone(),
-                Err(err) => {
-                    println!("{}", err);
-                    process::exit(1);
+            // announcements and changes of the message of the day typed in the admin console
+            for command in admin_console.iter().flat_map(admin::AdminConsole::commands) {
+                match command {
+                    admin::AdminCommand::Say(text) => {
+                        send_announcement_all_players(&mut client_streams, &text);
+                        send_announcement_all_players(&mut spectator_streams, &text);
+                        println!("Announcement sent");
+                    },
+                    admin::AdminCommand::Motd(text) => {
+                        motd = text;
+                        println!("Message of the day {}", if motd.is_empty() { "cleared" } else { "updated" });
+                    },
+                    admin::AdminCommand::Help => print!("{}", admin::ADMIN_HELP)
                 }
-            };
-            
- 
-            // if the player has no more cards, stop the game
-            if hands[player].number_cards() == 0 {
+            }
+
+            // the spectators only see the table and the number of cards of each player
+            send_spectator_view(&mut spectator_streams, &table, &deck, &player_names, &hand_sizes, 
+                                &notes, &forfeited, player, &turn_order);
+
+            // table and number of cards at the beginning of the turn, to know which sequences are
+            // left untouched and how many cards are played
+            let table_start_turn = table.clone();
+            let hand_start_turn = hands[player].clone();
+            let n_cards_start_turn = hands[player].number_cards();
+
+            // the moves of the turn are checked by the game state, whoever plays them
+            let mut game = game::GameState::new(std::mem::take(&mut table), std::mem::take(&mut hands), 
+                                                std::mem::take(&mut deck), player);
+            game.initial_meld_points = config.initial_meld_points;
+            game.opened = opened.clone();
+            game.max_hand_size = rules.max_hand_size;
+
+            // a player who has left the game while it waited for them does not play; the turns of 
+            // the bots are played by the server
+            if forfeited[player] {
+                println!("{} has left the game", &player_names[player]);
+            } else if client_streams[player].is_bot() {
+                long_wait();
+                let action = bot_player.play_turn(&mut game, &hooks, &rules, 
+                                                  !first_turn_done[player] && rules.no_table_on_first_turn);
                 send_message_all_players(&mut client_streams, 
-                    &format!("\n\u{0007}\u{0007}\u{0007}\x1b[1m{} wins! Congratulations!\x1b[0m{}\n\n", 
-                             player_names[player], &reset_style_string())
-                );
+                                         &format!("{} {}\n", &player_names[player], action));
+            } else {
+                // player turn
+                let turn_start = std::time::Instant::now();
+                match start_player_turn(&mut game, &hooks, &player_names,
+                                        config.n_players as usize, &mut client_streams,
+                                        &listener, &mut sort_modes[player], &previous_messages, &mut discard_pool,
+                                        &rules, &mut forfeited, &mut save_and_quit,
+                                        !first_turn_done[player], &mut exchanges_done[player], reversed,
+                                        &history)
+                {
+                    Ok(o_m) => previous_messages[player] = o_m.clone(),
+                    Err(err) => {
+                        println!("{}", err);
+                        process::exit(1);
+                    }
+                };
+                if !save_and_quit && !forfeited[player] {
+                    let duration = turn_start.elapsed();
+                    turn_times.record(player, duration);
+                    send_message_all_players(&mut client_streams, 
+                        &format!("{} took {}\n", &player_names[player], stats::format_duration(duration)));
+                }
+            }
+            table = game.table;
+            hands = game.hands;
+            deck = game.deck;
+            opened[player] = game.opened[player];
+ 
+            table_ages = update_table_ages(&table_start_turn, &table_ages, &table);
+            history.update(&table, player);
+            client_streams[player].set_note("");
+            first_turn_done[player] = true;
+            replay.record(player, &hand_start_turn, &table_start_turn, &hands[player], &table);
+            if !forfeited[player] {
+                let n_cards_played = n_cards_start_turn.saturating_sub(hands[player].number_cards());
+                records.update(&table, n_cards_played, &player_names[player]);
+            }
+ 
+            // the game has been saved at the beginning of the turn
+            if save_and_quit {
+                let message = format!("The game has been saved in {}; load it when starting the server to resume.\n", 
+                                      save_backend.describe(save_name));
+                println!("{}", &message);
+                for stream in client_streams.iter_mut().chain(spectator_streams.iter_mut()) {
+                    send_message_and_close_client(stream, &message).unwrap_or(());
+                }
+                return;
+            }
+
+            // the seat of a player who has left the game is offered to the spectators; the one who
+            // takes it starts with a new hand
+            if forfeited[player] {
+                if let Some((mut connection, name)) = offer_seat(&mut spectator_streams, player, 
+                                                                 &player_names[player]) {
+                    let name = if player_names.contains(&name) { format!("{} ({})", name, player + 1) } else { name };
+                    hands[player] = deck.deal(1, config.n_cards_to_start as usize).pop().unwrap_or_default();
+                    session_tokens[player] = new_session_token();
+                    connection.set_session_token(&session_tokens[player]);
+                    send_session_token(&mut connection, &session_tokens[player]).unwrap_or(());
+                    negotiate_updates(&mut connection).unwrap_or(());
+                    client_streams[player] = connection;
+                    println!("{} takes the seat of player {}", &name, player + 1);
+                    send_message_all_players(&mut client_streams, 
+                        &format!("{} takes the seat of {}!\n", &name, &player_names[player]));
+                    player_names[player] = name;
+                    sort_modes[player] = 0;
+                    previous_messages[player] = String::new();
+                    forfeited[player] = false;
+                    first_turn_done[player] = false;
+                    opened[player] = false;
+                    disconnection_announced[player] = false;
+                    exchanges_done[player] = false;
+                }
+            }
+
+            // if the player has no more cards, or is the only one left, stop the game
+            let remaining_players: Vec<usize> = (0..forfeited.len()).filter(|&i| !forfeited[i]).collect();
+            let winner = if remaining_players.len() == 1 {
+                Some(remaining_players[0])
+            } else if !forfeited[player] && hands[player].number_cards() == 0 {
+                Some(player)
+            } else {
+                None
+            };
+            if let Some(winner) = winner {
+                let hand_sizes: Vec<usize> = hands.iter().map(|h| h.number_cards()).collect();
+                let notes: Vec<&str> = client_streams.iter().map(connection_status).collect();
+                send_spectator_view(&mut spectator_streams, &table, &deck, &player_names, &hand_sizes, 
+                                    &notes, &forfeited, player, &turn_order);
+                for streams in [&mut client_streams, &mut spectator_streams] {
+                    send_message_all_players(streams, 
+                        &format!("\n\u{0007}\u{0007}\u{0007}{}{}\n\n", 
+                                 bold(&format!("{} wins! Congratulations!", player_names[winner])), 
+                                 &reset_style_string())
+                    );
+                }
+                game_winner = Some(winner);
                 break;
             }
             
-            // next player
-            player += 1;
-            if player >= config.n_players as usize {
-                player = 0;
+            // next player, after the changes of the turn order triggered by the house rules
+            hooks.on_table_changed(&table_start_turn, &table);
+            let change = hooks.on_turn_end();
+            if change.reverse {
+                reversed = !reversed;
+                send_message_all_players(&mut client_streams, &format!("\n{}\n", bold("The turn order is reversed!")));
+            }
+            player = next_player_in_direction(player, &forfeited, reversed);
+            if change.skip {
+                send_message_all_players(&mut client_streams, 
+                    &format!("\n{}\n", bold(&format!("{} skips their turn!", &player_names[player]))));
+                player = next_player_in_direction(player, &forfeited, reversed);
             }
 
+            // at the end of each round, estimate the chances of winning of each player
+            turns_in_round += 1;
+            if turns_in_round >= forfeited.iter().filter(|&&f| !f).count() {
+                turns_in_round = 0;
+                let chances = simulation::estimate_win_chances(&hands, &table, &deck, player, 
+                                                               &forfeited, simulation::N_ROLLOUTS, 
+                                                               &mut rng);
+                send_message_all_players(&mut client_streams, 
+                    &format!("\n{}\n{}", bold("Chances of winning:"), 
+                             simulation::chances_summary(&player_names, &chances, &forfeited)));
+            }
+
+        }
+
+        // let the players check that the deck matches the commitment
+        if let Some(secret) = deal_secret.take() {
+            reveal_deal_secret(&mut client_streams, &secret);
         }
 
+        // write the replay next to the save file, named after the time at which the game ended
+        if save_replays {
+            replay.config = config.clone();
+            replay.player_names = player_names.clone();
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let replay_name = format!("{}_replay_{}{}", savefile, timestamp, REPLAY_EXTENSION);
+            match save_backend.write(&replay_name, &replay.to_bytes()) {
+                Ok(_) => println!("Replay written to {}", save_backend.describe(&replay_name)),
+                Err(_) => println!("Could not write the replay!")
+            }
+        }
+
+        // in a match, add the points left in the hands, and check if the match is over
+        let mut match_in_progress = false;
+        if rules.match_score > 0 {
+            let round = scoring::round_scores(&hands, &forfeited, rules.joker_penalty);
+            match_scores.add_round(&player_names, &round);
+            let mut message = format!("\n{}\n{}\n{}\n{}", bold("Points left in the hands:"),
+                                      scoring::round_summary(&player_names, &round),
+                                      bold(&format!("Scores after round {} of the match:", match_scores.n_rounds)),
+                                      match_scores.summary());
+            if match_scores.is_over(rules.match_score) {
+                if let Some(winner) = match_scores.winner(&forfeited) {
+                    message += &format!("\n\u{0007}{}\n", bold(&format!("{} wins the match!", player_names[winner])));
+                }
+                match_scores = match_play::MatchScores::new(&player_names);
+                std::fs::remove_file(&match_name).unwrap_or(());
+            } else {
+                match_in_progress = true;
+                if match_scores.save(&match_name).is_err() {
+                    println!("Could not save the scores of the match!");
+                }
+            }
+            send_message_all_players(&mut client_streams, &message);
+        }
+
+        // send the report on the game to the league or tournament software
+        if let Some(target) = report_target.as_ref() {
+            let bots: Vec<bool> = client_streams.iter().map(|s| s.is_bot()).collect();
+            let end_time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let game_report = report::GameReport::new(&game_code, &config, &rules, &player_names, &bots, 
+                                                      &hands, &forfeited, game_winner, replay.turns.len(),
+                                                      game_start.elapsed(), end_time);
+            if let Err(e) = game_report.send(target) {
+                println!("Could not send the report on the game: {}", e);
+            }
+        }
+
+        // if the operator has opted in, keep anonymous statistics on the game
+        if telemetry {
+            let n_bots = client_streams.iter().filter(|s| s.is_bot()).count();
+            let n_forfeits = forfeited.iter().filter(|&&f| f).count();
+            let usage = telemetry::GameUsage::new(&config, &rules, n_bots, n_forfeits, 
+                                                  replay.turns.len(), game_start.elapsed());
+            if usage.record().is_err() {
+                println!("Could not write the statistics of the game!");
+            }
+        }
+
+        // show the records of the game and keep the all-time ones
+        let mut all_time_records = stats::GameRecords::load();
+        all_time_records.merge(&records);
+        if all_time_records.save().is_err() {
+            println!("Could not save the records!");
+        }
+        send_message_all_players(&mut client_streams, 
+            &format!("\n{}\n{}\n{}\n{}\n{}\n{}\n",
+                     bold("Records of the game:"), records.summary(), bold("All-time records:"), 
+                     all_time_records.summary(), bold("Turn times:"), turn_times.summary(&player_names)));
+
         // ask the players if they want to play again
-        send_message_all_players(&mut client_streams, "Play again? (‘y’ for yes)\n");
-        for stream in &mut client_streams {
+        let play_again_prompt = if match_in_progress {
+            "Play the next round of the match? (‘y’ for yes)\n"
+        } else {
+            "Play again? (‘y’ for yes)\n"
+        };
+        send_message_all_players(&mut client_streams, play_again_prompt);
+        for (i, stream) in client_streams.iter_mut().enumerate() {
+            
+            // the bots always want to play again, and the players who have forfeited have left
+            if stream.is_bot() || forfeited[i] {
+                continue;
+            }
             let reply: bool; 
             loop {
-                if let Ok(s) = get_string_from_client(stream) {
-                    if is_yes(&s) {
-                        reply = true;
-                        break;
-                    } else if is_no(&s) {
-                        reply = false;
-                        break;
+                match get_string_from_client(stream) {
+                    Ok(s) => {
+                        if is_yes(&s) {
+                            reply = true;
+                            break;
+                        } else if is_no(&s) {
+                            reply = false;
+                            break;
+                        }
+                    },
+                    Err(_) => {
+                        // wait for the player to reconnect and ask them again
+                        println!("Lost connection with player {}", i + 1);
+                        if let Err(e) = wait_for_reconnection(stream, &player_names[i], &listener) {
+                            
+                            // a player who can not come back does not play again
+                            println!("Could not wait for player {} to reconnect: {}", i + 1, e);
+                            reply = false;
+                            break;
+                        }
+                        println!("Player {} is back", i + 1);
+                        send_message_to_client(stream, play_again_prompt).unwrap_or(());
                     }
                 }
             }
@@ -421,20 +1274,28 @@ fn main() {
 
         // if all of them say yes, re-initialize the game
         if play_again {
-            deck = Sequence::multi_deck(config.n_decks, config.n_jokers, &mut rng);
-            hands = vec![Sequence::new(); config.n_players as usize];
+            seed = rng.gen();
+            replay = replay::Replay::new(&config, &rules, seed, &player_names);
+            game_start = std::time::Instant::now();
+            deck = Sequence::multi_deck(config.n_decks, config.n_jokers, &mut StdRng::seed_from_u64(seed));
+            hands = deal_active_players(&mut deck, config.n_cards_to_start as usize, &forfeited);
+            let secret = fairness::DealSecret::new(seed, &mut rng);
+            send_deal_commitments(&mut client_streams, &secret, &config, &forfeited);
+            deal_secret = Some(secret);
             table = Table::new();
-            for i in 0..config.n_players {
-                for _ in 0..config.n_cards_to_start {
-                    hands[i as usize].add_card(deck.draw_card().unwrap());
-                }
-            }
+            table_ages.clear();
+            history = TableHistory::new(&table);
+            discard_pool = Sequence::new();
+            first_turn_done = vec![false; config.n_players as usize];
+            opened = vec![false; config.n_players as usize];
+            exchanges_done = vec![false; config.n_players as usize];
+            reversed = false;
+            turns_in_round = 0;
+            records = stats::GameRecords::new();
+            turn_times = stats::TurnTimes::new(config.n_players as usize);
 
             // update the starting player
-            starting_player += 1;
-            if starting_player >= config.n_players {
-                starting_player = 0;
-            }
+            starting_player = next_player(starting_player as usize, &forfeited) as u8;
             player = starting_player as usize;
         }
     }
@@ -446,5 +1307,8 @@ fn main() {
             Err(_) => println!("Could not send the exit signal to client {}", i)
         };
     }
+    for cs in spectator_streams.iter_mut() {
+        cs.write_all(&[5]).unwrap_or(());
+    }
 
 }
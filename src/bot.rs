@@ -0,0 +1,455 @@
+//! Computer players taking the seats left empty
+//!
+//! A bot is connected to the server like any other player, through a loopback connection whose
+//! other end is read by a thread confirming the reception of every message. Its turns are played
+//! directly by the server.
+
+use std::net::{ TcpListener, TcpStream };
+use std::thread;
+use std::collections::HashMap;
+use crate::lib_client::{ get_bytes_from_server, send_str_to_server };
+use crate::{ Sequence, Table, Card, KING, pick_a_card_end_of_turn, solver };
+#[cfg(feature = "serde")]
+use crate::InvalidInputError;
+use crate::tournament::Move;
+use crate::evaluation::{ card_points, attachable_cards };
+use crate::game::GameState;
+use crate::hooks::RuleHooks;
+use crate::rules::RuleSet;
+use crate::net::EncryptedStream;
+
+/// connect a new bot and return the server end of its connection, which is not encrypted
+pub fn connect_bot() -> std::io::Result<EncryptedStream> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let mut bot_stream = TcpStream::connect(listener.local_addr()?)?;
+    let (server_stream, _) = listener.accept()?;
+    thread::spawn(move || {
+        
+        // read the requests until the connection is closed
+        let mut code = [0];
+        while std::io::Read::read_exact(&mut bot_stream, &mut code).is_ok() {
+            let result = match code[0] {
+                
+                // the server should not ask anything, but the bot always answers the same way
+                3 => get_bytes_from_server(&mut bot_stream)
+                    .and_then(|_| send_str_to_server(&mut bot_stream, "y")),
+                4 => send_str_to_server(&mut bot_stream, "e"),
+                
+                // exit
+                5 => break,
+                
+                // messages are ignored
+                _ => get_bytes_from_server(&mut bot_stream).map(|_| ())
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(EncryptedStream::plain(server_stream))
+}
+
+/// A computer player
+pub trait BotPlayer {
+
+    /// Play the turn of the current player of `game`, which has already started
+    ///
+    /// Nothing may be played on the table on the first turn of the bot if `no_table` is `true`.
+    /// Return what the bot has done, to be shown to the other players.
+    fn play_turn(&mut self, game: &mut GameState, hooks: &RuleHooks, rules: &RuleSet, no_table: bool) 
+        -> String;
+}
+
+/// Bot playing the longest meld it finds in its hand until there is none, then adding as many of its remaining cards as
+/// possible to the sequences on the table
+///
+/// It never takes sequences from the table. If it can not play anything, it picks a card, unless
+/// its hand already has `max_hand_size` cards.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::bot::{ BotPlayer, GreedyBot };
+/// use machiavelli::game::GameState;
+/// use machiavelli::hooks::RuleHooks;
+/// use machiavelli::rules::RuleSet;
+///
+/// let mut table = Table::new();
+/// table.add(Sequence::from_cards(&[RegularCard(Heart, 7), RegularCard(Spade, 7), RegularCard(Club, 7)]));
+/// let hand = Sequence::from_cards(&[
+///     RegularCard(Club, 4), RegularCard(Diamond, 7), RegularCard(Club, 6), 
+///     RegularCard(Club, 5), RegularCard(Heart, 1),
+/// ]);
+/// let deck = Sequence::from_cards(&[RegularCard(Spade, 2)]);
+/// let mut game = GameState::new(table, vec![hand], deck, 0);
+/// let rules = RuleSet::default();
+/// let hooks = RuleHooks::from_rules(false, &rules);
+///
+/// assert_eq!("plays 4 cards", GreedyBot.play_turn(&mut game, &hooks, &rules, false));
+/// assert_eq!(&Sequence::from_cards(&[RegularCard(Heart, 1)]), game.hand());
+/// game.start_turn(0);
+/// assert_eq!("picks a card", GreedyBot.play_turn(&mut game, &hooks, &rules, false));
+/// assert_eq!(2, game.hand().number_cards());
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GreedyBot;
+
+impl BotPlayer for GreedyBot {
+    fn play_turn(&mut self, game: &mut GameState, hooks: &RuleHooks, rules: &RuleSet, no_table: bool) 
+        -> String 
+    {
+        let n_cards = game.hand().number_cards();
+
+        // melds from the hand, the longest first, as found by the solver; a meld refused by a
+        // house rule is skipped
+        loop {
+            let melds = solver::find_valid_melds(game.hand(), &Table::new());
+            let played = melds.iter().any(|m| match m {
+                Move::Play(indices) => game.play_cards(indices, hooks).is_ok(),
+                _ => false
+            });
+            if !played {
+                break;
+            }
+        }
+
+        // cards added to the sequences on the table, until none can be
+        if !no_table {
+            let mut added = true;
+            while added {
+                added = false;
+                for (i, seq) in game.table.to_vec().iter().enumerate() {
+                    if let Some(&(j, _)) = attachable_cards(seq, game.hand(), hooks).first() {
+                        added = game.add_to_sequence(j, i + 1, hooks).is_ok();
+                        break;
+                    }
+                }
+            }
+        }
+
+        // a turn refused by a house rule is given up without penalty
+        if game.has_played() {
+            match game.pass(hooks) {
+                Ok(_) => {
+                    let n_played = n_cards - game.hand().number_cards();
+                    return format!("plays {} card{}", n_played, if n_played > 1 { "s" } else { "" });
+                },
+                Err(_) => { game.give_up(0); }
+            }
+        }
+        let player = game.current_player;
+        let n_cards = game.hand().number_cards();
+        pick_a_card_end_of_turn(&mut game.hands[player], &mut game.deck, rules.max_hand_size);
+        if game.hand().number_cards() > n_cards { "picks a card" } else { "passes" }.to_string()
+    }
+}
+
+/// find a valid sequence of cards in a hand
+///
+/// The first valid group of three cards found is extended with as many other cards as possible.
+/// Return the sequence and the indices (starting from 1) of its cards in the hand, in decreasing
+/// order so that they can be taken one after the other.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::bot::find_meld;
+///
+/// let mut hand = Sequence::from_cards(&[
+///     RegularCard(Club, 4), RegularCard(Heart, 9), RegularCard(Club, 6), 
+///     RegularCard(Club, 5), RegularCard(Club, 7),
+/// ]);
+/// let (meld, indices) = find_meld(&hand).unwrap();
+///
+/// assert_eq!(4, meld.number_cards());
+/// assert_eq!(vec![5, 4, 3, 1], indices);
+/// assert!(find_meld(&Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Heart, 9)])).is_none());
+/// ```
+pub fn find_meld(hand: &Sequence) -> Option<(Sequence, Vec<usize>)> {
+    let cards = hand.to_vec();
+    let n = cards.len();
+    for i in 0..n {
+        for j in i+1..n {
+            for k in j+1..n {
+                let mut indices = vec![i, j, k];
+                let mut meld = Sequence::from_cards(&[cards[i].clone(), cards[j].clone(), 
+                                                      cards[k].clone()]);
+                if !meld.is_valid() {
+                    continue;
+                }
+                for (l, card) in cards.iter().enumerate() {
+                    if indices.contains(&l) {
+                        continue;
+                    }
+                    let mut longer_meld = meld.clone();
+                    longer_meld.add_card(card.clone());
+                    if longer_meld.is_valid() {
+                        meld = longer_meld;
+                        indices.push(l);
+                    }
+                }
+                indices.sort_unstable_by(|a, b| b.cmp(a));
+                return Some((meld, indices.into_iter().map(|i| i + 1).collect()));
+            }
+        }
+    }
+    None
+}
+
+/// Cache of the results of `find_meld`, keyed by the multiset of cards of the hand
+///
+/// The search does not depend on the order of the cards, so the same hand sorted differently, or
+/// searched again later in the game, is found in the cache. It is emptied when it holds `capacity`
+/// hands.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::bot::MeldCache;
+///
+/// let mut cache = MeldCache::new(100);
+/// let hand = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 6), RegularCard(Club, 5)]);
+/// let reversed: Sequence = hand.to_vec().into_iter().rev().collect();
+///
+/// assert_eq!(vec![3, 2, 1], cache.find_meld(&hand).unwrap().1);
+/// assert_eq!(vec![3, 2, 1], cache.find_meld(&reversed).unwrap().1);
+/// assert_eq!((1, 1), (cache.hits(), cache.misses()));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MeldCache {
+    melds: HashMap<Vec<u8>, Option<Sequence>>,
+    capacity: usize,
+    hits: usize,
+    misses: usize
+}
+
+impl MeldCache {
+
+    /// Create an empty cache holding at most `capacity` hands
+    pub fn new(capacity: usize) -> MeldCache {
+        MeldCache { melds: HashMap::new(), capacity, hits: 0, misses: 0 }
+    }
+
+    /// Same as `find_meld`, using the cache
+    pub fn find_meld(&mut self, hand: &Sequence) -> Option<(Sequence, Vec<usize>)> {
+        let mut key = hand.to_bytes();
+        key.sort_unstable();
+        let meld = match self.melds.get(&key) {
+            Some(meld) => {
+                self.hits += 1;
+                meld.clone()
+            },
+            None => {
+                self.misses += 1;
+                let meld = find_meld(hand).map(|(meld, _)| meld);
+                if self.melds.len() >= self.capacity {
+                    self.melds.clear();
+                }
+                self.melds.insert(key, meld.clone());
+                meld
+            }
+        }?;
+
+        // indices of the cards of the meld in this hand
+        let mut cards: Vec<Option<Card>> = hand.to_vec().into_iter().map(Some).collect();
+        let mut indices: Vec<usize> = meld.to_vec().iter().map(|card| {
+            let i = cards.iter().position(|c| c.as_ref() == Some(card)).unwrap();
+            cards[i] = None;
+            i + 1
+        }).collect();
+        indices.sort_unstable_by(|a, b| b.cmp(a));
+        Some((meld, indices))
+    }
+
+    /// Number of searches answered by the cache
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Number of searches not found in the cache
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Proportion of searches answered by the cache (0 if there has been none)
+    pub fn hit_rate(&self) -> f64 {
+        if self.hits + self.misses == 0 {
+            return 0.;
+        }
+        self.hits as f64 / (self.hits + self.misses) as f64
+    }
+}
+
+/// quality of a hand: the number of pairs of cards which could be part of the same sequence
+///
+/// Two regular cards can be part of the same sequence if they have the same value, or the same
+/// suit and values differing by at most two (a king and an ace are consecutive); a joker goes with
+/// any card.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::bot::hand_quality;
+///
+/// let hand = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Heart, 9), 
+///                                   RegularCard(Club, 6), RegularCard(Spade, 9)]);
+///
+/// assert_eq!(2, hand_quality(&hand));
+/// assert_eq!(1, hand_quality(&Sequence::from_cards(&[RegularCard(Club, 13), RegularCard(Club, 1)])));
+/// ```
+pub fn hand_quality(hand: &Sequence) -> usize {
+    let cards = hand.to_vec();
+    let mut quality = 0;
+    for (i, card_1) in cards.iter().enumerate() {
+        for card_2 in &cards[i+1..] {
+            if can_go_together(card_1, card_2) {
+                quality += 1;
+            }
+        }
+    }
+    quality
+}
+
+/// determine if no pair or run can be formed with the cards of a hand (see `hand_quality`)
+pub fn is_hopeless(hand: &Sequence) -> bool {
+    hand_quality(hand) == 0
+}
+
+// determine if two cards could be part of the same sequence
+fn can_go_together(card_1: &Card, card_2: &Card) -> bool {
+    match (card_1.rank(), card_2.rank(), card_1.suit(), card_2.suit()) {
+        (Some(rank_1), Some(rank_2), Some(suit_1), Some(suit_2)) => {
+            let gap = (rank_1 as i8 - rank_2 as i8).rem_euclid(KING as i8);
+            rank_1 == rank_2 || (suit_1 == suit_2 && (gap <= 2 || gap >= KING as i8 - 2))
+        },
+        _ => true
+    }
+}
+
+/// Tunable weights of the bot strategies
+///
+/// With the `serde` feature, they can be read from a TOML profile (see
+/// `BotWeights::from_string`). The default weights make the bots play every sequence they find in
+/// their hand, and never touch the table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BotWeights {
+
+    /// value, in points, of keeping a joker in hand rather than playing it
+    pub keep_joker: f64,
+
+    /// weight of the points of the cards left in hand (see `evaluation::card_points`)
+    pub penalty_aversion: f64,
+
+    /// willingness to add cards to the sequences on the table, from 0 (never) to 1
+    pub table_aggressiveness: f64
+}
+
+impl Default for BotWeights {
+    fn default() -> Self {
+        BotWeights { keep_joker: 0., penalty_aversion: 1., table_aggressiveness: 0. }
+    }
+}
+
+impl BotWeights {
+
+    /// Read the weights from a TOML profile
+    ///
+    /// The weights are numbers given as top-level keys; those which are not given keep their
+    /// default value. The sections (tables) of the profile are left to other tools, for
+    /// instance the settings of the tuner which wrote it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::bot::BotWeights;
+    ///
+    /// let profile = "# cautious bot\nkeep_joker = 12.5 # points\n\n[tuner]\nname = \"run 3\"\n";
+    /// let weights = BotWeights::from_string(profile).unwrap();
+    ///
+    /// assert_eq!(12.5, weights.keep_joker);
+    /// assert_eq!(1., BotWeights::from_string("penalty_aversion = 1").unwrap().penalty_aversion);
+    /// assert_eq!(weights, BotWeights::from_string(&weights.to_string()).unwrap());
+    /// assert!(BotWeights::from_string("keep_jokers = 1").is_err());
+    /// assert!(BotWeights::from_string("keep_joker = \"high\"").is_err());
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn from_string(s: &str) -> Result<BotWeights, InvalidInputError> {
+        let mut weights = BotWeights::default();
+        for (name, value) in toml::from_str::<toml::Table>(s)? {
+            let weight = match name.as_str() {
+                "keep_joker" => &mut weights.keep_joker,
+                "penalty_aversion" => &mut weights.penalty_aversion,
+                "table_aggressiveness" => &mut weights.table_aggressiveness,
+                _ if value.is_table() => continue,
+                _ => return Err(InvalidInputError {})
+            };
+            *weight = match value {
+                toml::Value::Float(x) => x,
+                toml::Value::Integer(n) => n as f64,
+                _ => return Err(InvalidInputError {})
+            };
+        }
+        Ok(weights)
+    }
+
+    /// Read the weights from a TOML profile file
+    #[cfg(feature = "serde")]
+    pub fn load(fname: &str) -> Result<BotWeights, InvalidInputError> {
+        BotWeights::from_string(&std::fs::read_to_string(fname)?)
+    }
+
+    /// Score of playing some cards from the hand: the points they remove from it, weighted by
+    /// `penalty_aversion`, minus the value of the jokers among them
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::bot::BotWeights;
+    ///
+    /// let weights = BotWeights { keep_joker: 20., ..BotWeights::default() };
+    ///
+    /// assert_eq!(-2., weights.score(&[RegularCard(Heart, 8), Joker, RegularCard(Heart, 10)]));
+    /// ```
+    pub fn score(&self, cards: &[Card]) -> f64 {
+        cards.iter().map(|card| match card {
+            Card::Joker => -self.keep_joker,
+            card => self.penalty_aversion * card_points(card) as f64
+        }).sum()
+    }
+
+    /// Check if a card is worth adding to a sequence on the table
+    pub fn adds_to_table(&self, card: &Card) -> bool {
+        self.table_aggressiveness > 0. 
+            && self.table_aggressiveness * self.score(std::slice::from_ref(card)) >= 1.
+    }
+}
+
+impl std::fmt::Display for BotWeights {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "keep_joker = {:?}", self.keep_joker)?;
+        writeln!(f, "penalty_aversion = {:?}", self.penalty_aversion)?;
+        writeln!(f, "table_aggressiveness = {:?}", self.table_aggressiveness)
+    }
+}
+
+/// name of a bot, different from the given names
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::bot::bot_name;
+///
+/// let names = vec!["Alice".to_string(), "Bot 1".to_string()];
+///
+/// assert_eq!("Bot 2", bot_name(&names));
+/// ```
+pub fn bot_name(names: &[String]) -> String {
+    (1..).map(|i| format!("Bot {}", i))
+        .find(|name| !names.contains(name))
+        .unwrap()
+}
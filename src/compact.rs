@@ -0,0 +1,553 @@
+//! Compact binary encoding of the game state
+//!
+//! Each card is stored on 6 bits and each count as a variable-length integer (7 bits per group,
+//! the 8th telling whether another group follows), without any padding between the fields.
+//! Strings are stored as their length followed by their UTF-8 bytes.
+//!
+//! Each encoding starts with a byte giving the version of the format (`COMPACT_FORMAT_VERSION`),
+//! so that a program reading an encoding written by another version tells it apart from invalid
+//! data.
+
+use std::convert::TryFrom;
+use crate::*;
+
+/// version of the compact encoding, written as its first byte
+pub const COMPACT_FORMAT_VERSION: u8 = 1;
+
+/// number of bits used to store a card
+const CARD_BITS: u32 = 6;
+
+/// Writer of a sequence of bits
+#[derive(Debug, Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    n_bits: usize
+}
+
+impl BitWriter {
+
+    pub fn new() -> BitWriter {
+        BitWriter::default()
+    }
+
+    /// Write the `n` lowest bits of `value`, starting with the highest of them
+    pub fn write_bits(&mut self, value: u32, n: u32) {
+        for i in (0..n).rev() {
+            if self.n_bits % 8 == 0 {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.n_bits % 8);
+            }
+            self.n_bits += 1;
+        }
+    }
+
+    /// Write a variable-length integer
+    pub fn write_varint(&mut self, mut value: usize) {
+        loop {
+            let group = (value & 0x7f) as u32;
+            value >>= 7;
+            if value == 0 {
+                self.write_bits(group, 8);
+                return;
+            }
+            self.write_bits(group | 0x80, 8);
+        }
+    }
+
+    /// Write a sequence of cards, preceded by its number of cards
+    pub fn write_cards(&mut self, sequence: &Sequence) {
+        let bytes = sequence.to_bytes();
+        self.write_varint(bytes.len());
+        for byte in bytes {
+            self.write_bits(byte as u32, CARD_BITS);
+        }
+    }
+
+    /// Write a string, preceded by its length
+    pub fn write_str(&mut self, s: &str) {
+        self.write_varint(s.len());
+        for &byte in s.as_bytes() {
+            self.write_bits(byte as u32, 8);
+        }
+    }
+
+    /// Get the bytes written, the last one being padded with zeros
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reader of a sequence of bits written by a `BitWriter`
+#[derive(Debug)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    i_bit: usize
+}
+
+impl<'a> BitReader<'a> {
+
+    pub fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader { bytes, i_bit: 0 }
+    }
+
+    /// Read `n` bits
+    pub fn read_bits(&mut self, n: u32) -> Result<u32, LoadingError> {
+        let mut value = 0;
+        for _ in 0..n {
+            let byte = self.bytes.get(self.i_bit / 8).ok_or(LoadingError::Invalid)?;
+            value = (value << 1) | ((byte >> (7 - self.i_bit % 8)) & 1) as u32;
+            self.i_bit += 1;
+        }
+        Ok(value)
+    }
+
+    /// Read a variable-length integer
+    pub fn read_varint(&mut self) -> Result<usize, LoadingError> {
+        let mut value: usize = 0;
+        for shift in (0..usize::BITS).step_by(7) {
+            let group = self.read_bits(8)? as usize;
+            value |= (group & 0x7f) << shift;
+            if group & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(LoadingError::Invalid)
+    }
+
+    /// Read a sequence of cards
+    pub fn read_cards(&mut self) -> Result<Sequence, LoadingError> {
+        let n = self.read_varint()?;
+        let mut bytes = Vec::<u8>::new();
+        for _ in 0..n {
+            let byte = self.read_bits(CARD_BITS)? as u8;
+            if byte > 52 {
+                return Err(LoadingError::Invalid);
+            }
+            bytes.push(byte);
+        }
+        Ok(Sequence::from_bytes(&bytes))
+    }
+
+    /// Read a string
+    pub fn read_string(&mut self) -> Result<String, LoadingError> {
+        let n = self.read_varint()?;
+        let mut bytes = Vec::<u8>::new();
+        for _ in 0..n {
+            bytes.push(self.read_bits(8)? as u8);
+        }
+        String::from_utf8(bytes).map_err(|_| LoadingError::Invalid)
+    }
+}
+
+// read the version at the beginning of an encoding, and check that it is the current one
+fn read_version(reader: &mut BitReader) -> Result<(), LoadingError> {
+    let version = reader.read_bits(8)? as u8;
+    if version != COMPACT_FORMAT_VERSION {
+        return Err(LoadingError::UnsupportedCompactVersion(version));
+    }
+    Ok(())
+}
+
+// write the sequences of a table, preceded by their number
+fn write_table(writer: &mut BitWriter, table: &Table) {
+    let sequences = table.to_vec();
+    writer.write_varint(sequences.len());
+    for seq in &sequences {
+        writer.write_cards(seq);
+    }
+}
+
+// read a table written by `write_table`
+fn read_table(reader: &mut BitReader) -> Result<Table, LoadingError> {
+    let n = reader.read_varint()?;
+    let mut sequences = Vec::<Sequence>::new();
+    for _ in 0..n {
+        sequences.push(reader.read_cards()?);
+    }
+    let mut table = Table::new();
+    for seq in sequences.into_iter().rev() {
+        table.add(seq);
+    }
+    Ok(table)
+}
+
+/// State of the game visible by all the players
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublicState {
+    pub player_names: Vec<String>,
+    pub current_player: usize,
+    pub table: Table,
+    pub hand_sizes: Vec<usize>,
+    pub deck_size: usize
+}
+
+impl PublicState {
+
+    /// Encode the state
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::compact::*;
+    ///
+    /// let mut table = Table::new();
+    /// table.add(Sequence::from_cards(&[RegularCard(Club, 4), Joker, RegularCard(Club, 6)]));
+    /// let state = PublicState {
+    ///     player_names: vec!["Alice".to_string(), "Bob".to_string()],
+    ///     current_player: 1,
+    ///     table,
+    ///     hand_sizes: vec![13, 200],
+    ///     deck_size: 77
+    /// };
+    /// let bytes = state.to_bytes();
+    ///
+    /// assert_eq!(22, bytes.len());
+    /// assert_eq!(state, PublicState::from_bytes(&bytes).unwrap());
+    ///
+    /// // an encoding written by another version
+    /// let mut newer = bytes.clone();
+    /// newer[0] = COMPACT_FORMAT_VERSION + 1;
+    /// assert_eq!(Err(LoadingError::UnsupportedCompactVersion(COMPACT_FORMAT_VERSION + 1)),
+    ///            PublicState::from_bytes(&newer));
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        writer.write_bits(COMPACT_FORMAT_VERSION as u32, 8);
+        writer.write_varint(self.player_names.len());
+        for (name, &n_cards) in self.player_names.iter().zip(self.hand_sizes.iter()) {
+            writer.write_str(name);
+            writer.write_varint(n_cards);
+        }
+        writer.write_varint(self.current_player);
+        writer.write_varint(self.deck_size);
+        write_table(&mut writer, &self.table);
+        writer.into_bytes()
+    }
+
+    /// Hash of the state, identical on all platforms
+    ///
+    /// The names of the players are left out, as they are only sent with the whole state (see
+    /// `state_hash`).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::compact::PublicState;
+    ///
+    /// let mut state = PublicState {
+    ///     player_names: vec!["Alice".to_string()],
+    ///     current_player: 0,
+    ///     table: Table::new(),
+    ///     hand_sizes: vec![13],
+    ///     deck_size: 91
+    /// };
+    /// let hash = state.hash();
+    /// state.deck_size -= 1;
+    ///
+    /// assert_ne!(hash, state.hash());
+    /// ```
+    pub fn hash(&self) -> u64 {
+        state_hash(self.current_player, &self.table, &self.hand_sizes, self.deck_size)
+    }
+
+    /// Message synchronizing the state of a client: the hash of the state (8 bytes, big-endian)
+    /// followed by the encoded state
+    pub fn to_sync_message(&self) -> Vec<u8> {
+        let mut bytes = self.hash().to_be_bytes().to_vec();
+        bytes.extend(self.to_bytes());
+        bytes
+    }
+
+    /// Read a message written by `to_sync_message`
+    ///
+    /// Return a `LoadingError` if the state can not be decoded or does not match the hash.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::compact::PublicState;
+    ///
+    /// let state = PublicState {
+    ///     player_names: vec!["Alice".to_string()],
+    ///     current_player: 0,
+    ///     table: Table::new(),
+    ///     hand_sizes: vec![13],
+    ///     deck_size: 91
+    /// };
+    /// let mut message = state.to_sync_message();
+    ///
+    /// assert_eq!(state, PublicState::from_sync_message(&message).unwrap());
+    /// *message.last_mut().unwrap() ^= 1;
+    /// assert!(PublicState::from_sync_message(&message).is_err());
+    /// ```
+    pub fn from_sync_message(bytes: &[u8]) -> Result<PublicState, LoadingError> {
+        if bytes.len() < 8 {
+            return Err(LoadingError::Invalid);
+        }
+        let mut hash = [0; 8];
+        hash.copy_from_slice(&bytes[..8]);
+        let state = PublicState::from_bytes(&bytes[8..])?;
+        if state.hash() != u64::from_be_bytes(hash) {
+            return Err(LoadingError::Invalid);
+        }
+        Ok(state)
+    }
+
+    /// Message updating the state `previous` of a client to this one: the hash of the new state (8
+    /// bytes, big-endian) followed by the version of the format, the current player, the size of the deck, the size of each
+    /// hand, and the sequences of the table which have changed
+    ///
+    /// Return `None` if the players have changed, in which case the whole state must be sent.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::compact::PublicState;
+    ///
+    /// let mut table = Table::new();
+    /// table.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+    /// let previous = PublicState {
+    ///     player_names: vec!["Alice".to_string(), "Bob".to_string()],
+    ///     current_player: 0,
+    ///     table: table.clone(),
+    ///     hand_sizes: vec![13, 13],
+    ///     deck_size: 78
+    /// };
+    /// table.add(Sequence::from_cards(&[RegularCard(Heart, 7), RegularCard(Spade, 7), RegularCard(Club, 7)]));
+    /// let state = PublicState { table, hand_sizes: vec![10, 13], ..previous.clone() };
+    /// let message = state.to_update_message(&previous).unwrap();
+    ///
+    /// assert!(message.len() < state.to_sync_message().len());
+    /// assert_eq!(state, previous.apply_update_message(&message).unwrap());
+    ///
+    /// // a client whose state is not the one the update was made for
+    /// let other = PublicState { deck_size: 77, ..previous.clone() };
+    /// assert_eq!(state, other.apply_update_message(&message).unwrap());
+    /// let other = PublicState { table: Table::new(), ..previous.clone() };
+    /// assert!(other.apply_update_message(&message).is_err());
+    /// ```
+    pub fn to_update_message(&self, previous: &PublicState) -> Option<Vec<u8>> {
+        if self.player_names != previous.player_names {
+            return None;
+        }
+        let mut writer = BitWriter::new();
+        writer.write_bits(COMPACT_FORMAT_VERSION as u32, 8);
+        writer.write_varint(self.current_player);
+        writer.write_varint(self.deck_size);
+        for &n_cards in &self.hand_sizes {
+            writer.write_varint(n_cards);
+        }
+        // the sequences are compared in the order they have been added, which the new ones do not
+        // change
+        let sequences: Vec<Sequence> = self.table.to_vec().into_iter().rev().collect();
+        let previous_sequences: Vec<Sequence> = previous.table.to_vec().into_iter().rev().collect();
+        writer.write_varint(sequences.len());
+        for (i, seq) in sequences.iter().enumerate() {
+            if previous_sequences.get(i) == Some(seq) {
+                writer.write_bits(0, 1);
+            } else {
+                writer.write_bits(1, 1);
+                writer.write_cards(seq);
+            }
+        }
+        let mut bytes = self.hash().to_be_bytes().to_vec();
+        bytes.extend(writer.into_bytes());
+        Some(bytes)
+    }
+
+    /// Apply a message written by `to_update_message` to this state
+    ///
+    /// Return a `LoadingError` if the message can not be decoded or if the new state does not
+    /// match the hash, for instance because this state is not the one the update was made for.
+    pub fn apply_update_message(&self, bytes: &[u8]) -> Result<PublicState, LoadingError> {
+        if bytes.len() < 8 {
+            return Err(LoadingError::Invalid);
+        }
+        let mut hash = [0; 8];
+        hash.copy_from_slice(&bytes[..8]);
+        let mut reader = BitReader::new(&bytes[8..]);
+        read_version(&mut reader)?;
+        let current_player = reader.read_varint()?;
+        let deck_size = reader.read_varint()?;
+        let mut hand_sizes = Vec::<usize>::new();
+        for _ in 0..self.player_names.len() {
+            hand_sizes.push(reader.read_varint()?);
+        }
+        let previous_sequences: Vec<Sequence> = self.table.to_vec().into_iter().rev().collect();
+        let n = reader.read_varint()?;
+        let mut sequences = Vec::<Sequence>::new();
+        for i in 0..n {
+            if reader.read_bits(1)? == 1 {
+                sequences.push(reader.read_cards()?);
+            } else {
+                sequences.push(previous_sequences.get(i).cloned().ok_or(LoadingError::Invalid)?);
+            }
+        }
+        let mut table = Table::new();
+        for seq in sequences {
+            table.add(seq);
+        }
+        let state = PublicState { player_names: self.player_names.clone(), current_player, table,
+                                  hand_sizes, deck_size };
+        if state.hash() != u64::from_be_bytes(hash) {
+            return Err(LoadingError::Invalid);
+        }
+        Ok(state)
+    }
+
+    /// Decode a state encoded with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<PublicState, LoadingError> {
+        let mut reader = BitReader::new(bytes);
+        read_version(&mut reader)?;
+        let n_players = reader.read_varint()?;
+        let mut player_names = Vec::<String>::new();
+        let mut hand_sizes = Vec::<usize>::new();
+        for _ in 0..n_players {
+            player_names.push(reader.read_string()?);
+            hand_sizes.push(reader.read_varint()?);
+        }
+        let current_player = reader.read_varint()?;
+        let deck_size = reader.read_varint()?;
+        let table = read_table(&mut reader)?;
+        Ok(PublicState { player_names, current_player, table, hand_sizes, deck_size })
+    }
+}
+
+/// hash of the part of the state of a game visible by all the players which changes during the
+/// game, identical on all platforms
+pub fn state_hash(current_player: usize, table: &Table, hand_sizes: &[usize], deck_size: usize) -> u64 {
+    let mut writer = BitWriter::new();
+    writer.write_varint(hand_sizes.len());
+    for &n_cards in hand_sizes {
+        writer.write_varint(n_cards);
+    }
+    writer.write_varint(current_player);
+    writer.write_varint(deck_size);
+    write_table(&mut writer, table);
+    u64::from_be_bytes(encode::checksum(&writer.into_bytes(), &[]))
+}
+
+/// convert the game info to the compact encoding; the arguments are the same as for
+/// `game_to_bytes`
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::compact::*;
+///
+/// let config = Config { n_decks: 2, n_jokers: 4, n_cards_to_start: 13,
+///                       custom_rule_jokers: false, n_players: 2, initial_meld_points: 30 };
+/// let deck = Sequence::multi_deck(2, 4, &mut rand::thread_rng());
+/// let hands = vec![Sequence::from_cards(&[Joker]), Sequence::from_cards(&[RegularCard(Heart, 9)])];
+/// let names = vec!["Alice".to_string(), "Bob".to_string()];
+/// let bytes = compact_game_to_bytes(0, 1, &Table::new(), &hands, &deck, &config, &names);
+///
+/// let save = game_to_bytes(0, 1, &Table::new(), &hands, &deck, &config, &names, &[false, false]);
+/// assert!(bytes.len() < save.len());
+/// let (config_2, _, player, _, hands_2, deck_2, names_2) = compact_load_game(&bytes).unwrap();
+/// assert_eq!((config, 1, hands, deck, names), (config_2, player, hands_2, deck_2, names_2));
+/// ```
+pub fn compact_game_to_bytes(starting_player: u8, player: u8, table: &Table, hands: &[Sequence],
+                             deck: &Sequence, config: &Config, player_names: &[String]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    writer.write_bits(COMPACT_FORMAT_VERSION as u32, 8);
+    writer.write_varint(config.n_decks as usize);
+    writer.write_varint(config.n_jokers as usize);
+    writer.write_varint(config.n_cards_to_start as usize);
+    writer.write_bits(config.custom_rule_jokers as u32, 1);
+    writer.write_varint(config.n_players as usize);
+    writer.write_varint(config.initial_meld_points as usize);
+    writer.write_varint(starting_player as usize);
+    writer.write_varint(player as usize);
+    for (hand, name) in hands.iter().zip(player_names.iter()) {
+        writer.write_str(name);
+        writer.write_cards(hand);
+    }
+    writer.write_cards(deck);
+    write_table(&mut writer, table);
+    writer.into_bytes()
+}
+
+/// load the game info from its compact encoding
+///
+/// Return the same values as `load_game`, or a `LoadingError` if the bytes are not a valid game.
+#[allow(clippy::type_complexity)]
+pub fn compact_load_game(bytes: &[u8])
+    -> Result<(Config, u8, u8, Table, Vec<Sequence>, Sequence, Vec<String>), LoadingError>
+{
+    let mut reader = BitReader::new(bytes);
+    read_version(&mut reader)?;
+    let read_u8 = |reader: &mut BitReader| -> Result<u8, LoadingError> {
+        u8::try_from(reader.read_varint()?).map_err(|_| LoadingError::Invalid)
+    };
+    let n_decks = read_u8(&mut reader)?;
+    let n_jokers = read_u8(&mut reader)?;
+    let n_cards_to_start = u16::try_from(reader.read_varint()?).map_err(|_| LoadingError::Invalid)?;
+    let custom_rule_jokers = reader.read_bits(1)? == 1;
+    let n_players = read_u8(&mut reader)?;
+    let initial_meld_points = u16::try_from(reader.read_varint()?).map_err(|_| LoadingError::Invalid)?;
+    let config = Config { n_decks, n_jokers, n_cards_to_start, custom_rule_jokers, n_players, initial_meld_points };
+    let starting_player = read_u8(&mut reader)?;
+    let player = read_u8(&mut reader)?;
+    if n_players == 0 || starting_player >= n_players || player >= n_players {
+        return Err(LoadingError::Invalid);
+    }
+    let mut hands = Vec::<Sequence>::new();
+    let mut player_names = Vec::<String>::new();
+    for _ in 0..n_players {
+        player_names.push(reader.read_string()?);
+        hands.push(reader.read_cards()?);
+    }
+    let deck = reader.read_cards()?;
+    let table = read_table(&mut reader)?;
+    Ok((config, starting_player, player, table, hands, deck, player_names))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints() {
+        let values = [0, 1, 127, 128, 300, 16383, 16384, usize::MAX];
+        let mut writer = BitWriter::new();
+        writer.write_bits(1, 3);
+        for &v in &values {
+            writer.write_varint(v);
+        }
+        let bytes = writer.into_bytes();
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(1, reader.read_bits(3).unwrap());
+        for &v in &values {
+            assert_eq!(v, reader.read_varint().unwrap());
+        }
+    }
+
+    #[test]
+    fn every_card() {
+        let bytes: Vec<u8> = (0..=52).collect();
+        let sequence = Sequence::from_bytes(&bytes);
+        let mut writer = BitWriter::new();
+        writer.write_cards(&sequence);
+        let encoded = writer.into_bytes();
+        assert_eq!(1 + (53 * 6usize).div_ceil(8), encoded.len());
+        assert_eq!(sequence, BitReader::new(&encoded).read_cards().unwrap());
+    }
+
+    #[test]
+    fn truncated() {
+        let config = Config { n_decks: 1, n_jokers: 2, n_cards_to_start: 13,
+                              custom_rule_jokers: true, n_players: 1, initial_meld_points: 0 };
+        let bytes = compact_game_to_bytes(0, 0, &Table::new(), &[Sequence::new()],
+                                          &Sequence::new(), &config, &["Alice".to_string()]);
+        assert!(compact_load_game(&bytes).is_ok());
+        assert!(compact_load_game(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -1,4 +1,11 @@
-//! A simple encoding/decoding library using XOR
+//! Encryption of the save files
+//!
+//! The saves are encrypted with ChaCha20-Poly1305, using a key derived from a passphrase with
+//! PBKDF2 (see `encrypt_save`). The XOR-based obfuscation of the previous versions is kept to read
+//! their saves.
+
+use chacha20poly1305::{ ChaCha20Poly1305, Key, Nonce, KeyInit };
+use chacha20poly1305::aead::Aead;
 
 /// Encrypt a plaintext by xoring it with a password
 ///
@@ -63,3 +70,276 @@ pub fn decrypt_str(cipher: &[u8], password: &str) -> Result<String, std::str::Ut
         Err(e) => Err(e)
     }
 }
+
+/// Number of bytes of the checksum appended to the saves
+pub const CHECKSUM_SIZE: usize = 8;
+
+/// Keyed checksum of a sequence of bytes
+///
+/// This is the 64-bit FNV-1a hash of the key, the bytes, and the key again. It detects accidental
+/// changes and wrong keys, but is not cryptographically secure.
+///
+/// # Example
+/// ```
+/// use machiavelli::encode::checksum;
+///
+/// let bytes: Vec<u8> = vec![1,2,3,4,5];
+///
+/// assert_eq!(checksum(&bytes, b"key"), checksum(&bytes, b"key"));
+/// assert_ne!(checksum(&bytes, b"key"), checksum(&bytes, b"other key"));
+/// ```
+pub fn checksum(bytes: &[u8], key: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in key.iter().chain(bytes).chain(key) {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash.to_be_bytes()
+}
+
+/// CRC-32 (IEEE) of a sequence of bytes, as used by zip and png
+///
+/// # Example
+/// ```
+/// use machiavelli::encode::crc32;
+///
+/// assert_eq!(crc32(b"123456789"), 0xcbf43926);
+/// assert_eq!(crc32(b""), 0);
+/// ```
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffffffff;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Obfuscate a save by xoring it with a key, after appending a keyed checksum
+///
+/// This is the format of the saves written by the previous versions; new saves are encrypted with
+/// `encrypt_save`.
+///
+/// # Example
+/// ```
+/// use machiavelli::encode::{ encode_save, decode_save };
+///
+/// let bytes: Vec<u8> = vec![1,2,3,4,5];
+///
+/// let cipher = encode_save(&bytes, b"machiavelli_save.sav");
+///
+/// assert_eq!(bytes, decode_save(&cipher, b"machiavelli_save.sav").unwrap());
+/// assert!(decode_save(&cipher, b"renamed_save.sav").is_err());
+/// ```
+pub fn encode_save(bytes: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut plaintext = bytes.to_vec();
+    plaintext.extend_from_slice(&checksum(bytes, key));
+    if key.is_empty() {
+        plaintext
+    } else {
+        xor(&plaintext, key)
+    }
+}
+
+/// Decode a save obfuscated by `encode_save` and check its checksum
+pub fn decode_save(cipher: &[u8], key: &[u8]) -> Result<Vec<u8>, ChecksumError> {
+    if cipher.len() < CHECKSUM_SIZE {
+        return Err(ChecksumError {});
+    }
+    let plaintext = if key.is_empty() { cipher.to_vec() } else { xor(cipher, key) };
+    let (bytes, sum) = plaintext.split_at(plaintext.len() - CHECKSUM_SIZE);
+    if sum != checksum(bytes, key) {
+        return Err(ChecksumError {});
+    }
+    Ok(bytes.to_vec())
+}
+
+/// Error raised when the checksum of a save does not match its content
+#[derive(Debug)]
+pub struct ChecksumError {}
+
+impl std::fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "the passphrase is wrong, or the save file is corrupted, has been renamed, or was written by an older version")
+    }
+}
+
+impl std::error::Error for ChecksumError {}
+
+/// Key used to encrypt the saves when no passphrase is given
+pub const DEFAULT_SAVE_KEY: &str = "machiavelli";
+
+/// Key used to encrypt the saves: the passphrase, or `DEFAULT_SAVE_KEY` if it is empty
+///
+/// As it does not depend on the name of the file, the saves can be renamed or moved.
+pub fn save_key(passphrase: &str) -> &[u8] {
+    if passphrase.is_empty() {
+        DEFAULT_SAVE_KEY.as_bytes()
+    } else {
+        passphrase.as_bytes()
+    }
+}
+
+/// First bytes of a save encrypted by `encrypt_save`
+pub const ENCRYPTED_SAVE_MAGIC: [u8; 4] = *b"MCHE";
+
+/// Number of iterations of PBKDF2 used to derive the key of a save from the passphrase
+pub const PBKDF2_ROUNDS: u32 = 100_000;
+
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+
+// derive the 256-bit key of a save from the passphrase and the salt
+fn derive_save_key(passphrase: &str, salt: &[u8]) -> Key {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(save_key(passphrase), salt, PBKDF2_ROUNDS, &mut key);
+    key.into()
+}
+
+/// Key of the saves of a game, derived once from a passphrase
+///
+/// Deriving the key takes a deliberately long time (see `PBKDF2_ROUNDS`), so a game saved at every
+/// turn derives it once, with a random salt, and each save only draws a new nonce. The saves can be
+/// decrypted by `decrypt_save` like those written by `encrypt_save`.
+///
+/// # Example
+/// ```
+/// use machiavelli::encode::{ SaveKey, decrypt_save };
+///
+/// let key = SaveKey::new("open sesame");
+/// let first = key.encrypt(&[1, 2, 3]);
+/// let second = key.encrypt(&[1, 2, 3]);
+///
+/// assert_ne!(first, second);
+/// assert_eq!(vec![1, 2, 3], decrypt_save(&first, "open sesame").unwrap());
+/// assert_eq!(vec![1, 2, 3], decrypt_save(&second, "open sesame").unwrap());
+/// ```
+pub struct SaveKey {
+    salt: [u8; SALT_SIZE],
+    cipher: ChaCha20Poly1305
+}
+
+impl SaveKey {
+
+    /// Derive a key from the passphrase (or `DEFAULT_SAVE_KEY` if it is empty) and a random salt
+    pub fn new(passphrase: &str) -> SaveKey {
+        let salt: [u8; SALT_SIZE] = rand::random();
+        SaveKey {
+            salt,
+            cipher: ChaCha20Poly1305::new(&derive_save_key(passphrase, &salt))
+        }
+    }
+
+    /// Encrypt a save with the key and a random nonce, in the format described in `encrypt_save`
+    pub fn encrypt(&self, bytes: &[u8]) -> Vec<u8> {
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let mut save = ENCRYPTED_SAVE_MAGIC.to_vec();
+        save.extend_from_slice(&self.salt);
+        save.extend_from_slice(&nonce);
+        save.append(&mut self.cipher.encrypt(Nonce::from_slice(&nonce), bytes)
+                    .expect("the save is too large to be encrypted"));
+        save
+    }
+}
+
+/// Encrypt a save with a key derived from a passphrase
+///
+/// To write several saves with the same passphrase, a `SaveKey` avoids deriving the key each time.
+///
+/// The result is `ENCRYPTED_SAVE_MAGIC`, a random salt, a random nonce, and the bytes encrypted
+/// with ChaCha20-Poly1305. The key is derived from the passphrase (or `DEFAULT_SAVE_KEY` if it is
+/// empty) and the salt with PBKDF2-HMAC-SHA256. Without a passphrase, anyone with the program can
+/// read the save; the encryption only protects it from being modified by accident.
+///
+/// # Example
+/// ```
+/// use machiavelli::encode::{ encrypt_save, decrypt_save };
+///
+/// let bytes: Vec<u8> = vec![1,2,3,4,5];
+///
+/// let cipher = encrypt_save(&bytes, "open sesame");
+///
+/// assert_eq!(bytes, decrypt_save(&cipher, "open sesame").unwrap());
+/// assert!(decrypt_save(&cipher, "wrong passphrase").is_err());
+/// ```
+pub fn encrypt_save(bytes: &[u8], passphrase: &str) -> Vec<u8> {
+    SaveKey::new(passphrase).encrypt(bytes)
+}
+
+/// Decrypt a save encrypted by `encrypt_save`
+///
+/// Return a `ChecksumError` if the passphrase is wrong or if the save has been modified.
+pub fn decrypt_save(save: &[u8], passphrase: &str) -> Result<Vec<u8>, ChecksumError> {
+    let header_size = ENCRYPTED_SAVE_MAGIC.len() + SALT_SIZE + NONCE_SIZE;
+    if save.len() < header_size || !save.starts_with(&ENCRYPTED_SAVE_MAGIC) {
+        return Err(ChecksumError {});
+    }
+    let salt = &save[ENCRYPTED_SAVE_MAGIC.len()..ENCRYPTED_SAVE_MAGIC.len()+SALT_SIZE];
+    let nonce = &save[ENCRYPTED_SAVE_MAGIC.len()+SALT_SIZE..header_size];
+    let cipher = ChaCha20Poly1305::new(&derive_save_key(passphrase, salt));
+    cipher.decrypt(Nonce::from_slice(nonce), &save[header_size..]).map_err(|_| ChecksumError {})
+}
+
+/// Decode a save, falling back to the formats of the previous versions
+///
+/// A save written by `encrypt_save` is decrypted with `passphrase`. Otherwise, the save has been
+/// obfuscated by a previous version: it is decoded with the key derived from `passphrase`, and if
+/// the checksum does not match, with the name of the file. Return the decoded bytes and whether an
+/// older format was used, or a `ChecksumError` if the passphrase is wrong or nothing matches.
+///
+/// The first versions wrote the saves without a checksum; they can be read with
+/// `decode_unchecked_save`, after warning the user.
+///
+/// # Example
+/// ```
+/// use machiavelli::encode::{ encrypt_save, encode_save, decode_save_with_migration, save_key, xor };
+///
+/// let bytes: Vec<u8> = vec![1,2,3,4,5];
+///
+/// let cipher = encrypt_save(&bytes, "open sesame");
+/// assert_eq!((bytes.clone(), false), decode_save_with_migration(&cipher, "open sesame", "a.sav").unwrap());
+/// assert!(decode_save_with_migration(&cipher, "", "a.sav").is_err());
+///
+/// let old_cipher = encode_save(&bytes, save_key("open sesame"));
+/// assert_eq!((bytes.clone(), true), decode_save_with_migration(&old_cipher, "open sesame", "a.sav").unwrap());
+///
+/// let old_cipher = xor(&bytes, b"a.sav");
+/// assert!(decode_save_with_migration(&old_cipher, "", "a.sav").is_err());
+/// ```
+pub fn decode_save_with_migration(cipher: &[u8], passphrase: &str, fname: &str) 
+    -> Result<(Vec<u8>, bool), ChecksumError> 
+{
+    if cipher.starts_with(&ENCRYPTED_SAVE_MAGIC) {
+        return decrypt_save(cipher, passphrase).map(|bytes| (bytes, false));
+    }
+    decode_save(cipher, save_key(passphrase))
+        .or_else(|_| decode_save(cipher, fname.as_bytes()))
+        .map(|bytes| (bytes, true))
+}
+
+/// Decode a save written by the first versions, obfuscated with the name of the file and without
+/// a checksum
+///
+/// Nothing guarantees that the result is the save: it should only be used if
+/// `decode_save_with_migration` fails on a save which does not start with `ENCRYPTED_SAVE_MAGIC`,
+/// and after the user has agreed.
+///
+/// # Example
+/// ```
+/// use machiavelli::encode::{ decode_unchecked_save, xor };
+///
+/// let bytes: Vec<u8> = vec![1,2,3,4,5];
+///
+/// assert_eq!(bytes, decode_unchecked_save(&xor(&bytes, b"a.sav"), "a.sav"));
+/// assert_eq!(bytes, decode_unchecked_save(&bytes, ""));
+/// ```
+pub fn decode_unchecked_save(cipher: &[u8], fname: &str) -> Vec<u8> {
+    if fname.is_empty() {
+        cipher.to_vec()
+    } else {
+        xor(cipher, fname.as_bytes())
+    }
+}
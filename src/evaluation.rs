@@ -0,0 +1,155 @@
+//! Evaluation of a hand
+//!
+//! This is the common routine telling what can be done with a hand, for the bots of this crate as
+//! well as for external ones. Its output is meant to stay stable between versions.
+
+use crate::{ Card, Sequence, Table };
+use crate::bot::find_meld;
+use crate::hooks::RuleHooks;
+use crate::rules::RuleSet;
+
+/// points of a joker left in hand
+pub const JOKER_POINTS: u32 = 25;
+
+/// What can be done with a hand
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HandEvaluation {
+
+    /// sequences which can be formed with the cards of the hand alone, without sharing any card
+    pub melds: Vec<Sequence>,
+
+    /// cards which can be added to a sequence on the table, with the index of the sequence
+    /// (starting from 1, as in the commands)
+    pub additions: Vec<(Card, usize)>,
+
+    /// cards which can be played, either in one of the melds or added to the table
+    pub playable: Vec<Card>,
+
+    /// cards which can not be played for now
+    pub deadwood: Vec<Card>,
+
+    /// total points of the deadwood (see `card_points`)
+    pub deadwood_points: u32
+}
+
+/// points of a card left in hand: 1 for an ace, the value of the card up to 10, 10 for the
+/// figures, and `JOKER_POINTS` for a joker
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::evaluation::card_points;
+///
+/// assert_eq!(1, card_points(&RegularCard(Heart, 1)));
+/// assert_eq!(7, card_points(&RegularCard(Club, 7)));
+/// assert_eq!(10, card_points(&RegularCard(Spade, 12)));
+/// assert_eq!(25, card_points(&Joker));
+/// ```
+pub fn card_points(card: &Card) -> u32 {
+    match card.rank() {
+        Some(rank) => (rank as u32).min(10),
+        None => JOKER_POINTS
+    }
+}
+
+/// Evaluate a hand given the table and the optional rules
+///
+/// The melds are found greedily, so they are not always the largest possible; the remaining cards
+/// are then checked against each sequence of the table. A sequence is considered valid if it can be
+/// played under the house rules derived from `rules` (see `RuleHooks::from_rules`).
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::evaluation::evaluate_hand;
+/// use machiavelli::rules::RuleSet;
+///
+/// let hand = Sequence::from_cards(&[
+///     RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6),
+///     RegularCard(Heart, 9), RegularCard(Spade, 13),
+/// ]);
+/// let mut table = Table::new();
+/// table.add(Sequence::from_cards(&[RegularCard(Heart, 10), RegularCard(Heart, 11),
+///                                  RegularCard(Heart, 12)]));
+///
+/// let evaluation = evaluate_hand(&hand, &table, &RuleSet::default());
+///
+/// assert_eq!(1, evaluation.melds.len());
+/// assert_eq!(vec![(RegularCard(Heart, 9), 1)], evaluation.additions);
+/// assert_eq!(4, evaluation.playable.len());
+/// assert_eq!(vec![RegularCard(Spade, 13)], evaluation.deadwood);
+/// assert_eq!(10, evaluation.deadwood_points);
+/// ```
+pub fn evaluate_hand(hand: &Sequence, table: &Table, rules: &RuleSet) -> HandEvaluation {
+    let hooks = RuleHooks::from_rules(false, rules);
+    let mut evaluation = HandEvaluation::default();
+
+    // melds formed with the cards of the hand
+    let mut remaining = hand.clone();
+    while let Some((mut meld, indices)) = find_meld(&remaining) {
+        if !hooks.can_play(&mut meld) {
+            break;
+        }
+        for i in indices {
+            remaining.take_card(i);
+        }
+        evaluation.playable.extend(meld.to_vec());
+        evaluation.melds.push(meld);
+    }
+
+    // cards which can be added to the table
+    let sequences = table.to_vec();
+    for card in remaining.to_vec() {
+        let position = sequences.iter().position(|seq| {
+            let mut longer = seq.clone();
+            longer.add_card(card.clone());
+            hooks.can_play(&mut longer)
+        });
+        match position {
+            Some(i) => {
+                evaluation.additions.push((card.clone(), i + 1));
+                evaluation.playable.push(card);
+            },
+            None => {
+                evaluation.deadwood_points += card_points(&card);
+                evaluation.deadwood.push(card);
+            }
+        }
+    }
+    evaluation
+}
+
+/// Cards of a hand which can be added one by one to a sequence, with their indices in the hand
+/// (starting from 1, as in the commands)
+///
+/// Each card is checked on its own: two cards which could only be added together are not listed.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::evaluation::attachable_cards;
+/// use machiavelli::hooks::RuleHooks;
+/// use machiavelli::rules::RuleSet;
+///
+/// let sequence = Sequence::from_cards(&[RegularCard(Heart, 10), RegularCard(Heart, 11),
+///                                       RegularCard(Heart, 12)]);
+/// let hand = Sequence::from_cards(&[RegularCard(Heart, 8), RegularCard(Heart, 9), 
+///                                   RegularCard(Heart, 13), Joker]);
+/// let hooks = RuleHooks::from_rules(false, &RuleSet::default());
+///
+/// assert_eq!(vec![(2, RegularCard(Heart, 9)), (3, RegularCard(Heart, 13)), (4, Joker)],
+///            attachable_cards(&sequence, &hand, &hooks));
+/// ```
+pub fn attachable_cards(sequence: &Sequence, hand: &Sequence, hooks: &RuleHooks) -> Vec<(usize, Card)> {
+    hand.to_vec().into_iter().enumerate()
+        .filter(|(_, card)| {
+            let mut longer = sequence.clone();
+            longer.add_card(card.clone());
+            hooks.can_play(&mut longer)
+        })
+        .map(|(i, card)| (i + 1, card))
+        .collect()
+}
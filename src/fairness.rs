@@ -0,0 +1,197 @@
+//! Verification of the deal with a commit-reveal scheme
+//!
+//! When the cards are dealt, the server sends each client a commitment to the shuffled deck: the
+//! SHA-256 hash of a random salt followed by the cards of the deck, with what the client needs to
+//! find its own hand in the deck. At the end of the game, it reveals the seed used to shuffle the
+//! deck and the salt; each client then shuffles the deck again, checks that it matches the
+//! commitment, and shows the hand it should have been dealt. As the commitment is sent before the
+//! first turn, the server could not have chosen the deck after seeing the moves of the players.
+
+use std::fmt;
+use std::convert::TryInto;
+use rand::{ Rng, SeedableRng };
+use rand::rngs::StdRng;
+use sha2::{ Sha256, Digest };
+use crate::Sequence;
+
+/// number of bytes of the salt
+const SALT_SIZE: usize = 16;
+
+/// Seed of the deck and salt of the commitment, kept by the server until the end of the game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DealSecret {
+    pub seed: u64,
+    pub salt: [u8; SALT_SIZE]
+}
+
+impl DealSecret {
+
+    /// Secret for a deck shuffled with `seed`, with a random salt
+    pub fn new<R: Rng + ?Sized>(seed: u64, rng: &mut R) -> DealSecret {
+        let mut salt = [0; SALT_SIZE];
+        rng.fill(&mut salt);
+        DealSecret { seed, salt }
+    }
+
+    /// Commitment sent to the player dealt the hand number `position` (among the `n_dealt` hands)
+    /// of a deck with `n_decks` decks and `n_jokers` jokers
+    pub fn commitment(&self, n_decks: u8, n_jokers: u8, n_cards: usize, n_dealt: usize, position: usize)
+        -> DealCommitment
+    {
+        DealCommitment {
+            n_decks,
+            n_jokers,
+            n_cards,
+            n_dealt,
+            position,
+            digest: deck_digest(&self.deck(n_decks, n_jokers), &self.salt)
+        }
+    }
+
+    /// Read a secret written by its `Display` implementation: the seed and the salt in hexadecimal
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::fairness::DealSecret;
+    ///
+    /// let secret = DealSecret { seed: 42, salt: [7; 16] };
+    ///
+    /// assert_eq!("000000000000002a 07070707070707070707070707070707", secret.to_string());
+    /// assert_eq!(Some(secret.clone()), DealSecret::parse(&secret.to_string()));
+    /// assert_eq!(None, DealSecret::parse("2a"));
+    /// ```
+    pub fn parse(s: &str) -> Option<DealSecret> {
+        let mut words = s.split_whitespace();
+        let seed = u64::from_str_radix(words.next()?, 16).ok()?;
+        let salt = from_hex(words.next()?)?.try_into().ok()?;
+        Some(DealSecret { seed, salt })
+    }
+
+    // deck shuffled with the seed
+    fn deck(&self, n_decks: u8, n_jokers: u8) -> Sequence {
+        Sequence::multi_deck(n_decks, n_jokers, &mut StdRng::seed_from_u64(self.seed))
+    }
+}
+
+impl fmt::Display for DealSecret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x} {}", self.seed, to_hex(&self.salt))
+    }
+}
+
+/// Commitment to a shuffled deck, with the position of the hand of the player who receives it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DealCommitment {
+    pub n_decks: u8,
+    pub n_jokers: u8,
+
+    /// number of cards dealt to each player
+    pub n_cards: usize,
+
+    /// number of hands dealt
+    pub n_dealt: usize,
+
+    /// position of the hand of the player among those dealt
+    pub position: usize,
+
+    /// SHA-256 hash of the salt followed by the cards of the deck
+    pub digest: [u8; 32]
+}
+
+impl DealCommitment {
+
+    /// Check the secret revealed at the end of the game against the commitment
+    ///
+    /// Return the hand the player should have been dealt, or `None` if the deck shuffled with the
+    /// seed does not match the commitment.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rand::{ SeedableRng, rngs::StdRng };
+    /// use machiavelli::*;
+    /// use machiavelli::fairness::DealSecret;
+    ///
+    /// let seed = 1234;
+    /// let mut deck = Sequence::multi_deck(2, 4, &mut StdRng::seed_from_u64(seed));
+    /// let hands = deck.deal(3, 13);
+    ///
+    /// let secret = DealSecret::new(seed, &mut rand::thread_rng());
+    /// let commitment = secret.commitment(2, 4, 13, 3, 1);
+    ///
+    /// assert_eq!(Some(hands[1].clone()), commitment.verify(&secret));
+    /// assert_eq!(None, commitment.verify(&DealSecret { seed: 1235, salt: secret.salt }));
+    /// ```
+    pub fn verify(&self, secret: &DealSecret) -> Option<Sequence> {
+        let mut deck = secret.deck(self.n_decks, self.n_jokers);
+        if deck_digest(&deck, &secret.salt) != self.digest {
+            return None;
+        }
+        deck.deal(self.n_dealt, self.n_cards).into_iter().nth(self.position)
+    }
+
+    /// Read a commitment written by its `Display` implementation
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::fairness::DealSecret;
+    /// use machiavelli::fairness::DealCommitment;
+    ///
+    /// let commitment = DealSecret { seed: 42, salt: [7; 16] }.commitment(2, 4, 13, 3, 1);
+    ///
+    /// assert_eq!(Some(commitment.clone()), DealCommitment::parse(&commitment.to_string()));
+    /// assert_eq!(None, DealCommitment::parse("2 4 13 3 1"));
+    /// ```
+    pub fn parse(s: &str) -> Option<DealCommitment> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        if words.len() != 6 {
+            return None;
+        }
+        Some(DealCommitment {
+            n_decks: words[0].parse().ok()?,
+            n_jokers: words[1].parse().ok()?,
+            n_cards: words[2].parse().ok()?,
+            n_dealt: words[3].parse().ok()?,
+            position: words[4].parse().ok()?,
+            digest: from_hex(words[5])?.try_into().ok()?
+        })
+    }
+}
+
+impl fmt::Display for DealCommitment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} {} {} {}", self.n_decks, self.n_jokers, self.n_cards, self.n_dealt,
+               self.position, to_hex(&self.digest))
+    }
+}
+
+/// SHA-256 hash of the salt followed by the cards of the deck
+pub fn deck_digest(deck: &Sequence, salt: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(deck.to_bytes());
+    hasher.finalize().into()
+}
+
+/// Write bytes as hexadecimal digits
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::fairness::to_hex;
+///
+/// assert_eq!("00ff1a", to_hex(&[0, 255, 26]));
+/// ```
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// read bytes written as hexadecimal digits
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i+2], 16).ok()).collect()
+}
@@ -0,0 +1,155 @@
+//! Filter of unwanted words in the texts written by the players
+//!
+//! The list of words is read from a file with one word per line; lines starting with `#` are
+//! ignored, and a line `action = reject`, `mask`, or `warn` tells what to do with a text containing
+//! one of them (`mask` by default). Words are matched whole and regardless of case, so that a
+//! forbidden word does not make longer ones containing it forbidden.
+
+use std::fmt;
+use crate::InvalidInputError;
+
+/// What to do with a text containing a forbidden word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterAction {
+
+    /// refuse the text
+    Reject,
+
+    /// replace the letters of the forbidden words by `*`
+    Mask,
+
+    /// accept the text but warn the server operator
+    Warn
+}
+
+impl fmt::Display for FilterAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            FilterAction::Reject => "reject",
+            FilterAction::Mask => "mask",
+            FilterAction::Warn => "warn"
+        })
+    }
+}
+
+/// Result of the filter on a text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filtered {
+
+    /// the text contains no forbidden word
+    Clean(String),
+
+    /// the text has been masked
+    Masked(String),
+
+    /// the text is accepted, but contains the given forbidden word
+    Warned(String, String),
+
+    /// the text is refused because it contains the given forbidden word
+    Rejected(String)
+}
+
+/// List of forbidden words and action to take on them
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordFilter {
+    words: Vec<String>,
+    pub action: FilterAction
+}
+
+impl WordFilter {
+
+    /// Create a filter from a list of words
+    pub fn new(words: &[&str], action: FilterAction) -> WordFilter {
+        WordFilter { words: words.iter().map(|w| w.to_lowercase()).collect(), action }
+    }
+
+    /// Read a filter from a string (see the module documentation for the format)
+    pub fn from_string(s: &str) -> Result<WordFilter, InvalidInputError> {
+        let mut filter = WordFilter::new(&[], FilterAction::Mask);
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((name, value)) if name.trim() == "action" => {
+                    filter.action = match value.trim() {
+                        "reject" => FilterAction::Reject,
+                        "mask" => FilterAction::Mask,
+                        "warn" => FilterAction::Warn,
+                        _ => return Err(InvalidInputError {})
+                    };
+                },
+                Some(_) => return Err(InvalidInputError {}),
+                None => filter.words.push(line.to_lowercase())
+            }
+        }
+        Ok(filter)
+    }
+
+    /// Read a filter from a file
+    pub fn load(fname: &str) -> Result<WordFilter, InvalidInputError> {
+        WordFilter::from_string(&std::fs::read_to_string(fname)?)
+    }
+
+    // words of a text, with their positions in bytes
+    fn words_of(text: &str) -> Vec<(usize, &str)> {
+        let mut words = Vec::new();
+        let mut start = None;
+        for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+            match (start, c.is_alphanumeric()) {
+                (None, true) => start = Some(i),
+                (Some(s), false) => {
+                    words.push((s, &text[s..i]));
+                    start = None;
+                },
+                _ => ()
+            }
+        }
+        words
+    }
+
+    /// First forbidden word of a text, if any
+    pub fn find(&self, text: &str) -> Option<String> {
+        WordFilter::words_of(text).into_iter()
+            .map(|(_, word)| word.to_lowercase())
+            .find(|word| self.words.contains(word))
+    }
+
+    /// Apply the filter to a text
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::filter::*;
+    ///
+    /// let filter = WordFilter::from_string("# forbidden words\nheck\ndarn\n").unwrap();
+    ///
+    /// assert_eq!(Filtered::Masked("What the **** is this?".to_string()),
+    ///            filter.apply("What the Heck is this?"));
+    /// assert_eq!(Filtered::Clean("Checkmate".to_string()), filter.apply("Checkmate"));
+    ///
+    /// let filter = WordFilter::from_string("action = reject\nheck").unwrap();
+    /// assert_eq!(Filtered::Rejected("heck".to_string()), filter.apply("heck!"));
+    /// ```
+    pub fn apply(&self, text: &str) -> Filtered {
+        let word = match self.find(text) {
+            Some(word) => word,
+            None => return Filtered::Clean(text.to_string())
+        };
+        match self.action {
+            FilterAction::Reject => Filtered::Rejected(word),
+            FilterAction::Warn => Filtered::Warned(text.to_string(), word),
+            FilterAction::Mask => {
+                let mut masked = text.to_string();
+                // from the end, so that masking a word does not move the next ones
+                for (start, w) in WordFilter::words_of(text).into_iter().rev() {
+                    if self.words.contains(&w.to_lowercase()) {
+                        let stars = "*".repeat(w.chars().count());
+                        masked.replace_range(start..start + w.len(), &stars);
+                    }
+                }
+                Filtered::Masked(masked)
+            }
+        }
+    }
+}
@@ -0,0 +1,591 @@
+//! Rules of a turn, independent of how the players enter their moves
+//!
+//! A [`GameState`] holds the table, the hands, and the deck, and checks each move against the
+//! rules before applying it: a move which is not allowed returns a [`RuleError`] and leaves the
+//! state unchanged. The front-ends only have to ask the players for their moves and show the
+//! results.
+//!
+//! A [`PlayerView`] is what one player may know of a game, for programs which must not see the
+//! other hands, such as the bots of a tournament.
+
+use std::fmt;
+use crate::{ Card, Sequence, Table, render, reset_style_string, pick_a_card };
+use crate::hooks::RuleHooks;
+use crate::render::Visibility;
+
+/// What a move has done
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Event {
+
+    /// the sequence has been played on the table
+    Played(Sequence),
+
+    /// the sequence has been taken from the table
+    Taken(Sequence),
+
+    /// the card has been added to the sequence with the given index (starting from 1)
+    Added(Card, usize),
+
+    /// the turn has ended without playing, and the player has drawn the card (`None` if the deck
+    /// is empty or the hand is full)
+    Drew(Option<Card>),
+
+    /// the turn has ended after the player has played
+    Passed,
+
+    /// the table and the hand have been put back as they were at the beginning of the turn, and
+    /// the player has drawn the penalty
+    Reset
+}
+
+/// Reason why a move is not allowed
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleError {
+
+    /// the index of a card is not in the hand, or is given twice
+    NoSuchCard(usize),
+
+    /// the index of a sequence is not on the table
+    NoSuchSequence(usize),
+
+    /// the cards do not form a valid sequence
+    InvalidSequence(Sequence),
+
+    /// the player still holds cards taken from the table
+    CardsFromTableLeft,
+
+    /// the player has not played anything, so they can not pass
+    NothingPlayed,
+
+    /// the player has played something, so they can not draw
+    AlreadyPlayed,
+
+    /// the player can not use the table before their first meld, which must be worth at least
+    /// the given number of points
+    TableBeforeInitialMeld(u16),
+
+    /// the first meld of the player is worth the first number of points, less than the minimum
+    /// given by the second one
+    InitialMeldTooLow(u16, u16),
+
+    /// a house rule refuses the move, for the given reason
+    Refused(String)
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleError::NoSuchCard(n) => write!(f, "There is no card {} in your hand!", n),
+            RuleError::NoSuchSequence(n) => write!(f, "Sequence {} is not on the table!", n),
+            RuleError::InvalidSequence(seq) =>
+                write!(f, "{}{} is not a valid sequence!", render::sequence(seq), reset_style_string()),
+            RuleError::CardsFromTableLeft =>
+                write!(f, "You can't end your turn until you've played all the cards you've taken from the table!"),
+            RuleError::NothingPlayed => write!(f, "You need to play something to pass"),
+            RuleError::AlreadyPlayed => write!(f, "You can't pick a card after having played something"),
+            RuleError::TableBeforeInitialMeld(n) =>
+                write!(f, "You can't take or modify sequences on the table before your first meld of at least {} points!", n),
+            RuleError::InitialMeldTooLow(points, n) =>
+                write!(f, "Your first meld must be worth at least {} points (this one is worth {})!", n, points),
+            RuleError::Refused(reason) => write!(f, "{}", reason)
+        }
+    }
+}
+
+/// State of a game: the table, the hands, the deck, and the player whose turn it is
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GameState {
+    pub table: Table,
+    pub hands: Vec<Sequence>,
+    pub deck: Sequence,
+    pub current_player: usize,
+
+    /// minimum number of points of the first meld of each player (0 for no minimum)
+    pub initial_meld_points: u16,
+
+    /// players who have made their first meld
+    pub opened: Vec<bool>,
+
+    /// number of cards from which a player skips drawing at the end of a turn (0 for no limit)
+    pub max_hand_size: usize,
+
+    // hand of the current player and table at the beginning of the turn
+    hand_start_turn: Sequence,
+    table_start_turn: Table,
+
+    // moves made since the state has been created, with the players who made them
+    events: Vec<(usize, Event)>
+}
+
+impl GameState {
+
+    /// Create a game state, with the turn of `current_player` starting
+    ///
+    /// There is no minimum for the first meld and no limit to the size of the hands; to set them,
+    /// change `initial_meld_points` and `max_hand_size`.
+    pub fn new(table: Table, hands: Vec<Sequence>, deck: Sequence, current_player: usize) -> GameState {
+        let mut game = GameState {
+            opened: vec![false; hands.len()],
+            table,
+            hands,
+            deck,
+            current_player,
+            initial_meld_points: 0,
+            max_hand_size: 0,
+            hand_start_turn: Sequence::new(),
+            table_start_turn: Table::new(),
+            events: Vec::new()
+        };
+        game.start_turn(current_player);
+        game
+    }
+
+    /// Start the turn of a player
+    pub fn start_turn(&mut self, player: usize) {
+        self.current_player = player;
+        self.hand_start_turn = self.hands[player].clone();
+        self.table_start_turn = self.table.clone();
+    }
+
+    /// Hand of the current player
+    pub fn hand(&self) -> &Sequence {
+        &self.hands[self.current_player]
+    }
+
+    /// Hash of the part of the state visible by all the players: the table, the number of cards
+    /// in each hand and in the deck, and the current player
+    ///
+    /// It is the hash of the `PublicState` sent to the clients when they see all the numbers of
+    /// cards, so that they can tell whether they are in sync with the server.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::game::GameState;
+    /// use machiavelli::compact::PublicState;
+    ///
+    /// let hands = vec![Sequence::from_cards(&[Joker]), Sequence::new()];
+    /// let game = GameState::new(Table::new(), hands, Sequence::from_cards(&[RegularCard(Club, 4)]), 1);
+    /// let state = PublicState {
+    ///     player_names: vec!["Alice".to_string(), "Bob".to_string()],
+    ///     current_player: 1,
+    ///     table: Table::new(),
+    ///     hand_sizes: vec![1, 0],
+    ///     deck_size: 1
+    /// };
+    ///
+    /// assert_eq!(state.hash(), game.hash());
+    /// ```
+    pub fn hash(&self) -> u64 {
+        let hand_sizes: Vec<usize> = self.hands.iter().map(Sequence::number_cards).collect();
+        crate::compact::state_hash(self.current_player, &self.table, &hand_sizes, self.deck.number_cards())
+    }
+
+    /// Moves made since the state has been created, with the players who made them
+    pub fn events(&self) -> &[(usize, Event)] {
+        &self.events
+    }
+
+    /// What `player` may know of the game, with the numbers of cards hidden by `visibility`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::game::*;
+    /// use machiavelli::hooks::RuleHooks;
+    /// use machiavelli::render::Visibility;
+    /// use machiavelli::rules::RuleSet;
+    ///
+    /// let hands = vec![Sequence::from_cards(&[RegularCard(Club, 4)]), 
+    ///                  Sequence::from_cards(&[RegularCard(Heart, 9), Joker])];
+    /// let deck = Sequence::from_cards(&[RegularCard(Spade, 2), RegularCard(Spade, 3)]);
+    /// let mut game = GameState::new(Table::new(), hands, deck, 0);
+    /// let hooks = RuleHooks::from_rules(false, &RuleSet::default());
+    /// game.draw(&hooks).unwrap();
+    /// game.start_turn(1);
+    ///
+    /// let view = game.view(1, Visibility::default());
+    /// assert_eq!(&Sequence::from_cards(&[RegularCard(Heart, 9), Joker]), view.hand());
+    /// assert_eq!(vec![Some(2), Some(2)], view.hand_sizes());
+    /// assert_eq!(Some(1), view.deck_size());
+    ///
+    /// // the card drawn by the other player is hidden
+    /// assert_eq!(&[(0, Event::Drew(None))], view.events());
+    /// assert_eq!(&[(0, Event::Drew(Some(RegularCard(Spade, 3))))], game.view(0, Visibility::default()).events());
+    ///
+    /// let view = game.view(1, Visibility::blind());
+    /// assert_eq!(vec![None, Some(2)], view.hand_sizes());
+    /// assert_eq!(None, view.deck_size());
+    /// ```
+    pub fn view(&self, player: usize, visibility: Visibility) -> PlayerView {
+        PlayerView {
+            player,
+            current_player: self.current_player,
+            hand: self.hands[player].clone(),
+            table: self.table.clone(),
+            hand_sizes: self.hands.iter().enumerate()
+                .map(|(i, hand)| if visibility.hand_counts || i == player { Some(hand.number_cards()) } else { None })
+                .collect(),
+            deck_size: if visibility.deck_count { Some(self.deck.number_cards()) } else { None },
+            initial_meld_points: self.initial_meld_points,
+            opened: (0..self.hands.len()).map(|i| self.has_opened(i)).collect(),
+            events: self.events.iter()
+                .map(|(i, event)| match event {
+                    Event::Drew(_) if *i != player => (*i, Event::Drew(None)),
+                    event => (*i, event.clone())
+                })
+                .collect()
+        }
+    }
+
+    // keep a move made by the current player
+    fn record(&mut self, event: Event) -> Event {
+        self.events.push((self.current_player, event.clone()));
+        event
+    }
+
+    /// Check if the current player holds cards taken from the table
+    pub fn has_cards_from_table(&self) -> bool {
+        !self.hand_start_turn.contains(self.hand())
+    }
+
+    /// Whether each card of the hand of the current player has been taken from the table during
+    /// this turn
+    ///
+    /// Identical cards are told apart by their order: the first ones are those of the hand.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::game::GameState;
+    ///
+    /// let mut table = Table::new();
+    /// table.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+    /// let hand = Sequence::from_cards(&[RegularCard(Club, 4), Joker]);
+    /// let mut game = GameState::new(table, vec![hand], Sequence::new(), 0);
+    /// game.take_from_table(1).unwrap();
+    ///
+    /// assert_eq!(vec![false, false, true, true, true], game.taken_cards());
+    /// ```
+    pub fn taken_cards(&self) -> Vec<bool> {
+        let mut count = self.hand_start_turn.count_cards();
+        self.hand().to_vec().iter()
+            .map(|card| match count.get_mut(card) {
+                Some(n) if *n > 0 => {
+                    *n -= 1;
+                    false
+                },
+                _ => true
+            })
+            .collect()
+    }
+
+    /// Check if the current player has played some of their cards
+    pub fn has_played(&self) -> bool {
+        !self.hand().contains(&self.hand_start_turn)
+    }
+
+    /// Check if a player has made their first meld, or does not need to
+    pub fn has_opened(&self, player: usize) -> bool {
+        self.initial_meld_points == 0 || self.opened.get(player).copied().unwrap_or(false)
+    }
+
+    /// Points of the sequences played by the current player during this turn
+    ///
+    /// As a player can not use the table before their first meld, these are the points of their
+    /// first meld until it has been made.
+    pub fn points_played(&self) -> u16 {
+        self.table.iter().take(self.table.len().saturating_sub(self.table_start_turn.len()))
+            .map(Sequence::meld_points)
+            .sum()
+    }
+
+    /// Play the cards of the current hand with the given indices (starting from 1)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::game::*;
+    /// use machiavelli::hooks::RuleHooks;
+    /// use machiavelli::rules::RuleSet;
+    ///
+    /// let hand = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Heart, 9),
+    ///                                   RegularCard(Club, 5), RegularCard(Club, 6)]);
+    /// let mut game = GameState::new(Table::new(), vec![hand], Sequence::new(), 0);
+    /// let hooks = RuleHooks::from_rules(false, &RuleSet::default());
+    ///
+    /// assert!(matches!(game.play_cards(&[1, 2, 3], &hooks), Err(RuleError::InvalidSequence(_))));
+    /// assert_eq!(Err(RuleError::NoSuchCard(5)), game.play_cards(&[1, 5], &hooks));
+    /// assert!(game.play_cards(&[1, 3, 4], &hooks).is_ok());
+    /// assert_eq!(&Sequence::from_cards(&[RegularCard(Heart, 9)]), game.hand());
+    /// assert_eq!(Err(RuleError::AlreadyPlayed), game.draw(&hooks));
+    /// ```
+    pub fn play_cards(&mut self, indices: &[usize], hooks: &RuleHooks) -> Result<Event, RuleError> {
+        let cards = self.hand().to_vec();
+        for (i, &n) in indices.iter().enumerate() {
+            if n == 0 || n > cards.len() || indices[..i].contains(&n) {
+                return Err(RuleError::NoSuchCard(n));
+            }
+        }
+        let mut seq = Sequence::from_cards(&indices.iter().map(|&n| cards[n - 1].clone()).collect::<Vec<Card>>());
+        if !hooks.can_play(&mut seq) {
+            return Err(RuleError::InvalidSequence(seq));
+        }
+        let mut sorted = indices.to_vec();
+        sorted.sort_unstable();
+        let hand = &mut self.hands[self.current_player];
+        for &n in sorted.iter().rev() {
+            hand.take_card(n);
+        }
+        self.table.add(seq.clone());
+        Ok(self.record(Event::Played(seq)))
+    }
+
+    /// Take the sequence with the given index (starting from 1) from the table into the hand of
+    /// the current player
+    pub fn take_from_table(&mut self, index: usize) -> Result<Event, RuleError> {
+        if !self.has_opened(self.current_player) {
+            return Err(RuleError::TableBeforeInitialMeld(self.initial_meld_points));
+        }
+        let seq = self.table.take(index).ok_or(RuleError::NoSuchSequence(index))?;
+        self.hands[self.current_player].merge(seq.clone());
+        Ok(self.record(Event::Taken(seq)))
+    }
+
+    /// Add the card of the current hand with index `card_index` to the sequence of the table with
+    /// index `sequence_index` (both starting from 1)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::game::*;
+    /// use machiavelli::hooks::RuleHooks;
+    /// use machiavelli::rules::RuleSet;
+    ///
+    /// let mut table = Table::new();
+    /// table.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+    /// let hand = Sequence::from_cards(&[RegularCard(Heart, 9), RegularCard(Club, 7)]);
+    /// let mut game = GameState::new(table, vec![hand], Sequence::new(), 0);
+    /// let hooks = RuleHooks::from_rules(false, &RuleSet::default());
+    ///
+    /// assert!(matches!(game.add_to_sequence(1, 1, &hooks), Err(RuleError::InvalidSequence(_))));
+    /// assert_eq!(Err(RuleError::NoSuchSequence(2)), game.add_to_sequence(2, 2, &hooks));
+    /// assert_eq!(Ok(Event::Added(RegularCard(Club, 7), 1)), game.add_to_sequence(2, 1, &hooks));
+    /// assert_eq!(4, game.table.get(1).unwrap().number_cards());
+    /// ```
+    pub fn add_to_sequence(&mut self, card_index: usize, sequence_index: usize, hooks: &RuleHooks) 
+        -> Result<Event, RuleError> 
+    {
+        let mut events = self.add_cards_to_sequence(&[card_index], sequence_index, hooks)?;
+        Ok(events.remove(0))
+    }
+
+    /// Add the cards of the current hand with the given indices to the sequence of the table with
+    /// index `sequence_index` (all starting from 1), with one event for each card
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::game::*;
+    /// use machiavelli::hooks::RuleHooks;
+    /// use machiavelli::rules::RuleSet;
+    ///
+    /// let mut table = Table::new();
+    /// table.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+    /// let hand = Sequence::from_cards(&[RegularCard(Club, 8), RegularCard(Club, 7)]);
+    /// let mut game = GameState::new(table, vec![hand], Sequence::new(), 0);
+    /// let hooks = RuleHooks::from_rules(false, &RuleSet::default());
+    ///
+    /// assert!(matches!(game.add_cards_to_sequence(&[1], 1, &hooks), Err(RuleError::InvalidSequence(_))));
+    /// assert_eq!(Err(RuleError::NoSuchCard(2)), game.add_cards_to_sequence(&[2, 2], 1, &hooks));
+    /// assert_eq!(2, game.add_cards_to_sequence(&[1, 2], 1, &hooks).unwrap().len());
+    /// assert_eq!(5, game.table.get(1).unwrap().number_cards());
+    /// ```
+    pub fn add_cards_to_sequence(&mut self, card_indices: &[usize], sequence_index: usize, hooks: &RuleHooks) 
+        -> Result<Vec<Event>, RuleError> 
+    {
+        if !self.has_opened(self.current_player) {
+            return Err(RuleError::TableBeforeInitialMeld(self.initial_meld_points));
+        }
+        let cards = self.hand().to_vec();
+        for (i, &n) in card_indices.iter().enumerate() {
+            if n == 0 || n > cards.len() || card_indices[..i].contains(&n) {
+                return Err(RuleError::NoSuchCard(n));
+            }
+        }
+        let mut longer = self.table.get(sequence_index).cloned()
+            .ok_or(RuleError::NoSuchSequence(sequence_index))?;
+        for &n in card_indices {
+            longer.add_card(cards[n - 1].clone());
+        }
+        if !hooks.can_play(&mut longer) {
+            return Err(RuleError::InvalidSequence(longer));
+        }
+        let mut sorted = card_indices.to_vec();
+        sorted.sort_unstable();
+        let hand = &mut self.hands[self.current_player];
+        for &n in sorted.iter().rev() {
+            hand.take_card(n);
+        }
+        self.table.take(sequence_index);
+        self.table.insert(sequence_index, longer);
+        Ok(card_indices.iter()
+            .map(|&n| self.record(Event::Added(cards[n - 1].clone(), sequence_index)))
+            .collect())
+    }
+
+    /// Draw a card and end the turn, if the current player has not played
+    pub fn draw(&mut self, hooks: &RuleHooks) -> Result<Event, RuleError> {
+        if self.has_played() {
+            return Err(RuleError::AlreadyPlayed);
+        }
+        self.end_turn(hooks)
+    }
+
+    /// End the turn after having played
+    pub fn pass(&mut self, hooks: &RuleHooks) -> Result<Event, RuleError> {
+        if !self.has_played() {
+            return Err(RuleError::NothingPlayed);
+        }
+        self.end_turn(hooks)
+    }
+
+    /// End the turn: the current player draws a card if they have not played, unless their hand
+    /// already has `max_hand_size` cards
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::game::*;
+    /// use machiavelli::hooks::RuleHooks;
+    /// use machiavelli::rules::RuleSet;
+    ///
+    /// let hand = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6),
+    ///                                   RegularCard(Heart, 10), RegularCard(Spade, 10), RegularCard(Club, 10)]);
+    /// let mut game = GameState::new(Table::new(), vec![hand], Sequence::new(), 0);
+    /// game.initial_meld_points = 30;
+    /// let hooks = RuleHooks::from_rules(false, &RuleSet::default());
+    ///
+    /// game.play_cards(&[1, 2, 3], &hooks).unwrap();
+    /// assert_eq!(Err(RuleError::InitialMeldTooLow(15, 30)), game.end_turn(&hooks));
+    /// assert_eq!(Err(RuleError::TableBeforeInitialMeld(30)), game.take_from_table(1));
+    /// game.play_cards(&[1, 2, 3], &hooks).unwrap();
+    /// assert_eq!(Ok(Event::Passed), game.end_turn(&hooks));
+    /// assert!(game.has_opened(0));
+    /// ```
+    pub fn end_turn(&mut self, hooks: &RuleHooks) -> Result<Event, RuleError> {
+        if self.has_cards_from_table() {
+            return Err(RuleError::CardsFromTableLeft);
+        }
+        hooks.check_end_of_turn(self.hand()).map_err(RuleError::Refused)?;
+        if self.has_played() && !self.has_opened(self.current_player) {
+            let points = self.points_played();
+            if points < self.initial_meld_points {
+                return Err(RuleError::InitialMeldTooLow(points, self.initial_meld_points));
+            }
+            if self.opened.len() <= self.current_player {
+                self.opened.resize(self.current_player + 1, false);
+            }
+            self.opened[self.current_player] = true;
+        }
+        if self.has_played() {
+            Ok(self.record(Event::Passed))
+        } else if self.max_hand_size > 0 && self.hand().number_cards() >= self.max_hand_size {
+            Ok(self.record(Event::Drew(None)))
+        } else {
+            let hand = &mut self.hands[self.current_player];
+            let card = pick_a_card(hand, &mut self.deck).ok();
+            Ok(self.record(Event::Drew(card)))
+        }
+    }
+
+    /// Put the table and the hand back as they were at the beginning of the turn, without penalty
+    pub fn reset_turn(&mut self) {
+        self.hands[self.current_player] = self.hand_start_turn.clone();
+        self.table = self.table_start_turn.clone();
+    }
+
+    /// Put the table and the hand back as they were at the beginning of the turn, and draw
+    /// `penalty` cards
+    pub fn give_up(&mut self, penalty: usize) -> Event {
+        crate::give_up(&mut self.table, &mut self.hands[self.current_player], &mut self.deck,
+                       &self.hand_start_turn, &self.table_start_turn, &mut Sequence::new(), penalty);
+        self.record(Event::Reset)
+    }
+}
+
+/// What a player may know of a game: their own hand, the table, the numbers of cards the players
+/// and the deck have (unless they are hidden), and the moves made so far
+///
+/// Unlike a `GameState`, a view holds no other hand and not the deck, and the cards drawn by the
+/// other players are replaced by `None` in its events: a program given only a view can not use
+/// them, even by mistake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerView {
+    player: usize,
+    current_player: usize,
+    hand: Sequence,
+    table: Table,
+    hand_sizes: Vec<Option<usize>>,
+    deck_size: Option<usize>,
+    initial_meld_points: u16,
+    opened: Vec<bool>,
+    events: Vec<(usize, Event)>
+}
+
+impl PlayerView {
+
+    /// Index of the player seeing the game
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    /// Index of the player whose turn it is
+    pub fn current_player(&self) -> usize {
+        self.current_player
+    }
+
+    /// Hand of the player
+    pub fn hand(&self) -> &Sequence {
+        &self.hand
+    }
+
+    /// Sequences on the table
+    pub fn table(&self) -> &Table {
+        &self.table
+    }
+
+    /// Number of cards of each player (`None` if it is hidden)
+    pub fn hand_sizes(&self) -> &[Option<usize>] {
+        &self.hand_sizes
+    }
+
+    /// Number of cards in the deck (`None` if it is hidden)
+    pub fn deck_size(&self) -> Option<usize> {
+        self.deck_size
+    }
+
+    /// Minimum number of points of the first meld of each player (0 for no minimum)
+    pub fn initial_meld_points(&self) -> u16 {
+        self.initial_meld_points
+    }
+
+    /// Check if a player has made their first meld, or does not need to
+    pub fn has_opened(&self, player: usize) -> bool {
+        self.opened.get(player).copied().unwrap_or(self.initial_meld_points == 0)
+    }
+
+    /// Moves made so far, with the players who made them
+    pub fn events(&self) -> &[(usize, Event)] {
+        &self.events
+    }
+}
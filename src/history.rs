@@ -0,0 +1,183 @@
+//! History of the sequences on the table
+//!
+//! The sequences on the table have no identity of their own: their positions change whenever one
+//! is added or taken. To follow them from one turn to the next, each sequence is given a number
+//! when it first appears; after a turn, a sequence found unchanged keeps its number, and a modified
+//! one inherits the number of the sequence it shares the most cards with. Each change is recorded
+//! with the turn and the player who made it.
+
+use std::collections::HashMap;
+use crate::{ Card, Sequence, Table, render, reset_style_string };
+
+/// A change made to a sequence during a turn
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceChange {
+
+    /// number of the turn, from 1 for the first one recorded
+    pub turn: usize,
+
+    /// player who made the change
+    pub player: usize,
+
+    /// sequence after the change
+    pub sequence: Sequence
+}
+
+/// History of the sequences on the table
+#[derive(Debug, Clone, Default)]
+pub struct TableHistory {
+    table: Table,
+    ids: Vec<usize>,
+    next_id: usize,
+    n_turns: usize,
+    changes: HashMap<usize, Vec<SequenceChange>>
+}
+
+// number of cards two sequences have in common
+fn n_common_cards(a: &Sequence, b: &Sequence) -> usize {
+    let mut counts = HashMap::<Card, usize>::new();
+    for card in a.to_vec() {
+        *counts.entry(card).or_insert(0) += 1;
+    }
+    b.to_vec().into_iter().filter(|card| {
+        match counts.get_mut(card) {
+            Some(n) if *n > 0 => {
+                *n -= 1;
+                true
+            },
+            _ => false
+        }
+    }).count()
+}
+
+impl TableHistory {
+
+    /// Start following the sequences of a table, whose previous changes are unknown
+    pub fn new(table: &Table) -> TableHistory {
+        let n = table.len();
+        TableHistory {
+            table: table.clone(),
+            ids: (0..n).collect(),
+            next_id: n,
+            n_turns: 0,
+            changes: HashMap::new()
+        }
+    }
+
+    /// Record the changes made to the table by `player` during a turn
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::history::TableHistory;
+    ///
+    /// let mut table = Table::new();
+    /// let mut history = TableHistory::new(&table);
+    ///
+    /// table.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+    /// history.update(&table, 0);
+    /// table.add(Sequence::from_cards(&[RegularCard(Heart, 7), RegularCard(Spade, 7), RegularCard(Club, 7)]));
+    /// history.update(&table, 1);
+    /// let mut run = table.take(2).unwrap();
+    /// run.add_card(RegularCard(Club, 3));
+    /// table.add(run);
+    /// history.update(&table, 0);
+    ///
+    /// assert_eq!(vec![1, 3], history.changes(0).iter().map(|c| c.turn).collect::<Vec<usize>>());
+    /// assert_eq!(vec![2], history.changes(1).iter().map(|c| c.turn).collect::<Vec<usize>>());
+    /// ```
+    pub fn update(&mut self, table: &Table, player: usize) {
+        self.n_turns += 1;
+        let mut previous: Vec<Option<Sequence>> = self.table.to_vec().into_iter().map(Some).collect();
+        let current = table.to_vec();
+        let mut ids: Vec<Option<usize>> = vec![None; current.len()];
+
+        // sequences left unchanged
+        for (j, seq) in current.iter().enumerate() {
+            if let Some(i) = previous.iter().position(|p| p.as_ref() == Some(seq)) {
+                previous[i] = None;
+                ids[j] = self.ids.get(i).copied();
+            }
+        }
+
+        // modified or new sequences
+        for (j, seq) in current.iter().enumerate() {
+            if ids[j].is_some() {
+                continue;
+            }
+            let best = (0..previous.len())
+                .filter_map(|i| previous[i].as_ref().map(|p| (i, n_common_cards(p, seq))))
+                .filter(|&(_, n)| n > 0)
+                .max_by_key(|&(i, n)| (n, std::cmp::Reverse(i)));
+            let id = match best {
+                Some((i, _)) => {
+                    previous[i] = None;
+                    self.ids[i]
+                },
+                None => {
+                    self.next_id += 1;
+                    self.next_id - 1
+                }
+            };
+            ids[j] = Some(id);
+            self.changes.entry(id).or_default().push(SequenceChange {
+                turn: self.n_turns,
+                player,
+                sequence: seq.clone()
+            });
+        }
+
+        // the sequences which are no longer on the table are forgotten
+        let ids: Vec<usize> = ids.into_iter().flatten().collect();
+        self.changes.retain(|id, _| ids.contains(id));
+        self.ids = ids;
+        self.table = table.clone();
+    }
+
+    /// Changes recorded for the sequence at position `index` (from 0) on the table, from the
+    /// oldest to the most recent
+    pub fn changes(&self, index: usize) -> &[SequenceChange] {
+        self.ids.get(index)
+            .and_then(|id| self.changes.get(id))
+            .map(|changes| changes.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Describe the evolution of the sequence at position `index` (from 0) on the table
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::history::TableHistory;
+    ///
+    /// set_colors(false);
+    /// let names = vec!["Alice".to_string(), "Bob".to_string()];
+    /// let mut table = Table::new();
+    /// let mut history = TableHistory::new(&table);
+    ///
+    /// table.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+    /// history.update(&table, 1);
+    ///
+    /// assert_eq!(Some("History of sequence 1:\n  turn 1, Bob: 4♣ 5♣ 6♣ \n".to_string()),
+    ///            history.describe(0, &names));
+    /// assert_eq!(None, history.describe(1, &names));
+    /// ```
+    pub fn describe(&self, index: usize, player_names: &[String]) -> Option<String> {
+        if index >= self.ids.len() {
+            return None;
+        }
+        let changes = self.changes(index);
+        let mut s = format!("History of sequence {}:\n", index + 1);
+        if changes.is_empty() {
+            s += "  no change since the beginning of the game or since it was loaded\n";
+        }
+        for change in changes {
+            s += &format!("  turn {}, {}: {}{}\n", change.turn,
+                          player_names.get(change.player).map(|n| n.as_str()).unwrap_or("?"),
+                          render::sequence(&change.sequence), reset_style_string());
+        }
+        Some(s)
+    }
+}
@@ -0,0 +1,210 @@
+//! Pluggable house rules
+//!
+//! House rules and variants are implemented as [`RuleHook`]s, which the game calls at given points
+//! of each turn. They are combined in a [`RuleHooks`], built from the configuration of the game and
+//! the optional rules (see `RuleHooks::from_rules`).
+
+use crate::{ Sequence, Table, update_table_ages };
+use crate::rules::RuleSet;
+
+/// Change of the turn order triggered by the house rules at the end of a turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TurnOrderChange {
+
+    /// the direction of play is reversed
+    pub reverse: bool,
+
+    /// the next player skips their turn
+    pub skip: bool
+}
+
+/// Callbacks through which a house rule changes the course of a game
+///
+/// All the callbacks but `name` do nothing by default, so a rule only implements those it needs.
+pub trait RuleHook {
+
+    /// name of the rule
+    fn name(&self) -> &str;
+
+    /// called for each sequence formed on the table during a turn, when the turn is over
+    fn on_meld_played(&mut self, _sequence: &Sequence) {}
+
+    /// called when a turn is over, after `on_meld_played`
+    fn on_turn_end(&mut self) -> TurnOrderChange {
+        TurnOrderChange::default()
+    }
+
+    /// check if a player may end their turn with this hand, and give the reason if not
+    fn check_end_of_turn(&self, _hand: &Sequence) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// decide if a sequence can be played, given whether it is valid for the standard rules
+    fn is_valid_meld(&self, _sequence: &Sequence, valid: bool) -> bool {
+        valid
+    }
+}
+
+/// Jokers must be played before the end of the turn (the custom joker rule of the configuration)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JokersMustBePlayed;
+
+impl RuleHook for JokersMustBePlayed {
+
+    fn name(&self) -> &str {
+        "jokers must be played"
+    }
+
+    fn check_end_of_turn(&self, hand: &Sequence) -> Result<(), String> {
+        if hand.contains_joker() {
+            Err("Jokers must be played!".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Forming a sequence with four cards of a given rank changes the turn order (see the
+/// `reverse_rank` and `skip_rank` rules)
+#[derive(Debug, Clone, Copy)]
+pub struct FourOfAKind {
+    rank: u8,
+    change: TurnOrderChange,
+    triggered: bool
+}
+
+impl FourOfAKind {
+
+    /// reverse the turn order when four cards of rank `rank` are played together
+    pub fn reverse(rank: u8) -> FourOfAKind {
+        FourOfAKind { rank, change: TurnOrderChange { reverse: true, skip: false }, triggered: false }
+    }
+
+    /// make the next player skip their turn when four cards of rank `rank` are played together
+    pub fn skip(rank: u8) -> FourOfAKind {
+        FourOfAKind { rank, change: TurnOrderChange { reverse: false, skip: true }, triggered: false }
+    }
+}
+
+impl RuleHook for FourOfAKind {
+
+    fn name(&self) -> &str {
+        if self.change.reverse { "four of a kind reverses" } else { "four of a kind skips" }
+    }
+
+    fn on_meld_played(&mut self, sequence: &Sequence) {
+        let count = sequence.to_vec().iter()
+            .filter(|c| c.rank().map(|r| r as u8) == Some(self.rank))
+            .count();
+        self.triggered |= count >= 4;
+    }
+
+    fn on_turn_end(&mut self) -> TurnOrderChange {
+        if std::mem::take(&mut self.triggered) {
+            self.change
+        } else {
+            TurnOrderChange::default()
+        }
+    }
+}
+
+/// Set of house rules, called in the order in which they were added
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::hooks::{ RuleHooks, TurnOrderChange };
+/// use machiavelli::rules::RuleSet;
+///
+/// let rules = RuleSet::from_string("reverse_rank = 8").unwrap();
+/// let mut hooks = RuleHooks::from_rules(true, &rules);
+/// assert!(hooks.check_end_of_turn(&Sequence::from_cards(&[Joker])).is_err());
+///
+/// let previous = Table::new();
+/// let mut current = Table::new();
+/// current.add(Sequence::from_cards(&[RegularCard(Heart, 8), RegularCard(Club, 8),
+///                                    RegularCard(Diamond, 8), RegularCard(Spade, 8)]));
+/// hooks.on_table_changed(&previous, &current);
+/// assert_eq!(TurnOrderChange { reverse: true, skip: false }, hooks.on_turn_end());
+/// assert_eq!(TurnOrderChange::default(), hooks.on_turn_end());
+/// ```
+#[derive(Default)]
+pub struct RuleHooks {
+    hooks: Vec<Box<dyn RuleHook>>
+}
+
+impl RuleHooks {
+
+    /// Create an empty set of house rules
+    pub fn new() -> RuleHooks {
+        RuleHooks { hooks: Vec::new() }
+    }
+
+    /// House rules corresponding to the configuration and optional rules of a game
+    pub fn from_rules(custom_rule_jokers: bool, rules: &RuleSet) -> RuleHooks {
+        let mut hooks = RuleHooks::new();
+        if custom_rule_jokers {
+            hooks.add(JokersMustBePlayed);
+        }
+        if rules.reverse_rank != 0 {
+            hooks.add(FourOfAKind::reverse(rules.reverse_rank));
+        }
+        if rules.skip_rank != 0 {
+            hooks.add(FourOfAKind::skip(rules.skip_rank));
+        }
+        hooks
+    }
+
+    /// Add a house rule
+    pub fn add<H: RuleHook + 'static>(&mut self, hook: H) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Names of the house rules
+    pub fn names(&self) -> Vec<&str> {
+        self.hooks.iter().map(|h| h.name()).collect()
+    }
+
+    /// Call `on_meld_played` for each sequence of `current` which was not on the table `previous`
+    /// (that is, which has been played or rearranged during the turn)
+    pub fn on_table_changed(&mut self, previous: &Table, current: &Table) {
+        let ages = update_table_ages(previous, &vec![0; previous.len()], current);
+        for (sequence, age) in current.iter().zip(ages) {
+            if age == 0 {
+                for hook in self.hooks.iter_mut() {
+                    hook.on_meld_played(sequence);
+                }
+            }
+        }
+    }
+
+    /// Combined change of the turn order once a turn is over
+    pub fn on_turn_end(&mut self) -> TurnOrderChange {
+        let mut change = TurnOrderChange::default();
+        for hook in self.hooks.iter_mut() {
+            let c = hook.on_turn_end();
+            change.reverse ^= c.reverse;
+            change.skip |= c.skip;
+        }
+        change
+    }
+
+    /// Check if a player may end their turn with this hand; the first rule objecting gives the
+    /// reason
+    pub fn check_end_of_turn(&self, hand: &Sequence) -> Result<(), String> {
+        self.hooks.iter().try_for_each(|h| h.check_end_of_turn(hand))
+    }
+
+    /// Decide if a sequence can be played, given whether it is valid for the standard rules
+    pub fn is_valid_meld(&self, sequence: &Sequence, valid: bool) -> bool {
+        self.hooks.iter().fold(valid, |valid, h| h.is_valid_meld(sequence, valid))
+    }
+
+    /// Check if a sequence can be played: it must be valid for the standard rules, unless a house
+    /// rule decides otherwise
+    pub fn can_play(&self, sequence: &mut Sequence) -> bool {
+        let valid = sequence.is_valid();
+        self.is_valid_meld(sequence, valid)
+    }
+}
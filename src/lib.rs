@@ -3,28 +3,152 @@
 //! A simple machiavelli card game *(work in progress)*
 
 
-use std::io::{ stdin, Write };
+use std::io::{ stdin, Write, IsTerminal };
+use std::sync::atomic::{ AtomicBool, Ordering };
 pub mod sequence_cards;
+pub mod render;
 pub mod table;
+pub mod rules;
+pub mod preset;
+pub mod hooks;
+#[cfg(feature = "scripting")]
+pub mod scripting;
 pub mod sort;
 pub mod encode;
+pub mod save;
+pub mod terminal;
+pub mod net;
 pub mod lib_server;
 pub mod lib_client;
+pub mod bot;
+pub mod evaluation;
+pub mod simulation;
+pub mod replay;
+pub mod filter;
+pub mod telemetry;
+pub mod report;
+pub mod fairness;
+pub mod match_play;
+pub mod scoring;
+pub mod game;
+pub mod history;
+pub mod watchdog;
+pub mod admin;
+pub mod stats;
+pub mod bench;
+pub mod tournament;
+pub mod solver;
+pub mod compact;
 pub use sequence_cards::*;
 pub use table::*;
 
 /// number of cards to take when resetting 
 pub const PENALTY_RESET: usize = 3;
 
+/// whether ANSI escape sequences should be used for styling the output
+static COLORS: AtomicBool = AtomicBool::new(true);
+
+/// check if ANSI escape sequences are used for styling the output
+pub fn colors_enabled() -> bool {
+    COLORS.load(Ordering::Relaxed)
+}
+
+/// enable or disable the use of ANSI escape sequences for styling the output
+pub fn set_colors(enabled: bool) {
+    COLORS.store(enabled, Ordering::Relaxed);
+}
+
+/// lock taken by the tests which change the use of the ANSI escape sequences, as the tests run in
+/// parallel and share it
+#[cfg(test)]
+pub(crate) static COLORS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// determine whether the standard output supports colours
+///
+/// Colours are disabled if the standard output is not a terminal, if the `NO_COLOR` environment
+/// variable is set to a non-empty value, or if `TERM` is `dumb`.
+pub fn detect_colors() -> bool {
+    let no_color = std::env::var_os("NO_COLOR").map(|v| !v.is_empty()).unwrap_or(false);
+    let dumb_term = std::env::var("TERM").map(|t| t == "dumb").unwrap_or(false);
+    std::io::stdout().is_terminal() && !no_color && !dumb_term
+}
+
+/// return the escape sequence `code` if colours are enabled, and an empty string otherwise
+pub fn style_code(code: &'static str) -> &'static str {
+    if colors_enabled() {
+        code
+    } else {
+        ""
+    }
+}
+
+/// remove the ANSI escape sequences from a string
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::strip_escape_sequences;
+///
+/// let s = "\x1b[1;31mA♥ \x1b[0m\x1b[30;47m\x1b[?25l\x1b[Kdone\x1b7";
+///
+/// assert_eq!("A♥ done", strip_escape_sequences(s));
+/// ```
+pub fn strip_escape_sequences(s: &str) -> String {
+    let mut res = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            res.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'[') {
+            // control sequence: skip until the final byte
+            chars.next();
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        } else {
+            // two-character sequence
+            chars.next();
+        }
+    }
+    res
+}
+
 pub fn reset_style_string() -> String {
+    if !colors_enabled() {
+        return String::new();
+    }
     [
-        "\x1b[0m", // reset attributes
-        "\x1b[30;47m", // set the foreground and background colours
-        "\x1b[?25l", // hide the cursor
-        "\x1b[K" // redraw the prompt
+        terminal::ansi(crossterm::style::SetAttribute(crossterm::style::Attribute::Reset)),
+        "\x1b[30;47m".to_string(), // black on white (crossterm writes 256-colour codes instead)
+        terminal::ansi(crossterm::cursor::Hide),
+        terminal::ansi(crossterm::terminal::Clear(crossterm::terminal::ClearType::UntilNewLine)) // redraw the prompt
     ].join("")
 }
 
+/// `text` in bold, followed by the default attributes; plain text if colours are disabled
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// set_colors(true);
+/// assert_eq!("\u{1b}[1mDraw!\u{1b}[0m", bold("Draw!"));
+/// set_colors(false);
+/// assert_eq!("Draw!", bold("Draw!"));
+/// ```
+pub fn bold(text: &str) -> String {
+    if !colors_enabled() {
+        return text.to_string();
+    }
+    format!("{}{}{}", terminal::ansi(crossterm::style::SetAttribute(crossterm::style::Attribute::Bold)), 
+            text, terminal::ansi(crossterm::style::SetAttribute(crossterm::style::Attribute::Reset)))
+}
+
 /// reset the terminal output style
 pub fn reset_style() {
     print!("{}", reset_style_string());
@@ -32,21 +156,48 @@ pub fn reset_style() {
 
 /// clear the terminal
 pub fn clear_terminal() {
-    print!("\x1b[2J\x1b[1;1H");
+    terminal::clear();
 }
 
 
 /// Structure to store the game configuration
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Config {
     pub n_decks: u8,
     pub n_jokers: u8,
     pub n_cards_to_start: u16,
     pub custom_rule_jokers: bool,
-    pub n_players: u8
+    pub n_players: u8,
+
+    /// minimum number of points of the first meld of each player (0 for no minimum; see
+    /// `Sequence::meld_points`)
+    pub initial_meld_points: u16
 }
 
 
+/// Default configuration: two decks with four jokers, 13 cards each for two players
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::Config;
+///
+/// assert_eq!(Config::from_bytes(&[2,4,0,13,0,2]), Config::default());
+/// ```
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            n_decks: 2,
+            n_jokers: 4,
+            n_cards_to_start: 13,
+            custom_rule_jokers: false,
+            n_players: 2,
+            initial_meld_points: 0
+        }
+    }
+}
+
 impl Config {
 
     /// Convert the config structure to a sequence of bytes
@@ -61,7 +212,8 @@ impl Config {
     ///     n_jokers: 4,
     ///     n_cards_to_start: 13,
     ///     custom_rule_jokers: false,
-    ///     n_players: 2
+    ///     n_players: 2,
+    ///     initial_meld_points: 0
     /// };
     ///
     /// let config_bytes = config.to_bytes();
@@ -97,7 +249,8 @@ impl Config {
     ///     n_jokers: 4,
     ///     n_cards_to_start: 13,
     ///     custom_rule_jokers: false,
-    ///     n_players: 2
+    ///     n_players: 2,
+    ///     initial_meld_points: 0
     /// };
     ///
     /// assert_eq!(expected_config, config);
@@ -108,11 +261,37 @@ impl Config {
             n_jokers: bytes[1],
             n_cards_to_start: (bytes[2] as u16)*256 + (bytes[3] as u16),
             custom_rule_jokers: bytes[4] != 0,
-            n_players: bytes[5]
+            n_players: bytes[5],
+            initial_meld_points: 0
         }
     }
 }
 
+/// directory in which the user-specific files are stored
+///
+/// This is `$XDG_CONFIG_HOME/machiavelli` or `$HOME/.config/machiavelli` (`%APPDATA%\machiavelli`
+/// on Windows). The directory is created if needed; `None` is returned if it can not be determined
+/// or created.
+pub fn user_config_dir() -> Option<std::path::PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => std::path::PathBuf::from(dir),
+        _ => match std::env::var_os("APPDATA") {
+            Some(dir) if cfg!(windows) => std::path::PathBuf::from(dir),
+            _ => std::path::PathBuf::from(std::env::var_os("HOME")?).join(".config")
+        }
+    };
+    let dir = base.join("machiavelli");
+    std::fs::create_dir_all(&dir).ok()?;
+    Some(dir)
+}
+
+/// passphrase used to obfuscate the saves, from the `MACHIAVELLI_PASSPHRASE` environment variable
+///
+/// Return an empty string if it is not set, in which case a default key is used.
+pub fn passphrase_from_env() -> String {
+    std::env::var("MACHIAVELLI_PASSPHRASE").unwrap_or_default()
+}
+
 /// get the vector of player names from a file
 pub fn load_names(fname: &str) -> Result<Vec<String>, InvalidInputError> {
     let content = std::fs::read_to_string(fname)?;
@@ -153,10 +332,16 @@ pub fn get_config_from_file(fname: &str) -> Result<(Config,String),InvalidInputE
     let custom_rule_jokers = first_word(content[3])? == "1";
     let n_players = first_word(content[4])?.parse::<u8>()?;
     let savefile = first_word(content[5])?;
+
+    // minimum number of points of the first meld (optional)
+    let initial_meld_points = match content.get(6).map(|line| first_word(line)) {
+        Some(Ok(w)) if !w.is_empty() => w.parse::<u16>()?,
+        _ => 0
+    };
    
     // print the parameters
     #[allow(clippy::print_literal)] {
-        println!("{}: {}\n{}: {}\n{}: {}\n{}: {}\n{}: {}\n{}: {}",
+        println!("{}: {}\n{}: {}\n{}: {}\n{}: {}\n{}: {}\n{}: {}\n{}: {}",
                  "Number of decks",
                  n_decks,
                  "Number of jokers",
@@ -167,6 +352,8 @@ pub fn get_config_from_file(fname: &str) -> Result<(Config,String),InvalidInputE
                  custom_rule_jokers,
                  "Number of players",
                  n_players,
+                 "Points needed for the first meld",
+                 initial_meld_points,
                  "Savefile", 
                  savefile);
     }
@@ -176,7 +363,8 @@ pub fn get_config_from_file(fname: &str) -> Result<(Config,String),InvalidInputE
         n_jokers,
         n_cards_to_start,
         custom_rule_jokers,
-        n_players
+        n_players,
+        initial_meld_points
     }, savefile))
 }
 
@@ -214,7 +402,8 @@ pub fn get_config() -> Result<Config,InvalidInputError> {
             n_jokers: 0,
             n_cards_to_start: 0,
             custom_rule_jokers: false,
-            n_players: 0
+            n_players: 0,
+            initial_meld_points: 0
         });
     }
     
@@ -272,21 +461,38 @@ pub fn get_config() -> Result<Config,InvalidInputError> {
         };
     }
 
+    println!("Points needed for the first meld of each player (0 for no minimum): ");
+    let mut initial_meld_points = None;
+    while initial_meld_points.is_none() {
+        initial_meld_points = match get_input()?.trim() {
+            "" => Some(0),
+            s => s.parse::<u16>().ok()
+        };
+        if initial_meld_points.is_none() {
+            println!("Could not parse the input");
+        }
+    }
+
     Ok(Config {
         n_decks, 
         n_jokers,
         n_cards_to_start,
         custom_rule_jokers,
-        n_players
+        n_players,
+        initial_meld_points: initial_meld_points.unwrap_or(0)
     })
 }
 
 fn instructions() -> String {
-    format!("{}\n{}\n{}\n{}\n{}\n{}\n{}\n",
+    format!("{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n",
         "q: Save and quit",
         "c: Pick a card",
         "p: Play a sequence",
         "t: Take from the table",
+        "k: Set cards aside",
+        "m: Play the cards set aside",
+        "u, U: Undo your last move or redo it",
+        "h: Suggest a sequence to play",
         "a: Pass",
         "r, s: Sort cards by rank or suit",
         "g: Give up and reset"
@@ -304,101 +510,152 @@ pub fn instructions_no_save(must_pick_a_card: bool, print_reset_option: bool)
     if print_reset_option {
         reset_option = &"g: Give up and reset\n";
     }
-    format!("{}{}\n{}\n{}\n{}\n{}\n{}\n",
+    format!("{}{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n",
         "e: End your turn",
         will_pick_a_card,
         "p x y ...: Play the sequence x y ...",
         "t x y ...: Take the sequences x, y, ... from the table",
         "a x y z ...: Add the sequence y z ... to sequence x on the table",
+        "q x y: Add card x of your hand to sequence y on the table",
+        "k x y ...: Set the cards x y ... aside (k alone puts them back)",
+        "m: Play the cards set aside",
         "r, s: Sort cards by rank or suit",
+        "f: Forfeit and leave the game",
+        "q: Propose to save the game and quit",
         reset_option
         )
 }
 
-pub fn player_turn(table: &mut Table, hand: &mut Sequence, deck: &mut Sequence, 
-                   custom_rule_jokers: bool, player_name: &str) -> bool {
+// ask a player who can not play all the cards taken from the table if they want to reset it and
+// end their turn
+fn offer_reset(rules: &rules::RuleSet) -> bool {
+    println!("You have not played all the cards you've taken from the table. Reset the table{} and pick a card? (‘y’ for yes)",
+             if rules.free_reset { "" } else { ", take the penalty," });
+    lib_server::is_yes(get_input().unwrap_or_default().trim())
+}
 
-    // copy the initial hand
-    let hand_start_round = hand.clone();
-    
-    // copy the initial table
-    let table_start_round = table.clone();
+pub fn player_turn(game: &mut game::GameState, hooks: &hooks::RuleHooks, rules: &rules::RuleSet, 
+                   player_name: &str) -> bool {
 
     // get the player choice
     let mut message = String::new();
+
+    // states of the game before the moves of the turn, to undo them
+    let mut moves = UndoStack::new();
+
+    // cards set aside by the player
+    let mut staged = StagedCards::new();
     loop {
         
         // clear the terminal
         clear_terminal();
         
-        println!("\x1b[1m{}'s turn", player_name);
+        println!("{}", bold(&format!("{}'s turn", player_name)));
         reset_style();
         
-        print_situation(table, hand, deck);
+        print_situation(&game.table, game.hand(), &game.deck);
+        if staged.number_cards() > 0 {
+            println!("Cards set aside:\n{}\n", render::sequence(&staged.to_sequence()));
+            reset_style();
+        }
+        if !game.has_opened(game.current_player) {
+            println!("Your first meld must be worth at least {} points.", game.initial_meld_points);
+        }
 
         // print the options
         println!("{}", &instructions());
         
-        if message.is_empty() {
+        if !message.is_empty() {
             println!("\n{}", message);
             message.clear()
         }
         
-        match get_input().unwrap_or_else(|_| {"".to_string()}).trim() {
+        let input = get_input().unwrap_or_else(|_| {"".to_string()});
+        let command = input.trim();
+
+        // the cards set aside go back to the hand before the other moves, so that the game (and
+        // the states kept to undo the moves) always holds all of them
+        if !matches!(command, "k" | "m" | "r" | "s") {
+            staged.unstage(&mut game.hands[game.current_player], &mut Sequence::new());
+        }
+        
+        match command {
             "q" => {
-                if !hand_start_round.contains(hand) {
+                if game.has_cards_from_table() {
                     message = "You can't save until you've played all the cards you've taken from the table!".to_string();
-                } else if !hand.contains(&hand_start_round) {
+                } else if game.has_played() {
                     message = "You need to pass before saving".to_string();
                 } else {
                     return true;
                 }
             },
             "c" => {
-                if !hand_start_round.contains(hand) {
-                    message = "You can't pick a card until you've played all the cards you've taken from the table!".to_string();
-                } else if !hand.contains(&hand_start_round) {
-                    message = "You can't pick a card after having played something".to_string();
-                } else if custom_rule_jokers && hand.contains_joker() {
-                    message = "Jokers must be played!".to_string();
-                } else {
-                    match pick_a_card(hand, deck) {
-                        Ok(card) => println!("You have picked a {}\x1b[38;2;0;0;0;1m", &card),
-                        Err(_) => println!("No more card to draw!")
-                    };
-                    break
+                if game.has_cards_from_table() && offer_reset(rules) {
+                    game.give_up(rules.reset_penalty());
+                }
+                match game.draw(hooks) {
+                    Ok(game::Event::Drew(Some(card))) => {
+                        println!("You have picked a {}{}", render::card(&card), style_code("\x1b[38;2;0;0;0;1m"));
+                        break
+                    },
+                    Ok(_) => {
+                        println!("No more card to draw!");
+                        break
+                    },
+                    Err(e) => message = e.to_string()
                 }
             },
             "p" => {
-                message = play_sequence(hand, table);
-                print_situation(table, hand, deck);
+                let before = game.clone();
+                message = play_sequence(game, hooks);
+                record_move(&mut moves, before, game);
+                print_situation(&game.table, game.hand(), &game.deck);
             },
             "t" => {
-                message = take_sequence(table, hand);
-                print_situation(table, hand, deck);
+                let before = game.clone();
+                message = take_sequence(game);
+                record_move(&mut moves, before, game);
+                print_situation(&game.table, game.hand(), &game.deck);
+            },
+            "k" => message = stage_cards(game, &mut staged),
+            "m" => {
+                let n_staged = staged.number_cards();
+                staged.unstage(&mut game.hands[game.current_player], &mut Sequence::new());
+                let before = game.clone();
+                message = play_staged_cards(game, hooks, &mut staged, n_staged);
+                record_move(&mut moves, before, game);
             },
+
+            "h" => {
+                let hint = solver::find_valid_melds(game.hand(), &game.table).into_iter()
+                    .find(|m| matches!(m, tournament::Move::Play(_)));
+                message = match hint {
+                    Some(m) => format!("Hint: {}", solver::describe_move(&m, game.hand())),
+                    None => "No sequence can be formed with your cards alone.".to_string()
+                };
+            },
+
+            // the move is undone or redone by the guard, if there is one
+            "u" if !moves.undo(game) => message = "There is no move to undo!".to_string(),
+            "U" if !moves.redo(game) => message = "There is no move to redo!".to_string(),
             "a" => {
-                if !hand_start_round.contains(hand) {
-                    message = "You can't pass until you've played all the cards you've taken from the table!".to_string();
-                } else if hand.contains(&hand_start_round) {
-                    message = "You need to play something to pass".to_string();
-                } else if custom_rule_jokers && hand.contains_joker() {
-                    message = "Jokers need to be played!".to_string();
-                } else {
-                    break
+                match game.pass(hooks) {
+                    Ok(_) => break,
+                    Err(e) => message = e.to_string()
                 }
             }
             "r" => {
-                hand.sort_by_rank();
-                print_situation(table, hand, deck);
+                game.hands[game.current_player].sort_by_rank();
+                print_situation(&game.table, game.hand(), &game.deck);
             },
             "s" => {
-                hand.sort_by_suit();
-                print_situation(table, hand, deck);
+                game.hands[game.current_player].sort_by_suit();
+                print_situation(&game.table, game.hand(), &game.deck);
             },
             "g" => {
-                give_up(table, hand, deck, &hand_start_round, &table_start_round, &mut Sequence::new());
-                print_situation(table, hand, deck);
+                moves.clear();
+                game.give_up(rules.reset_penalty());
+                print_situation(&game.table, game.hand(), &game.deck);
             },
             _ => ()
         };
@@ -413,28 +670,52 @@ fn print_situation(table: &Table, hand: &Sequence, deck: &Sequence) {
     println!("\n{} cards remaining in the deck", deck.number_cards());
     
     // print the table
-    println!("Table: \n{}", table);
+    println!("Table: \n{}", render::table(table));
 
     // print the player hand
-    println!("Your hand:\n{}\n", hand);
+    println!("Your hand:\n{}\n", render::sequence(hand));
     reset_style();
 
 }
 
 
-pub fn situation_to_string(table: &Table, hand: &Sequence, 
-                           cards_from_table: &Sequence, message: &str) -> String {
+/// Situation shown to a player: the table, their hand, the cards they have taken from the table,
+/// and those they have set aside
+///
+/// The hand and the cards taken from the table are numbered together, the latter in a different
+/// colour; each group is followed by its number of cards and points.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// set_colors(false);
+/// let hand = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Heart, 12)]);
+/// let taken = Sequence::from_cards(&[Joker]);
+///
+/// assert_eq!("\nTable:\n\nYour hand: 2 cards, 14 points\n4♣ Q♥\n1  2\n\n\
+///             Cards from the table: 1 card, 25 points (numbered from 3)\n#\n3\n",
+///            situation_to_string(&Table::new(), &hand, &taken, &Sequence::new(), ""));
+/// ```
+pub fn situation_to_string(table: &Table, hand: &Sequence, cards_from_table: &Sequence, 
+                           staged: &Sequence, message: &str) -> String {
   
-    let hi = hand.show_indices();
-    let ht = cards_from_table.show_indices_shifted(hand.number_cards());
-    if cards_from_table.number_cards() == 0 {
-        format!("\n{}\n{}\n{}{}:\n{}\n{}{}\n",
-                "Table:", table, "Your hand", message, hi.0, reset_style_string(), hi.1)
-    } else {
-        format!("\n{}\n{}\n{}{}:\n{}{}\n{}\n\n{}\n{}\n{}{}\n", 
-                "Table:", table, "Your hand", message, hi.0, reset_style_string(), hi.1,
-                "Cards from the table:", ht.0, reset_style_string(), ht.1)
+    let hi = render::show_indices_shifted(hand, 0);
+    let ht = render::show_indices_taken(cards_from_table, hand.number_cards());
+    let table = render::table(table);
+    let mut res = format!("\n{}\n{}\n{}{}: {}\n{}{}\n{}\n",
+                          "Table:", table, "Your hand", message, render::subtotal(hand), 
+                          hi.0, reset_style_string(), hi.1);
+    if cards_from_table.number_cards() > 0 {
+        res += &format!("\n{}: {} (numbered from {})\n{}{}\n{}\n", 
+                        "Cards from the table", render::subtotal(cards_from_table),
+                        hand.number_cards() + 1, ht.0, reset_style_string(), ht.1);
     }
+    if staged.number_cards() > 0 {
+        res += &format!("\n{}\n{}{}\n", "Cards set aside:", render::sequence(staged), reset_style_string());
+    }
+    res
 }
 
 
@@ -457,65 +738,326 @@ fn pick_a_card(hand: &mut Sequence, deck: &mut Sequence) -> Result<Card, NoMoreC
     Ok(card)
 }
 
+/// pick a card at the end of a turn, unless the hand already has `max_hand_size` cards
+///
+/// There is no limit if `max_hand_size` is 0. Return the message for the player.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// let mut hand = Sequence::from_cards(&[RegularCard(Club, 4), Joker]);
+/// let mut deck = Sequence::from_cards(&[RegularCard(Heart, 7)]);
+///
+/// pick_a_card_end_of_turn(&mut hand, &mut deck, 2);
+/// assert_eq!(2, hand.number_cards());
+///
+/// pick_a_card_end_of_turn(&mut hand, &mut deck, 0);
+/// assert_eq!(3, hand.number_cards());
+/// ```
+pub fn pick_a_card_end_of_turn(hand: &mut Sequence, deck: &mut Sequence, max_hand_size: usize) 
+    -> String 
+{
+    if max_hand_size > 0 && hand.number_cards() >= max_hand_size {
+        return format!(" (your hand is full with {} cards: you skip drawing)", hand.number_cards());
+    }
+    match pick_a_card(hand, deck) {
+        Ok(card) => format!(" (you picked a {}{})", render::card(&card), &reset_style_string()),
+        Err(_) => "No more card to draw!\n".to_string()
+    }
+}
+
+/// remove a player from the game after they have forfeited
+///
+/// The cards in their hand are shuffled back into the deck if `to_deck` is `true`, and removed
+/// from the game otherwise.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::sequence_cards::{ Card::*, Suit::* };
+///
+/// let mut hand = Sequence::from_cards(&[Joker, RegularCard(Heart, 1)]);
+/// let mut deck = Sequence::from_cards(&[RegularCard(Spade, 2)]);
+///
+/// forfeit(&mut hand, &mut deck, true);
+///
+/// assert_eq!(0, hand.number_cards());
+/// assert_eq!(3, deck.number_cards());
+/// ```
+pub fn forfeit(hand: &mut Sequence, deck: &mut Sequence, to_deck: bool) {
+    let cards = std::mem::take(hand);
+    if to_deck {
+        deck.merge(cards);
+        deck.shuffle(&mut rand::thread_rng());
+    }
+}
+
+/// index of the next player, skipping those who have forfeited
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::next_player;
+///
+/// let forfeited = vec![false, true, false];
+///
+/// assert_eq!(2, next_player(0, &forfeited));
+/// assert_eq!(0, next_player(2, &forfeited));
+/// ```
+pub fn next_player(current_player: usize, forfeited: &[bool]) -> usize {
+    let n_players = forfeited.len();
+    (1..=n_players).map(|i| (current_player + i) % n_players)
+        .find(|&i| !forfeited[i])
+        .unwrap_or((current_player + 1) % n_players)
+}
+
+/// index of the player playing after `current_player`, going backwards if `reversed` is set and
+/// skipping those who have forfeited
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::next_player_in_direction;
+///
+/// let forfeited = vec![false, false, true, false];
+///
+/// assert_eq!(1, next_player_in_direction(0, &forfeited, false));
+/// assert_eq!(3, next_player_in_direction(0, &forfeited, true));
+/// assert_eq!(1, next_player_in_direction(3, &forfeited, true));
+/// ```
+pub fn next_player_in_direction(current_player: usize, forfeited: &[bool], reversed: bool) -> usize {
+    if !reversed {
+        return next_player(current_player, forfeited);
+    }
+    let n_players = forfeited.len();
+    (1..=n_players).map(|i| (current_player + n_players - i) % n_players)
+        .find(|&i| !forfeited[i])
+        .unwrap_or((current_player + n_players - 1) % n_players)
+}
+
+/// number of turns each sequence on the table has been left untouched
+///
+/// `previous_ages` gives the ages of the sequences of `previous` (the table at the beginning of
+/// the turn); a sequence of `current` found unchanged in `previous` gets one more turn, the other
+/// ones start from 0.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// let mut previous = Table::new();
+/// previous.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+/// let mut current = previous.clone();
+/// current.add(Sequence::from_cards(&[RegularCard(Heart, 7), RegularCard(Spade, 7), RegularCard(Club, 7)]));
+///
+/// assert_eq!(vec![0, 3], update_table_ages(&previous, &[2], &current));
+/// ```
+pub fn update_table_ages(previous: &Table, previous_ages: &[usize], current: &Table) -> Vec<usize> {
+    let mut previous: Vec<Option<Sequence>> = previous.to_vec().into_iter().map(Some).collect();
+    current.iter().map(|seq| {
+        match previous.iter().position(|p| p.as_ref() == Some(seq)) {
+            Some(i) => {
+                previous[i] = None;
+                previous_ages.get(i).map(|age| age + 1).unwrap_or(0)
+            },
+            None => 0
+        }
+    }).collect()
+}
+
+/// whether every sequence on the table has been left untouched for at least `min_age` turns
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// assert!(table_settled(&[4, 3], 3));
+/// assert!(!table_settled(&[4, 2], 3));
+/// ```
+pub fn table_settled(ages: &[usize], min_age: usize) -> bool {
+    ages.iter().all(|&age| age >= min_age)
+}
+
+/// move `n_cards` cards from the top of the deck to the discard pool (fewer if the deck runs out)
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// let mut deck = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), Joker]);
+/// let mut discard_pool = Sequence::new();
+/// discard_from_deck(&mut deck, &mut discard_pool, 2);
+///
+/// assert_eq!(1, deck.number_cards());
+/// assert_eq!(2, discard_pool.number_cards());
+/// ```
+pub fn discard_from_deck(deck: &mut Sequence, discard_pool: &mut Sequence, n_cards: usize) {
+    for _i in 0..n_cards {
+        match deck.draw_card() {
+            Some(card) => discard_pool.add_card(card),
+            None => break
+        }
+    }
+}
+
+/// shuffle the discard pool into the deck
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// let mut deck = Sequence::new();
+/// let mut discard_pool = Sequence::from_cards(&[RegularCard(Club, 4), Joker]);
+/// reshuffle_discard_pool(&mut deck, &mut discard_pool);
+///
+/// assert_eq!(2, deck.number_cards());
+/// assert_eq!(0, discard_pool.number_cards());
+/// ```
+pub fn reshuffle_discard_pool(deck: &mut Sequence, discard_pool: &mut Sequence) {
+    deck.merge(std::mem::take(discard_pool));
+    deck.shuffle(&mut rand::thread_rng());
+}
+
+/// deal `n_cards` cards to each player who has not forfeited
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// let mut deck = Sequence::multi_deck(1, 0, &mut rand::thread_rng());
+/// let hands = deal_active_players(&mut deck, 13, &[false, true, false]);
+///
+/// assert_eq!(vec![13, 0, 13], hands.iter().map(Sequence::number_cards).collect::<Vec<_>>());
+/// ```
+pub fn deal_active_players(deck: &mut Sequence, n_cards: usize, forfeited: &[bool]) -> Vec<Sequence> {
+    let n_active_players = forfeited.iter().filter(|&&f| !f).count();
+    let mut new_hands = deck.deal(n_active_players, n_cards).into_iter();
+    forfeited.iter()
+        .map(|&f| if f { Sequence::new() } else { new_hands.next().unwrap() })
+        .collect()
+}
+
+/// add a player to a game in progress, at the end of the rotation
+///
+/// The new player gets a fresh hand drawn from the deck (smaller than usual if there are not
+/// enough cards left). Return the index of the new player.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// let mut config = Config { n_decks: 1, n_jokers: 0, n_cards_to_start: 13, 
+///                           custom_rule_jokers: false, n_players: 2, initial_meld_points: 0 };
+/// let mut hands = vec![Sequence::new(), Sequence::new()];
+/// let mut deck = Sequence::multi_deck(1, 0, &mut rand::thread_rng());
+///
+/// assert_eq!(2, add_player(&mut hands, &mut deck, &mut config));
+/// assert_eq!(3, config.n_players);
+/// assert_eq!(13, hands[2].number_cards());
+/// assert_eq!(39, deck.number_cards());
+/// ```
+pub fn add_player(hands: &mut Vec<Sequence>, deck: &mut Sequence, config: &mut Config) -> usize {
+    hands.append(&mut deck.deal(1, config.n_cards_to_start as usize));
+    config.n_players += 1;
+    hands.len() - 1
+}
+
+// keep the state of the game before a move, if the move has changed anything
+fn record_move(moves: &mut UndoStack<game::GameState>, before: game::GameState, game: &game::GameState) {
+    if before.table != game.table || before.hand() != game.hand() {
+        moves.record(before);
+    }
+}
 
-fn play_sequence(hand: &mut Sequence, table: &mut Table) -> String {
+fn play_sequence(game: &mut game::GameState, hooks: &hooks::RuleHooks) -> String {
     println!("Please enter the sequence, separated by spaces");
-    let hand_and_indices = hand.show_indices();
+    let hand_and_indices = render::show_indices_shifted(game.hand(), 0);
     println!("{}", hand_and_indices.0);
     reset_style();
     println!("{}", hand_and_indices.1);
-    let mut seq = Sequence::new();
     
-    let mut s = get_input().unwrap_or_else(|_| {"".to_string()});
-    s.pop();
-    let mut seq_i = Vec::<usize>::new();
-    for item in s.split(' ') {
-        if let Ok(n) = item.parse::<usize>() {
-            let mut n_i = 0;
-            for &i in &seq_i {
-                if i < n {
-                    n_i += 1;
-                }
-            }
-            let card = match hand.take_card(n-n_i) {
-                Some(c) => c,
-                None => continue
-            };
-            seq.add_card(card);
-            seq_i.push(n);
-        }
+    let indices: Vec<usize> = get_input().unwrap_or_else(|_| {"".to_string()})
+        .split_whitespace()
+        .filter_map(|item| item.parse::<usize>().ok())
+        .collect();
+    match game.play_cards(&indices, hooks) {
+        Ok(_) => String::new(),
+        Err(e) => e.to_string()
     }
+}
+
+
+// ask the player which cards to set aside; with no card, put them back in the hand
+fn stage_cards(game: &mut game::GameState, staged: &mut StagedCards) -> String {
+    println!("Which cards would you like to set aside? (none to put them back)");
+    let hand_and_indices = render::show_indices_shifted(game.hand(), 0);
+    println!("{}", hand_and_indices.0);
+    reset_style();
+    println!("{}", hand_and_indices.1);
 
-    if seq.is_valid() {
-        table.add(seq);
+    let indices: Vec<usize> = get_input().unwrap_or_else(|_| {"".to_string()})
+        .split_whitespace()
+        .filter_map(|item| item.parse::<usize>().ok())
+        .collect();
+    if indices.is_empty() {
+        staged.unstage(&mut game.hands[game.current_player], &mut Sequence::new());
         String::new()
     } else {
-        let message = format!("{} is not a valid sequence!", &seq);
-        hand.merge(seq);
-        message
+        match staged.stage(&indices, &mut game.hands[game.current_player], &mut Sequence::new()) {
+            Ok(()) => String::new(),
+            Err(e) => e.to_string()
+        }
+    }
+}
+
+
+// play the `n_staged` cards which have been set aside and put back at the end of the hand; if 
+// they can not be played, they are set aside again
+fn play_staged_cards(game: &mut game::GameState, hooks: &hooks::RuleHooks, staged: &mut StagedCards,
+                     n_staged: usize) -> String {
+    if n_staged == 0 {
+        return "You have not set any card aside!".to_string();
+    }
+    let n = game.hand().number_cards();
+    let indices: Vec<usize> = (n - n_staged + 1..=n).collect();
+    match game.play_cards(&indices, hooks) {
+        Ok(_) => String::new(),
+        Err(e) => {
+            // the indices are those of the last cards of the hand, so they can always be set aside
+            let _ = staged.stage(&indices, &mut game.hands[game.current_player], &mut Sequence::new());
+            e.to_string()
+        }
     }
 }
 
 
-fn take_sequence(table: &mut Table, hand: &mut Sequence) -> String {
+fn take_sequence(game: &mut game::GameState) -> String {
     println!("Which sequence would you like to take?");
     match get_input().unwrap_or_else(|_| {"".to_string()})
           .trim().parse::<usize>() {
-        Ok(n) => match table.take(n) {
-            Some(seq) => {
-                hand.merge(seq);
-                String::new()
-            },
-            None => "This sequence is not on the table".to_string()
+        Ok(n) => match game.take_from_table(n) {
+            Ok(_) => String::new(),
+            Err(e) => e.to_string()
         },
         Err(_) => "Error parsing the input!".to_string()
     }
 }
 
 
+/// put the hand and the table back as they were at the beginning of the turn, and draw `penalty`
+/// cards
 pub fn give_up(table: &mut Table, hand: &mut Sequence, deck: &mut Sequence, 
                hand_start_round: &Sequence, table_start_round: &Table,
-               cards_from_table: &mut Sequence) {
+               cards_from_table: &mut Sequence, penalty: usize) {
     
     // reset the situation
     *hand = hand_start_round.clone();
@@ -523,7 +1065,7 @@ pub fn give_up(table: &mut Table, hand: &mut Sequence, deck: &mut Sequence,
     *cards_from_table = Sequence::new();
 
     // penalty
-    for _i in 0..PENALTY_RESET {
+    for _i in 0..penalty {
         match pick_a_card(hand, deck) {
             Ok(_) => (),
             Err(_) => {
@@ -535,21 +1077,260 @@ pub fn give_up(table: &mut Table, hand: &mut Sequence, deck: &mut Sequence,
 }
 
 
+/// States of a turn before each of the moves made so far, to undo them, and after the moves
+/// undone, to redo them
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::UndoStack;
+///
+/// let mut stack = UndoStack::new();
+/// let mut state = "start";
+/// stack.record(state);
+/// state = "after p 1 2 3";
+/// stack.record(state);
+/// state = "after t 1";
+///
+/// assert!(stack.undo(&mut state));
+/// assert_eq!("after p 1 2 3", state);
+/// assert!(stack.undo(&mut state));
+/// assert!(!stack.undo(&mut state));
+/// assert!(stack.redo(&mut state));
+/// assert_eq!("after p 1 2 3", state);
+///
+/// // a new move can not be followed by the moves undone before it
+/// stack.record(state);
+/// state = "after p 4 5 6";
+/// assert!(!stack.redo(&mut state));
+/// assert_eq!(2, stack.len());
+/// ```
+#[derive(Debug, Clone)]
+pub struct UndoStack<T> {
+    undo: Vec<T>,
+    redo: Vec<T>
+}
+
+impl<T> Default for UndoStack<T> {
+    fn default() -> Self {
+        UndoStack { undo: Vec::new(), redo: Vec::new() }
+    }
+}
+
+impl<T> UndoStack<T> {
+
+    /// Create a stack with no move
+    pub fn new() -> UndoStack<T> {
+        UndoStack::default()
+    }
+
+    /// Keep the state before a move; the moves undone can no longer be redone
+    pub fn record(&mut self, before: T) {
+        self.undo.push(before);
+        self.redo.clear();
+    }
+
+    /// Put `state` back as it was before the last move, if there is one
+    pub fn undo(&mut self, state: &mut T) -> bool {
+        match self.undo.pop() {
+            Some(previous) => {
+                self.redo.push(std::mem::replace(state, previous));
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Make the last move undone again, if there is one
+    pub fn redo(&mut self, state: &mut T) -> bool {
+        match self.redo.pop() {
+            Some(next) => {
+                self.undo.push(std::mem::replace(state, next));
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Number of moves which can be undone
+    pub fn len(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Check if there is no move to undo
+    pub fn is_empty(&self) -> bool {
+        self.undo.is_empty()
+    }
+
+    /// Forget the moves after the first `len` ones, and those undone
+    pub fn truncate(&mut self, len: usize) {
+        self.undo.truncate(len);
+        self.redo.clear();
+    }
+
+    /// Forget all the moves
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+}
+
+
+/// Cards set aside by the player for a sequence they intend to play
+///
+/// The cards are kept out of the hand (so they are not affected by sorting) and remember whether
+/// they come from the hand or from the table, so they can be put back where they belong.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StagedCards {
+    from_hand: Sequence,
+    from_table: Sequence
+}
+
+impl StagedCards {
+
+    /// Create an empty set of staged cards
+    pub fn new() -> StagedCards {
+        StagedCards {
+            from_hand: Sequence::new(),
+            from_table: Sequence::new()
+        }
+    }
+
+    /// Number of cards set aside
+    pub fn number_cards(&self) -> usize {
+        self.from_hand.number_cards() + self.from_table.number_cards()
+    }
+
+    /// Number of cards set aside which come from the hand
+    pub fn number_cards_from_hand(&self) -> usize {
+        self.from_hand.number_cards()
+    }
+
+    /// Set aside the cards with the given indices
+    ///
+    /// Indices up to the number of cards in the hand refer to the hand; the following ones refer
+    /// to the cards taken from the table. An index given several times sets the card aside once.
+    /// If an index does not refer to any card, no card is set aside and the error gives this
+    /// index.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    ///
+    /// let mut hand = Sequence::from_cards(&[
+    ///     RegularCard(Heart, 1),
+    ///     RegularCard(Club, 5),
+    ///     RegularCard(Heart, 2),
+    /// ]);
+    /// let mut cards_from_table = Sequence::from_cards(&[RegularCard(Heart, 3)]);
+    /// let mut staged = StagedCards::new();
+    ///
+    /// assert_eq!(Err(game::RuleError::NoSuchCard(5)),
+    ///            staged.stage(&[1, 5], &mut hand, &mut cards_from_table));
+    /// assert_eq!(0, staged.number_cards());
+    ///
+    /// staged.stage(&[4, 1, 3, 1], &mut hand, &mut cards_from_table).unwrap();
+    ///
+    /// assert_eq!(Sequence::from_cards(&[RegularCard(Club, 5)]), hand);
+    /// assert_eq!(0, cards_from_table.number_cards());
+    /// assert_eq!(Sequence::from_cards(&[
+    ///     RegularCard(Heart, 1),
+    ///     RegularCard(Heart, 2),
+    ///     RegularCard(Heart, 3),
+    /// ]), staged.to_sequence());
+    ///
+    /// staged.unstage(&mut hand, &mut cards_from_table);
+    ///
+    /// assert_eq!(3, hand.number_cards());
+    /// assert_eq!(1, cards_from_table.number_cards());
+    /// assert_eq!(0, staged.number_cards());
+    /// ```
+    pub fn stage(&mut self, indices: &[usize], hand: &mut Sequence, cards_from_table: &mut Sequence) 
+        -> Result<(), game::RuleError>
+    {
+        let n_hand = hand.number_cards();
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+        if let Some(&n) = indices.iter().find(|&&n| n == 0 || n > n_hand + cards_from_table.number_cards()) {
+            return Err(game::RuleError::NoSuchCard(n));
+        }
+        let mut seq_i_hand = Vec::<usize>::new();
+        let mut seq_i_cft = Vec::<usize>::new();
+        for n in indices {
+            if n <= n_hand {
+                let n_i = seq_i_hand.iter().filter(|&&i| i < n).count();
+                if let Some(card) = hand.take_card(n-n_i) {
+                    self.from_hand.add_card(card);
+                    seq_i_hand.push(n);
+                }
+            } else {
+                let m = n - n_hand;
+                let n_i = seq_i_cft.iter().filter(|&&i| i < m).count();
+                if let Some(card) = cards_from_table.take_card(m-n_i) {
+                    self.from_table.add_card(card);
+                    seq_i_cft.push(m);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Put the cards back in the hand and with the cards taken from the table
+    pub fn unstage(&mut self, hand: &mut Sequence, cards_from_table: &mut Sequence) {
+        let staged = std::mem::take(self);
+        hand.merge(staged.from_hand.reverse());
+        cards_from_table.merge(staged.from_table.reverse());
+    }
+
+    /// Return all the cards set aside as a single sequence
+    pub fn to_sequence(&self) -> Sequence {
+        let mut seq = self.from_hand.clone();
+        seq.merge(self.from_table.clone().reverse());
+        seq
+    }
+}
+
+
+/// first bytes of a save file written with a header
+pub const SAVE_MAGIC: [u8; 4] = *b"MCHV";
+
+/// version of the save format written by `game_to_bytes`
+///
+/// Saves without a header were written before the versioning was introduced and are still read.
+/// Version 2 adds the minimum points of the first meld after the config, and version 3 whether
+/// each player has made their first meld after the current player. From version 4, the length and
+/// CRC-32 of the header also cover the graveyard and the session tokens appended to the save.
+pub const SAVE_FORMAT_VERSION: u8 = 4;
+
+/// number of bytes of the save header: magic bytes, format version, length and CRC-32 of the body
+const SAVE_HEADER_SIZE: usize = 13;
+
 /// convert the game info to a sequence of bytes
+///
+/// The bytes start with a header containing `SAVE_MAGIC`, `SAVE_FORMAT_VERSION`, and the length
+/// and CRC-32 of the rest of the save.
+#[allow(clippy::too_many_arguments)]
 pub fn game_to_bytes (starting_player: u8, player: u8, table: &Table, hands: &[Sequence], 
-                      deck: &Sequence, config: &Config, player_names: &[String]) -> Vec<u8> {
+                      deck: &Sequence, config: &Config, player_names: &[String], opened: &[bool]) -> Vec<u8> {
     
     // construct the sequence of bytes to be saved
     let mut bytes = Vec::<u8>::new();
     
     // config
     bytes.append(&mut config.to_bytes());
+    bytes.extend_from_slice(&config.initial_meld_points.to_be_bytes());
 
     // starting player
     bytes.push(starting_player);
     
     // player about to play
     bytes.push(player);
+
+    // whether each player has made their first meld
+    for i_player in 0..config.n_players {
+        bytes.push(opened.get(i_player as usize).copied().unwrap_or(false) as u8);
+    }
     
     // hand of each player
     for i_player in 0..config.n_players {
@@ -579,39 +1360,139 @@ pub fn game_to_bytes (starting_player: u8, player: u8, table: &Table, hands: &[S
     // table 
     bytes.append(&mut table.to_bytes());
 
-    bytes
+    // header
+    let mut save = SAVE_MAGIC.to_vec();
+    save.push(SAVE_FORMAT_VERSION);
+    save.extend_from_slice(&[0; SAVE_HEADER_SIZE - 5]);
+    save.append(&mut bytes);
+    update_save_header(&mut save);
+
+    save
+}
+
+// write the length and CRC-32 of the bytes following the header of a save, if it has one
+fn update_save_header(save: &mut [u8]) {
+    if !save.starts_with(&SAVE_MAGIC) || save.len() < SAVE_HEADER_SIZE {
+        return;
+    }
+    let (header, body) = save.split_at_mut(SAVE_HEADER_SIZE);
+    header[5..9].copy_from_slice(&(body.len() as u32).to_be_bytes());
+    header[9..13].copy_from_slice(&encode::crc32(body).to_be_bytes());
 }
 
 
 /// load the game info from a sequence of bytes
+///
+/// Saves without a header, written by the versions preceding `SAVE_FORMAT_VERSION`, are still
+/// read; they get a header the next time the game is saved.
+///
+/// Return a `LoadingError` if the bytes are not a valid game, if the save was written by a newer
+/// version, or if its checksum does not match.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// let config = Config { n_decks: 1, n_jokers: 0, n_cards_to_start: 2, custom_rule_jokers: false,
+///                       n_players: 2, initial_meld_points: 30 };
+/// let names = vec!["Alice".to_string(), "Bob".to_string()];
+/// let hands = vec![Sequence::new(), Sequence::new()];
+/// let bytes = game_to_bytes(0, 1, &Table::new(), &hands, &Sequence::new(), &config, &names, 
+///                           &[true, false]);
+/// assert_eq!(load_game(&bytes).unwrap().0, config);
+/// assert_eq!(load_game(&bytes).unwrap().6, names);
+/// assert_eq!(load_game(&bytes).unwrap().7, vec![true, false]);
+///
+/// // a damaged save
+/// let mut damaged = bytes.clone();
+/// *damaged.last_mut().unwrap() ^= 1;
+/// assert_eq!(load_game(&damaged).unwrap_err(), LoadingError::Checksum);
+///
+/// // a save from a newer version
+/// let mut newer = bytes.clone();
+/// newer[SAVE_MAGIC.len()] = SAVE_FORMAT_VERSION + 1;
+/// assert_eq!(load_game(&newer).unwrap_err(), LoadingError::UnsupportedVersion(SAVE_FORMAT_VERSION + 1));
+///
+/// // a save without header, as written by the previous versions, which did not have the minimum
+/// // points of the first meld nor whether the players had made it
+/// let legacy = [&bytes[13..19], &bytes[21..23], &bytes[25..]].concat();
+/// assert_eq!(load_game(&legacy).unwrap().0.initial_meld_points, 0);
+/// assert_eq!(load_game(&legacy).unwrap().6, names);
+/// assert_eq!(load_game(&legacy).unwrap().7, vec![true, true]);
+/// ```
+#[allow(clippy::type_complexity)]
+pub fn load_game(bytes: &[u8]) -> Result<(Config, u8, u8, Table, Vec<Sequence>, Sequence, Vec<String>, Vec<bool>), LoadingError> {
+    if !bytes.starts_with(&SAVE_MAGIC) {
+        return load_game_body(bytes, 1);
+    }
+    if bytes.len() < SAVE_HEADER_SIZE {
+        return Err(LoadingError::Invalid);
+    }
+    let version = bytes[4];
+    if version > SAVE_FORMAT_VERSION {
+        return Err(LoadingError::UnsupportedVersion(version));
+    }
+    let length = u32::from_be_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]) as usize;
+    let crc = u32::from_be_bytes([bytes[9], bytes[10], bytes[11], bytes[12]]);
+    let body = bytes.get(SAVE_HEADER_SIZE..SAVE_HEADER_SIZE+length).ok_or(LoadingError::Invalid)?;
+    if encode::crc32(body) != crc {
+        return Err(LoadingError::Checksum);
+    }
+    load_game_body(body, version)
+}
+
+// load the game info from the bytes following the header, written with the given format version,
+// or from a save without header (version 1)
 #[allow(clippy::type_complexity)]
-pub fn load_game(bytes: &[u8]) -> Result<(Config, u8, u8, Table, Vec<Sequence>, Sequence, Vec<String>), LoadingError> {
+fn load_game_body(bytes: &[u8], version: u8) -> Result<(Config, u8, u8, Table, Vec<Sequence>, Sequence, Vec<String>, Vec<bool>), LoadingError> {
     let mut i_byte: usize = 0; // index of the current element in bytes
 
+    // take the next `n` bytes, checking that they exist
+    let mut take = |n: usize| -> Result<&[u8], LoadingError> {
+        let slice = bytes.get(i_byte..i_byte+n).ok_or(LoadingError::Invalid)?;
+        i_byte += n;
+        Ok(slice)
+    };
+
     // load the config
     let n_bytes_config: usize = 6;
-    let config = Config::from_bytes(&bytes[i_byte..n_bytes_config]);
-    i_byte += n_bytes_config;
+    let mut config = Config::from_bytes(take(n_bytes_config)?);
+    if version >= 2 {
+        let n = take(2)?;
+        config.initial_meld_points = u16::from_be_bytes([n[0], n[1]]);
+    }
+    if config.n_players == 0 {
+        return Err(LoadingError::Invalid);
+    }
     
     // load the starting player
-    let starting_player = bytes[i_byte];
-    i_byte += 1;
+    let starting_player = take(1)?[0];
     
     // load the current player
-    let player = bytes[i_byte];
-    i_byte += 1;
+    let player = take(1)?[0];
+    if starting_player >= config.n_players || player >= config.n_players {
+        return Err(LoadingError::Invalid);
+    }
+
+    // whether each player has made their first meld; the older saves did not keep it, so all the
+    // players are considered to have made it
+    let opened = if version >= 3 {
+        take(config.n_players as usize)?.iter().map(|&b| b != 0).collect()
+    } else {
+        vec![true; config.n_players as usize]
+    };
     
     // hand of each player
     let mut hands = Vec::<Sequence>::new();
     for _i_player in 0..config.n_players {
         
         // number of cards in the hand as 2 u8
-        let n_cards_in_hand = ((bytes[i_byte] as usize) << 8) + (bytes[i_byte+1] as usize);
-        i_byte += 2;
+        let n = take(2)?;
+        let n_cards_in_hand = ((n[0] as usize) << 8) + (n[1] as usize);
  
         // append the hand
-        hands.push(Sequence::from_bytes(&bytes[i_byte..i_byte+n_cards_in_hand]));
-        i_byte += n_cards_in_hand;
+        hands.push(Sequence::from_bytes(take(n_cards_in_hand)?));
     }
     
     // player names
@@ -619,23 +1500,26 @@ pub fn load_game(bytes: &[u8]) -> Result<(Config, u8, u8, Table, Vec<Sequence>,
     for i_player in 0..config.n_players {
         
         // number of characters in the name
-        let n_chars = bytes[i_byte] as usize;
-        i_byte += 1;
+        let n_chars = take(1)?[0] as usize;
         
         // append the name
-        player_names.push(String::from_utf8(bytes[i_byte..i_byte+n_chars].to_vec())
+        player_names.push(String::from_utf8(take(n_chars)?.to_vec())
                           .unwrap_or_else(|_| {format!("Player {}", i_player+1)}));
-        i_byte += n_chars;
     }
 
     // deck
-    let n_cards_in_deck = ((bytes[i_byte] as usize) << 8) + (bytes[i_byte+1] as usize);
-    i_byte += 2;
-    let deck = Sequence::from_bytes(&bytes[i_byte..i_byte+n_cards_in_deck]);
-    i_byte += n_cards_in_deck;
-
-    // table
-    let table = Table::from_bytes(&bytes[i_byte..]);
+    let n = take(2)?;
+    let n_cards_in_deck = ((n[0] as usize) << 8) + (n[1] as usize);
+    let deck = Sequence::from_bytes(take(n_cards_in_deck)?);
+
+    // table, followed by the graveyard and the session tokens if any
+    let n_bytes_tokens = match bytes.last() {
+        Some(&SESSION_TOKENS_MARKER) => (config.n_players as usize) * SESSION_TOKEN_LENGTH + 1,
+        _ => 0
+    };
+    let table_end = bytes.len().checked_sub(n_bytes_tokens).ok_or(LoadingError::Invalid)?;
+    let table_end = graveyard_bounds(bytes, config.n_players as usize).map_or(table_end, |(start, _)| start);
+    let table = Table::from_bytes(bytes.get(i_byte..table_end).ok_or(LoadingError::Invalid)?);
 
     Ok((
         config,
@@ -644,10 +1528,166 @@ pub fn load_game(bytes: &[u8]) -> Result<(Config, u8, u8, Table, Vec<Sequence>,
         table,
         hands,
         deck,
-        player_names
+        player_names,
+        opened
     ))
 }
 
+/// number of characters of a session token
+pub const SESSION_TOKEN_LENGTH: usize = 16;
+
+/// last byte of a save file containing session tokens
+///
+/// It can not be confused with the end of a save file without them, which is either a card, the
+/// separator 255 used by the table, or `GRAVEYARD_MARKER`.
+const SESSION_TOKENS_MARKER: u8 = 254;
+
+/// generate a random session token
+///
+/// Session tokens allow the players to reconnect without typing their name again, including to a
+/// server restarted from a save file.
+pub fn new_session_token() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// words used in the game codes (see `new_game_code`)
+const GAME_CODE_WORDS: [&str; 32] = [
+    "BEAR", "CRANE", "EAGLE", "FALCON", "FOX", "GECKO", "HERON", "IBIS", "JAGUAR", "KOALA", "LEMUR",
+    "LION", "LYNX", "MOOSE", "OTTER", "OWL", "PANDA", "PUMA", "RAVEN", "ROBIN", "SEAL", "SHARK",
+    "SWAN", "TIGER", "TOUCAN", "TROUT", "VIPER", "WHALE", "WOLF", "WREN", "YAK", "ZEBRA"
+];
+
+/// generate a short code identifying a game, such as "TIGER-42"
+///
+/// The code is easier to tell other players than an address; it is shown when the game is created
+/// and when the players join.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// let code = new_game_code();
+/// let (word, number) = code.split_once('-').unwrap();
+/// assert!(word.chars().all(|c| c.is_ascii_uppercase()));
+/// assert!(number.parse::<u8>().unwrap() < 100);
+/// ```
+pub fn new_game_code() -> String {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    format!("{}-{}", GAME_CODE_WORDS[rng.gen_range(0..GAME_CODE_WORDS.len())], rng.gen_range(10..100))
+}
+
+/// append the session tokens to a sequence of bytes produced by `game_to_bytes`
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// let config = Config { n_decks: 1, n_jokers: 0, n_cards_to_start: 2, custom_rule_jokers: false,
+///                       n_players: 2, initial_meld_points: 0 };
+/// let names = vec!["Alice".to_string(), "Bob".to_string()];
+/// let hands = vec![Sequence::new(), Sequence::new()];
+/// let tokens = vec![new_session_token(), new_session_token()];
+/// 
+/// let mut bytes = game_to_bytes(0, 1, &Table::new(), &hands, &Sequence::new(), &config, &names, 
+///                               &[false, false]);
+/// append_session_tokens(&mut bytes, &tokens);
+///
+/// assert_eq!(Some(tokens), session_tokens_from_bytes(&bytes, 2));
+/// assert!(load_game(&bytes).is_ok());
+///
+/// // the session tokens are covered by the checksum of the save
+/// let n = bytes.len();
+/// bytes[n - 2] ^= 1;
+/// assert_eq!(load_game(&bytes).unwrap_err(), LoadingError::Checksum);
+/// ```
+pub fn append_session_tokens(bytes: &mut Vec<u8>, tokens: &[String]) {
+    for token in tokens {
+        bytes.extend_from_slice(token.as_bytes());
+    }
+    bytes.push(SESSION_TOKENS_MARKER);
+    update_save_header(bytes);
+}
+
+/// get the session tokens from a saved game, if it contains them
+pub fn session_tokens_from_bytes(bytes: &[u8], n_players: usize) -> Option<Vec<String>> {
+    if bytes.last() != Some(&SESSION_TOKENS_MARKER) || bytes.len() < n_players * SESSION_TOKEN_LENGTH + 1 {
+        return None;
+    }
+    let start = bytes.len() - 1 - n_players * SESSION_TOKEN_LENGTH;
+    bytes[start..bytes.len()-1].chunks(SESSION_TOKEN_LENGTH)
+        .map(|chunk| String::from_utf8(chunk.to_vec()).ok())
+        .collect()
+}
+
+/// last byte of the ages of the sequences and the discard pool in a save file
+///
+/// They are written before the session tokens, if any, so this byte is either the last one of the
+/// save file or the one preceding the session tokens.
+const GRAVEYARD_MARKER: u8 = 253;
+
+/// append the number of turns each sequence on the table has been left untouched and the discard
+/// pool to a sequence of bytes produced by `game_to_bytes`, before the session tokens
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// let config = Config { n_decks: 1, n_jokers: 0, n_cards_to_start: 2, custom_rule_jokers: false,
+///                       n_players: 2, initial_meld_points: 0 };
+/// let names = vec!["Alice".to_string(), "Bob".to_string()];
+/// let hands = vec![Sequence::new(), Sequence::new()];
+/// let tokens = vec![new_session_token(), new_session_token()];
+/// let discard_pool = Sequence::from_cards(&[RegularCard(Club, 4), Joker]);
+/// 
+/// let mut bytes = game_to_bytes(0, 1, &Table::new(), &hands, &Sequence::new(), &config, &names, 
+///                               &[false, false]);
+/// append_graveyard(&mut bytes, &[3, 255, 7], &discard_pool);
+/// append_session_tokens(&mut bytes, &tokens);
+///
+/// assert_eq!(Some((vec![3, 255, 7], discard_pool)), graveyard_from_bytes(&bytes, 2));
+/// assert_eq!(Some(tokens), session_tokens_from_bytes(&bytes, 2));
+/// assert_eq!(load_game(&bytes).unwrap().3, Table::new());
+/// ```
+pub fn append_graveyard(bytes: &mut Vec<u8>, ages: &[usize], discard_pool: &Sequence) {
+    bytes.append(&mut discard_pool.to_bytes());
+    for &age in ages {
+        bytes.extend_from_slice(&(age.min(u16::MAX as usize) as u16).to_be_bytes());
+    }
+    bytes.extend_from_slice(&(discard_pool.number_cards() as u16).to_be_bytes());
+    bytes.extend_from_slice(&(ages.len() as u16).to_be_bytes());
+    bytes.push(GRAVEYARD_MARKER);
+    update_save_header(bytes);
+}
+
+// first byte of the graveyard in a saved game and the byte following it, if the save contains one
+fn graveyard_bounds(bytes: &[u8], n_players: usize) -> Option<(usize, usize)> {
+    let mut end = bytes.len();
+    if session_tokens_from_bytes(bytes, n_players).is_some() {
+        end -= n_players * SESSION_TOKEN_LENGTH + 1;
+    }
+    if end < 5 || bytes[end-1] != GRAVEYARD_MARKER {
+        return None;
+    }
+    let n_ages = u16::from_be_bytes([bytes[end-3], bytes[end-2]]) as usize;
+    let n_cards = u16::from_be_bytes([bytes[end-5], bytes[end-4]]) as usize;
+    let start = end.checked_sub(5 + 2 * n_ages + n_cards)?;
+    Some((start, end))
+}
+
+/// get the ages of the sequences on the table and the discard pool from a saved game, if it 
+/// contains them
+pub fn graveyard_from_bytes(bytes: &[u8], n_players: usize) -> Option<(Vec<usize>, Sequence)> {
+    let (start, end) = graveyard_bounds(bytes, n_players)?;
+    let n_cards = u16::from_be_bytes([bytes[end-5], bytes[end-4]]) as usize;
+    let ages = bytes[start+n_cards..end-5].chunks(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]) as usize)
+        .collect();
+    Some((ages, Sequence::from_bytes(&bytes[start..start+n_cards])))
+}
 
 #[derive(Debug)]
 pub struct InvalidInputError {}
@@ -659,6 +1699,36 @@ impl<T: std::error::Error> From<T> for InvalidInputError {
 }
 
 pub struct NoMoreCards {}
-pub struct LoadingError {}
+
+/// Error raised when a game, a replay, or a state can not be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadingError {
+    /// the bytes are not a valid game
+    Invalid,
+    /// the save was written by a newer version, using the given format version
+    UnsupportedVersion(u8),
+    /// the checksum in the header does not match the save
+    Checksum,
+    /// the compact encoding was written by another version, using the given format version (see
+    /// `compact::COMPACT_FORMAT_VERSION`)
+    UnsupportedCompactVersion(u8),
+}
+
+impl std::fmt::Display for LoadingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadingError::Invalid => write!(f, "the save file is not valid"),
+            LoadingError::UnsupportedVersion(v) => write!(f, 
+                "the save file uses format version {}, but this version only reads up to {}; \
+                 please update Machiavelli", v, SAVE_FORMAT_VERSION),
+            LoadingError::Checksum => write!(f, "the save file is damaged (checksum mismatch)"),
+            LoadingError::UnsupportedCompactVersion(v) => write!(f,
+                "the data uses version {} of the compact encoding, but this version only reads version {}; \
+                 please update Machiavelli", v, compact::COMPACT_FORMAT_VERSION),
+        }
+    }
+}
+
+impl std::error::Error for LoadingError {}
 
 
@@ -4,64 +4,381 @@ use super::*;
 pub use std::net::TcpStream;
 pub use std::io::{ Read, Write };
 pub use std::str::from_utf8;
+pub use crate::net::{ StreamError, Transport, EncryptedStream, is_timeout, 
+                      SESSION_TOKEN_PREFIX, SPECTATOR_PREFIX, GAME_CODE_PREFIX, Updates };
+use crate::net::{ tag_move, split_move_id, RESYNC_REQUEST };
+pub use crate::compact::PublicState;
 
 const BUFFER_SIZE: usize = 50;
 const MAX_N_BUFFERS: usize = 255;
 const N_MILLISECONDS_WAIT: u64 = 10;
 
-// ask for the port
-fn get_address() -> String {
+/// number of attempts to reconnect to the server before giving up
+const MAX_N_RECONNECTION_ATTEMPTS: u32 = 8;
+
+/// delay before the first attempt to reconnect, in seconds
+const N_SECONDS_FIRST_RECONNECTION_DELAY: u64 = 1;
+
+/// maximum delay between two attempts to reconnect, in seconds
+const N_SECONDS_MAX_RECONNECTION_DELAY: u64 = 30;
+
+/// time after which connecting to the server fails, in seconds
+const N_SECONDS_CONNECTION_TIMEOUT: u64 = 10;
+
+/// time after which a read from or write to the server is interrupted, in seconds
+///
+/// When the client is waiting for a request, the read is simply tried again; within a message, the
+/// connection is considered as lost.
+const N_SECONDS_STREAM_TIMEOUT: u64 = 30;
+
+/// number of lines reserved for the status bar at the top of the terminal
+const STATUS_BAR_HEIGHT: usize = 2;
+
+/// Fixed status bar shown at the top of the terminal
+///
+/// The rest of the terminal is a scrolling region, so that long messages from the server do not
+/// push the status off-screen.
+pub struct StatusBar {
+    text: String,
+    since: std::time::Instant,
+    latency: Option<std::time::Duration>
+}
+
+impl Default for StatusBar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatusBar {
+
+    /// Create an empty status bar
+    pub fn new() -> StatusBar {
+        StatusBar {
+            text: String::new(),
+            since: std::time::Instant::now(),
+            latency: None
+        }
+    }
+
+    /// Set the status text; the timer is restarted if the text changes
+    ///
+    /// Without colours, the status bar can not be drawn and the new status is printed instead.
+    pub fn set(&mut self, text: String) {
+        if text != self.text {
+            if !colors_enabled() {
+                println!("[{}]", &text);
+            }
+            self.text = text;
+            self.since = std::time::Instant::now();
+        }
+    }
+
+    /// Set the round-trip time to the server, shown at the end of the status bar
+    ///
+    /// A low latency while the game feels slow means that the server itself is slow.
+    pub fn set_latency(&mut self, latency: Option<std::time::Duration>) {
+        self.latency = latency;
+    }
+
+    /// Reserve the top of the terminal for the status bar and draw it
+    pub fn init(&self) {
+        if !colors_enabled() {
+            return;
+        }
+        terminal::set_scroll_region(STATUS_BAR_HEIGHT + 1);
+        self.clear();
+    }
+
+    /// Clear the scrolling region and redraw the status bar
+    pub fn clear(&self) {
+        clear_scrolling_region();
+        self.draw();
+    }
+
+    /// Draw the status bar without moving the cursor
+    pub fn draw(&self) {
+        if !colors_enabled() {
+            return;
+        }
+        let mut line = self.text.clone();
+        if !line.is_empty() {
+            let elapsed = self.since.elapsed().as_secs();
+            line += &format!(" ({}m{:02}s)", elapsed / 60, elapsed % 60);
+        }
+        if let Some(latency) = self.latency {
+            line += &format!(" | ping {} ms", latency.as_millis());
+        }
+        terminal::save_cursor();
+        terminal::move_to_row(0);
+        print!("{}{}{}", style_code("\x1b[1m"), line, reset_style_string());
+        terminal::clear_line();
+        terminal::move_to_row(1);
+        print!("{}", "─".repeat(40));
+        terminal::clear_line();
+        terminal::restore_cursor();
+        std::io::stdout().flush().unwrap_or(());
+    }
+}
+
+// clear the terminal below the status bar
+fn clear_scrolling_region() {
+    terminal::clear();
+    if colors_enabled() {
+        terminal::move_to_row(STATUS_BAR_HEIGHT as u16);
+    }
+}
+
+// remove the escape sequences from a message from the server if colours are disabled
+fn for_display(s: String) -> String {
+    if colors_enabled() {
+        s
+    } else {
+        strip_escape_sequences(&s)
+    }
+}
+
+/// maximum number of server addresses remembered
+const MAX_N_ADDRESSES: usize = 5;
+
+/// name of the file, in the user config directory, storing the last name and addresses used
+const HISTORY_FILE: &str = "client_history.dat";
+
+/// Last player name and server addresses used by the client
+#[derive(Debug, PartialEq, Default)]
+pub struct ClientHistory {
+    pub name: String,
+    pub addresses: Vec<String>
+}
+
+impl ClientHistory {
+
+    /// Parse the history from a string
+    ///
+    /// The first line is the player name and each of the following ones is an address, starting
+    /// with the most recent one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::lib_client::ClientHistory;
+    ///
+    /// let history = ClientHistory::from_string("Alice\nlocalhost:3333\n192.168.1.2:3333\n");
+    ///
+    /// assert_eq!("Alice", history.name);
+    /// assert_eq!(vec!["localhost:3333", "192.168.1.2:3333"], history.addresses);
+    /// assert_eq!("Alice\nlocalhost:3333\n192.168.1.2:3333", history.to_string());
+    /// ```
+    pub fn from_string(s: &str) -> ClientHistory {
+        let mut lines = s.lines().map(str::trim);
+        let name = lines.next().unwrap_or("").to_string();
+        let addresses = lines.filter(|l| !l.is_empty())
+                             .take(MAX_N_ADDRESSES)
+                             .map(String::from)
+                             .collect();
+        ClientHistory { name, addresses }
+    }
+
+    /// Load the history from the user config directory (empty if there is none)
+    pub fn load() -> ClientHistory {
+        user_config_dir()
+            .and_then(|dir| std::fs::read_to_string(dir.join(HISTORY_FILE)).ok())
+            .map(|s| ClientHistory::from_string(&s))
+            .unwrap_or_default()
+    }
+
+    /// Save the history in the user config directory
+    pub fn save(&self) -> Result<(), InvalidInputError> {
+        let dir = user_config_dir().ok_or(InvalidInputError {})?;
+        std::fs::write(dir.join(HISTORY_FILE), self.to_string())?;
+        Ok(())
+    }
+
+    /// Put an address at the top of the list
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::lib_client::ClientHistory;
+    ///
+    /// let mut history = ClientHistory::from_string("Alice\na:1\nb:2\n");
+    /// history.add_address("b:2");
+    ///
+    /// assert_eq!(vec!["b:2", "a:1"], history.addresses);
+    /// ```
+    pub fn add_address(&mut self, address: &str) {
+        self.addresses.retain(|a| a != address);
+        self.addresses.insert(0, address.to_string());
+        self.addresses.truncate(MAX_N_ADDRESSES);
+    }
+}
+
+impl std::fmt::Display for ClientHistory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", &self.name)?;
+        for address in &self.addresses {
+            write!(f, "\n{}", address)?;
+        }
+        Ok(())
+    }
+}
+
+// ask for the port, offering the previous addresses
+fn get_address(previous_addresses: &[String]) -> String {
     println!("Address and port of the server?");
+    if !previous_addresses.is_empty() {
+        println!("(press Enter for {}, or enter the number of a recent address)", &previous_addresses[0]);
+        for (i, address) in previous_addresses.iter().enumerate() {
+            println!("  {}: {}", i+1, address);
+        }
+    }
     loop {
         match get_input() {
-            Ok(s) => return s.trim().to_string(),
+            Ok(s) => {
+                let s = s.trim();
+                if s.is_empty() {
+                    match previous_addresses.first() {
+                        Some(address) => return address.clone(),
+                        None => continue
+                    }
+                }
+                if let Ok(i) = s.parse::<usize>() {
+                    if (i > 0) && (i <= previous_addresses.len()) {
+                        return previous_addresses[i-1].clone();
+                    }
+                }
+                return s.to_string()
+            },
             Err(_) => println!("Could not parse the input")
         };
     }
 }
 
+/// connect to the server, with timeouts on the connection and on all reads and writes
+pub fn connect_to_server(host: &str) -> std::io::Result<TcpStream> {
+    use std::net::ToSocketAddrs;
+    let timeout = std::time::Duration::from_secs(N_SECONDS_STREAM_TIMEOUT);
+    let mut last_error = std::io::Error::new(std::io::ErrorKind::InvalidInput, 
+                                             "could not resolve the address");
+    for address in host.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, 
+                                         std::time::Duration::from_secs(N_SECONDS_CONNECTION_TIMEOUT)) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            },
+            Err(e) => last_error = e
+        }
+    }
+    Err(last_error)
+}
+
 /// try to connect to the server and send the player name
 ///
-/// If the connection is successful, clear the terminal, print the reply from the server, and
-/// return a `TcpStream`. 
+/// The connection is encrypted (see `EncryptedStream`). If it is successful, clear the terminal,
+/// print the reply from the server, and return the stream with the address of the server and the player name (which are needed to
+/// reconnect). 
 /// If not, return a `StreamError`.
-pub fn say_hello(mut name: String) -> Result<TcpStream, StreamError> {
+///
+/// If `spectator` is `true`, the client asks to watch the game without taking a seat; the name
+/// returned then starts with `SPECTATOR_PREFIX`, so that the client reconnects as a spectator.
+///
+/// If `game_code` is given, it is sent before the name; if the server hosts another game, return
+/// a `StreamError` with the reply of the server.
+pub fn say_hello(mut name: String, spectator: bool, game_code: Option<&str>) 
+    -> Result<(EncryptedStream, String, String), StreamError> 
+{
+
+    // name and addresses used previously
+    let mut history = ClientHistory::load();
 
     // host address
     let name_file_port_server = "Config/port_client.dat";
     let host = match std::fs::read_to_string(name_file_port_server) {
         Ok(s) => s.trim().to_string(),
-        Err(_) => get_address()
+        Err(_) => get_address(&history.addresses)
     };
 
-    match TcpStream::connect(&host) {
+    match connect_to_server(&host).and_then(EncryptedStream::connect) {
         Ok(mut stream) => {
             println!("Successfully connected to {}", &host);
+
+            // check that the server hosts the game with this code
+            if let Some(code) = game_code {
+                send_str_to_server(&mut stream, &format!("{}{}", GAME_CODE_PREFIX, code))?;
+                let mut buffer: [u8; 1] = [0];
+                stream.read_exact(&mut buffer)?;
+                let reply = for_display(get_str_from_server(&mut stream)?);
+                if buffer[0] != 1 {
+                    return Err(StreamError::new(reply.trim_end()));
+                }
+                print!("{}", reply);
+            }
             
             loop {
                 
                 if name.is_empty() {
-                    // get the player name
+                    // get the player name, using the previous one by default
                     let mut cont = true;
-                    println!("Player name:");
+                    if history.name.is_empty() {
+                        println!("Player name:");
+                    } else {
+                        println!("Player name (press Enter for {}):", &history.name);
+                    }
                     while cont {
                         match get_input() {
                             Ok(s) => {
                                 name = s.trim().to_string();
-                                cont = false
+                                if name.is_empty() {
+                                    name = history.name.clone();
+                                }
+                                cont = !name.is_empty();
                             },
                             Err(_) => println!("Could not parse the input")
                         };
                     }
                 }
 
-                send_str_to_server(&mut stream, &name)?;
+                if spectator {
+                    send_str_to_server(&mut stream, &format!("{}{}", SPECTATOR_PREFIX, name))?;
+                } else {
+                    send_str_to_server(&mut stream, &name)?;
+                }
                 println!("Sent the name to server; awaiting reply...");
     
                 let mut buffer: [u8; 1] = [0];
                 stream.read_exact(&mut buffer)?;
+                
+                // the server may ask a question first, for instance whether to replace another 
+                // connection with the same name
+                while buffer[0] == 3 {
+                    println!("{}", for_display(get_str_from_server(&mut stream)?));
+                    let mut reply = String::new();
+                    let mut cont = true;
+                    while cont {
+                        match get_input() {
+                            Ok(s) => {
+                                reply = s.trim().to_string();
+                                cont = false;
+                            },
+                            Err(_) => println!("Could not parse the input")
+                        };
+                    }
+                    send_str_to_server(&mut stream, &reply)?;
+                    stream.read_exact(&mut buffer)?;
+                }
+                
                 match buffer[0] {
                     1 => {
+                        
+                        // remember the name and address for the next time
+                        history.name = name.clone();
+                        history.add_address(&host);
+                        if history.save().is_err() {
+                            println!("Could not save the name and address");
+                        }
+
                         match get_str_from_server(&mut stream) {
                             Ok(s) => {
                                 
@@ -69,10 +386,10 @@ pub fn say_hello(mut name: String) -> Result<TcpStream, StreamError> {
                                 reset_style();
 
                                 // clear the terminal
-                                clear_terminal();
+                                clear_scrolling_region();
 
                                 // print the message sent by the server
-                                println!("{}", s);
+                                println!("{}", for_display(s));
                             }
                             Err(e) => {
                                 println!("Failed to receive data: {}", e);
@@ -84,7 +401,7 @@ pub fn say_hello(mut name: String) -> Result<TcpStream, StreamError> {
                         match get_str_from_server(&mut stream) {
                             Ok(s) => { 
                                 // print the message sent by the server
-                                println!("{}", s);
+                                println!("{}", for_display(s));
                             }
                             Err(e) => {
                                 println!("Failed to receive data: {}", e);
@@ -94,102 +411,499 @@ pub fn say_hello(mut name: String) -> Result<TcpStream, StreamError> {
                     },
                     _ => {
                         name.clear();
-                        println!("{}", get_str_from_server(&mut stream)?)
+                        println!("{}", for_display(get_str_from_server(&mut stream)?))
                     }
                 };
             }
-            Ok(stream)
+            if spectator {
+                name = format!("{}{}", SPECTATOR_PREFIX, name);
+            }
+            Ok((stream, host, name))
         }
         Err(e) => { Err(StreamError::from(e)) }
     }
 }
 
+/// try to reconnect to the server after the connection has been lost
+///
+/// The connection is attempted up to `MAX_N_RECONNECTION_ATTEMPTS` times, waiting twice as long
+/// after each failure (starting with `N_SECONDS_FIRST_RECONNECTION_DELAY` seconds). Each attempt
+/// sends the session token if the server has given one, or the player name otherwise, and succeeds
+/// if the server is waiting for this player. The session token also works if the server has been
+/// restarted from its save file.
+pub fn reconnect(host: &str, name: &str, session_token: Option<&str>) 
+    -> Result<EncryptedStream, StreamError> 
+{
+    let hello = match session_token {
+        Some(token) => format!("{}{}", SESSION_TOKEN_PREFIX, token),
+        None => name.to_string()
+    };
+    let mut delay = N_SECONDS_FIRST_RECONNECTION_DELAY;
+    for attempt in 1..=MAX_N_RECONNECTION_ATTEMPTS {
+        println!("Lost connection to the server; trying to reconnect in {} s (attempt {}/{})...", 
+                 delay, attempt, MAX_N_RECONNECTION_ATTEMPTS);
+        std::thread::sleep(std::time::Duration::from_secs(delay));
+        delay = (2 * delay).min(N_SECONDS_MAX_RECONNECTION_DELAY);
+
+        let mut stream = match connect_to_server(host).and_then(EncryptedStream::connect) {
+            Ok(stream) => stream,
+            Err(_) => continue
+        };
+        if send_str_to_server(&mut stream, &hello).is_err() {
+            continue;
+        }
+        let mut buffer: [u8; 1] = [0];
+        if stream.read_exact(&mut buffer).is_err() {
+            continue;
+        }
+        match get_str_from_server(&mut stream) {
+            Ok(s) if buffer[0] == 1 => {
+                print!("{}", for_display(s));
+                println!("Reconnected to the server");
+                return Ok(stream);
+            },
+            Ok(s) => print!("{}", for_display(s)),
+            Err(_) => ()
+        };
+    }
+    Err(StreamError::new("Could not reconnect to the server"))
+}
+
+/// State of the client between two requests from the server
+#[derive(Default)]
+pub struct ClientState {
+    
+    /// status bar at the top of the terminal
+    pub status_bar: StatusBar,
+    
+    /// messages which could not be sent to the server, to be sent again after reconnection if they
+    /// still apply
+    pub pending_messages: std::collections::VecDeque<PendingMove>,
+
+    /// session token given by the server, used to reconnect
+    pub session_token: Option<String>,
+
+    /// last public state of the game sent by the server
+    pub game_state: Option<PublicState>,
+
+    /// number of states received which did not match their hash
+    pub n_desyncs: usize,
+
+    /// identifier of the next move sent to the server (see `net::tag_move`)
+    pub next_move_id: u64,
+
+    /// macro of commands recorded by the player
+    pub command_macro: CommandMacro,
+
+    /// how often the client wants to be sent the situation while it is not its turn
+    pub updates: Updates,
+
+    /// commitment of the server to the deck of the current game (see the `fairness` module)
+    pub deal_commitment: Option<fairness::DealCommitment>
+}
+
+impl ClientState {
+
+    /// Create the state of a newly-connected client
+    pub fn new() -> ClientState {
+        ClientState {
+            status_bar: StatusBar::new(),
+            pending_messages: std::collections::VecDeque::new(),
+            session_token: None,
+            game_state: None,
+            n_desyncs: 0,
+
+            // a random start, so that the first move of a new client is not mistaken for a
+            // duplicate of the last move of a previous one
+            next_move_id: rand::random(),
+
+            command_macro: CommandMacro::new(),
+            updates: Updates::Full,
+            deal_commitment: None
+        }
+    }
+}
+
+/// Move which could not be sent to the server
+#[derive(Debug, Clone)]
+pub struct PendingMove {
+
+    /// move tagged with its identifier (see `net::tag_move`)
+    pub message: String,
+
+    /// public state of the game when the move was typed, if known
+    pub typed_against: Option<PublicState>
+}
+
+/// Check that a move which could not be sent still applies to the current state of the game
+///
+/// The cards and sequences of a move are numbered as they were shown when it was typed. If the
+/// state has changed since, the move is only sent again if the hand of the player has the same
+/// size and the sequences of the table it refers to are the same; a line of several commands
+/// needs the whole table to be the same. Otherwise, the reason why it no longer applies is
+/// returned. Moves are accepted if either state is unknown, as the server checks them anyway.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::compact::PublicState;
+/// use machiavelli::lib_client::check_pending_move;
+///
+/// let mut table = Table::new();
+/// table.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+/// let before = PublicState {
+///     player_names: vec!["Alice".to_string(), "Bob".to_string()],
+///     current_player: 0,
+///     table,
+///     hand_sizes: vec![10, 12],
+///     deck_size: 80
+/// };
+/// let mut now = before.clone();
+/// now.table.add(Sequence::from_cards(&[RegularCard(Heart, 7), RegularCard(Spade, 7), RegularCard(Club, 7)]));
+///
+/// assert!(check_pending_move("p 1 2 3", Some(&before), Some(&now)).is_ok());
+/// assert!(check_pending_move("a 1 4", Some(&before), Some(&now)).is_err());
+/// assert!(check_pending_move("a 1 4", Some(&before), Some(&before)).is_ok());
+/// assert!(check_pending_move("a 1 4", None, Some(&now)).is_ok());
+/// ```
+pub fn check_pending_move(command: &str, typed_against: Option<&PublicState>, current: Option<&PublicState>) 
+    -> Result<(), String> 
+{
+    let (before, now) = match (typed_against, current) {
+        (Some(before), Some(now)) if before != now => (before, now),
+        _ => return Ok(())
+    };
+    let hand_size = |state: &PublicState| state.hand_sizes.get(state.current_player).copied();
+    if before.current_player != now.current_player || hand_size(before) != hand_size(now) {
+        return Err("your hand has changed".to_string());
+    }
+    if command.contains(';') {
+        return if before.table == now.table { Ok(()) } else { Err("the table has changed".to_string()) };
+    }
+    let command = command.trim();
+    let numbers = command.get(1..).unwrap_or("").split_whitespace()
+        .filter_map(|s| s.parse::<usize>().ok());
+    let sequences: Vec<usize> = match command.chars().next() {
+        Some('t') => numbers.collect(),
+        Some('a') | Some('i') => numbers.take(1).collect(),
+        _ => Vec::new()
+    };
+    match sequences.into_iter().find(|&n| before.table.get(n) != now.table.get(n)) {
+        Some(n) => Err(format!("sequence {} has changed", n)),
+        None => Ok(())
+    }
+}
+
+/// What to do with a line typed by the player, once the macro commands have been handled
+#[derive(Debug, PartialEq)]
+pub enum MacroInput {
+
+    /// send this line to the server
+    Send(String),
+
+    /// show this message and read another line
+    Show(String)
+}
+
+/// Macro of commands recorded by the player
+///
+/// The player starts recording with `@r` and stops with `@s`; the commands typed in between are
+/// sent as usual and kept. `@` sends them again as a single line, separated by semicolons, so that
+/// the server undoes all of them if one fails, and `@?` shows them. The macro is kept by the client
+/// only, until it is closed.
+#[derive(Debug, Clone, Default)]
+pub struct CommandMacro {
+    commands: Vec<String>,
+    recording: Option<Vec<String>>
+}
+
+impl CommandMacro {
+
+    /// Create an empty macro
+    pub fn new() -> CommandMacro {
+        CommandMacro::default()
+    }
+
+    /// Handle a line typed by the player
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::lib_client::{ CommandMacro, MacroInput };
+    ///
+    /// let mut command_macro = CommandMacro::new();
+    ///
+    /// assert!(matches!(command_macro.process("@r"), MacroInput::Show(_)));
+    /// assert_eq!(MacroInput::Send("t 3".to_string()), command_macro.process("t 3"));
+    /// assert_eq!(MacroInput::Send("a 2 5".to_string()), command_macro.process(" a 2 5\n"));
+    /// assert!(matches!(command_macro.process("@s"), MacroInput::Show(_)));
+    /// assert_eq!(MacroInput::Send("t 3; a 2 5".to_string()), command_macro.process("@"));
+    /// ```
+    pub fn process(&mut self, line: &str) -> MacroInput {
+        match line.trim() {
+            "@r" => {
+                self.recording = Some(Vec::new());
+                MacroInput::Show("Recording a macro; ‘@s’ to stop.\n".to_string())
+            },
+            "@s" => match self.recording.take() {
+                Some(commands) => {
+                    self.commands = commands;
+                    MacroInput::Show(format!("Macro recorded: {}\n", self.commands.join("; ")))
+                },
+                None => MacroInput::Show("No macro is being recorded.\n".to_string())
+            },
+            "@" | "@?" if self.commands.is_empty() => 
+                MacroInput::Show("No macro has been recorded; ‘@r’ to start recording one.\n".to_string()),
+            "@" => MacroInput::Send(self.commands.join("; ")),
+            "@?" => MacroInput::Show(format!("Macro: {}\n", self.commands.join("; "))),
+            line => {
+                if let Some(commands) = self.recording.as_mut() {
+                    if !line.is_empty() {
+                        commands.push(line.to_string());
+                    }
+                }
+                MacroInput::Send(line.to_string())
+            }
+        }
+    }
+}
+
 /// get a request from te server and act accordingly
 ///
 /// The request is initially encoded in a single byte sent by the server to `stream`. 
-/// Five values are currently supported: 
+/// The following values are currently supported: 
 ///
 /// * 1: print the next message sent by the server
 /// * 2: clear the terminal and print the next message sent by the server
 /// * 3: print the next message sent by the server and send back a message from stdin
 /// * 4: send a message from stdin
 /// * 5: close the client
-pub fn handle_server_request(single_byte_buffer: &mut [u8; 1], stream: &mut TcpStream) -> Result<(), StreamError> {
-    stream.read_exact(single_byte_buffer)?;
+/// * 6: update the status bar with the next message sent by the server
+/// * 7: keep the next message sent by the server as session token
+/// * 8: print the next message sent by the server and close the client
+/// * 9: keep the next message as public state of the game, after checking its hash
+/// * 15: apply the changes in the next message to the public state of the game, and ask for the
+///   whole state if the result does not match its hash
+/// * 10: heartbeat; the next message is the round-trip time measured by the server with the
+///   previous one, in milliseconds (empty if unknown)
+/// * 11: send how often the situation should be sent while it is not the turn of the player
+/// * 12: print the next message sent by the server as an announcement of its administrator
+/// * 13: keep the next message as the commitment of the server to the deck (see the `fairness`
+///   module)
+/// * 14: check the seed and salt revealed in the next message against the commitment
+///
+/// Waiting for the request is not limited in time, as other players may take long to play; once the
+/// request has started, a timeout is treated as a lost connection.
+///
+/// If the message typed by the player can not be sent, it is kept in `state` and sent instead of
+/// a new one the next time the server asks for a message, unless it no longer applies to the state
+/// of the game (see `check_pending_move`).
+pub fn handle_server_request<S: Transport>(single_byte_buffer: &mut [u8; 1], stream: &mut S, 
+                             state: &mut ClientState) -> Result<(), StreamError> {
+    let status_bar = &mut state.status_bar;
+    loop {
+        match stream.read_exact(single_byte_buffer) {
+            Ok(()) => break,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => return Err(StreamError::from(e))
+        }
+    }
     match single_byte_buffer[0] {
         
         // value 1: print the message from the server
         1 => print_str_from_server(stream)?,
         
         // value 2: clear the terminal and print the message from the server
-        2 => clear_and_print_str_from_server(stream)?,
+        2 => clear_and_print_str_from_server(stream, status_bar)?,
         
         // value 3: print the message and return a reply in bytes
-        3 => print_and_reply(stream)?,
+        3 => print_and_reply(stream, &mut state.pending_messages, state.game_state.as_ref(), 
+                             &mut state.next_move_id)?,
         
         // value 4: send a message
-        4 => send_message(stream)?,
+        4 => send_message(stream, &mut state.pending_messages, state.game_state.as_ref(), 
+                          &mut state.next_move_id, Some(&mut state.command_macro))?,
         
         // value 5: exit
         5 => {
-            print!("\x1b[0m\x1b[?25h"); // reset the style and show the cursor
-            print!("\x1b[2J\x1b[1;1H"); // clear the screen
-            print!("\x1b[K"); // redraw the screen
-            std::process::exit(0)
+            terminal::clear();
+            terminal::exit(0)
+        },
+
+        // value 6: update the status bar
+        6 => status_bar.set(get_str_from_server(stream)?),
+
+        // value 7: keep the session token
+        7 => state.session_token = Some(get_str_from_server(stream)?),
+
+        // value 8: print the message from the server and exit
+        8 => {
+            let message = get_str_from_server(stream)?;
+            terminal::clear();
+            println!("{}", for_display(message));
+            terminal::exit(0)
+        },
+
+        // value 9: public state of the game
+        9 => {
+            let game_state = PublicState::from_sync_message(&get_bytes_from_server_no_confirmation(stream)?);
+            sync_game_state(stream, state, game_state)?;
+        },
+
+        // value 15: changes to the public state of the game
+        15 => {
+            let update = get_bytes_from_server_no_confirmation(stream)?;
+            let game_state = state.game_state.as_ref().ok_or(LoadingError::Invalid)
+                .and_then(|game_state| game_state.apply_update_message(&update));
+            sync_game_state(stream, state, game_state)?;
+        },
+
+        // value 10: heartbeat
+        10 => {
+            let latency = get_str_from_server(stream)?.parse::<u64>().ok();
+            status_bar.set_latency(latency.map(std::time::Duration::from_millis));
+        },
+
+        // value 11: update granularity
+        11 => send_str_to_server(stream, &state.updates.to_string())?,
+
+        // value 12: announcement
+        12 => print!("{}", render::announcement(&get_str_from_server(stream)?)),
+
+        // value 13: commitment to the deck
+        13 => state.deal_commitment = fairness::DealCommitment::parse(&get_str_from_server(stream)?),
+
+        // value 14: secret of the commitment
+        14 => {
+            let secret = fairness::DealSecret::parse(&get_str_from_server(stream)?);
+            if let Some(commitment) = state.deal_commitment.take() {
+                print!("{}", for_display(verify_deal(&commitment, secret.as_ref())));
+            }
         },
 
         _ => ()
     };
+    state.status_bar.draw();
+    Ok(())
+}
+
+// keep the public state of the game received from the server, and confirm its reception; if it
+// does not match its hash, ask for the whole state instead
+fn sync_game_state<S: Transport>(stream: &mut S, state: &mut ClientState, 
+                                 game_state: Result<PublicState, LoadingError>) -> Result<(), StreamError> {
+    match game_state {
+        Ok(game_state) => {
+            state.game_state = Some(game_state);
+            stream.write_all(&[0])?;
+        },
+        Err(_) => {
+            state.n_desyncs += 1;
+            state.game_state = None;
+            stream.write_all(&[RESYNC_REQUEST])?;
+        }
+    }
     Ok(())
 }
 
-fn clear_and_print_str_from_server(stream:  &mut TcpStream) -> Result<(), StreamError> {
-    clear_terminal();
-    println!("{}", get_str_from_server(stream)?);
+// result of the verification of the deal, to be shown to the player
+fn verify_deal(commitment: &fairness::DealCommitment, secret: Option<&fairness::DealSecret>) -> String {
+    match secret.and_then(|secret| commitment.verify(secret)) {
+        Some(hand) => format!("\nThe deck matches the commitment made by the server when the cards were dealt; \
+                               your hand should have been:\n{}{}\n", 
+                              render::sequence(&hand), reset_style_string()),
+        None => format!("\n{}\n", bold("Warning: the deck revealed by the server does not match the \
+                                         commitment made when the cards were dealt!"))
+    }
+}
+
+fn clear_and_print_str_from_server<S: Transport>(stream: &mut S, status_bar: &StatusBar) 
+    -> Result<(), StreamError> 
+{
+    status_bar.clear();
+    println!("{}", for_display(get_str_from_server(stream)?));
     Ok(())
 }
 
-fn print_str_from_server(stream:  &mut TcpStream) -> Result<(), StreamError> {
-    print!("{}", get_str_from_server(stream)?);
+fn print_str_from_server<S: Transport>(stream: &mut S) -> Result<(), StreamError> {
+    print!("{}", for_display(get_str_from_server(stream)?));
     Ok(())
 }
 
-fn print_and_reply(stream:  &mut TcpStream) -> Result<(), StreamError> {
-    println!("{}", get_str_from_server(stream)?);
-    send_message(stream)
+fn print_and_reply<S: Transport>(stream: &mut S, 
+                                 pending_messages: &mut std::collections::VecDeque<PendingMove>,
+                                 game_state: Option<&PublicState>, next_move_id: &mut u64) 
+    -> Result<(), StreamError> 
+{
+    println!("{}", for_display(get_str_from_server(stream)?));
+    send_message(stream, pending_messages, game_state, next_move_id, None)
 }
 
-fn send_message(stream:  &mut TcpStream) -> Result<(), StreamError> {
+// send a line typed by the player, after handling the macro commands if `command_macro` is given
+fn send_message<S: Transport>(stream: &mut S, pending_messages: &mut std::collections::VecDeque<PendingMove>,
+                              game_state: Option<&PublicState>, next_move_id: &mut u64, 
+                              mut command_macro: Option<&mut CommandMacro>) 
+    -> Result<(), StreamError> 
+{
+    // send the message which could not be sent before, if any, with the same identifier so that
+    // the server ignores it if it has received it already; it is dropped if it no longer applies
+    while let Some(pending) = pending_messages.pop_front() {
+        let command = String::from_utf8_lossy(split_move_id(pending.message.as_bytes()).1).to_string();
+        if let Err(reason) = check_pending_move(&command, pending.typed_against.as_ref(), game_state) {
+            println!("Not sending again ‘{}’: {} since it was typed.", command, reason);
+            continue;
+        }
+        println!("Sending again: {}", command);
+        if let Err(e) = send_str_to_server(stream, &pending.message) {
+            pending_messages.push_front(pending);
+            return Err(e);
+        }
+        return Ok(());
+    }
+
     let mut reply = String::new();
     let mut cont = true;
     while cont {
         match get_input() {
             Ok(s) => {
-                reply = s.trim().to_string();
-                cont = false
+                let input = match command_macro.as_mut() {
+                    Some(command_macro) => command_macro.process(&s),
+                    None => MacroInput::Send(s.trim().to_string())
+                };
+                match input {
+                    MacroInput::Send(s) => {
+                        reply = s;
+                        cont = false
+                    },
+                    MacroInput::Show(message) => print!("{}", message)
+                }
             },
             Err(_) => println!("Could not parse the input")
         };
     }
-    send_str_to_server(stream, &reply)?;
+    let reply = tag_move(*next_move_id, &reply);
+    *next_move_id = next_move_id.wrapping_add(1);
+    
+    // if the message can not be sent, keep it for later
+    if let Err(e) = send_str_to_server(stream, &reply) {
+        pending_messages.push_back(PendingMove { message: reply, typed_against: game_state.cloned() });
+        return Err(e);
+    }
     Ok(())
 }
 
 /// convert a string to a sequence of bytes and send it to the server
-pub fn send_str_to_server(stream: &mut TcpStream, s: &str) -> Result<(), StreamError> {
+pub fn send_str_to_server<S: Transport>(stream: &mut S, s: &str) -> Result<(), StreamError> {
     send_bytes_to_server(stream, s.as_bytes())?;
     Ok(())
 }
 
 /// send a sequence of bytes to the server and wait for confirmation that it has been received
-pub fn send_bytes_to_server(stream: &mut TcpStream, bytes: &[u8]) -> Result<(), StreamError> {
+pub fn send_bytes_to_server<S: Transport>(stream: &mut S, bytes: &[u8]) -> Result<(), StreamError> {
     
     // ensure that the number of bytes is small enough
     if bytes.len() > MAX_N_BUFFERS * BUFFER_SIZE {
-        return Err(StreamError { message: format!(
-                    "Stream too long: size: {}, maximum size: {}",
-                    bytes.len(), MAX_N_BUFFERS*BUFFER_SIZE
-                   ) })
+        return Err(StreamError::TooLong { size: bytes.len(), max_size: MAX_N_BUFFERS*BUFFER_SIZE })
     }
 
     // the first bytes will determine the number of times the buffer should be read
@@ -208,22 +922,30 @@ pub fn send_bytes_to_server(stream: &mut TcpStream, bytes: &[u8]) -> Result<(),
     }
 
     // wait for a reply to be sent from the receiver
-    while stream.read_exact(&mut [0]).is_err() {}
+    stream.read_exact(&mut [0])?;
     
     Ok(())
 }
 
 /// get a sequence of bytes from the server and convert it to a string
-pub fn get_str_from_server(stream: &mut TcpStream) -> Result<String, StreamError> {
+pub fn get_str_from_server<S: Transport>(stream: &mut S) -> Result<String, StreamError> {
     let bytes = get_bytes_from_server(stream)?;
-    match String::from_utf8(bytes) {
-        Ok(s) => Ok(s),
-        Err(_) => Err(StreamError::from(BytesToStringError {}))
-    }
+    Ok(String::from_utf8(bytes)?)
 }
 
 /// get a sequence of bytes from the server
-pub fn get_bytes_from_server(stream: &mut TcpStream) -> Result<Vec<u8>, StreamError> {
+pub fn get_bytes_from_server<S: Transport>(stream: &mut S) -> Result<Vec<u8>, StreamError> {
+    let res = get_bytes_from_server_no_confirmation(stream)?;
+   
+    // send something to confirm I have received the data
+    stream.write_all(&[0])?;
+
+    // return the result
+    Ok(res)
+}
+
+// get a message (bytes) from the server without confirming its reception
+fn get_bytes_from_server_no_confirmation<S: Transport>(stream: &mut S) -> Result<Vec<u8>, StreamError> {
     
     // buffer
     let mut buffer: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
@@ -241,11 +963,6 @@ pub fn get_bytes_from_server(stream: &mut TcpStream) -> Result<Vec<u8>, StreamEr
         size = stream.read(&mut buffer)?;
         res.extend_from_slice(&buffer[..size]);
     }
-   
-    // send something to confirm I have received the data
-    stream.write_all(&[0])?;
-
-    // return the result
     Ok(res)
 }
 
@@ -253,34 +970,3 @@ pub fn get_bytes_from_server(stream: &mut TcpStream) -> Result<Vec<u8>, StreamEr
 pub fn wait() {
     std::thread::sleep(std::time::Duration::from_millis(N_MILLISECONDS_WAIT));
 }
-
-
-// errors
-
-/// generic error raised when reading from or writing to a stream fails
-#[derive(Debug)]
-pub struct StreamError {
-    message: String
-}
-
-/// generic error raised when conversion from a sequence of bytes to a string fails
-#[derive(Debug)]
-pub struct BytesToStringError {}
-
-impl std::fmt::Display for StreamError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "StreamError: {}", self.message)
-    }
-}
-
-impl std::convert::From<std::io::Error> for StreamError {
-    fn from(error: std::io::Error) -> Self {
-        StreamError { message: format!("IO Error: {}", error) }
-    }
-}
-
-impl std::convert::From<BytesToStringError> for StreamError {
-    fn from(_error: BytesToStringError) -> Self {
-        StreamError { message: "Could not convert the byte sequence to a string".to_string() }
-    }
-}
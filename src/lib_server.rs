@@ -5,14 +5,36 @@ pub use std::io::{ stdin, Read, Write };
 pub use std::net::{ TcpListener, TcpStream, Shutdown };
 pub use std::str::from_utf8;
 pub use std::sync::{ Arc, Mutex };
-use std::string::FromUtf8Error;
+use std::sync::atomic::{ AtomicU8, Ordering };
+use std::collections::VecDeque;
+pub use crate::net::connection::{ PlayerConnection, DeliveryState, client_label };
+pub use crate::rules::{ RuleSet, Speed };
+pub use crate::hooks::RuleHooks;
+pub use crate::compact::PublicState;
+pub use crate::filter::{ WordFilter, Filtered };
+pub use crate::history::TableHistory;
+use crate::game::{ GameState, Event, RuleError };
+use crate::tournament::Move;
+pub use crate::evaluation::attachable_cards;
+pub use crate::net::{ StreamError, Transport, EncryptedStream, is_timeout, 
+                      SESSION_TOKEN_PREFIX, SPECTATOR_PREFIX, GAME_CODE_PREFIX, Updates, RESYNC_REQUEST };
 
 const BUFFER_SIZE: usize = 50;
 const MAX_N_BUFFERS: usize = 255;
-const N_MILLISECONDS_WAIT: u64 = 10;
-const N_MILLISECONDS_LONG_WAIT: u64 = 1000;
+const N_MILLISECONDS_ACKNOWLEDGEMENT_TIMEOUT: u64 = 5000;
+const N_SECONDS_HANDSHAKE_TIMEOUT: u64 = 300;
+const N_SECONDS_ANSWER_TIMEOUT: u64 = 60;
 const YES_VALUES: [&str;10] = ["y", "yes", "yeah", "aye", "oui", "ja", "da", "ok", "si", "sim"];
 const NO_VALUES: [&str;8] = ["n", "no", "nah", "nay", "non", "nein", "niet", "nope"];
+const N_MESSAGES_LOG_DEFAULT: usize = 10;
+const LOG_INSTRUCTION: &str = "l n: Show the last n messages (10 by default)\n";
+const INSPECT_INSTRUCTION: &str = "i x: Show sequence x on the table, its history, and the cards you could add to it\n";
+const TRY_INSTRUCTION: &str = "try: Try moves which can be put back without penalty (then ‘commit’ or ‘discard’)\n";
+const UNDO_INSTRUCTION: &str = "u: Undo your last move without penalty (U to redo it)\n";
+const HINT_INSTRUCTION: &str = "h: Suggest a move\n";
+const NOTE_INSTRUCTION: &str = "note text: Keep a note on screen until the end of your turn (‘note’ alone clears it)\n";
+const MAX_NOTE_LENGTH: usize = 80;
+const MAX_N_PASSWORD_ATTEMPTS: usize = 3;
 
 /// check if a string is a synonym of ‘yes’
 ///
@@ -60,97 +82,439 @@ pub fn is_no(s: &str) -> bool {
     false
 }
 
-/// get the player name
-pub fn handle_client(mut stream: TcpStream) -> Result<(TcpStream, String, usize), StreamError> {
-    let mut player_name: String = "".to_string();
-    match get_str_from_client(&mut stream) {
-        Ok(s) => {
-            // great the player
-            player_name = s.clone();
-            let msg = format!("Hello {}!\nWaiting for other players to join...", &s);
-            stream.write_all(&[1])?;
-            send_str_to_client(&mut stream, &msg)?;
+/// set the read and write timeouts used before the game starts
+///
+/// They leave the player enough time to type their name, but ensure that a client which does not
+/// answer at all does not prevent the game from starting.
+pub fn set_handshake_timeouts(stream: &TcpStream) -> Result<(), StreamError> {
+    let timeout = Some(std::time::Duration::from_secs(N_SECONDS_HANDSHAKE_TIMEOUT));
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    Ok(())
+}
+
+/// password needed to join the game (none if empty)
+static JOIN_PASSWORD: Mutex<String> = Mutex::new(String::new());
+
+/// set the password needed to join the game (an empty one for a public game)
+pub fn set_join_password(password: &str) {
+    *JOIN_PASSWORD.lock().unwrap() = password.to_string();
+}
+
+/// ask the client for the password of the game, if there is one, and check it
+///
+/// Nothing about the game is sent before the password is right.
+fn check_password<S: Transport>(stream: &mut S) -> Result<bool, StreamError> {
+    let password = JOIN_PASSWORD.lock().unwrap().clone();
+    if password.is_empty() {
+        return Ok(true);
+    }
+    stream.write_all(&[3])?;
+    send_str_to_client(stream, "This game is private. Password:\n")?;
+    Ok(get_str_from_client(stream)? == password)
+}
+
+// tell the client that the password is wrong, and return an error after too many attempts
+fn reject_password<S: Transport>(stream: &mut S, n_attempts: &mut usize) -> Result<(), StreamError> {
+    *n_attempts += 1;
+    stream.write_all(&[0])?;
+    send_str_to_client(stream, "Wrong password!\n")?;
+    if *n_attempts >= MAX_N_PASSWORD_ATTEMPTS {
+        return Err(StreamError::new("too many wrong passwords"));
+    }
+    Ok(())
+}
+
+/// code of the game hosted by the server (see `new_game_code`)
+static GAME_CODE: Mutex<String> = Mutex::new(String::new());
+
+/// set the code of the game, which the clients may give to check that they join the right one
+pub fn set_game_code(code: &str) {
+    *GAME_CODE.lock().unwrap() = code.to_string();
+}
+
+/// get the next message of the client, checking the game code first if the client sends one
+///
+/// A client joining with a code (see `GAME_CODE_PREFIX`) sends it before its name; it is told
+/// whether the code matches the game hosted by the server, case ignored, and turned away if not.
+fn get_hello_from_client<S: Transport>(stream: &mut S) -> Result<String, StreamError> {
+    let s = get_str_from_client(stream)?;
+    let code = match s.strip_prefix(GAME_CODE_PREFIX) {
+        Some(code) => code.trim(),
+        None => return Ok(s)
+    };
+    let game_code = GAME_CODE.lock().unwrap().clone();
+    if !code.eq_ignore_ascii_case(&game_code) {
+        stream.write_all(&[0])?;
+        send_str_to_client(stream, &format!("This server hosts another game than {}.\n", code))?;
+        return Err(StreamError::new("wrong game code"));
+    }
+    stream.write_all(&[1])?;
+    send_str_to_client(stream, &format!("Joining game {}\n", game_code))?;
+    get_str_from_client(stream)
+}
+
+/// filter applied to the player names (none by default)
+static NAME_FILTER: Mutex<Option<WordFilter>> = Mutex::new(None);
+
+/// set the filter applied to the names of the players when they join
+pub fn set_name_filter(filter: WordFilter) {
+    *NAME_FILTER.lock().unwrap() = Some(filter);
+}
+
+/// apply the filter set by `set_name_filter` to a player name
+///
+/// Return the name to use, or `None` if it is rejected; a warning is printed on the server for
+/// the names which are accepted with a forbidden word.
+fn filter_name(name: &str) -> Option<String> {
+    let filtered = match NAME_FILTER.lock().unwrap().as_ref() {
+        Some(filter) => filter.apply(name),
+        None => return Some(name.to_string())
+    };
+    match filtered {
+        Filtered::Clean(name) | Filtered::Masked(name) => Some(name),
+        Filtered::Warned(name, word) => {
+            println!("Warning: the name {} contains ‘{}’", name, word);
+            Some(name)
         },
-        Err(_)=> {
-            println!("An error occured while reading the stream; terminating connection with {}", 
-                     stream.peer_addr()?);
-            stream.shutdown(Shutdown::Both)?;
+        Filtered::Rejected(_) => None
+    }
+}
+
+/// set up the encryption of the connection (see `EncryptedStream`) and get the player name
+///
+/// The name goes through the filter set by `set_name_filter`, if any. If the game is private (see `set_join_password`), the client must give the password, unless it
+/// sends one of the session tokens `session_tokens` instead of a name. If the client does not 
+/// answer, or gives too many wrong passwords, return an error; the connection is closed when the 
+/// stream is dropped.
+///
+/// A client sending its name after `SPECTATOR_PREFIX` asks to watch the game as a spectator; the
+/// name returned then keeps the prefix. A client may also send the code of the game it wants to
+/// join before its name; it is turned away if the code is not the one set by `set_game_code`.
+pub fn handle_client(stream: TcpStream, session_tokens: &[String]) 
+    -> Result<(EncryptedStream, String, usize), StreamError> 
+{
+    set_handshake_timeouts(&stream)?;
+    let mut stream = EncryptedStream::accept(stream)?;
+    let mut n_attempts = 0;
+    let player_name = loop {
+        let s = get_hello_from_client(&mut stream)?;
+        let has_token = s.strip_prefix(SESSION_TOKEN_PREFIX)
+            .map(|token| session_tokens.iter().any(|x| x == token))
+            .unwrap_or(false);
+        if !has_token && !check_password(&mut stream)? {
+            reject_password(&mut stream, &mut n_attempts)?;
+            continue;
+        }
+        if has_token {
+            break s;
+        }
+        match filter_client_name(&s) {
+            Some(name) => break name,
+            None => {
+                stream.write_all(&[0])?;
+                send_str_to_client(&mut stream, "This name is not allowed; please choose another one.\n")?;
+            }
         }
     };
+    
+    // great the player
+    stream.write_all(&[1])?;
+    send_str_to_client(&mut stream, &greeting(&player_name))?;
     Ok((stream, player_name, 0))
 }
 
-/// get the player name and check that it is in the list of players and not already taken
-pub fn handle_client_load(mut stream: TcpStream, names: &[String], names_taken: Arc<Mutex<Vec<String>>>) 
-    -> Result<(TcpStream, String, usize), StreamError> 
+// apply the name filter to the name sent by a client, keeping the prefix of the spectators
+fn filter_client_name(s: &str) -> Option<String> {
+    match s.strip_prefix(SPECTATOR_PREFIX) {
+        Some(name) => filter_name(name).map(|name| format!("{}{}", SPECTATOR_PREFIX, name)),
+        None => filter_name(s)
+    }
+}
+
+// message sent to a client once it has given its name
+fn greeting(name: &str) -> String {
+    match name.strip_prefix(SPECTATOR_PREFIX) {
+        Some(name) => format!("Hello {}!\nYou are watching the game as a spectator.\n", name),
+        None => format!("Hello {}!\nWaiting for other players to join...", name)
+    }
+}
+
+/// set up the encryption of the connection and get the player name, checking that it is in the 
+/// list of players
+///
+/// Instead of their name, the players may send the session token they were given before the server
+/// was restarted (see `send_session_token`).
+/// In a private game, the players sending their name instead must also give the password (see
+/// `set_join_password`).
+///
+/// If the name is already used by another connection, only a client sending the session token of
+/// that seat may replace it, so that nobody else can take the seat of a disconnected player; a
+/// player who has lost their token takes the seat back once the game has started (see
+/// `wait_for_reconnection`). The caller is responsible for closing the replaced connection.
+///
+/// Spectators (see `handle_client`) are greeted without checking the list; the name returned keeps
+/// their prefix.
+pub fn handle_client_load(stream: TcpStream, names: &[String], session_tokens: &[String], 
+                          names_taken: Arc<Mutex<Vec<String>>>) 
+    -> Result<(EncryptedStream, String, usize), StreamError> 
 {
-    let mut player_name: String;
-    let position: usize;
+    set_handshake_timeouts(&stream)?;
+    let mut stream = EncryptedStream::accept(stream)?;
+    let mut n_attempts = 0;
     loop {
-        match get_str_from_client(&mut stream) {
-            Ok(s) => {
-                player_name = s.clone();
-                
-                // check if the name is in the list
-                match names.iter().position(|x| x == &player_name) {
-                    Some(i) => {
-                        // check if it is not already taken
-                        let mut lock = names_taken.lock().unwrap();
-                        match lock.iter().position(|x| x == &player_name) {
-                            Some(_) => {
-                                stream.write_all(&[0])?;
-                                let msg = "Sorry, this name is already taken!\n".to_string();
-                                send_str_to_client(&mut stream, &msg)?;
-                            },
-                            None => {
-                                position = i;
-                                stream.write_all(&[1])?;
-                                let msg = format!("Hello {}!\nWaiting for other players to join...", &s);
-                                send_str_to_client(&mut stream, &msg)?;
-                                lock.push(player_name.clone());
-                                break;
-                            }
-                        }
-                    },
-                    None => {
-                        stream.write_all(&[0])?;
-                        let msg = format!("Sorry, {} is not in the list of players!\n", &s);
-                        send_str_to_client(&mut stream, &msg)?;
-                    }
+        let s = get_hello_from_client(&mut stream)?;
+        let token_position = s.strip_prefix(SESSION_TOKEN_PREFIX)
+            .and_then(|token| session_tokens.iter().position(|x| x == token));
+        if token_position.is_none() && !check_password(&mut stream)? {
+            reject_password(&mut stream, &mut n_attempts)?;
+            continue;
+        }
+
+        // spectators do not take a seat
+        if s.starts_with(SPECTATOR_PREFIX) {
+            match filter_client_name(&s) {
+                Some(name) => {
+                    stream.write_all(&[1])?;
+                    send_str_to_client(&mut stream, &greeting(&name))?;
+                    return Ok((stream, name, 0));
+                },
+                None => {
+                    stream.write_all(&[0])?;
+                    send_str_to_client(&mut stream, "This name is not allowed; please choose another one.\n")?;
+                    continue;
                 }
+            }
+        }
 
-            },
-            Err(_)=> {
-                println!("An error occured while reading the stream; terminating connection with {}", 
-                         stream.peer_addr()?);
-                stream.shutdown(Shutdown::Both)?;
+        let player_name = match token_position {
+            Some(i) => names[i].clone(),
+            None => s.clone()
+        };
+                
+        // check if the name is in the list
+        let position = match names.iter().position(|x| x == &player_name) {
+            Some(i) => i,
+            None => {
+                stream.write_all(&[0])?;
+                let msg = format!("Sorry, {} is not in the list of players!\n", &s);
+                send_str_to_client(&mut stream, &msg)?;
+                continue;
+            }
+        };
+
+        // reserve the name if it is not already taken; the lock is released before communicating 
+        // with the client, so that a slow client does not block the others
+        let already_taken = {
+            let mut lock = names_taken.lock().unwrap();
+            let taken = lock.contains(&player_name);
+            if !taken {
+                lock.push(player_name.clone());
             }
+            taken
         };
+
+        // if the name is taken, only the session token of the seat can replace the other
+        // connection
+        if already_taken && token_position.is_none() {
+            stream.write_all(&[0])?;
+            let msg = format!("{} is already connected; only their session token can replace that connection. \
+                               Please choose another name.\n", &player_name);
+            send_str_to_client(&mut stream, &msg)?;
+            continue;
+        }
+
+        // great the player; if it fails, the name is available again
+        let greeted = stream.write_all(&[1]).map_err(StreamError::from)
+            .and_then(|_| send_str_to_client(&mut stream, &greeting(&player_name)));
+        if let Err(e) = greeted {
+            if !already_taken {
+                names_taken.lock().unwrap().retain(|x| x != &player_name);
+            }
+            return Err(e);
+        }
+        return Ok((stream, player_name, position));
     }
-    Ok((stream, player_name, position))
 }
 
-/// wait for a player to reconnect
-pub fn wait_for_reconnection(stream: &mut TcpStream, name: &str, port: usize) 
-    -> Result<(), StreamError>
+/// add a client greeted as a spectator by `handle_client` or `handle_client_load`
+pub fn add_spectator(spectators: &mut Vec<PlayerConnection>, stream: EncryptedStream, name: &str) {
+    let name = name.trim_start_matches(SPECTATOR_PREFIX);
+    match PlayerConnection::spectator(stream, name) {
+        Ok(connection) => {
+            println!("{} is watching the game", name);
+            spectators.push(connection);
+        },
+        Err(e) => println!("Could not add the spectator: {}", e)
+    }
+}
+
+/// offer the seat of a player who has left the game to the spectators, all at once
+///
+/// The first spectator, in the order they arrived, who accepts is removed from `spectators` and
+/// returned, with their name, as the connection of player `seat`; the spectators who can not be 
+/// reached or do not answer in time count as refusing.
+pub fn offer_seat(spectators: &mut Vec<PlayerConnection>, seat: usize, player_name: &str) 
+    -> Option<(PlayerConnection, String)> 
 {
+    spectators.retain(|s| s.is_connected());
+    let question = format!("{} has left the game. Take their seat? (‘y’ for yes)\n", player_name);
+    let asked: Vec<usize> = (0..spectators.len()).collect();
+    let answers = ask_all(spectators, &asked, &question);
+    let position = answers.iter().position(|&yes| yes)?;
+    let mut connection = spectators.remove(position);
+    connection.take_seat(seat);
+    let name = connection.name().to_string();
+
+    // the other spectators who have accepted are told that the seat is taken
+    for (i, spectator) in spectators.iter_mut().enumerate() {
+        if answers[if i < position { i } else { i + 1 }] {
+            send_message_all_players(std::slice::from_mut(spectator), 
+                                     &format!("{} has been quicker to take the seat.\n", &name));
+        }
+    }
+    Some((connection, name))
+}
 
-    // wait for a connection
+// ask a yes-or-no question to the clients with the given indices at once, and return their 
+// answers in the same order; the clients who can not be reached, or do not answer within 
+// `N_SECONDS_ANSWER_TIMEOUT` seconds, answer no
+fn ask_all(streams: &mut [PlayerConnection], asked: &[usize], question: &str) -> Vec<bool> {
+    let questions: Vec<(usize, String)> = asked.iter().map(|&i| (i, question.to_string())).collect();
+    ask_each(streams, &questions)
+}
+
+// ask each client with the given index its own yes-or-no question, all at once, and return their
+// answers in the same order (see `ask_all`)
+fn ask_each(streams: &mut [PlayerConnection], questions: &[(usize, String)]) -> Vec<bool> {
+    for (i, question) in questions {
+        if streams[*i].is_connected() {
+            streams[*i].write_all(&[3]).unwrap_or(());
+            send_bytes_to_client_no_wait(&mut streams[*i], question.as_bytes()).unwrap_or(());
+        }
+    }
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(N_SECONDS_ANSWER_TIMEOUT);
+    let remaining = || deadline.saturating_duration_since(std::time::Instant::now());
+    questions.iter().map(|&(i, _)| {
+        let stream = &mut streams[i];
+        let answered = stream.wait_for_acknowledgement(remaining()) == DeliveryState::Delivered
+            && stream.wait_for_data(remaining());
+        if !answered {
+            if stream.is_connected() {
+                println!("{} has not answered in time", client_label(stream.player(), stream.is_spectator()));
+                send_message_all_players(std::slice::from_mut(stream), 
+                                         "You have not answered in time, which counts as a no.\n");
+            }
+            return false;
+        }
+        match get_bytes_from_client(stream).map(|reply| stream.accept_move(&reply)) {
+            Ok(Some(reply)) => is_yes(&String::from_utf8_lossy(&reply)),
+            _ => false
+        }
+    }).collect()
+}
 
-    // set-up the tcp listener
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
+/// send each player the commitment to the deck their hand has been dealt from (see the `fairness`
+/// module)
+///
+/// The players who have forfeited, and were not dealt a hand, are sent an empty commitment.
+pub fn send_deal_commitments(client_streams: &mut [PlayerConnection], secret: &fairness::DealSecret,
+                             config: &Config, forfeited: &[bool]) -> Vec<DeliveryState> {
+    let n_dealt = forfeited.iter().filter(|&&f| !f).count();
+    let messages: Vec<Vec<u8>> = (0..client_streams.len())
+        .map(|i| if forfeited[i] {
+            Vec::new()
+        } else {
+            let position = forfeited[..i].iter().filter(|&&f| !f).count();
+            secret.commitment(config.n_decks, config.n_jokers, config.n_cards_to_start as usize, 
+                              n_dealt, position).to_string().into_bytes()
+        })
+        .collect();
+    broadcast_each(client_streams, 13, &messages)
+}
+
+/// reveal the seed of the deck and the salt of the commitments at the end of the game, so that the
+/// clients can check the deal
+pub fn reveal_deal_secret(client_streams: &mut [PlayerConnection], secret: &fairness::DealSecret) 
+    -> Vec<DeliveryState> 
+{
+    broadcast(client_streams, 14, secret.to_string().as_bytes())
+}
+
+/// send the situation at the start of a turn to the spectators: the table, the number of cards of
+/// each player, and the turn order, but no hand
+///
+/// The spectators who have disconnected are removed first; those who can not see the number of
+/// cards of the players (see `render::set_visibility`) are shown `?` instead.
+#[allow(clippy::too_many_arguments)]
+pub fn send_spectator_view(spectators: &mut Vec<PlayerConnection>, table: &Table, deck: &Sequence,
+                           player_names: &[String], hand_sizes: &[usize], notes: &[&str], 
+                           forfeited: &[bool], current_player: usize, turn_order: &str) {
+    spectators.retain(|s| s.is_connected());
+    if spectators.is_empty() {
+        return;
+    }
+    let card_counts = render::card_counts(player_names, hand_sizes, notes, forfeited, usize::MAX, 
+                                          deck.number_cards());
+    broadcast(spectators, 6, status_string(&player_names[current_player], deck).as_bytes());
+    broadcast(spectators, 2, format!("{}{}", bold(&format!("{}'s turn:", &player_names[current_player])), 
+                                     &reset_style_string()).as_bytes());
+    send_message_all_players(spectators, &format!("{}\n{}\n\nTable:\n{}", card_counts, turn_order, 
+                                                  render::table(table)));
+}
+
+/// tell a client that there is no seat left for them and close it
+pub fn turn_away_client<S: Transport>(stream: &mut S, n_players: u8) -> Result<(), StreamError> {
+    send_message_and_close_client(stream, &format!(
+        "Sorry, the game is full ({} players) and has started without you. Please try again later!\n", 
+        n_players))
+}
+
+/// send a message to a client and close it
+pub fn send_message_and_close_client<S: Transport>(stream: &mut S, msg: &str) -> Result<(), StreamError> {
+    stream.write_all(&[8])?;
+    send_str_to_client(stream, msg)
+}
+
+/// wait for a player to reconnect
+///
+/// The connections are taken from the listener used for the whole game, which must be in 
+/// non-blocking mode.
+pub fn wait_for_reconnection(stream: &mut PlayerConnection, name: &str, listener: &TcpListener) 
+    -> Result<(), StreamError>
+{
 
     // get connections and check the player is the right one
-    for mut new_stream in listener.incoming().flatten() {
-        println!("New connection: {}", new_stream.peer_addr()?);
+    loop {
+        let new_stream = match listener.accept() {
+            Ok((new_stream, address)) => {
+                println!("New connection: {}", address);
+                new_stream
+            },
+            Err(e) if is_timeout(&e) => {
+                wait();
+                continue;
+            },
+            Err(e) => return Err(StreamError::from(e))
+        };
+        if new_stream.set_nonblocking(false).is_err() || set_handshake_timeouts(&new_stream).is_err() {
+            continue;
+        }
+        let mut new_stream = match EncryptedStream::accept(new_stream) {
+            Ok(new_stream) => new_stream,
+            Err(_) => continue
+        };
 
-        // get the name 
+        // get the name or session token
         if let Ok(s) = get_str_from_client(&mut new_stream) {
-            if s == name {
+            let is_player = stream.matches_session_token(&s) 
+                || (s == name && check_password(&mut new_stream).unwrap_or(false));
+            if is_player {
                 new_stream.write_all(&[1]).unwrap_or(());
                 send_str_to_client(&mut new_stream, 
                         &reset_style_string()).unwrap_or(());
-                *stream = new_stream;
+                stream.replace_stream(new_stream)?;
+                negotiate_updates(stream).unwrap_or(());
                 break;
             } else {
                 new_stream.write_all(&[2]).unwrap_or(());
@@ -163,228 +527,703 @@ pub fn wait_for_reconnection(stream: &mut TcpStream, name: &str, port: usize)
     Ok(())
 } 
 
+/// ask all the players except `proposer` (who is assumed to agree) to vote on a question
+///
+/// The players are asked at once. Bots always agree; players who have forfeited do not vote, and 
+/// players who can not be reached or do not answer within a minute count as disagreeing. Return 
+/// `true` if all the players agree or, if `majority` is `true`, if more than half of them do.
+pub fn vote(streams: &mut [PlayerConnection], proposer: usize, question: &str, forfeited: &[bool], 
+            majority: bool) -> bool 
+{
+    let voters: Vec<usize> = (0..streams.len()).filter(|&i| !forfeited[i]).collect();
+    let asked: Vec<usize> = voters.iter().copied()
+        .filter(|&i| i != proposer && !streams[i].is_bot())
+        .collect();
+    let n_voters = voters.len();
+    let n_yes = n_voters - asked.len() + ask_all(streams, &asked, question).iter().filter(|&&yes| yes).count();
+    if majority {
+        2 * n_yes > n_voters
+    } else {
+        n_yes == n_voters
+    }
+}
+
+/// offer a re-deal to the players whose hand can not form any pair or run
+///
+/// These players are asked at once (bots always ask), and those who do not answer within a minute
+/// do not ask. The first of them who asks for a re-deal puts it to a vote of all the players, and 
+/// it happens if a majority agrees. Return `true` if the cards should be dealt again.
+pub fn offer_redeal(streams: &mut [PlayerConnection], hands: &[Sequence], player_names: &[String],
+                    forfeited: &[bool]) -> bool 
+{
+    let hopeless: Vec<usize> = (0..streams.len())
+        .filter(|&i| !forfeited[i] && crate::bot::is_hopeless(&hands[i]))
+        .collect();
+    let questions: Vec<(usize, String)> = hopeless.iter()
+        .filter(|&&i| !streams[i].is_bot())
+        .map(|&i| (i, format!("Your hand can not form any pair or run:\n{}{}\nAsk for a re-deal? (‘y’ for yes)\n",
+                              render::sequence(&hands[i]), reset_style_string())))
+        .collect();
+    let answers = ask_each(streams, &questions);
+    let first_asking = hopeless.into_iter().find(|&i| {
+        streams[i].is_bot() || questions.iter().zip(&answers).any(|((j, _), &yes)| *j == i && yes)
+    });
+    let i = match first_asking {
+        Some(i) => i,
+        None => return false
+    };
+    let question = format!("{} can not form any pair or run and asks for a re-deal. Agree? (‘y’ for yes)\n", 
+                           &player_names[i]);
+    let agreed = vote(streams, i, &question, forfeited, true);
+    send_message_all_players(streams, &format!("{}\n", if agreed {
+        "The cards are dealt again."
+    } else {
+        "The re-deal has been refused."
+    }));
+    agreed
+}
+
+/// send its session token to a client
+///
+/// The client can then reconnect by sending the token instead of the player name, even to a
+/// server restarted from the save file.
+pub fn send_session_token<S: Transport>(stream: &mut S, token: &str) -> Result<(), StreamError> {
+    stream.write_all(&[7])?;
+    send_str_to_client(stream, token)
+}
+
 /// player turn
+///
+/// The moves are checked by `game` (see `GameState`), which holds the minimum of the first meld of
+/// the player, if they have not made it yet, and the maximum size of the hands. The cards taken
+/// from the table are shown apart from the hand, numbered after it.
 #[allow(clippy::too_many_arguments)]
-pub fn start_player_turn(table: &mut Table, hands: &mut [Sequence], deck: &mut Sequence, 
-                         custom_rule_jokers: bool, player_names: &[String], current_player: usize, 
-                         n_players: usize, streams: &mut [TcpStream], port: usize, 
-                         sort_mode: &mut u8, previous_messages: &[String])
+pub fn start_player_turn(game: &mut GameState, hooks: &RuleHooks, player_names: &[String], 
+                         n_players: usize, streams: &mut [PlayerConnection], listener: &TcpListener, 
+                         sort_mode: &mut u8, previous_messages: &[String], 
+                         discard_pool: &mut Sequence, rules: &RuleSet,
+                         forfeited: &mut [bool], save_and_quit: &mut bool, first_turn: bool,
+                         exchange_done: &mut bool, reversed: bool, history: &TableHistory)
     -> Result<String,StreamError> {
     
-    // copy the initial hand
-    let hand_start_round = hands[current_player].clone();
-
-    // copy the initial table
-    let table_start_round = table.clone();
+    let current_player = game.current_player;
     
-    // cards taken from the table
-    let mut cards_from_table = Sequence::new();
+    // cards set aside by the player
+    let mut staged = StagedCards::new();
     
     // send the instructions
+    let exchange_instruction = if rules.card_exchange && !*exchange_done {
+        "x n: Exchange card n blind with the next player (once per game, before any other move)\n"
+    } else {
+        ""
+    };
     send_message_to_client(&mut streams[current_player], 
-                           &format!("\u{0007}\n{}", instructions_no_save(true,false)))?;
+                           &format!("\u{0007}\n{}{}{}{}{}{}{}{}", instructions_no_save(true,false), 
+                                    exchange_instruction, UNDO_INSTRUCTION, HINT_INSTRUCTION, 
+                                    LOG_INSTRUCTION, INSPECT_INSTRUCTION, TRY_INSTRUCTION, 
+                                    NOTE_INSTRUCTION))?;
+
+    // remind the player of the minimum of their first meld
+    if !game.has_opened(current_player) {
+        send_message_to_client(&mut streams[current_player], 
+            &format!("Your first meld must be worth at least {} points.\n", game.initial_meld_points))?;
+    }
+
+    // warn the player if they can not draw
+    if game.max_hand_size > 0 && game.hand().number_cards() >= game.max_hand_size {
+        send_message_to_client(&mut streams[current_player], 
+            &format!("Your hand is full ({} cards at most): play some cards, or you will skip drawing.\n",
+                     game.max_hand_size))?;
+    }
 
     // get and process the player choice
     let mut message: String;
+    
+    // last message sent to the player, to be sent again if they reconnect
+    let mut pending_prompt = String::new();
+
+    // states of the turn before the moves played, taken, or added so far, to undo them
+    let mut moves = UndoStack::<TurnSnapshot>::new();
+
+    // commands of a line not processed yet, and state of the turn and number of moves before the
+    // line
+    let mut batch = VecDeque::<Vec<u8>>::new();
+    let mut batch_start: Option<TurnSnapshot> = None;
+    let mut batch_moves = 0;
+
+    // state of the turn and number of moves when the player has started trying moves (see
+    // `try`), if they have
+    let mut try_start: Option<TurnSnapshot> = None;
+    let mut try_moves = 0;
     loop {
-        match get_message_from_client(&mut streams[current_player]) {
+        let received = match batch.pop_front() {
+            Some(mes) => Ok(mes),
+            None => get_message_from_client(&mut streams[current_player])
+        };
+        match received {
             Ok(mes) => {
+                pending_prompt.clear();
+
+                // several commands separated by semicolons are processed one after the other; a
+                // note is kept whole
+                let mes = if batch_start.is_none() && mes.contains(&b';') && parse_note(&mes).is_none() {
+                    batch = split_batch(&mes);
+                    batch_start = Some(TurnSnapshot::new(game, &staged, *exchange_done));
+                    batch_moves = moves.len();
+                    batch.pop_front().unwrap_or_default()
+                } else {
+                    mes
+                };
+
+                let mes = match quick_play_to_add(&mes, split_hand(game).0.number_cards()) {
+                    Some(Ok(mes)) => mes,
+                    Some(Err(error)) => {
+                        send_prompt(&mut streams[current_player], &mut pending_prompt, error)?;
+                        Vec::new()
+                    },
+                    None => mes
+                };
+                let word = String::from_utf8_lossy(&mes).trim().to_string();
                 if mes.is_empty() {
+                } else if let Some(note) = parse_note(&mes) {
+                    if note.chars().count() > MAX_NOTE_LENGTH {
+                        send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                    &format!("A note can not be longer than {} characters!\n", MAX_NOTE_LENGTH))?;
+                    } else {
+                        streams[current_player].set_note(&note);
+                        let reply = if note.is_empty() { "Note cleared.\n" } else { "Note kept until the end of your turn.\n" };
+                        send_message_to_client(&mut streams[current_player], reply)?;
+                    }
+                } else if word == "try" || word == "commit" || word == "discard" {
+                    if batch_start.is_some() {
+                        send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                    "‘try’, ‘commit’, and ‘discard’ can not be used in a line of several commands!\n")?;
+                    } else if word == "try" {
+                        if try_start.is_some() {
+                            send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                        "You are already trying moves; ‘commit’ or ‘discard’ them first.\n")?;
+                        } else {
+                            try_start = Some(TurnSnapshot::new(game, &staged, *exchange_done));
+                            try_moves = moves.len();
+                            send_message_to_client(&mut streams[current_player], 
+                                "Trying moves: ‘commit’ keeps them once you have played all the cards taken from the table, ‘discard’ puts everything back as it is now.\n")?;
+                        }
+                    } else if try_start.is_none() {
+                        send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                    "You are not trying any move; ‘try’ to start.\n")?;
+                    } else if word == "commit" {
+                        if game.has_cards_from_table() 
+                            || staged.number_cards() > staged.number_cards_from_hand() {
+                            send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                        "You can only commit once you have played all the cards taken from the table!\n")?;
+                        } else {
+                            try_start = None;
+                            send_message_to_client(&mut streams[current_player], "Your moves are kept.\n")?;
+                        }
+                    } else if let Some(start) = try_start.take() {
+                        start.restore(game, &mut staged, exchange_done);
+                        moves.truncate(try_moves);
+                        for i in (0..n_players).filter(|&i| !forfeited[i]) {
+                            print_situation_remote(game, player_names, forfeited, reversed, i, streams,
+                                                   i == current_player, &staged, &previous_messages[i])
+                                        .or_else(|e| if i == current_player { Err(e) } else { Ok(()) })?;
+                        }
+                        send_message_to_client(&mut streams[current_player], "Your moves have been discarded.\n")?;
+                    }
                 } else {
+
+                    // state before a move which can be undone
+                    let before = if b"ptam".contains(&mes[0]) {
+                        Some(TurnSnapshot::new(game, &staged, *exchange_done))
+                    } else {
+                        None
+                    };
                     match mes[0] {
+
+                        // value 'e' while trying moves
+                        101 if try_start.is_some() => {
+                            send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                        "‘commit’ or ‘discard’ the moves you are trying before ending your turn!\n")?;
+                        },
                     
                         // value 'e': end the turn
                         101 => {
-                            if cards_from_table.number_cards() != 0 {
-                                message = "You can't end your turn until you've played all the cards you've taken from the table!\n"
-                                          .to_string();
-                                send_message_to_client(&mut streams[current_player], &message)?;
-                            } else if custom_rule_jokers && hands[current_player].contains_joker() {
-                                message = "Jokers must be played!\n".to_string();
-                                send_message_to_client(&mut streams[current_player], &message)?;
-                            } else if hands[current_player].contains(&hand_start_round) {
-                                match pick_a_card(&mut hands[current_player], deck) {
-                                    Ok(card) => message = format!(" (you picked a {}{})", &card, &reset_style_string()),
-                                    Err(_) => message = "No more card to draw!\n".to_string()
-                                };
-                                match *sort_mode {
-                                    1 => hands[current_player].sort_by_rank(),
-                                    2 => hands[current_player].sort_by_suit(),
-                                    _ => ()
+                            unstage_cards(game, &mut staged);
+
+                            // a player who can not play all the cards taken from the table is
+                            // offered to reset it
+                            let mut stuck = game.has_cards_from_table();
+                            if stuck {
+                                let question = format!("You can't end your turn until you've played all the cards you've taken from the table!\nReset the table{} and end your turn? (‘y’ for yes)\n",
+                                                       if rules.free_reset { "" } else { ", take the penalty," });
+                                let reply = send_message_get_reply(&mut streams[current_player], &question)?;
+                                if is_yes(&String::from_utf8_lossy(&reply)) {
+                                    send_message_all_players(
+                                        streams,
+                                        &format!("{} resets the table{}\n", &player_names[current_player],
+                                                 if rules.free_reset { "" } else { " and takes the penalty" })
+                                    );
+                                    game.give_up(rules.reset_penalty());
+                                    if rules.graveyard_rounds > 0 {
+                                        discard_from_deck(&mut game.deck, discard_pool, rules.reset_penalty());
+                                    }
+                                    stuck = false;
                                 }
-                                return Ok(message);
+                            }
+                            if stuck {
+                                send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                            "Your turn goes on.\n")?;
                             } else {
-                                break
+                                let hand_full = game.max_hand_size > 0 
+                                    && game.hand().number_cards() >= game.max_hand_size;
+                                match game.end_turn(hooks) {
+                                    Ok(Event::Drew(card)) => {
+                                        message = match card {
+                                            Some(card) => format!(" (you picked a {}{})", render::card(&card), 
+                                                                  &reset_style_string()),
+                                            None if hand_full => format!(" (your hand is full with {} cards: you skip drawing)", 
+                                                                         game.hand().number_cards()),
+                                            None => "No more card to draw!\n".to_string()
+                                        };
+                                        match *sort_mode {
+                                            1 => game.hands[current_player].sort_by_rank(),
+                                            2 => game.hands[current_player].sort_by_suit(),
+                                            _ => ()
+                                        }
+                                        return Ok(message);
+                                    },
+                                    Ok(_) => break,
+                                    Err(e) => {
+                                        message = format!("{}\n", e);
+                                        send_prompt(&mut streams[current_player], &mut pending_prompt, &message)?;
+                                    }
+                                }
                             }
                         },
                     
                         // value 'p': play a sequence
                         112 => {
-                            match play_sequence_remote(&mut hands[current_player], &mut cards_from_table,
-                                                       table, &mes[1..]) {
+                            match play_sequence_remote(game, &mes[1..], hooks) {
                                 Ok(None) => {
                                     
                                     // print the situation for the current player
-                                    print_situation_remote(table, hands, deck, player_names, current_player,
-                                                           current_player, &mut streams[current_player],
-                                                           true, &cards_from_table, 
-                                                           !hands[current_player].contains(&hand_start_round),
-                                                           cards_from_table.number_cards() > 0, 
+                                    print_situation_remote(game, player_names, forfeited, reversed, current_player,
+                                                           streams, true, &staged, 
                                                            &previous_messages[current_player])?;
 
                                     // print the new situation for the other players
+                                    // (a disconnected player catches up after reconnecting)
                                     for i in 0..n_players {
-                                        if i != current_player {
-                                            print_situation_remote(table, hands, deck, player_names, 
-                                                                   i, current_player, &mut streams[i],
-                                                                   false, &cards_from_table, false, false, 
-                                                                   &previous_messages[i])?;
+                                        if i != current_player && !forfeited[i] {
+                                            print_situation_remote(game, player_names, forfeited, reversed, 
+                                                                   i, streams, false, &staged, 
+                                                                   &previous_messages[i])
+                                                .unwrap_or(());
                                         }
                                     }
 
-                                    // if the player has no more card and there is no card on the
-                                    // table, end the turn 
-                                    if (hands[current_player].number_cards() == 0) 
-                                        && (cards_from_table.number_cards() == 0) {
+                                    // if the player has no more card, end the turn 
+                                    if hand_emptied(game, &staged, hooks) {
                                         break;
                                     }
                                 },
 
                                 Ok(Some(s)) => {
-                                    print_situation_remote(table, hands, deck, player_names, current_player,
-                                                           current_player, &mut streams[current_player],
-                                                           true, &cards_from_table, 
-                                                           !hands[current_player].contains(&hand_start_round),
-                                                           cards_from_table.number_cards() > 0,
+                                    print_situation_remote(game, player_names, forfeited, reversed, current_player,
+                                                           streams, true, &staged, 
                                                            &previous_messages[current_player])?;
-                                    send_message_to_client(&mut streams[current_player], &s)?;
+                                    send_prompt(&mut streams[current_player], &mut pending_prompt, &s)?;
                                 },
 
-                                Err(_) => send_message_to_client(&mut streams[current_player], "Communication error\n")?
+                                Err(_) => send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                                      "Communication error\n")?
                             };
                         },
                         
+                        // values 't' and 'a' on the first turn, if the rules forbid touching 
+                        // the table
+                        116 | 97 if first_turn && rules.no_table_on_first_turn => {
+                            message = "You can't take or modify sequences on the table during your first turn!\n"
+                                      .to_string();
+                            send_prompt(&mut streams[current_player], &mut pending_prompt, &message)?;
+                        },
+
                         // value 't': take a sequence from the table
                         116 => {
-                            match take_sequence_remote(table, &mut cards_from_table, &mes[1..], 
-                                                       &mut streams[current_player]) {
-                                Ok(()) => {
+                            match take_sequence_remote(game, &mes[1..]) {
+                                Ok(reply) => {
 
                                     // print the new situation for the current player
-                                    print_situation_remote(table, hands, deck, player_names, 
-                                                           current_player, current_player, 
-                                                           &mut streams[current_player], true, &cards_from_table,
-                                                           false, cards_from_table.number_cards() > 0,
+                                    print_situation_remote(game, player_names, forfeited, reversed, 
+                                                           current_player, streams, true, &staged,
                                                            &previous_messages[current_player])?;
 
                                     // print the new situation for the other players
+                                    // (a disconnected player catches up after reconnecting)
                                     for i in 0..n_players {
-                                        if i != current_player {
-                                            print_situation_remote(table, hands, deck, player_names, 
-                                                                   i, current_player, &mut streams[i],
-                                                                   false, &cards_from_table, false, false,
-                                                                   &previous_messages[i])?;
+                                        if i != current_player && !forfeited[i] {
+                                            print_situation_remote(game, player_names, forfeited, reversed, 
+                                                                   i, streams, false, &staged,
+                                                                   &previous_messages[i])
+                                                .unwrap_or(());
                                         }
                                     }
+                                    if let Some(s) = reply {
+                                        send_prompt(&mut streams[current_player], &mut pending_prompt, &s)?;
+                                    }
                                 },
 
-                                Err(_) => send_message_to_client(&mut streams[current_player], "Communication error\n")?
+                                Err(_) => send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                                      "Communication error\n")?
                             };
                         },
                         
                         // value 'a': add cards to a sequence already on the table
                         97 => {
-                            match add_to_table_sequence_remote(table, &mut hands[current_player], 
-                                                               &mut cards_from_table, &mes[1..]) {
+                            match add_to_table_sequence_remote(game, &mes[1..], hooks) {
                                 Ok(None) => {
 
                                     // print the new situation for the current player
-                                    print_situation_remote(table, hands, deck, player_names, 
-                                                           current_player, current_player, 
-                                                           &mut streams[current_player], true, &cards_from_table,
-                                                           !hands[current_player].contains(&hand_start_round),
-                                                           cards_from_table.number_cards() > 0,
+                                    print_situation_remote(game, player_names, forfeited, reversed, 
+                                                           current_player, streams, true, &staged,
                                                            &previous_messages[current_player])?;
 
                                     // print the new situation for the other players
+                                    // (a disconnected player catches up after reconnecting)
                                     for i in 0..n_players {
-                                        if i != current_player {
-                                            print_situation_remote(table, hands, deck, player_names, 
-                                                                   i, current_player, &mut streams[i],
-                                                                   false, &cards_from_table, false, false,
-                                                                   &previous_messages[i])?;
+                                        if i != current_player && !forfeited[i] {
+                                            print_situation_remote(game, player_names, forfeited, reversed, 
+                                                                   i, streams, false, &staged,
+                                                                   &previous_messages[i])
+                                                .unwrap_or(());
                                         }
                                     }
                                     
-                                    // if the player has no more card and there is no card on the
-                                    // table, end the turn 
-                                    if (hands[current_player].number_cards() == 0) 
-                                        && (cards_from_table.number_cards() == 0) {
+                                    // if the player has no more card, end the turn 
+                                    if hand_emptied(game, &staged, hooks) {
                                         break;
                                     }
                                 },
                                 Ok(Some(s)) => {
-                                    print_situation_remote(table, hands, deck, player_names, 
-                                                           current_player, current_player, 
-                                                           &mut streams[current_player], true, &cards_from_table,
-                                                           !hands[current_player].contains(&hand_start_round),
-                                                           cards_from_table.number_cards() > 0, 
+                                    print_situation_remote(game, player_names, forfeited, reversed, 
+                                                           current_player, streams, true, &staged,
                                                            &previous_messages[current_player])?;
-                                    send_message_to_client(&mut streams[current_player], &s)?;
+                                    send_prompt(&mut streams[current_player], &mut pending_prompt, &s)?;
                                 },
-                                Err(_) => send_message_to_client(&mut streams[current_player], "Communication error\n")?
+                                Err(_) => send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                                      "Communication error\n")?
                             };
                         },
  
-                        // value 'r': sort cards by rank
-                        114 => {
-                            hands[current_player].sort_by_rank();
-                            cards_from_table.sort_by_rank();
-                            *sort_mode = 1;
-                            print_situation_remote(table, hands, deck, player_names, current_player,
-                                                   current_player, &mut streams[current_player],
-                                                   true, &cards_from_table,
-                                                   !hands[current_player].contains(&hand_start_round),
-                                                   cards_from_table.number_cards() > 0, 
-                                                   &previous_messages[current_player])?;
+                        // value 'x': exchange a card with the next player
+                        120 if rules.card_exchange => {
+                            let receiver = next_player_in_direction(current_player, forfeited, reversed);
+                            let index = String::from_utf8_lossy(&mes[1..]).trim().parse::<usize>();
+                            let error = if *exchange_done {
+                                Some("You have already exchanged a card during this game!\n")
+                            } else if game.has_played() || game.has_cards_from_table() || staged.number_cards() > 0 {
+                                Some("You can only exchange a card before any other move!\n")
+                            } else if receiver == current_player {
+                                Some("There is nobody to exchange a card with!\n")
+                            } else if game.hands[receiver].number_cards() == 0 {
+                                Some("The next player has no card to exchange!\n")
+                            } else {
+                                match index {
+                                    Ok(n) if n >= 1 && n <= game.hand().number_cards() => None,
+                                    _ => Some("Please give the index of a card in your hand\n")
+                                }
+                            };
+                            if let Some(error) = error {
+                                send_prompt(&mut streams[current_player], &mut pending_prompt, error)?;
+                            } else {
+                                let (given, received) = exchange_cards(&mut game.hands, current_player, receiver, 
+                                                                       index.unwrap());
+                                game.start_turn(current_player);
+                                *exchange_done = true;
+                                send_message_all_players(streams, 
+                                    &format!("{} exchanges a card with {}\n", &player_names[current_player],
+                                             &player_names[receiver]));
+
+                                // the receiving player is told without waiting for them, and the
+                                // message is kept in their log in case the screen is redrawn
+                                let notice = format!("{} gives you a {}{} and takes your {}{}\n",
+                                                     &player_names[current_player], render::card(&given), 
+                                                     reset_style_string(), render::card(&received), 
+                                                     reset_style_string());
+                                streams[receiver].log_message(&notice);
+                                send_message_to_client(&mut streams[receiver], &notice).unwrap_or(());
+                                print_situation_remote(game, player_names, forfeited, reversed, current_player,
+                                                       streams, true, &staged, 
+                                                       &previous_messages[current_player])?;
+                                send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                    &format!("You gave your {}{} and received a {}{}\n", 
+                                             render::card(&given), reset_style_string(), 
+                                             render::card(&received), reset_style_string()))?;
+                            }
                         },
-                        
-                        // value 's': sort cards by suit
-                        115 => {
-                            hands[current_player].sort_by_suit();
-                            cards_from_table.sort_by_suit();
-                            *sort_mode = 2;
-                            print_situation_remote(table, hands, deck, player_names, current_player,
-                                                   current_player, &mut streams[current_player],
-                                                   true, &cards_from_table, 
-                                                   !hands[current_player].contains(&hand_start_round),
-                                                   cards_from_table.number_cards() > 0,
-                                                   &previous_messages[current_player])?;
+
+                        // values 'u' and 'U': undo the last move, or redo the last move undone
+                        117 | 85 => {
+                            let mut state = TurnSnapshot::new(game, &staged, *exchange_done);
+                            let done = if mes[0] == 117 { moves.undo(&mut state) } else { moves.redo(&mut state) };
+                            if done {
+                                state.restore(game, &mut staged, exchange_done);
+                                for i in (0..n_players).filter(|&i| !forfeited[i]) {
+                                    print_situation_remote(game, player_names, forfeited, reversed, i, streams,
+                                                           i == current_player, &staged, &previous_messages[i])
+                                                .or_else(|e| if i == current_player { Err(e) } else { Ok(()) })?;
+                                }
+                            } else {
+                                send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                            if mes[0] == 117 { "There is no move to undo!\n" } else { "There is no move to redo!\n" })?;
+                            }
+                        },
+
+                        // value 'h': suggest a move with the hand and the cards taken from the table,
+                        // if the table may be used
+                        104 => {
+                            let (mut cards, cards_from_table) = split_hand(game);
+                            let n_hand = cards.number_cards();
+                            cards.add_cards(&cards_from_table.to_vec());
+                            let table_allowed = !(first_turn && rules.no_table_on_first_turn) 
+                                && game.has_opened(current_player);
+                            let solution = solver::find_valid_melds_within(&cards, &game.table, &solver::limits());
+                            let hint = solution.moves.into_iter()
+                                .find(|m| table_allowed || matches!(m, Move::Play(_)));
+                            let mut reply = match hint {
+                                Some(m) => {
+                                    let command = match m {
+                                        Move::Add(card, seq) if card > n_hand => format!("a {} {}", seq, card),
+                                        _ => m.to_string()
+                                    };
+                                    format!("Hint: {} (‘{}’)\n", solver::describe_move(&m, &cards), command)
+                                },
+                                None => "No move found with your cards alone: you may rearrange the table, or pick a card.\n"
+                                        .to_string()
+                            };
+                            if solution.degraded {
+                                reply.push_str("(Quick search only: other moves may have been missed.)\n");
+                            }
+                            send_message_to_client(&mut streams[current_player], &reply)?;
+                        },
+
+                        // value 'l': show the last messages
+                        108 => {
+                            let n = String::from_utf8_lossy(&mes[1..]).split_whitespace()
+                                .find_map(|x| x.parse::<usize>().ok())
+                                .unwrap_or(N_MESSAGES_LOG_DEFAULT);
+                            let messages = streams[current_player].recent_messages(n);
+                            let log = if messages.is_empty() {
+                                "No message yet.\n".to_string()
+                            } else {
+                                format!("Last messages:\n{}", messages.iter()
+                                    .map(|m| format!("  {}\n", m.trim()))
+                                    .collect::<String>())
+                            };
+                            send_message_to_client(&mut streams[current_player], &log)?;
+                        },
+
+                        // value 'i': show a sequence, its history (including the changes of this
+                        // turn), and the cards of the hand which can be added to it
+                        105 => {
+                            let mut history = history.clone();
+                            history.update(&game.table, current_player);
+                            let hand = split_hand(game).0;
+                            let description = String::from_utf8_lossy(&mes[1..]).trim().parse::<usize>().ok()
+                                .and_then(|n| n.checked_sub(1))
+                                .and_then(|i| Some((i, game.table.get(i + 1)?.clone())))
+                                .map(|(i, seq)| {
+                                    let kind = seq.meld_kind().map(|kind| format!(" [{}]", kind)).unwrap_or_default();
+                                    let cards = attachable_cards(&seq, &hand, hooks);
+                                    let cards = if cards.is_empty() {
+                                        "No card of your hand can be added to it on its own.\n".to_string()
+                                    } else {
+                                        format!("Cards of your hand which can be added to it: {}{}\n",
+                                                cards.iter()
+                                                    .map(|(j, card)| format!("{} ({}{})", j, render::card(card),
+                                                                             reset_style_string()))
+                                                    .collect::<Vec<String>>().join(", "),
+                                                reset_style_string())
+                                    };
+                                    format!("\nSequence {}: {}{}{}\n{}{}", i + 1, render::sequence(&seq),
+                                            reset_style_string(), kind,
+                                            history.describe(i, player_names).unwrap_or_default(), cards)
+                                });
+                            match description {
+                                Some(s) => send_message_to_client(&mut streams[current_player], &s)?,
+                                None => send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                                    "There is no such sequence on the table!\n")?
+                            }
+                        },
+
+                        // values 'r' and 's': sort cards by rank or by suit
+                        114 | 115 => {
+                            let (mut hand, mut cards_from_table) = split_hand(game);
+                            if mes[0] == 114 {
+                                hand.sort_by_rank();
+                                cards_from_table.sort_by_rank();
+                                *sort_mode = 1;
+                            } else {
+                                hand.sort_by_suit();
+                                cards_from_table.sort_by_suit();
+                                *sort_mode = 2;
+                            }
+                            join_hand(game, hand, cards_from_table);
+                            print_situation_remote(game, player_names, forfeited, reversed, current_player,
+                                                   streams, true, &staged, &previous_messages[current_player])?;
+                        },
+            
+                        // value 'k': set cards aside, or put them back if no card is given
+                        107 => {
+                            let indices: Vec<usize> = String::from_utf8_lossy(&mes[1..])
+                                .split_whitespace()
+                                .filter_map(|x| x.parse::<usize>().ok())
+                                .collect();
+                            let staged_ok = if indices.is_empty() {
+                                unstage_cards(game, &mut staged);
+                                Ok(())
+                            } else {
+                                let (mut hand, mut cards_from_table) = split_hand(game);
+                                let staged_ok = staged.stage(&indices, &mut hand, &mut cards_from_table);
+                                join_hand(game, hand, cards_from_table);
+                                staged_ok
+                            };
+                            match staged_ok {
+                                Ok(()) => print_situation_remote(game, player_names, forfeited, reversed, 
+                                                                 current_player, streams, true, &staged, 
+                                                                 &previous_messages[current_player])?,
+                                Err(e) => send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                                      &format!("{}\n", e))?
+                            }
+                        },
+
+                        // value 'm': play the cards set aside
+                        109 => {
+                            let seq = staged.to_sequence();
+                            if seq.number_cards() == 0 {
+                                send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                                       "You have not set any card aside!\n")?;
+                            } else {
+                                let (game_before, staged_before) = (game.clone(), staged.clone());
+                                unstage_cards(game, &mut staged);
+                                let indices = positions_in_hand(game.hand(), &seq);
+                                match game.play_cards(&indices, hooks) {
+                                    Ok(_) => {
+
+                                        // print the new situation for all players
+                                        for i in (0..n_players).filter(|&i| !forfeited[i]) {
+                                            print_situation_remote(game, player_names, forfeited, reversed, i, 
+                                                                   streams, i == current_player, &staged, 
+                                                                   &previous_messages[i])
+                                                .or_else(|e| if i == current_player { Err(e) } else { Ok(()) })?;
+                                        }
+
+                                        // if the player has no more card, end the turn 
+                                        if hand_emptied(game, &staged, hooks) {
+                                            break;
+                                        }
+                                    },
+                                    Err(e) => {
+                                        *game = game_before;
+                                        staged = staged_before;
+                                        send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                                    &format!("{}\n", e))?;
+                                    }
+                                }
+                            }
                         },
             
                         // value 'g': give up on that round and take the penalty
                         103 => {
+                            try_start = None;
+                            moves.clear();
+                            unstage_cards(game, &mut staged);
                             send_message_all_players(
                                 streams,
-                                &format!("{} resets the table and takes the penalty\n", 
-                                         &player_names[current_player])
+                                &format!("{} resets the table{}\n", &player_names[current_player],
+                                         if rules.free_reset { "" } else { " and takes the penalty" })
                             );
-                            match cards_from_table.number_cards() {
-                                0 => (),
-                                _ => {
-                                    give_up(table, &mut hands[current_player], deck, &hand_start_round, 
-                                            &table_start_round, &mut cards_from_table);
-                                    print_situation_remote(table, hands, deck, player_names, current_player,
-                                                           current_player, &mut streams[current_player],
-                                                           true, &cards_from_table, false, false,
-                                                           &previous_messages[current_player])?;
+                            if game.has_cards_from_table() {
+                                game.give_up(rules.reset_penalty());
+                                if rules.graveyard_rounds > 0 {
+                                    discard_from_deck(&mut game.deck, discard_pool, rules.reset_penalty());
                                 }
+                                print_situation_remote(game, player_names, forfeited, reversed, current_player,
+                                                       streams, true, &staged, &previous_messages[current_player])?;
                             }
                         },
 
-                        _ => send_message_to_client(&mut streams[current_player], "Invalid input; please try again.")?,
+                        // value 'f': forfeit and leave the game
+                        102 => {
+                            let reply = send_message_get_reply(&mut streams[current_player], 
+                                "Do you really want to forfeit and leave the game? (‘y’ for yes)\n")?;
+                            if is_yes(&String::from_utf8_lossy(&reply)) {
+                                
+                                // the table is restored, without penalty
+                                unstage_cards(game, &mut staged);
+                                game.reset_turn();
+                                forfeit(&mut game.hands[current_player], &mut game.deck, rules.forfeited_cards_to_deck);
+                                forfeited[current_player] = true;
+                                send_message_and_close_client(&mut streams[current_player], 
+                                                              "You have left the game.\n")?;
+                                send_message_all_players(
+                                    streams,
+                                    &format!("{} forfeits and leaves the game\n", &player_names[current_player])
+                                );
+                                return Ok(String::new());
+                            }
+                            send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                        "You are still in the game.\n")?;
+                        },
+
+                        // value 'q': propose to save the game and quit
+                        113 => {
+                            send_message_all_players(
+                                streams,
+                                &format!("{} proposes to save the game and quit\n", &player_names[current_player])
+                            );
+                            if vote(streams, current_player, "Save the game and quit? (‘y’ for yes)\n", 
+                                    forfeited, rules.save_by_majority) {
+                                
+                                // the game is saved as it was at the beginning of the turn
+                                unstage_cards(game, &mut staged);
+                                game.reset_turn();
+                                *save_and_quit = true;
+                                return Ok(String::new());
+                            }
+                            send_message_all_players(streams, "The game goes on\n");
+                            send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                        "Not enough players agreed to quit.\n")?;
+                        },
+
+                        _ => send_prompt(&mut streams[current_player], &mut pending_prompt, 
+                                         "Invalid input; please try again.")?,
+                    }
+                    if let Some(before) = before {
+                        if before.differs(game, &staged) {
+                            moves.record(before);
+                        }
                     }
                 }
+
+                // if a command of a line fails, the whole line is undone
+                if !pending_prompt.is_empty() {
+                    if let Some(start) = batch_start.take() {
+                        start.restore(game, &mut staged, exchange_done);
+                        moves.truncate(batch_moves);
+                        batch.clear();
+                        for i in (0..n_players).filter(|&i| !forfeited[i]) {
+                            print_situation_remote(game, player_names, forfeited, reversed, i, streams,
+                                                   i == current_player, &staged, &previous_messages[i])
+                                        .or_else(|e| if i == current_player { Err(e) } else { Ok(()) })?;
+                        }
+                        let message = format!("{}The commands of the line have been undone.\n", pending_prompt);
+                        send_prompt(&mut streams[current_player], &mut pending_prompt, &message)?;
+                    }
+                }
+                if batch.is_empty() {
+                    batch_start = None;
+                }
             },
             Err(_) => {
                 send_message_all_players(
@@ -393,14 +1232,13 @@ pub fn start_player_turn(table: &mut Table, hands: &mut [Sequence], deck: &mut S
                              &player_names[current_player])
                 );
                 println!("Lost connection with player {}", current_player + 1);
-                wait_for_reconnection(&mut streams[current_player], &player_names[current_player], port)?;
+                wait_for_reconnection(&mut streams[current_player], &player_names[current_player], listener)?;
                 println!("Player {} is back", current_player + 1);
-                print_situation_remote(table, hands, deck, player_names, current_player,
-                                       current_player, &mut streams[current_player],
-                                       true, &cards_from_table, 
-                                       !hands[current_player].contains(&hand_start_round),
-                                       cards_from_table.number_cards() > 0,
-                                       &previous_messages[current_player])?;
+                print_situation_remote(game, player_names, forfeited, reversed, current_player,
+                                       streams, true, &staged, &previous_messages[current_player])?;
+                if !pending_prompt.is_empty() {
+                    send_message_to_client(&mut streams[current_player], &pending_prompt)?;
+                }
                 send_message_all_players(
                     streams,
                     &format!("{} is back!\n", 
@@ -412,234 +1250,328 @@ pub fn start_player_turn(table: &mut Table, hands: &mut [Sequence], deck: &mut S
     Ok("".to_string())
 }
 
-fn play_sequence_remote(hand: &mut Sequence, cards_from_table: &mut Sequence,
-                        table: &mut Table, mes: &[u8]) 
+// hand of the current player and cards taken from the table, as they are shown to the player
+fn split_hand(game: &GameState) -> (Sequence, Sequence) {
+    let mut hand = Sequence::new();
+    let mut cards_from_table = Sequence::new();
+    for (card, taken) in game.hand().to_vec().into_iter().zip(game.taken_cards()) {
+        if taken {
+            cards_from_table.add_card(card);
+        } else {
+            hand.add_card(card);
+        }
+    }
+    (hand, cards_from_table)
+}
+
+// put the hand of the current player back together, with the cards taken from the table last
+fn join_hand(game: &mut GameState, mut hand: Sequence, cards_from_table: Sequence) {
+    hand.add_cards(&cards_from_table.to_vec());
+    game.hands[game.current_player] = hand;
+}
+
+// positions (starting from 1) in the hand of the current player of the cards with the given
+// indices, as they are shown to the player
+fn indices_in_hand(game: &GameState, indices: &[usize]) -> Result<Vec<usize>, RuleError> {
+    let taken = game.taken_cards();
+    let order: Vec<usize> = (1..=taken.len()).filter(|&i| !taken[i - 1])
+        .chain((1..=taken.len()).filter(|&i| taken[i - 1]))
+        .collect();
+    indices.iter()
+        .map(|&n| n.checked_sub(1).and_then(|i| order.get(i)).copied().ok_or(RuleError::NoSuchCard(n)))
+        .collect()
+}
+
+// positions (starting from 1) in `hand` of the cards of `seq`
+fn positions_in_hand(hand: &Sequence, seq: &Sequence) -> Vec<usize> {
+    let mut cards: Vec<Option<Card>> = hand.to_vec().into_iter().map(Some).collect();
+    seq.to_vec().iter().filter_map(|card| {
+        let i = cards.iter().position(|c| c.as_ref() == Some(card))?;
+        cards[i] = None;
+        Some(i + 1)
+    }).collect()
+}
+
+// put the cards set aside back in the hand of the current player
+fn unstage_cards(game: &mut GameState, staged: &mut StagedCards) {
+    let (mut hand, mut cards_from_table) = split_hand(game);
+    staged.unstage(&mut hand, &mut cards_from_table);
+    join_hand(game, hand, cards_from_table);
+}
+
+// end the turn if the current player has no card left, unless the rules refuse it
+fn hand_emptied(game: &mut GameState, staged: &StagedCards, hooks: &RuleHooks) -> bool {
+    game.hand().number_cards() == 0 && staged.number_cards() == 0 && game.pass(hooks).is_ok()
+}
+
+/// exchange the card `index` (starting from 1) of the hand of `giver` with a random card from the
+/// hand of `receiver`
+///
+/// Return the card given and the card received.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::lib_server::*;
+///
+/// let mut hands = vec![Sequence::from_cards(&[Joker, RegularCard(Heart, 4)]), 
+///                      Sequence::from_cards(&[RegularCard(Club, 7)])];
+/// let (given, received) = exchange_cards(&mut hands, 0, 1, 2);
+///
+/// assert_eq!(RegularCard(Heart, 4), given);
+/// assert_eq!(RegularCard(Club, 7), received);
+/// assert_eq!(Sequence::from_cards(&[Joker, RegularCard(Club, 7)]), hands[0]);
+/// assert_eq!(Sequence::from_cards(&[RegularCard(Heart, 4)]), hands[1]);
+/// ```
+pub fn exchange_cards(hands: &mut [Sequence], giver: usize, receiver: usize, index: usize) -> (Card, Card) {
+    let given = hands[giver].take_card(index).unwrap();
+    let random_index = rand::Rng::gen_range(&mut rand::thread_rng(), 1..=hands[receiver].number_cards());
+    let received = hands[receiver].take_card(random_index).unwrap();
+    hands[giver].add_card(received.clone());
+    hands[receiver].add_card(given.clone());
+    (given, received)
+}
+
+fn play_sequence_remote(game: &mut GameState, mes: &[u8], hooks: &RuleHooks) 
     -> Result<Option<String>, StreamError>
 {
-    // copy the initial hand and cards from tables
-    let hand_copy = hand.clone();
-    let cards_from_table_copy = cards_from_table.clone();
-
-    // combine the hand and cards from the table
-    let mut full_hand = hand.clone();
-    let buffer = cards_from_table.clone();
-    full_hand.merge(buffer.reverse());
-  
-    let mut seq = Sequence::new();
-    
-    let s = String::from_utf8(mes.to_vec())?;
-    
-    let mut seq_i_hand = Vec::<usize>::new();
-    let mut seq_i_cft = Vec::<usize>::new();
-    let n_hand = hand.number_cards();
-    for item in s.trim().split(' ') {
-        if let Ok(n) = item.parse::<usize>() {
-            if n <= n_hand {
-                let mut n_i = 0;
-                for &i in &seq_i_hand {
-                    if i < n {
-                        n_i += 1;
-                    }
-                }
-                let card = match hand.take_card(n-n_i) {
-                    Some(c) => c,
-                    None => continue
-                };
-                seq.add_card(card);
-                seq_i_hand.push(n);
-            } else {
-                let m = n - n_hand;
-                let mut n_i = 0;
-                for &i in &seq_i_cft {
-                    if i < m {
-                        n_i += 1;
-                    }
-                }
-                let card = match cards_from_table.take_card(m-n_i) {
-                    Some(c) => c,
-                    None => continue
-                };
-                seq.add_card(card);
-                seq_i_cft.push(m);
-            }
+    let indices: Vec<usize> = String::from_utf8(mes.to_vec())?
+        .split_whitespace()
+        .filter_map(|x| x.parse::<usize>().ok())
+        .collect();
+    let played = indices_in_hand(game, &indices)
+        .and_then(|indices| game.play_cards(&indices, hooks));
+    Ok(played.err().map(|e| format!("{}\n", e)))
+}
+
+// state of a turn, restored if a command of a line of several commands fails, if the player
+// discards the moves they were trying, or if they undo a move
+struct TurnSnapshot {
+    game: GameState,
+    staged: StagedCards,
+    exchange_done: bool
+}
+
+impl TurnSnapshot {
+
+    fn new(game: &GameState, staged: &StagedCards, exchange_done: bool) -> TurnSnapshot {
+        TurnSnapshot {
+            game: game.clone(),
+            staged: staged.clone(),
+            exchange_done
         }
     }
 
-    if seq.is_valid() {
-        table.add(seq);
-        Ok(None)
+    // check if the cards of the hands or of the table have changed since the snapshot
+    fn differs(&self, game: &GameState, staged: &StagedCards) -> bool {
+        self.game.hands != game.hands || self.game.table != game.table || self.staged != *staged
+    }
+
+    fn restore(self, game: &mut GameState, staged: &mut StagedCards, exchange_done: &mut bool) {
+        *game = self.game;
+        *staged = self.staged;
+        *exchange_done = self.exchange_done;
+    }
+}
+
+/// text of a note written with the command `note` (empty if the note is cleared), or `None` if the
+/// message is not a note
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::lib_server::parse_note;
+///
+/// assert_eq!(Some("remember: need 7♦".to_string()), parse_note("note remember: need 7♦ ".as_bytes()));
+/// assert_eq!(Some(String::new()), parse_note(b"note"));
+/// assert_eq!(None, parse_note(b"notes"));
+/// assert_eq!(None, parse_note(b"p 1 2 3"));
+/// ```
+pub fn parse_note(mes: &[u8]) -> Option<String> {
+    let mes = String::from_utf8_lossy(mes);
+    let rest = mes.trim().strip_prefix("note")?;
+    if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+        Some(rest.trim().to_string())
     } else {
-        *hand = hand_copy;
-        *cards_from_table = cards_from_table_copy;
-        let message = format!("{}{} is not a valid sequence!\n", 
-                              &seq, &reset_style_string());
-        Ok(Some(message))
+        None
     }
 }
 
-fn take_sequence_remote(table: &mut Table, hand: &mut Sequence, mes: &[u8], stream: &mut TcpStream) 
-    -> Result<(), StreamError> 
-{
+/// split a line into the commands separated by semicolons, ignoring the empty ones
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::lib_server::split_batch;
+///
+/// assert_eq!(vec![b"t 3".to_vec(), b"p 1 2 14".to_vec(), b"e".to_vec()], 
+///            Vec::from(split_batch(b"t 3; p 1 2 14;; e ")));
+/// ```
+pub fn split_batch(mes: &[u8]) -> VecDeque<Vec<u8>> {
+    String::from_utf8_lossy(mes).split(';')
+        .map(str::trim)
+        .filter(|command| !command.is_empty())
+        .map(|command| command.as_bytes().to_vec())
+        .collect()
+}
+
+/// translate the quick-play command `q x y` (add card `x` of the hand to sequence `y` on the table)
+/// into the equivalent `a y x`
+///
+/// Return `None` if the message is not a quick-play command, including `q` alone (which proposes
+/// to save the game and quit).
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::lib_server::quick_play_to_add;
+///
+/// assert_eq!(Some(Ok(b"a 2 5".to_vec())), quick_play_to_add(b"q 5 2", 7));
+/// assert!(quick_play_to_add(b"q 9 2", 7).unwrap().is_err());
+/// assert_eq!(None, quick_play_to_add(b"q", 7));
+/// assert_eq!(None, quick_play_to_add(b"a 2 5", 7));
+/// ```
+pub fn quick_play_to_add(mes: &[u8], n_hand: usize) -> Option<Result<Vec<u8>, &'static str>> {
+    if mes.first() != Some(&b'q') {
+        return None;
+    }
+    let content = String::from_utf8_lossy(&mes[1..]);
+    let args: Vec<&str> = content.split_whitespace().collect();
+    if args.is_empty() {
+        return None;
+    }
+    Some(match (args.len(), args[0].parse::<usize>(), args.get(1).map(|s| s.parse::<usize>())) {
+        (2, Ok(card), Some(Ok(seq))) if card >= 1 && card <= n_hand => 
+            Ok(format!("a {} {}", seq, card).into_bytes()),
+        (2, Ok(_), Some(Ok(_))) => Err("Please give the index of a card in your hand\n"),
+        _ => Err("Error parsing the input!\n")
+    })
+}
+
+fn take_sequence_remote(game: &mut GameState, mes: &[u8]) -> Result<Option<String>, StreamError> {
     let content = String::from_utf8(mes.to_vec())?;
-    let content = content.trim().split(' ');
     let mut seq_i = Vec::<usize>::new();
-    for s in content {
-        match s.parse::<usize>() {
-            Ok(n) => {
-                let mut n_i: usize = 0;
-                for &i in &seq_i {
-                    if i < n {
-                        n_i += 1;
-                    }
-                }
-                seq_i.push(n);
-                match table.take(n-n_i) {
-                    Some(seq) => {
-                        hand.merge(seq.reverse());
-                    },
-                    None => send_message_to_client(stream, "This sequence is not on the table\n")?
-                }
-            },
-            Err(_) => send_message_to_client(stream, "Error parsing the input!\n")?
+    for s in content.split_whitespace() {
+        let n = match s.parse::<usize>() {
+            Ok(n) => n,
+            Err(_) => return Ok(Some("Error parsing the input!\n".to_string()))
         };
+        let n_i = seq_i.iter().filter(|&&i| i < n).count();
+        match game.take_from_table(n.saturating_sub(n_i)) {
+            Ok(_) => seq_i.push(n),
+            Err(RuleError::NoSuchSequence(_)) => 
+                return Ok(Some(format!("{}\n", RuleError::NoSuchSequence(n)))),
+            Err(e) => return Ok(Some(format!("{}\n", e)))
+        }
     }
-    Ok(())
+    Ok(None)
 }
 
-fn add_to_table_sequence_remote(table: &mut Table, hand: &mut Sequence, 
-                                cards_from_table: &mut Sequence, mes: &[u8]) 
+fn add_to_table_sequence_remote(game: &mut GameState, mes: &[u8], hooks: &RuleHooks) 
     -> Result<Option<String>, StreamError> 
 {
-    
-    // copy the initial hand and cards from tables
-    let hand_copy = hand.clone();
-    let cards_from_table_copy = cards_from_table.clone();
-
-    let mut seq_from_table: Sequence;
-    let mut seq_from_hand = Sequence::new();
-    let mut seq_from_hand_from_table = Sequence::new();
-
-    // parse the request
     let content = String::from_utf8(mes.to_vec())?;
-    let mut content = content.trim().split(' ');
+    let mut content = content.split_whitespace();
 
     // parse the index of the sequence to which to add cards
-    match content.next() {
-        Some(x) => match x.parse::<usize>() {
-            Ok(n) => match table.take(n) {
-                Some(seq) => {
-                    seq_from_table = seq;
-                },
-                None => {
-                    let message = format!("Sequence {} is not on the table\n", n);
-                    return Ok(Some(message))
-                }
-            },
-            Err(_) => {
-                let message = "Error parsing the input!\n".to_string();
-                return Ok(Some(message))
-            }
-        },
+    let sequence_index = match content.next().map(|x| x.parse::<usize>()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => return Ok(Some("Error parsing the input!\n".to_string())),
         None => return Ok(None)
-    }
-
-    // parse the sequence to play
-    let mut seq_i_hand = Vec::<usize>::new();
-    let mut seq_i_cft = Vec::<usize>::new();
-    let n_hand = hand.number_cards();
-    for s in content {
-        if let Ok(n) = s.parse::<usize>() {
-            if n <= n_hand {
-                let mut n_i = 0;
-                for &i in &seq_i_hand {
-                    if i < n {
-                        n_i += 1;
-                    }
-                }
-                let card = match hand.take_card(n-n_i) {
-                    Some(c) => c,
-                    None => continue
-                };
-                seq_from_hand.add_card(card);
-                seq_i_hand.push(n);
-            } else {
-                let m = n - n_hand;
-                let mut n_i = 0;
-                for &i in &seq_i_cft {
-                    if i < m {
-                        n_i += 1;
-                    }
-                }
-                let card = match cards_from_table.take_card(m-n_i) {
-                    Some(c) => c,
-                    None => continue
-                };
-                seq_from_hand_from_table.add_card(card);
-                seq_i_cft.push(m);
-            }
-        }
-    }
-
-    // clone the sequence from the table 
-    let seq_from_table_org = seq_from_table.clone();
-
-    // merge the sequences
-    seq_from_hand.merge(seq_from_hand_from_table);
-    seq_from_table.merge(seq_from_hand);
+    };
 
-    // if it is valid, add it to the table; if not, restore the original situation
-    if seq_from_table.is_valid() {
-         table.add(seq_from_table);
-         Ok(None)
-    } else {
-        *hand = hand_copy;
-        *cards_from_table = cards_from_table_copy;
-        table.add(seq_from_table_org);
-        let message = format!("{}{} is not a valid sequence!\n", 
-                              &seq_from_table, &reset_style_string());
-        Ok(Some(message))
-    }
+    // parse the cards to add
+    let indices: Vec<usize> = content.filter_map(|x| x.parse::<usize>().ok()).collect();
+    let added = indices_in_hand(game, &indices)
+        .and_then(|indices| game.add_cards_to_sequence(&indices, sequence_index, hooks));
+    Ok(added.err().map(|e| format!("{}\n", e)))
 }
 
 #[allow(clippy::too_many_arguments)]
-fn print_situation_remote(table: &Table, hands: &[Sequence], deck: &Sequence, 
-                          player_names: &[String], player: usize, current_player: usize, 
-                          stream: &mut TcpStream, print_instructions: bool, cards_from_table: &Sequence, 
-                          has_played_something: bool, print_reset_option: bool, message: &str) 
+fn print_situation_remote(game: &GameState, player_names: &[String], forfeited: &[bool], reversed: bool, 
+                          player: usize, streams: &mut [PlayerConnection], print_instructions: bool, 
+                          staged: &StagedCards, message: &str) 
     -> Result<(), StreamError>
 {
-    // string with the number of cards each player has
-    let mut string_n_cards = format!("\nNumber of cards ({} remaining in the deck):", deck.number_cards());
-    for i in 0..(hands.len()) {
-        string_n_cards += &format!("\n  {}: {}", &player_names[i], &hands[i].number_cards());
+    let current_player = game.current_player;
+
+    // the clients which only want the situation when a turn starts are not sent the moves
+    if player != current_player && streams[player].updates() == Updates::TurnBoundary {
+        return Ok(());
     }
-    string_n_cards += "\n";
 
+    // the cards taken from the table are not counted in the hand of the current player
+    let (hand_current_player, cards_from_table) = split_hand(game);
+
+    // string with the number of cards each player has
+    let hand_sizes: Vec<usize> = (0..game.hands.len()).map(|i| {
+        if i == current_player { 
+            hand_current_player.number_cards() + staged.number_cards_from_hand()
+        } else { 
+            game.hands[i].number_cards() 
+        }
+    }).collect();
+    let notes: Vec<&str> = streams.iter().map(connection_status).collect();
+    let string_n_cards = format!("{}\n{}\n", 
+        render::card_counts(player_names, &hand_sizes, &notes, forfeited, player, game.deck.number_cards()),
+        render::turn_order(player_names, current_player, forfeited, reversed));
+    let stream = &mut streams[player];
+
+    // only the current player sees their hand without the cards from the table, and the cards
+    // they have set aside
+    let (hand, staged_for_player) = if player == current_player {
+        (hand_current_player, staged.to_sequence())
+    } else {
+        (game.hands[player].clone(), Sequence::new())
+    };
+
+    // public state of the game, which the client checks against its hash; the numbers hidden from
+    // the player are left out, so that a modified client cannot show them
+    let visibility = render::visibility();
+    let state = PublicState {
+        player_names: player_names.to_vec(),
+        current_player,
+        table: game.table.clone(),
+        hand_sizes: hand_sizes.iter().enumerate()
+            .map(|(i, &n)| if visibility.hand_counts || i == player { n } else { 0 })
+            .collect(),
+        deck_size: if visibility.deck_count { game.deck.number_cards() } else { 0 }
+    };
+    send_state_to_client(stream, &state)?;
+
+    send_status_to_client(stream, &status_string(&player_names[current_player], &game.deck))?;
     clear_and_send_message_to_client(stream, 
-        &format!("\x1b[1m{}'s turn:{}", player_names[current_player], &reset_style_string()))?;
+        &format!("{}{}", bold(&format!("{}'s turn:", player_names[current_player])), &reset_style_string()))?;
     send_message_to_client(stream, &string_n_cards)?;
-    send_message_to_client(stream, &situation_to_string(table, &hands[player], cards_from_table, message))?;
+    send_message_to_client(stream, &situation_to_string(&game.table, &hand, &cards_from_table, 
+                                                             &staged_for_player, message))?;
+
+    // the note of the current player is shown until the end of their turn
+    if player == current_player && !stream.note().is_empty() {
+        let note = format!("\n{}Note:{} {}\n", bold("Note:"), reset_style_string(), stream.note());
+        send_message_to_client(stream, &note)?;
+    }
     if print_instructions {
         send_message_to_client(stream, "\n")?;
-        send_message_to_client(stream, &instructions_no_save(!has_played_something, print_reset_option))?;
+        send_message_to_client(stream, &format!("{}{}{}{}{}", 
+                                                instructions_no_save(!game.has_played(), game.has_cards_from_table()),
+                                                LOG_INSTRUCTION, INSPECT_INSTRUCTION, TRY_INSTRUCTION, 
+                                                NOTE_INSTRUCTION))?;
     }
     Ok(())
 }
 
 /// send a message as a string to a client
-pub fn send_str_to_client(stream: &mut TcpStream, s: &str) -> Result<(), StreamError> {
+pub fn send_str_to_client<S: Transport>(stream: &mut S, s: &str) -> Result<(), StreamError> {
     send_bytes_to_client(stream, s.as_bytes())?;
     Ok(())
 }
 
-fn send_bytes_to_client_no_wait(stream: &mut TcpStream, bytes: &[u8]) -> Result<(), StreamError> {
+fn send_bytes_to_client_no_wait<S: Transport>(stream: &mut S, bytes: &[u8]) -> Result<(), StreamError> {
     
     // ensure that the number of bytes is small enough
     if bytes.len() > MAX_N_BUFFERS * BUFFER_SIZE {
-        return Err(StreamError { message: format!(
-                    "Stream too long: size: {}, maximum size: {}",
-                    bytes.len(), MAX_N_BUFFERS*BUFFER_SIZE
-                   ) })
+        return Err(StreamError::TooLong { size: bytes.len(), max_size: MAX_N_BUFFERS*BUFFER_SIZE })
     }
 
     // the first bytes will determine the number of times the buffer should be read
@@ -649,17 +1581,19 @@ fn send_bytes_to_client_no_wait(stream: &mut TcpStream, bytes: &[u8]) -> Result<
     }
     stream.write_all(&[n_buffers])?;
 
-    // write the data stream
-    for i in 0..((n_buffers-1) as usize) {
-        stream.write_all(&bytes[i*BUFFER_SIZE..(i+1)*BUFFER_SIZE])?;
+    // write the data stream (nothing for an empty message, such as the first heartbeat)
+    for i in 1..(n_buffers as usize) {
+        stream.write_all(&bytes[(i-1)*BUFFER_SIZE..i*BUFFER_SIZE])?;
+    }
+    if n_buffers > 0 {
+        stream.write_all(&bytes[((n_buffers-1) as usize)*BUFFER_SIZE..])?;
     }
-    stream.write_all(&bytes[((n_buffers-1) as usize)*BUFFER_SIZE..])?;
     
     Ok(())
 }
 
 /// send a message as bytes to a client
-pub fn send_bytes_to_client(stream: &mut TcpStream, bytes: &[u8]) -> Result<(), StreamError> {
+pub fn send_bytes_to_client<S: Transport>(stream: &mut S, bytes: &[u8]) -> Result<(), StreamError> {
     
     send_bytes_to_client_no_wait(stream, bytes)?;
     
@@ -670,16 +1604,13 @@ pub fn send_bytes_to_client(stream: &mut TcpStream, bytes: &[u8]) -> Result<(),
 }
 
 /// get a message (string) from a client
-pub fn get_str_from_client(stream: &mut TcpStream) -> Result<String, StreamError> {
+pub fn get_str_from_client<S: Transport>(stream: &mut S) -> Result<String, StreamError> {
     let bytes = get_bytes_from_client(stream)?;
-    match String::from_utf8(bytes) {
-        Ok(s) => Ok(s),
-        Err(_) => Err(StreamError::from(BytesToStringError {}))
-    }
+    Ok(String::from_utf8(bytes)?)
 }
 
 /// get a message (bytes) from a client
-pub fn get_bytes_from_client(stream: &mut TcpStream) -> Result<Vec<u8>, StreamError> {
+pub fn get_bytes_from_client<S: Transport>(stream: &mut S) -> Result<Vec<u8>, StreamError> {
     
     // buffer
     let mut buffer: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
@@ -705,18 +1636,44 @@ pub fn get_bytes_from_client(stream: &mut TcpStream) -> Result<Vec<u8>, StreamEr
     Ok(res)
 }
 
+/// speed of the game, as the index of a `Speed` variant
+static SPEED: AtomicU8 = AtomicU8::new(Speed::Normal as u8);
+
+/// set the speed of the game, used by `wait` and `long_wait`
+pub fn set_speed(speed: Speed) {
+    SPEED.store(speed as u8, Ordering::Relaxed);
+}
+
+/// speed of the game
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::lib_server::*;
+///
+/// set_speed(Speed::Instant);
+/// assert_eq!(Speed::Instant, speed());
+/// ```
+pub fn speed() -> Speed {
+    match SPEED.load(Ordering::Relaxed) {
+        0 => Speed::Instant,
+        2 => Speed::Relaxed,
+        _ => Speed::Normal
+    }
+}
+
 /// wait a moment
 pub fn wait() {
-    std::thread::sleep(std::time::Duration::from_millis(N_MILLISECONDS_WAIT));
+    std::thread::sleep(std::time::Duration::from_millis(speed().wait_milliseconds()));
 }
 
 /// wait a longer moment
 pub fn long_wait() {
-    std::thread::sleep(std::time::Duration::from_millis(N_MILLISECONDS_LONG_WAIT));
+    std::thread::sleep(std::time::Duration::from_millis(speed().long_wait_milliseconds()));
 }
 
 /// check that no players have the same name; if yes, rename players
-pub fn ensure_names_are_different(player_names: &mut [String], client_streams: &mut [TcpStream]) 
+pub fn ensure_names_are_different(player_names: &mut [String], client_streams: &mut [PlayerConnection]) 
     -> Result<(), StreamError>
 {
     let mut cont = true;
@@ -740,103 +1697,395 @@ pub fn ensure_names_are_different(player_names: &mut [String], client_streams: &
 }
 
 /// send the instruction to send a message to the client, and read the response as a string
-pub fn get_string_from_client(stream: &mut TcpStream) -> Result<String, StreamError> {
+pub fn get_string_from_client(stream: &mut PlayerConnection) -> Result<String, StreamError> {
     let msg = get_message_from_client(stream)?;
-    match String::from_utf8(msg) {
-        Ok(s) => Ok(s),
-        Err(_) => Err(StreamError { message: "Could not convert the input to a string".to_string() })
-    }
+    Ok(String::from_utf8(msg)?)
 }
 
-fn get_message_from_client(stream: &mut TcpStream) -> Result<Vec<u8>, StreamError>{
-    stream.write_all(&[4])?;
-    get_bytes_from_client(stream)
+// ask the client for a message until it sends one which is not a duplicate of the previous one
+fn get_message_from_client(stream: &mut PlayerConnection) -> Result<Vec<u8>, StreamError>{
+    loop {
+        stream.write_all(&[4])?;
+        let message = get_bytes_from_client(stream)?;
+        if let Some(content) = stream.accept_move(&message) {
+            return Ok(content);
+        }
+    }
 }
 
 /// send the instruction to clear the screen and send back a message to the client, and read the 
 /// response as a string
-pub fn clear_and_send_message_to_client(stream: &mut TcpStream, msg: &str) -> Result<(), StreamError>{
+pub fn clear_and_send_message_to_client<S: Transport>(stream: &mut S, msg: &str) -> Result<(), StreamError>{
     stream.write_all(&[2])?;
     send_str_to_client(stream, msg)
 }
 
 /// send the instruction to print a message to the client, then send a message to the same client
-pub fn send_message_to_client(stream: &mut TcpStream, msg: &str) -> Result<(), StreamError>{
+pub fn send_message_to_client<S: Transport>(stream: &mut S, msg: &str) -> Result<(), StreamError>{
     stream.write_all(&[1])?;
     send_str_to_client(stream, msg)
 }
 
+/// send the instruction to update the status bar, then send the status to the same client
+pub fn send_status_to_client<S: Transport>(stream: &mut S, status: &str) -> Result<(), StreamError>{
+    stream.write_all(&[6])?;
+    send_str_to_client(stream, status)
+}
+
+/// send the public state of the game to a client, preceded by its hash
+///
+/// If the client has been sent a state with the same players, only the changes are sent, and the
+/// client applies them to its own copy of the state; if the result does not match the hash, the
+/// client asks for the whole state, which is sent again.
+pub fn send_state_to_client(stream: &mut PlayerConnection, state: &PublicState) -> Result<(), StreamError> {
+    let update = stream.last_state().and_then(|previous| state.to_update_message(previous));
+    stream.set_last_state(Some(state.clone()));
+    if let Some(update) = update {
+        stream.write_all(&[15])?;
+        send_bytes_to_client_no_wait(stream, &update)?;
+        let mut confirmation = [0];
+        stream.read_exact(&mut confirmation)?;
+        if confirmation[0] != RESYNC_REQUEST {
+            return Ok(());
+        }
+    }
+
+    // if the client still can not read the state, it asks for it again after the next update
+    stream.write_all(&[9])?;
+    send_bytes_to_client(stream, &state.to_sync_message())
+}
+
+/// send a message to a client and keep it in `pending_prompt`, to be sent again if the client 
+/// reconnects before replying
+pub fn send_prompt(stream: &mut PlayerConnection, pending_prompt: &mut String, msg: &str) 
+    -> Result<(), StreamError>
+{
+    *pending_prompt = msg.to_string();
+    stream.log_message(msg);
+    send_message_to_client(stream, msg)
+}
+
+/// ask a client how often it wants to be sent the situation while it is not its turn (see
+/// `Updates`)
+///
+/// Bots are not asked, and a reply which is not understood counts as `Updates::Full`.
+pub fn negotiate_updates(stream: &mut PlayerConnection) -> Result<(), StreamError> {
+    if stream.is_bot() {
+        return Ok(());
+    }
+    stream.write_all(&[11])?;
+    let reply = get_str_from_client(stream)?;
+    stream.set_updates(Updates::parse(&reply).unwrap_or_default());
+    Ok(())
+}
+
 /// send a message and get the response
-pub fn send_message_get_reply(stream: &mut TcpStream, message: &str) 
+///
+/// If the client sends again its previous reply, the message is sent again.
+pub fn send_message_get_reply(stream: &mut PlayerConnection, message: &str) 
     -> Result<Vec<u8>, StreamError>
 {
-    stream.write_all(&[3])?;
-    send_str_to_client(stream, message)?;
-    get_bytes_from_client(stream)
+    loop {
+        stream.write_all(&[3])?;
+        send_str_to_client(stream, message)?;
+        let reply = get_bytes_from_client(stream)?;
+        if let Some(content) = stream.accept_move(&reply) {
+            return Ok(content);
+        }
+    }
 }
 
 /// send the same message to all players
-pub fn send_message_all_players(client_streams: &mut [TcpStream], message: &str) {
+///
+/// Clients which do not confirm reception in time are reported as slow, but do not block the
+/// others.
+pub fn send_message_all_players(client_streams: &mut [PlayerConnection], message: &str) 
+    -> Vec<DeliveryState> 
+{
+    for stream in client_streams.iter_mut() {
+        stream.log_message(message);
+    }
+    broadcast(client_streams, 1, message.as_bytes())
+}
 
-    // send the messages
-    for cs in client_streams.iter_mut() {
-        cs.write_all(&[1]).unwrap_or(());
-        send_bytes_to_client_no_wait(cs, message.as_bytes()).unwrap_or(());
+/// send an announcement of the administrator of the server to all players, shown by the clients
+/// apart from the messages of the game
+pub fn send_announcement_all_players(client_streams: &mut [PlayerConnection], text: &str) 
+    -> Vec<DeliveryState> 
+{
+    for stream in client_streams.iter_mut() {
+        stream.log_message(&format!("[Announcement] {}\n", text));
     }
+    broadcast(client_streams, 12, text.as_bytes())
+}
 
-    // wait until all clients have confirmed reception
+/// update the status bar of all players
+///
+/// Clients which do not confirm reception in time are reported as slow, but do not block the
+/// others.
+pub fn send_status_all_players(client_streams: &mut [PlayerConnection], status: &str) 
+    -> Vec<DeliveryState> 
+{
+    broadcast(client_streams, 6, status.as_bytes())
+}
+
+/// send a heartbeat to all players, carrying the round-trip time (in milliseconds) measured with
+/// the previous one, to be shown in their status bars
+///
+/// The round-trip time is measured from the moment the reader thread of the connection receives
+/// the confirmation, so it does not include the time taken by the server to handle it.
+pub fn send_heartbeat_all_players(client_streams: &mut [PlayerConnection]) -> Vec<DeliveryState> {
+    let latencies: Vec<Vec<u8>> = client_streams.iter()
+        .map(|cs| cs.latency().map(|l| l.as_millis().to_string()).unwrap_or_default().into_bytes())
+        .collect();
     for cs in client_streams.iter_mut() {
-        cs.read_exact(&mut [0]).unwrap_or(());
+        cs.start_heartbeat();
+    }
+    broadcast_each(client_streams, 10, &latencies)
+}
+
+/// indication shown after the number of cards of a player who is disconnected
+pub fn connection_status(stream: &PlayerConnection) -> &'static str {
+    if stream.is_connected() { "" } else { " (disconnected)" }
+}
+
+/// string shown in the status bar of the clients
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::lib_server::*;
+///
+/// let deck = Sequence::from_cards(&[Joker, RegularCard(Heart, 3)]);
+///
+/// assert_eq!("Alice's turn | 2 cards in the deck", status_string("Alice", &deck));
+/// ```
+pub fn status_string(current_player_name: &str, deck: &Sequence) -> String {
+    if render::visibility().deck_count {
+        format!("{}'s turn | {} cards in the deck", current_player_name, deck.number_cards())
+    } else {
+        format!("{}'s turn", current_player_name)
     }
-    
 }
 
 /// clear the screens and send the same message to all players
-pub fn clear_and_send_message_all_players(client_streams: &mut [TcpStream], message: &str) {
+///
+/// Clients which do not confirm reception in time are reported as slow, but do not block the
+/// others.
+pub fn clear_and_send_message_all_players(client_streams: &mut [PlayerConnection], message: &str) 
+    -> Vec<DeliveryState> 
+{
+    broadcast(client_streams, 2, message.as_bytes())
+}
+
+// send the same request to all connected clients, then wait for their confirmations
+fn broadcast(client_streams: &mut [PlayerConnection], code: u8, bytes: &[u8]) -> Vec<DeliveryState> {
+    let messages = vec![bytes.to_vec(); client_streams.len()];
+    broadcast_each(client_streams, code, &messages)
+}
+
+// send a request to all connected clients, with a message for each of them, then wait for their
+// confirmations
+fn broadcast_each(client_streams: &mut [PlayerConnection], code: u8, messages: &[Vec<u8>]) 
+    -> Vec<DeliveryState> 
+{
 
     // send the messages
-    for cs in client_streams.iter_mut() {
-        cs.write_all(&[2]).unwrap_or(());
-        send_bytes_to_client_no_wait(cs, message.as_bytes()).unwrap_or(());
+    for (cs, bytes) in client_streams.iter_mut().zip(messages) {
+        if cs.is_connected() {
+            cs.write_all(&[code]).unwrap_or(());
+            send_bytes_to_client_no_wait(cs, bytes).unwrap_or(());
+        }
     }
 
-    // wait until all clients have confirmed reception
+    // wait until all clients have confirmed reception, or for the timeout; the confirmations are
+    // read by the reader thread of each client in the meantime, so the clients are given the same
+    // deadline and a slow one does not delay the others
+    let deadline = std::time::Instant::now() 
+        + std::time::Duration::from_millis(N_MILLISECONDS_ACKNOWLEDGEMENT_TIMEOUT);
+    let mut states = Vec::<DeliveryState>::new();
     for cs in client_streams.iter_mut() {
-        cs.read_exact(&mut [0]).unwrap_or(());
+        let state = cs.wait_for_acknowledgement(deadline.saturating_duration_since(std::time::Instant::now()));
+        if state == DeliveryState::Slow {
+            println!("{} is slow to respond", client_label(cs.player(), cs.is_spectator()));
+        }
+        states.push(state);
     }
-    
+    states
 }
 
-// errors
+#[cfg(test)]
+mod tests {
 
-#[derive(Debug)]
-pub struct StreamError {
-    message: String
-}
+    use super::*;
+    use crate::lib_client::{ send_str_to_server, get_str_from_server };
 
-#[derive(Debug)]
-pub struct BytesToStringError {}
+    // answer the request of the server for the update granularity after reconnecting
+    fn answer_updates(client: &mut EncryptedStream, updates: &str) {
+        let mut code = [0];
+        client.read_exact(&mut code).unwrap();
+        assert_eq!([11], code);
+        send_str_to_server(client, updates).unwrap();
+    }
 
-impl std::fmt::Display for StreamError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "StreamError: {}", self.message)
+    // connect to the listener as `message` (a name or session token) and read the reply of the 
+    // server: its code and message, plus the next code if the server closes the connection
+    fn connect_as(address: std::net::SocketAddr, message: &str) -> (EncryptedStream, Vec<u8>, String) {
+        let mut client = EncryptedStream::connect(TcpStream::connect(address).unwrap()).unwrap();
+        send_str_to_server(&mut client, message).unwrap();
+        let mut code = [0];
+        client.read_exact(&mut code).unwrap();
+        let mut codes = vec![code[0]];
+        let reply = get_str_from_server(&mut client).unwrap();
+        if code[0] == 2 {
+            client.read_exact(&mut code).unwrap();
+            codes.push(code[0]);
+        }
+        (client, codes, reply)
     }
-}
 
-impl std::convert::From<std::io::Error> for StreamError {
-    fn from(error: std::io::Error) -> Self {
-        StreamError { message: format!("IO Error: {}", error) }
+    // listener in the same mode as in the server, and a connection to it for player 0
+    fn listener_and_connection() -> (TcpListener, PlayerConnection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let old_client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+        listener.set_nonblocking(true).unwrap();
+        (listener, PlayerConnection::new(EncryptedStream::plain(server_side), 0).unwrap(), old_client)
     }
-}
 
-impl std::convert::From<BytesToStringError> for StreamError {
-    fn from(_error: BytesToStringError) -> Self {
-        StreamError { message: "Could not convert the byte sequence to a string".to_string() }
+    #[test]
+    fn reconnect_on_primary_listener() {
+        let (listener, mut connection, old_client) = listener_and_connection();
+        let address = listener.local_addr().unwrap();
+        drop(old_client);
+
+        let client = std::thread::spawn(move || {
+            let (_, codes, reply) = connect_as(address, "Mallory");
+            assert_eq!(vec![2, 5], codes);
+            assert_eq!("Sorry; you're not the player we're expecting\n", reply);
+            let mut client = connect_as(address, "Alice").0;
+            answer_updates(&mut client, "full");
+            client
+        });
+        wait_for_reconnection(&mut connection, "Alice", &listener).unwrap();
+        let mut client = client.join().unwrap();
+
+        // the connection now goes to the new client
+        connection.write_all(&[42]).unwrap();
+        let mut buffer = [0];
+        client.read_exact(&mut buffer).unwrap();
+        assert_eq!([42], buffer);
+    }
+
+    #[test]
+    fn reconnect_with_session_token() {
+        let (listener, mut connection, _old_client) = listener_and_connection();
+        let address = listener.local_addr().unwrap();
+        connection.set_session_token("token");
+
+        let client = std::thread::spawn(move || {
+            let (mut stream, codes, _) = connect_as(address, &format!("{}token", SESSION_TOKEN_PREFIX));
+            assert_eq!(vec![1], codes);
+            answer_updates(&mut stream, "turn");
+            stream
+        });
+        wait_for_reconnection(&mut connection, "Alice", &listener).unwrap();
+
+        // keep the client stream open, or the connection may already be seen as lost
+        let _client = client.join().unwrap();
+        assert!(connection.is_connected());
+        assert_eq!(Updates::TurnBoundary, connection.updates());
+    }
+
+    #[test]
+    fn resync_state() {
+        let (_listener, mut connection, client) = listener_and_connection();
+        let client = std::thread::spawn(move || {
+            let mut client = client;
+            let mut state = crate::lib_client::ClientState::new();
+            for _ in 0..4 {
+                crate::lib_client::handle_server_request(&mut [0], &mut client, &mut state).unwrap();
+            }
+            state
+        });
+
+        let mut table = Table::new();
+        table.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+        let first = PublicState { player_names: vec!["Alice".to_string(), "Bob".to_string()], 
+                                  current_player: 0, table, hand_sizes: vec![13, 13], deck_size: 78 };
+        let second = PublicState { hand_sizes: vec![10, 13], ..first.clone() };
+        let mut other_table = Table::new();
+        other_table.add(Sequence::from_cards(&[RegularCard(Heart, 7), RegularCard(Spade, 7), RegularCard(Club, 7)]));
+        let third = PublicState { current_player: 1, table: other_table.clone(), ..second.clone() };
+
+        // the whole state, then an update, then an update from a state the client does not have
+        send_state_to_client(&mut connection, &first).unwrap();
+        send_state_to_client(&mut connection, &second).unwrap();
+        connection.set_last_state(Some(PublicState { table: other_table, ..second.clone() }));
+        send_state_to_client(&mut connection, &third).unwrap();
+
+        let state = client.join().unwrap();
+        assert_eq!(Some(third), state.game_state);
+        assert_eq!(1, state.n_desyncs);
+    }
+
+    #[test]
+    fn greet_spectator() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            connect_as(address, &format!("{}Eve", SPECTATOR_PREFIX))
+        });
+        let (server_side, _) = listener.accept().unwrap();
+        let (_, name, _) = handle_client(server_side, &[]).unwrap();
+        let (_, codes, reply) = client.join().unwrap();
+
+        assert_eq!(format!("{}Eve", SPECTATOR_PREFIX), name);
+        assert_eq!(vec![1], codes);
+        assert_eq!("Hello Eve!\nYou are watching the game as a spectator.\n", reply);
+    }
+
+    #[test]
+    fn check_game_code() {
+        set_game_code("TIGER-42");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let wrong = connect_as(address, &format!("{}LION-7", GAME_CODE_PREFIX));
+            let (mut stream, codes, _) = connect_as(address, &format!("{}tiger-42", GAME_CODE_PREFIX));
+            send_str_to_server(&mut stream, "Alice").unwrap();
+            let mut code = [0];
+            stream.read_exact(&mut code).unwrap();
+            get_str_from_server(&mut stream).unwrap();
+            (wrong.1, wrong.2, codes, code[0])
+        });
+        let (server_side, _) = listener.accept().unwrap();
+        assert!(handle_client(server_side, &[]).is_err());
+        let (server_side, _) = listener.accept().unwrap();
+        let (_, name, _) = handle_client(server_side, &[]).unwrap();
+        let (wrong_codes, wrong_reply, codes, greeting) = client.join().unwrap();
+
+        assert_eq!(vec![0], wrong_codes);
+        assert_eq!("This server hosts another game than LION-7.\n", wrong_reply);
+        assert_eq!(vec![1], codes);
+        assert_eq!(1, greeting);
+        assert_eq!("Alice", name);
     }
-}
 
-impl std::convert::From<FromUtf8Error> for StreamError {
-    fn from(error: FromUtf8Error) -> Self {
-        StreamError { message: format!("UTF-8 error: {}", &error) }
+    #[test]
+    fn play_cards_taken_from_table() {
+        let mut table = Table::new();
+        table.add(Sequence::from_cards(&[RegularCard(Club, 5), RegularCard(Club, 6), RegularCard(Club, 7)]));
+        let hand = Sequence::from_cards(&[RegularCard(Heart, 9), RegularCard(Club, 4)]);
+        let mut game = GameState::new(table, vec![hand], Sequence::new(), 0);
+        let hooks = RuleHooks::from_rules(false, &RuleSet::default());
+
+        assert_eq!(None, take_sequence_remote(&mut game, b" 1").unwrap());
+        assert_eq!(Sequence::from_cards(&[RegularCard(Heart, 9), RegularCard(Club, 4)]), split_hand(&game).0);
+        assert_eq!(3, split_hand(&game).1.number_cards());
+        assert!(play_sequence_remote(&mut game, b" 1 3 4 5", &hooks).unwrap().is_some());
+        assert_eq!(None, play_sequence_remote(&mut game, b" 2 3 4 5", &hooks).unwrap());
+        assert!(!game.has_cards_from_table());
+        assert_eq!(&Sequence::from_cards(&[RegularCard(Heart, 9)]), game.hand());
     }
 }
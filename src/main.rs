@@ -3,28 +3,236 @@
 //! A simple machiavelli card game *(work in progress)*
 
 use std::process;
-use std::io::{ stdin, Read, Write };
-use std::fs::File;
+use std::env;
+use std::io::stdin;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
 use rand::thread_rng;
 use machiavelli::*;
 
+/// file where the game is saved if the program panics
+const EMERGENCY_SAVE_NAME: &str = "machiavelli_emergency.sav";
+
 fn main() {
 
+    // with `--bench-game [seed]`, play a game between bots and print the timings; each
+    // `--bot-profile file` gives the weights of the next bot
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(i) = args.iter().position(|a| a == "--bench-game") {
+        let seed = args.get(i + 1).and_then(|s| s.parse::<u64>().ok()).unwrap_or(0);
+        let save_name = env::temp_dir().join("machiavelli_bench.sav");
+        let weights: Vec<bot::BotWeights> = bot_profiles(&args).into_iter().map(|(_, w)| w).collect();
+        match bench::run_bench_game_with_weights(seed, &save_name.to_string_lossy(), &weights) {
+            Ok(report) => print!("{}", report),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        std::fs::remove_file(save_name).unwrap_or(());
+        return;
+    }
+
+    // with `--tournament [games per pair] [seed]`, play a tournament between the built-in
+    // strategies and those of the `--bot-profile` files, each turn taking at most `--move-time`
+    // milliseconds
+    if let Some(i) = args.iter().position(|a| a == "--tournament") {
+        let games_per_pair = args.get(i + 1).and_then(|s| s.parse::<usize>().ok()).unwrap_or(10);
+        let seed = args.get(i + 2).and_then(|s| s.parse::<u64>().ok()).unwrap_or(0);
+        let move_time = args.iter().position(|a| a == "--move-time")
+            .and_then(|j| args.get(j + 1))
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(1000);
+        let mut competition = tournament::Tournament::new(games_per_pair, Duration::from_millis(move_time), seed);
+        for name in tournament::BUILTIN_STRATEGIES {
+            let strategy = tournament::builtin_strategy(name, &competition.config, seed).unwrap();
+            competition.register(name, strategy);
+        }
+        for (fname, w) in bot_profiles(&args) {
+            competition.register(&fname, Box::new(tournament::WeightedStrategy::new(w)));
+        }
+        print!("{}", competition.run());
+        return;
+    }
+
+    // with `--analyze file`, print the annotated transcript of a replay
+    if let Some(i) = args.iter().position(|a| a == "--analyze") {
+        let fname = args.get(i + 1).map(String::as_str).unwrap_or("");
+        match replay::Replay::load(fname) {
+            Ok(replay) => print!("{}", replay.analyze()),
+            Err(_) => {
+                eprintln!("Could not read the replay {}", fname);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // set-up the terminal (plain output if it does not support colours)
+    terminal::init();
+
+    // the terminal is restored when leaving `main`, even after a panic
+    let _terminal_guard = terminal::TerminalGuard::new(false);
+
     // set the style
     reset_style();
 
     // clear the terminal
-    print!("\x1b[2J\x1b[1;1H");
+    clear_terminal();
 
-    // get the config
-    println!("Hi there! Up for a game of Machiavelli?\n");
-    let mut config = match get_config() {
-        Ok(conf) => conf, 
-        Err(_) => {
-            println!("Invalid input!");
-            process::exit(1);
+    // with `--continue`, resume the most recent save in the current directory without prompts
+    let mut continue_from = String::new();
+    if args.iter().any(|a| a == "--continue") {
+        match save::newest_save(".", ".sav") {
+            Some(path) => continue_from = path.to_string_lossy().to_string(),
+            None => {
+                println!("No save file to continue from!");
+                terminal::exit(1);
+            }
+        }
+    }
+
+    // optional rules
+    let name_file_rules = "Config/rules.dat";
+    let mut rules = if std::path::Path::new(name_file_rules).exists() {
+        match rules::RuleSet::load(name_file_rules) {
+            Ok(rules) => rules,
+            Err(_) => {
+                println!("Could not read the rules from {}", name_file_rules);
+                process::exit(1);
+            }
+        }
+    } else {
+        rules::RuleSet::default()
+    };
+
+    // limits of the search for the hints, with the `serde` feature (defaults if the file does not
+    // exist)
+    #[cfg(feature = "serde")]
+    {
+        let name_file_solver = "Config/solver.dat";
+        if std::path::Path::new(name_file_solver).exists() {
+            match solver::SolverLimits::load(name_file_solver) {
+                Ok(limits) => solver::set_limits(limits),
+                Err(_) => {
+                    println!("Could not read the limits of the solver from {}", name_file_solver);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+
+    // with `config save <name>`, ask for the settings and save them with the rules as a preset;
+    // with `config load <name>`, start a new game with the settings and rules of a preset
+    let mut preset_from_args = None;
+    match preset::parse_config_command(&args) {
+        Some(Ok(preset::ConfigCommand::Save(name))) => {
+            let config = match get_config() {
+                Ok(conf) if conf.n_decks > 0 => conf,
+                _ => {
+                    println!("Invalid input!");
+                    terminal::exit(1);
+                }
+            };
+            match (preset::Preset { config, rules }).save(&name) {
+                Ok(()) => println!("Settings saved as the preset {}", &name),
+                Err(_) => {
+                    println!("Could not write the preset {}", &name);
+                    terminal::exit(1);
+                }
+            }
+            return;
+        },
+        Some(Ok(preset::ConfigCommand::Load(name))) => match preset::Preset::load(&name) {
+            Ok(p) => preset_from_args = Some(p),
+            Err(_) => {
+                println!("{}", preset::load_error_message(&name));
+                terminal::exit(1);
+            }
+        },
+        Some(Err(e)) => {
+            println!("{}", e);
+            terminal::exit(1);
         },
+        None => ()
+    }
+
+    // without arguments, show the startup menu
+    println!("Hi there! Up for a game of Machiavelli?\n");
+    let mut load_requested = false;
+    if preset_from_args.is_none() && continue_from.is_empty() {
+        loop {
+            match startup_menu() {
+                MenuChoice::NewGame => {
+                    preset_from_args = choose_preset();
+                    break;
+                },
+                MenuChoice::Continue => match save::newest_save(".", ".sav") {
+                    Some(path) => {
+                        continue_from = path.to_string_lossy().to_string();
+                        break;
+                    },
+                    None => println!("No save file to continue from!\n")
+                },
+                MenuChoice::Load => {
+                    load_requested = true;
+                    break;
+                },
+                MenuChoice::Join => run_companion("client"),
+                MenuChoice::Host => run_companion("server"),
+                MenuChoice::Tutorial => {
+                    clear_terminal();
+                    println!("{}", TUTORIAL);
+                    println!("(press enter to go back to the menu)");
+                    get_input().unwrap_or_default();
+                    clear_terminal();
+                },
+                MenuChoice::Quit => return
+            }
+        }
+    }
+
+    // get the config
+    let mut config = if let Some(p) = preset_from_args {
+        rules = p.rules;
+        p.config
+    } else if !continue_from.is_empty() || load_requested {
+        if !continue_from.is_empty() {
+            println!("Continuing the game saved in {}", &continue_from);
+        }
+        Config {
+            n_decks: 0,
+            n_jokers: 0,
+            n_cards_to_start: 0,
+            custom_rule_jokers: false,
+            n_players: 0,
+            initial_meld_points: 0
+        }
+    } else {
+        match get_config() {
+            Ok(conf) => conf, 
+            Err(_) => {
+                println!("Invalid input!");
+                terminal::exit(1);
+            },
+        }
+    };
+    
+    // where the save files are stored
+    let save_backend = match save::backend_from_env() {
+        Ok(b) => b,
+        Err(e) => {
+            println!("{}", e);
+            terminal::exit(1);
+        }
     };
+
+    // `--continue` only finds the local save files
+    if !continue_from.is_empty() && !save_backend.is_local() {
+        println!("Only the local save files can be continued; give the name of the save file instead.");
+        terminal::exit(1);
+    }
     
     // create the table
     let mut table = Table::new();
@@ -33,51 +241,79 @@ fn main() {
     let mut starting_player: u8 = 0;
     let mut player: u8 = 0;
     let mut player_names = Vec::<String>::new();
+    let mut opened = Vec::<bool>::new();
 
-    if config.n_decks == 0 {
+    // number of seats given to bots (none if the file does not exist); at least one seat is left
+    // to the players
+    let name_file_n_bots = "Config/n_bots.dat";
+    let n_bots = std::fs::read_to_string(name_file_n_bots).ok()
+        .and_then(|s| s.split_whitespace().next().and_then(|w| w.parse::<u8>().ok()))
+        .unwrap_or(0);
+    let mut n_humans = config.n_players.saturating_sub(n_bots).max(1);
+
+    let loaded = config.n_decks == 0;
+    if loaded {
         
         // load the previous game
-        println!("Name of the save file:");
-        let mut fname = String::new();
-        let mut bytes = Vec::<u8>::new();
+        let mut fname = continue_from.clone();
+        let mut bytes: Vec<u8>;
         let mut retry = true;
         while retry {
 
             retry = false;
             
-            // get the file name
-            match stdin().read_line(&mut fname) {
-                Ok(_) => (),
-                Err(_) => retry = true
-            };
+            // get the file name if not set
+            if fname.is_empty() {
+                println!("Name of the save file:");
+                match stdin().read_line(&mut fname) {
+                    Ok(_) => (),
+                    Err(_) => retry = true
+                };
+            }
 
             fname = fname.trim().to_string();
 
             if !retry {
 
-                // load the data from the file
-                let mut file: File; 
-                match File::open(fname.clone()) {
-                    Ok(f) => file = f,
+                // load the data from the save backend
+                match save_backend.read(&fname) {
+                    Ok(b) => bytes = b,
                     Err(_) => {
-                        println!("Could not open the file!");
+                        println!("Could not read from the file!");
                         retry = true;
                         fname.clear();
                         continue;
                     }
                 };
-                match file.read_to_end(&mut bytes) {
-                    Ok(_) => (),
-                    Err(_) => {
-                        println!("Could not read from the file!");
-                        retry = true;
-                        bytes.clear();
-                        fname.clear();
+                
+                // decode the sequence of bytes; a save written by the first versions can not be
+                // checked, and is only loaded if the player agrees
+                bytes = match encode::decode_save_with_migration(&bytes, &passphrase_from_env(), &fname) {
+                    Ok((decoded_bytes, legacy)) => {
+                        if legacy {
+                            println!("The save file has been written by a previous version; it will be converted the next time the game is saved.");
+                        }
+                        decoded_bytes
+                    },
+                    Err(e) => {
+                        println!("Could not decrypt the save file: {}.", e);
+                        let unchecked = continue_from.is_empty() 
+                            && !bytes.starts_with(&encode::ENCRYPTED_SAVE_MAGIC) && {
+                            println!("It may have been written by one of the first versions, which can not be checked. Load it anyway? (‘y’ for yes)");
+                            lib_server::is_yes(get_input().unwrap_or_default().trim())
+                        };
+                        if !unchecked {
+                            if !continue_from.is_empty() {
+                                println!("Is MACHIAVELLI_PASSPHRASE set correctly?");
+                                terminal::exit(1);
+                            }
+                            retry = true;
+                            fname.clear();
+                            continue;
+                        }
+                        encode::decode_unchecked_save(&bytes, &fname)
                     }
                 };
-                
-                // decode the sequence of bytes
-                bytes = encode::xor(&bytes, fname.as_bytes());
 
                 match load_game(&bytes) {
                     Ok(lg) => {
@@ -88,10 +324,16 @@ fn main() {
                         hands = lg.4; 
                         deck = lg.5;
                         player_names = lg.6;
-                        bytes = Vec::<u8>::new();
+                        opened = lg.7;
+                        n_humans = config.n_players.saturating_sub(n_bots).max(1);
                     },
-                    Err(_) => {
-                        println!("Error loading the save file!");
+                    Err(e) => {
+                        println!("Error loading the save file: {}!", e);
+                        if !continue_from.is_empty() {
+                            terminal::exit(1);
+                        }
+                        retry = true;
+                        fname.clear();
                     }
                 };
             }
@@ -104,15 +346,12 @@ fn main() {
         deck = Sequence::multi_deck(config.n_decks, config.n_jokers, &mut rng);
         
         // build the hands
-        hands = vec![Sequence::new(); config.n_players as usize];
-        for i in 0..config.n_players {
-            for _ in 0..config.n_cards_to_start {
-                hands[i as usize].add_card(deck.draw_card().unwrap());
-            }
-        }
+        hands = deck.deal(config.n_players as usize, config.n_cards_to_start as usize);
+        debug_assert_eq!(Ok(()), deck_audit(hands.iter().chain(Some(&deck)).flatten(),
+                                             config.n_decks, config.n_jokers));
 
-        // get the players name
-        for i in 0..config.n_players {
+        // get the players name; the last seats go to bots
+        for i in 0..n_humans {
             println!("Player {}'s name: ", i+1);
             let mut cont = true;
             while cont {
@@ -125,6 +364,10 @@ fn main() {
                 };
             }
         }
+        for _ in n_humans..config.n_players {
+            let name = bot::bot_name(&player_names);
+            player_names.push(name);
+        }
 
 
     }
@@ -132,17 +375,49 @@ fn main() {
     // play until a player wins, there is no card left in the deck, or the player decides to save
     // and quit
     let mut save_and_quit: bool;
+    print!("{}", rules.summary());
+    if config.initial_meld_points > 0 {
+        println!("The first meld of each player must be worth at least {} points.", config.initial_meld_points);
+    }
+    let hooks = hooks::RuleHooks::from_rules(config.custom_rule_jokers, &rules);
+    let mut game = game::GameState::new(table, hands, deck, player as usize);
+
+    // in a loaded game, the players who had made their first meld keep it
+    game.initial_meld_points = config.initial_meld_points;
+    if loaded {
+        game.opened = opened;
+    }
+    let mut bot_player: Box<dyn bot::BotPlayer> = Box::new(bot::GreedyBot);
+    save::install_emergency_save_hook(PathBuf::from(EMERGENCY_SAVE_NAME));
+
+    // the key of the saves is derived once for the whole game
+    let save_key = encode::SaveKey::new(&passphrase_from_env());
     loop {
-        if deck.number_cards() == 0 {
-            println!("\x1b[1mNo more cards in the deck—It's a draw!\x1b[0m\n");
+
+        // keep the state at the start of the turn, to be saved if the game panics
+        let bytes = game_to_bytes(starting_player, player, &game.table, &game.hands, &game.deck, &config, 
+                                  &player_names, &game.opened);
+        save::set_emergency_state(save_key.encrypt(&bytes));
+
+        if game.deck.number_cards() == 0 {
+            println!("{}No more cards in the deck—It's a draw!{}\n", 
+                     style_code("\x1b[1m"), style_code("\x1b[0m"));
             break;
         }
-        save_and_quit = player_turn(&mut table, &mut hands[player as usize], 
-                                    &mut deck, config.custom_rule_jokers, &player_names[player as usize]);
+        game.start_turn(player as usize);
+        if player >= n_humans {
+            let action = bot_player.play_turn(&mut game, &hooks, &rules, false);
+            println!("{} {}", &player_names[player as usize], action);
+            thread::sleep(Duration::from_secs(1));
+            save_and_quit = false;
+        } else {
+            save_and_quit = player_turn(&mut game, &hooks, &rules, &player_names[player as usize]);
+        }
         if save_and_quit {
             
             // convert the game data to a sequence of bytes
-            let mut bytes = game_to_bytes(starting_player, player, &table, &hands, &deck, &config, &player_names);
+            let bytes = game_to_bytes(starting_player, player, &game.table, &game.hands, &game.deck, &config,
+                                      &player_names, &game.opened);
 
             println!("Name of the save file:");
             let mut fname = String::new();
@@ -159,25 +434,17 @@ fn main() {
                 fname = fname.trim().to_string();
 
                 // obfuscate the save file (not very secure!)
-                bytes = encode::xor(&bytes, fname.as_bytes());
+                let encoded_bytes = save_key.encrypt(&bytes);
                 
                 if !retry {
 
-                    // save the data to the file
-                    let mut file: File; 
-                    match File::create(fname.clone()) {
-                        Ok(f) => file = f,
-                        Err(_) => {
-                            println!("Could not create the file!");
-                            retry = true;
-                            continue;
-                        }
-                    };
-                    match file.write_all(&bytes) {
+                    // save the data with the save backend
+                    match save_backend.write(&fname, &encoded_bytes) {
                         Ok(_) => (),
                         Err(_) => {
                             println!("Could not write to the file!");
                             retry = true;
+                            fname.clear();
                         }
                     };
                 }
@@ -185,14 +452,136 @@ fn main() {
 
             break;
         }
-        if hands[player as usize].number_cards() == 0 {
-            println!("\x1b[1mPlayer {} wins! Congratulations!\x1b[0m\n", player+1);
+        if game.hands[player as usize].number_cards() == 0 {
+            println!("{}Player {} wins! Congratulations!{}\n", 
+                     style_code("\x1b[1m"), player+1, style_code("\x1b[0m"));
             break;
         }
         player = (player + 1) % config.n_players;
     }
     
-    // reset the style
-    println!("\x1b[0m");
-    print!("\x1b[?25h");
+    // the style is reset when the terminal guard is dropped
+    println!();
+}
+
+// names and weights of the bots of the `--bot-profile` files
+#[cfg(feature = "serde")]
+fn bot_profiles(args: &[String]) -> Vec<(String, bot::BotWeights)> {
+    let mut profiles = Vec::new();
+    for (j, _) in args.iter().enumerate().filter(|(_, a)| *a == "--bot-profile") {
+        let fname = args.get(j + 1).map(String::as_str).unwrap_or("");
+        match bot::BotWeights::load(fname) {
+            Ok(w) => profiles.push((fname.to_string(), w)),
+            Err(_) => {
+                eprintln!("Could not read the bot profile {}", fname);
+                process::exit(1);
+            }
+        }
+    }
+    profiles
+}
+
+// without the `serde` feature, the profiles can not be read
+#[cfg(not(feature = "serde"))]
+fn bot_profiles(args: &[String]) -> Vec<(String, bot::BotWeights)> {
+    if args.iter().any(|a| a == "--bot-profile") {
+        eprintln!("The bot profiles can only be read with the serde feature");
+        process::exit(1);
+    }
+    Vec::new()
+}
+
+// offer to start from one of the saved presets, if any
+fn choose_preset() -> Option<preset::Preset> {
+    let presets = preset::list_presets();
+    if presets.is_empty() {
+        return None;
+    }
+    println!("Saved presets: {}", presets.join(", "));
+    loop {
+        println!("Preset to use (nothing to choose the settings):");
+        let name = get_input().ok()?.trim().to_string();
+        if name.is_empty() {
+            return None;
+        }
+        match preset::Preset::load(&name) {
+            Ok(p) => {
+                println!("Using the preset {}", &name);
+                return Some(p);
+            },
+            Err(_) => println!("{}", preset::load_error_message(&name))
+        }
+    }
+}
+
+
+/// entries of the startup menu
+enum MenuChoice {
+    NewGame,
+    Continue,
+    Load,
+    Join,
+    Host,
+    Tutorial,
+    Quit
+}
+
+// show the startup menu until a valid entry is chosen
+fn startup_menu() -> MenuChoice {
+    loop {
+        println!("1: New local game\n2: Continue the last game\n3: Load a save file\n\
+                  4: Join a server\n5: Host a server\n6: Tutorial\n7: Quit");
+        let choice = match get_input() {
+            Ok(s) => s,
+            Err(_) => return MenuChoice::Quit
+        };
+        match choice.trim() {
+            "1" | "" => return MenuChoice::NewGame,
+            "2" => return MenuChoice::Continue,
+            "3" => return MenuChoice::Load,
+            "4" => return MenuChoice::Join,
+            "5" => return MenuChoice::Host,
+            "6" => return MenuChoice::Tutorial,
+            "7" | "q" => return MenuChoice::Quit,
+            _ => println!("Invalid input\n")
+        }
+    }
+}
+
+// run the client or the server, installed next to this executable, and wait until it stops
+fn run_companion(name: &str) {
+    let path = env::current_exe().ok()
+        .and_then(|exe| Some(exe.parent()?.join(format!("{}{}", name, env::consts::EXE_SUFFIX))));
+    let status = match path {
+        Some(path) => process::Command::new(&path).status()
+            .map_err(|e| format!("Could not start {}: {}", path.display(), e)),
+        None => Err(format!("Could not find the {} executable", name))
+    };
+    match status {
+        Ok(_) => println!(),
+        Err(e) => println!("{}\n", e)
+    }
+    reset_style();
 }
+
+/// short introduction to the game, shown by the startup menu
+const TUTORIAL: &str = "\
+The aim of the game is to be the first player with no card left in their hand.
+
+The cards are played on the table in sequences of at least three cards: either cards of the same
+value and different suits (like 7♥ 7♠ 7♣), or cards of the same suit with consecutive values (like
+4♣ 5♣ 6♣; an ace can come before a two or after a king). A joker can replace any card.
+
+During your turn, you can:
+  * play a sequence from your hand (p),
+  * take a sequence from the table (t) to make new ones with your cards and the other sequences;
+    all the cards taken from the table must be played again before the end of your turn,
+  * end your turn (a) once you have played at least one card, or pick a card (c) if you can not
+    play anything,
+  * sort your hand by rank or suit (r, s), or put the table back as it was at the beginning of
+    your turn (g), at the cost of a penalty.
+
+The cards of your hand are numbered: to play the cards 2, 5, and 7, type p, then 2 5 7.
+
+Settings such as the number of decks, of jokers, and of players are asked when starting a new
+game; optional rules can be set in Config/rules.dat (see the Readme).";
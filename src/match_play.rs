@@ -0,0 +1,124 @@
+//! Matches of several rounds
+//!
+//! With the `match_score` rule, the games are the rounds of a match: at the end of each round, the
+//! players score the points of the cards left in their hand (see the `scoring` module), and the
+//! match ends when someone's total goes over `match_score`. The player with the lowest total
+//! then wins the match.
+
+use std::fmt;
+use crate::InvalidInputError;
+use crate::scoring::HandScore;
+
+/// Scores of the players during a match
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MatchScores {
+    pub n_rounds: usize,
+    pub names: Vec<String>,
+    pub scores: Vec<u32>
+}
+
+impl MatchScores {
+
+    /// Start a match between the given players
+    pub fn new(names: &[String]) -> MatchScores {
+        MatchScores { n_rounds: 0, names: names.to_vec(), scores: vec![0; names.len()] }
+    }
+
+    /// Add the scores of a round (see `scoring::round_scores`); the players who joined during the
+    /// match start from 0
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::match_play::MatchScores;
+    /// use machiavelli::scoring::round_scores;
+    ///
+    /// let names = vec!["Alice".to_string(), "Bob".to_string()];
+    /// let mut scores = MatchScores::new(&names);
+    /// let hands = vec![Sequence::new(), Sequence::from_cards(&[RegularCard(Heart, 12), Joker])];
+    ///
+    /// scores.add_round(&names, &round_scores(&hands, &[false, false], 25));
+    /// assert_eq!(vec![0, 35], scores.scores);
+    /// assert!(scores.is_over(30));
+    /// assert_eq!(Some(0), scores.winner(&[false, false]));
+    /// ```
+    pub fn add_round(&mut self, names: &[String], round: &[HandScore]) {
+        self.names = names.to_vec();
+        self.scores.resize(names.len(), 0);
+        for (score, hand) in self.scores.iter_mut().zip(round) {
+            *score += hand.total();
+        }
+        self.n_rounds += 1;
+    }
+
+    /// Check if someone has gone over the target score
+    pub fn is_over(&self, target: u32) -> bool {
+        self.scores.iter().any(|&s| s > target)
+    }
+
+    /// Player with the lowest score among those still in the game (the first one in case of a tie)
+    pub fn winner(&self, forfeited: &[bool]) -> Option<usize> {
+        (0..self.scores.len())
+            .filter(|&i| !forfeited.get(i).copied().unwrap_or(false))
+            .min_by_key(|&i| self.scores[i])
+    }
+
+    /// Standings, from the lowest score to the highest
+    pub fn summary(&self) -> String {
+        let mut order: Vec<usize> = (0..self.scores.len()).collect();
+        order.sort_by_key(|&i| self.scores[i]);
+        order.iter().map(|&i| format!("  {}: {} points\n", self.names[i], self.scores[i])).collect()
+    }
+
+    /// Read the scores from a string, with the number of rounds on the first line and then one
+    /// player per line, as the score followed by the name
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::match_play::MatchScores;
+    ///
+    /// let scores = MatchScores::from_string("rounds = 2\n12 Alice\n40 Bob Smith\n").unwrap();
+    ///
+    /// assert_eq!(vec![12, 40], scores.scores);
+    /// assert_eq!("Bob Smith", scores.names[1]);
+    /// assert_eq!(scores, MatchScores::from_string(&scores.to_string()).unwrap());
+    /// ```
+    pub fn from_string(s: &str) -> Result<MatchScores, InvalidInputError> {
+        let mut lines = s.lines();
+        let n_rounds = lines.next()
+            .and_then(|line| line.split_once('='))
+            .filter(|(name, _)| name.trim() == "rounds")
+            .ok_or(InvalidInputError {})?
+            .1.trim().parse()?;
+        let mut scores = MatchScores { n_rounds, ..MatchScores::default() };
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            let (score, name) = line.split_once(' ').ok_or(InvalidInputError {})?;
+            scores.scores.push(score.parse()?);
+            scores.names.push(name.to_string());
+        }
+        Ok(scores)
+    }
+
+    /// Read the scores from a file
+    pub fn load(fname: &str) -> Result<MatchScores, InvalidInputError> {
+        MatchScores::from_string(&std::fs::read_to_string(fname)?)
+    }
+
+    /// Write the scores to a file
+    pub fn save(&self, fname: &str) -> Result<(), InvalidInputError> {
+        std::fs::write(fname, self.to_string())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for MatchScores {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "rounds = {}", self.n_rounds)?;
+        for (name, score) in self.names.iter().zip(&self.scores) {
+            writeln!(f, "{} {}", score, name)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,561 @@
+//! Connections between the server and the clients
+//!
+//! Each client has its own reader thread, which reads everything the client sends and forwards it
+//! through a channel to the game logic running in the main thread. This way, a disconnection is
+//! noticed as soon as it happens, even if the client is not the one the server is waiting for.
+//!
+//! Similarly, each client has its own writer thread with an outbound queue, so that a client which
+//! does not read what it is sent can not block the server.
+//!
+//! All the streams have read and write timeouts. A read timeout only means that the client has
+//! nothing to say, and the reader thread tries again; a write timeout means that the client has
+//! stopped reading (for instance, because the connection is half-open), and it is disconnected.
+//!
+//! The reader threads note when each piece of data arrives, so that the round-trip time of a
+//! heartbeat does not include the time the game logic takes to get to its confirmation.
+
+use std::io::{ Read, Write };
+use std::net::{ TcpStream, Shutdown };
+use std::sync::Arc;
+use std::collections::VecDeque;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::{ channel, Receiver, Sender, RecvTimeoutError };
+use std::thread;
+use std::time::{ Duration, Instant };
+use super::is_timeout;
+use super::encryption::EncryptedStream;
+
+/// size of the buffer used by the reader threads
+const READ_BUFFER_SIZE: usize = 1024;
+
+/// time after which a read from a client is interrupted and tried again (in seconds)
+const N_SECONDS_READ_TIMEOUT: u64 = 60;
+
+/// time after which a client which does not read what it is sent is disconnected (in seconds)
+const N_SECONDS_WRITE_TIMEOUT: u64 = 30;
+
+/// number of messages kept in the log of each player
+const MESSAGE_LOG_SIZE: usize = 50;
+
+/// Delivery state of a message sent to a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryState {
+    
+    /// the client has confirmed reception
+    Delivered,
+    
+    /// the client has not confirmed reception in time
+    Slow,
+    
+    /// the client is disconnected
+    Disconnected
+}
+
+/// Connection to a client
+///
+/// Reading from a `PlayerConnection` takes the data received by its reader thread; writing to it
+/// puts the data in the outbound queue of its writer thread.
+pub struct PlayerConnection {
+    player: usize,
+    stream: TcpStream,
+    incoming: Receiver<(Instant, Vec<u8>)>,
+    outgoing: Sender<Vec<u8>>,
+    pending: Vec<u8>,
+    received_at: Instant,
+    heartbeat_sent_at: Option<Instant>,
+    latency: Option<Duration>,
+    updates: super::Updates,
+    note: String,
+    connected: Arc<AtomicBool>,
+    n_missing_acknowledgements: usize,
+    session_token: String,
+    bot: bool,
+    spectator: bool,
+    name: String,
+    log: VecDeque<String>,
+    last_move_id: Option<u64>,
+    last_state: Option<crate::compact::PublicState>
+}
+
+impl PlayerConnection {
+
+    /// Create a connection for the given player and start its reader and writer threads
+    pub fn new(stream: EncryptedStream, player: usize) -> std::io::Result<PlayerConnection> {
+        PlayerConnection::start(stream, player, false)
+    }
+
+    /// Create the connection of a spectator, who is only sent what all the players can see
+    pub fn spectator(stream: EncryptedStream, name: &str) -> std::io::Result<PlayerConnection> {
+        let mut connection = PlayerConnection::start(stream, 0, true)?;
+        connection.name = name.to_string();
+        Ok(connection)
+    }
+
+    // create a connection and start its reader and writer threads
+    fn start(stream: EncryptedStream, player: usize, spectator: bool) -> std::io::Result<PlayerConnection> {
+        set_timeouts(stream.get_ref())?;
+        let connected = Arc::new(AtomicBool::new(true));
+        let (reader_stream, writer_stream) = stream.split()?;
+        let stream = writer_stream.get_ref().try_clone()?;
+        let incoming = spawn_reader(reader_stream, client_label(player, spectator), connected.clone());
+        let outgoing = spawn_writer(writer_stream, connected.clone());
+        Ok(PlayerConnection {
+            player,
+            stream,
+            incoming,
+            outgoing,
+            pending: Vec::new(),
+            received_at: Instant::now(),
+            heartbeat_sent_at: None,
+            latency: None,
+            updates: super::Updates::Full,
+            note: String::new(),
+            connected,
+            n_missing_acknowledgements: 0,
+            session_token: String::new(),
+            bot: false,
+            spectator,
+            name: String::new(),
+            log: VecDeque::with_capacity(MESSAGE_LOG_SIZE),
+            last_move_id: None,
+            last_state: None
+        })
+    }
+
+    /// Replace the underlying stream, for instance after the player has reconnected
+    pub fn replace_stream(&mut self, stream: EncryptedStream) -> std::io::Result<()> {
+        set_timeouts(stream.get_ref())?;
+        let (reader_stream, writer_stream) = stream.split()?;
+        let stream = writer_stream.get_ref().try_clone()?;
+        let old_stream = std::mem::replace(&mut self.stream, stream);
+        self.connected = Arc::new(AtomicBool::new(true));
+        
+        // replacing the channels stops the previous threads silently
+        self.incoming = spawn_reader(reader_stream, client_label(self.player, self.spectator), 
+                                     self.connected.clone());
+        self.outgoing = spawn_writer(writer_stream, self.connected.clone());
+        self.pending.clear();
+        self.n_missing_acknowledgements = 0;
+        self.heartbeat_sent_at = None;
+        self.latency = None;
+        self.updates = super::Updates::Full;
+        self.last_state = None;
+        old_stream.shutdown(Shutdown::Both).unwrap_or(());
+        Ok(())
+    }
+
+    /// Last state of the game sent to the client, from which the next one is sent as an update
+    pub fn last_state(&self) -> Option<&crate::compact::PublicState> {
+        self.last_state.as_ref()
+    }
+
+    /// Set the last state of the game sent to the client
+    pub fn set_last_state(&mut self, state: Option<crate::compact::PublicState>) {
+        self.last_state = state;
+    }
+
+    /// Check if the client is still connected
+    ///
+    /// This is updated by the reader thread as soon as the connection is closed.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Check if the client has not yet confirmed reception of some messages
+    pub fn is_slow(&self) -> bool {
+        self.n_missing_acknowledgements > 0
+    }
+
+    /// Clone of the underlying stream, which can be used to close the connection from another
+    /// thread
+    pub fn try_clone_stream(&self) -> std::io::Result<TcpStream> {
+        self.stream.try_clone()
+    }
+
+    /// Note that a heartbeat is being sent; its round-trip time is measured when the client
+    /// confirms reception (see `wait_for_acknowledgement`)
+    pub fn start_heartbeat(&mut self) {
+        self.heartbeat_sent_at = Some(Instant::now());
+    }
+
+    /// Round-trip time of the last heartbeat confirmed in time, if any
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// How often the client wants to be sent the situation while it is not its turn
+    pub fn updates(&self) -> super::Updates {
+        self.updates
+    }
+
+    /// Set how often the client wants to be sent the situation while it is not its turn
+    pub fn set_updates(&mut self, updates: super::Updates) {
+        self.updates = updates;
+    }
+
+    /// Note written by the player during their turn (empty if none); it is kept when the player
+    /// reconnects
+    pub fn note(&self) -> &str {
+        &self.note
+    }
+
+    /// Set the note of the player; an empty note clears it
+    pub fn set_note(&mut self, note: &str) {
+        self.note = note.to_string();
+    }
+
+    /// Index of the player
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    /// Check if the player is a bot (see the `bot` module)
+    pub fn is_bot(&self) -> bool {
+        self.bot
+    }
+
+    /// Mark the player as a bot
+    pub fn set_bot(&mut self) {
+        self.bot = true;
+    }
+
+    /// Check if the client is a spectator
+    pub fn is_spectator(&self) -> bool {
+        self.spectator
+    }
+
+    /// Name of the spectator (empty for the players, whose names are kept with the game)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Turn the connection of a spectator into the connection of the given player, who takes a
+    /// seat left by someone else
+    pub fn take_seat(&mut self, player: usize) {
+        self.player = player;
+        self.spectator = false;
+    }
+
+    /// Session token allowing the player to reconnect without typing their name
+    pub fn session_token(&self) -> &str {
+        &self.session_token
+    }
+
+    /// Set the session token of the player
+    pub fn set_session_token(&mut self, token: &str) {
+        self.session_token = token.to_string();
+    }
+
+    /// Check if a message sent by a client reconnecting is the session token of this player
+    pub fn matches_session_token(&self, message: &str) -> bool {
+        !self.session_token.is_empty() 
+            && message.strip_prefix(super::SESSION_TOKEN_PREFIX) == Some(&self.session_token)
+    }
+
+    /// Keep a message in the log of the player, forgetting the oldest one if the log is full
+    pub fn log_message(&mut self, message: &str) {
+        if self.log.len() == MESSAGE_LOG_SIZE {
+            self.log.pop_front();
+        }
+        self.log.push_back(message.to_string());
+    }
+
+    /// Last `n` messages of the log, from the oldest to the most recent
+    pub fn recent_messages(&self, n: usize) -> Vec<String> {
+        self.log.iter().skip(self.log.len().saturating_sub(n)).cloned().collect()
+    }
+
+    /// Content of a message sent by the client, or `None` if it repeats the previous move
+    ///
+    /// The clients tag their messages with the identifier of the move (see `tag_move`) and send
+    /// them again if they are not sure they have been received, for instance after reconnecting; a
+    /// message with the same identifier as the previous one has already been handled. Untagged
+    /// messages are always accepted.
+    pub fn accept_move(&mut self, message: &[u8]) -> Option<Vec<u8>> {
+        match super::split_move_id(message) {
+            (Some(id), _) if self.last_move_id == Some(id) => None,
+            (Some(id), content) => {
+                self.last_move_id = Some(id);
+                Some(content.to_vec())
+            },
+            (None, content) => Some(content.to_vec())
+        }
+    }
+
+    /// Wait at most `timeout` for the client to confirm reception of the last message
+    ///
+    /// If the confirmation does not arrive in time, it is expected later and will be skipped by the
+    /// next read. Only the bytes 0 count as confirmations: if the client sends something else 
+    /// first, it is kept for the next read and the confirmation is expected after it. If a 
+    /// heartbeat has been started, its round-trip time is measured if the confirmation arrives in 
+    /// time, and forgotten otherwise.
+    pub fn wait_for_acknowledgement(&mut self, timeout: Duration) -> DeliveryState {
+        let heartbeat_sent_at = self.heartbeat_sent_at.take();
+        if !self.is_connected() && self.pending.is_empty() {
+            return DeliveryState::Disconnected;
+        }
+        self.n_missing_acknowledgements += 1;
+        let deadline = Instant::now() + timeout;
+        while self.n_missing_acknowledgements > 0 {
+            if self.pending.is_empty() {
+                match self.incoming.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok((_, bytes)) if bytes.is_empty() => return DeliveryState::Disconnected,
+                    Ok((received_at, bytes)) => {
+                        self.pending = bytes;
+                        self.received_at = received_at;
+                    },
+                    Err(RecvTimeoutError::Timeout) => return DeliveryState::Slow,
+                    Err(RecvTimeoutError::Disconnected) => return DeliveryState::Disconnected
+                }
+            }
+            if !self.skip_acknowledgements() {
+                return DeliveryState::Slow;
+            }
+        }
+        if let Some(sent_at) = heartbeat_sent_at {
+            self.latency = Some(self.received_at.saturating_duration_since(sent_at));
+        }
+        DeliveryState::Delivered
+    }
+
+    /// Wait at most `timeout` for the client to send something, skipping the confirmations of 
+    /// reception it still owes; return `true` if something can be read
+    pub fn wait_for_data(&mut self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.skip_acknowledgements();
+            if !self.pending.is_empty() {
+                return true;
+            }
+            match self.incoming.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((received_at, bytes)) if !bytes.is_empty() => {
+                    self.pending = bytes;
+                    self.received_at = received_at;
+                },
+                _ => return false
+            }
+        }
+    }
+
+    // remove the confirmations of reception still expected from the start of the bytes received;
+    // return `false` if something else than a confirmation comes first
+    fn skip_acknowledgements(&mut self) -> bool {
+        let n = self.pending.iter().take(self.n_missing_acknowledgements).take_while(|&&b| b == 0).count();
+        self.pending.drain(..n);
+        self.n_missing_acknowledgements -= n;
+        self.n_missing_acknowledgements == 0 || self.pending.is_empty()
+    }
+
+    // wait for the next bytes sent by the client
+    fn receive(&mut self) -> std::io::Result<()> {
+        let (received_at, bytes) = self.incoming.recv().map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "connection closed")
+        })?;
+        self.pending = bytes;
+        self.received_at = received_at;
+        Ok(())
+    }
+}
+
+// set the read and write timeouts of a stream (they are shared by its clones)
+fn set_timeouts(stream: &TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(N_SECONDS_READ_TIMEOUT)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(N_SECONDS_WRITE_TIMEOUT)))
+}
+
+/// name of a client in the messages of the server: `Player n` for the player with index `n - 1`,
+/// or `A spectator`
+pub fn client_label(player: usize, spectator: bool) -> String {
+    if spectator {
+        "A spectator".to_string()
+    } else {
+        format!("Player {}", player + 1)
+    }
+}
+
+// start a thread reading from the stream and forwarding the data through a channel, with the time
+// it has arrived
+fn spawn_reader(mut stream: EncryptedStream, label: String, connected: Arc<AtomicBool>) 
+    -> Receiver<(Instant, Vec<u8>)> 
+{
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut buffer = [0; READ_BUFFER_SIZE];
+        loop {
+            match stream.read(&mut buffer) {
+                
+                // the client has nothing to say for now
+                Err(e) if is_timeout(&e) => continue,
+                
+                Ok(0) | Err(_) => {
+                    connected.store(false, Ordering::Relaxed);
+                    
+                    // an empty message signals the end of the stream; if it can not be sent, the
+                    // connection has been replaced and there is nothing to report
+                    if sender.send((Instant::now(), Vec::new())).is_err() {
+                        return;
+                    }
+                    break;
+                },
+                Ok(n) => {
+                    if sender.send((Instant::now(), buffer[..n].to_vec())).is_err() {
+                        // the connection has been replaced or dropped
+                        return;
+                    }
+                }
+            }
+        }
+        println!("{} has disconnected", label);
+    });
+    receiver
+}
+
+// start a thread writing to the stream the data received through a channel
+fn spawn_writer(mut stream: EncryptedStream, connected: Arc<AtomicBool>) -> Sender<Vec<u8>> {
+    let (sender, receiver) = channel::<Vec<u8>>();
+    thread::spawn(move || {
+        for bytes in receiver {
+            if stream.write_all(&bytes).is_err() {
+                
+                // shutting the stream down stops the reader thread, which reports the disconnection
+                connected.store(false, Ordering::Relaxed);
+                stream.shutdown(Shutdown::Both).unwrap_or(());
+                break;
+            }
+        }
+    });
+    sender
+}
+
+impl Read for PlayerConnection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        
+        // skip the late confirmations of reception, unless the client has sent something else 
+        // first
+        while self.n_missing_acknowledgements > 0 {
+            if self.pending.is_empty() {
+                self.receive()?;
+                if self.pending.is_empty() {
+                    return Ok(0);
+                }
+            }
+            if !self.skip_acknowledgements() {
+                break;
+            }
+        }
+
+        if self.pending.is_empty() {
+            self.receive()?;
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for PlayerConnection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.is_connected() {
+            return Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "connection closed"));
+        }
+        self.outgoing.send(buf.to_vec()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "connection closed")
+        })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn message_log() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+        let mut connection = PlayerConnection::new(EncryptedStream::plain(server_side), 0).unwrap();
+
+        for i in 0..(MESSAGE_LOG_SIZE + 2) {
+            connection.log_message(&i.to_string());
+        }
+        assert_eq!(vec!["50".to_string(), "51".to_string()], connection.recent_messages(2));
+        assert_eq!(MESSAGE_LOG_SIZE, connection.recent_messages(100).len());
+        assert_eq!("2", connection.recent_messages(100)[0]);
+    }
+
+    #[test]
+    fn duplicate_moves() {
+        use crate::net::tag_move;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+        let mut connection = PlayerConnection::new(EncryptedStream::plain(server_side), 0).unwrap();
+
+        assert_eq!(Some(b"e".to_vec()), connection.accept_move(tag_move(7, "e").as_bytes()));
+        assert_eq!(None, connection.accept_move(tag_move(7, "e").as_bytes()));
+        assert_eq!(Some(b"e".to_vec()), connection.accept_move(tag_move(8, "e").as_bytes()));
+        assert_eq!(Some(b"e".to_vec()), connection.accept_move(b"e"));
+    }
+
+    #[test]
+    fn read_and_detect_disconnection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || EncryptedStream::connect(TcpStream::connect(address).unwrap()));
+        let (server_side, _) = listener.accept().unwrap();
+        let mut connection = PlayerConnection::new(EncryptedStream::accept(server_side).unwrap(), 0).unwrap();
+        let mut client = client.join().unwrap().unwrap();
+
+        client.write_all(&[1, 2, 3]).unwrap();
+        let mut buffer = [0; 3];
+        connection.read_exact(&mut buffer).unwrap();
+        assert_eq!([1, 2, 3], buffer);
+
+        connection.write_all(&[4]).unwrap();
+        let mut buffer = [0; 1];
+        client.read_exact(&mut buffer).unwrap();
+        assert_eq!([4], buffer);
+
+        connection.start_heartbeat();
+        connection.write_all(&[4]).unwrap();
+        client.read_exact(&mut buffer).unwrap();
+        client.write_all(&[0]).unwrap();
+        assert_eq!(DeliveryState::Delivered, connection.wait_for_acknowledgement(Duration::from_secs(5)));
+        assert!(connection.latency().is_some());
+
+        connection.write_all(&[5]).unwrap();
+        assert_eq!(DeliveryState::Slow, connection.wait_for_acknowledgement(Duration::from_millis(10)));
+        assert!(connection.is_slow());
+        client.write_all(&[0, 6]).unwrap();
+        connection.read_exact(&mut buffer).unwrap();
+        assert_eq!([6], buffer);
+        assert!(!connection.is_slow());
+
+        // a message sent before a confirmation is not taken for it
+        connection.write_all(&[8]).unwrap();
+        client.read_exact(&mut buffer).unwrap();
+        client.write_all(&[9, 0]).unwrap();
+        assert_eq!(DeliveryState::Slow, connection.wait_for_acknowledgement(Duration::from_secs(5)));
+        connection.read_exact(&mut buffer).unwrap();
+        assert_eq!([9], buffer);
+
+        assert!(!connection.wait_for_data(Duration::from_millis(10)));
+        assert!(!connection.is_slow());
+        client.write_all(&[7]).unwrap();
+        assert!(connection.wait_for_data(Duration::from_secs(5)));
+        connection.read_exact(&mut buffer).unwrap();
+        assert_eq!([7], buffer);
+
+        drop(client);
+        assert!(connection.read_exact(&mut buffer).is_err());
+        assert!(!connection.is_connected());
+    }
+}
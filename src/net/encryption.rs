@@ -0,0 +1,161 @@
+//! Encryption of the connections between the server and the clients
+//!
+//! When a client connects, it sends an ephemeral X25519 public key and the server answers with its
+//! own. Both sides derive from the shared secret one key for each direction, and everything sent
+//! afterwards is encrypted with Salsa20. This keeps the hands of the players away from anyone
+//! listening on the network; since the keys are not authenticated, it does not protect against an
+//! attacker able to stand between the client and the server.
+
+use std::io::{ Read, Write };
+use std::net::{ TcpStream, Shutdown };
+use rand::rngs::OsRng;
+use salsa20::Salsa20;
+use salsa20::cipher::{ KeyIvInit, StreamCipher };
+use sha2::{ Sha256, Digest };
+use x25519_dalek::{ EphemeralSecret, PublicKey };
+
+/// nonce of the ciphers; it can be constant since the keys are only used for one connection
+const NONCE: [u8; 8] = [0; 8];
+
+/// TCP stream whose content is encrypted in both directions
+///
+/// Each direction has its own cipher, so a stream can be split into a reading half and a writing
+/// half used by different threads (see `split`).
+pub struct EncryptedStream {
+    stream: TcpStream,
+    read_cipher: Option<Salsa20>,
+    write_cipher: Option<Salsa20>
+}
+
+// key of one direction, derived from the shared secret and the public keys
+fn derive_key(shared_secret: &[u8], client_key: &PublicKey, server_key: &PublicKey, direction: &[u8])
+    -> [u8; 32]
+{
+    Sha256::new()
+        .chain_update(shared_secret)
+        .chain_update(client_key.as_bytes())
+        .chain_update(server_key.as_bytes())
+        .chain_update(direction)
+        .finalize()
+        .into()
+}
+
+impl EncryptedStream {
+
+    // exchange the public keys and set up the ciphers; the client sends its key first
+    fn key_exchange(mut stream: TcpStream, is_client: bool) -> std::io::Result<EncryptedStream> {
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let own_key = PublicKey::from(&secret);
+        let mut other_key = [0; 32];
+        if is_client {
+            stream.write_all(own_key.as_bytes())?;
+            stream.read_exact(&mut other_key)?;
+        } else {
+            stream.read_exact(&mut other_key)?;
+            stream.write_all(own_key.as_bytes())?;
+        }
+        let other_key = PublicKey::from(other_key);
+        let shared_secret = secret.diffie_hellman(&other_key);
+        let (client_key, server_key) = if is_client { (&own_key, &other_key) } else { (&other_key, &own_key) };
+        let to_server = derive_key(shared_secret.as_bytes(), client_key, server_key, b"to server");
+        let to_client = derive_key(shared_secret.as_bytes(), client_key, server_key, b"to client");
+        let (read_key, write_key) = if is_client { (to_client, to_server) } else { (to_server, to_client) };
+        Ok(EncryptedStream {
+            stream,
+            read_cipher: Some(Salsa20::new(&read_key.into(), &NONCE.into())),
+            write_cipher: Some(Salsa20::new(&write_key.into(), &NONCE.into()))
+        })
+    }
+
+    /// Set up the encryption of a connection from the client side
+    pub fn connect(stream: TcpStream) -> std::io::Result<EncryptedStream> {
+        EncryptedStream::key_exchange(stream, true)
+    }
+
+    /// Set up the encryption of a connection from the server side
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::{ Read, Write };
+    /// use std::net::{ TcpListener, TcpStream };
+    /// use machiavelli::net::EncryptedStream;
+    ///
+    /// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    /// let address = listener.local_addr().unwrap();
+    /// let client = std::thread::spawn(move || {
+    ///     let mut stream = EncryptedStream::connect(TcpStream::connect(address).unwrap()).unwrap();
+    ///     stream.write_all(b"Hello!").unwrap();
+    /// });
+    ///
+    /// let mut stream = EncryptedStream::accept(listener.accept().unwrap().0).unwrap();
+    /// let mut message = [0; 6];
+    /// stream.read_exact(&mut message).unwrap();
+    /// assert_eq!(b"Hello!", &message);
+    /// client.join().unwrap();
+    /// ```
+    pub fn accept(stream: TcpStream) -> std::io::Result<EncryptedStream> {
+        EncryptedStream::key_exchange(stream, false)
+    }
+
+    /// Wrap a stream without encrypting it, for the loopback connections of the bots
+    pub fn plain(stream: TcpStream) -> EncryptedStream {
+        EncryptedStream { stream, read_cipher: None, write_cipher: None }
+    }
+
+    /// Underlying TCP stream, to set its timeouts or shut it down
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Shut down the connection
+    pub fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        self.stream.shutdown(how)
+    }
+
+    /// Split the stream into a half which can only be read from and one which can only be written
+    /// to
+    pub fn split(self) -> std::io::Result<(EncryptedStream, EncryptedStream)> {
+        let reader = EncryptedStream {
+            stream: self.stream.try_clone()?,
+            read_cipher: self.read_cipher,
+            write_cipher: None
+        };
+        let writer = EncryptedStream {
+            stream: self.stream,
+            read_cipher: None,
+            write_cipher: self.write_cipher
+        };
+        Ok((reader, writer))
+    }
+}
+
+impl Read for EncryptedStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.stream.read(buf)?;
+        if let Some(cipher) = self.read_cipher.as_mut() {
+            cipher.apply_keystream(&mut buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl Write for EncryptedStream {
+
+    // the whole buffer is written, so that the cipher stays in step with the other side
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.write_cipher.as_mut() {
+            Some(cipher) => {
+                let mut bytes = buf.to_vec();
+                cipher.apply_keystream(&mut bytes);
+                self.stream.write_all(&bytes)?;
+            },
+            None => self.stream.write_all(buf)?
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
@@ -0,0 +1,86 @@
+//! Errors raised when communicating between the server and the clients
+
+use std::string::FromUtf8Error;
+
+/// generic error raised when reading from or writing to a stream fails
+#[derive(Debug)]
+pub enum StreamError {
+    
+    /// reading from or writing to the stream failed
+    Io(std::io::Error),
+    
+    /// the bytes received are not valid UTF-8
+    Utf8(FromUtf8Error),
+    
+    /// the message is too long to be sent
+    TooLong { size: usize, max_size: usize },
+    
+    /// any other error
+    Other(String)
+}
+
+impl StreamError {
+    
+    /// create an error from a message
+    pub fn new(message: &str) -> StreamError {
+        StreamError::Other(message.to_string())
+    }
+
+    /// check if the error is due to a timeout
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            StreamError::Io(error) => super::is_timeout(error),
+            _ => false
+        }
+    }
+}
+
+impl std::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StreamError::Io(error) => write!(f, "StreamError: IO Error: {}", error),
+            StreamError::Utf8(error) => write!(f, "StreamError: UTF-8 error: {}", error),
+            StreamError::TooLong { size, max_size } => write!(f, 
+                "StreamError: Stream too long: size: {}, maximum size: {}", size, max_size),
+            StreamError::Other(message) => write!(f, "StreamError: {}", message)
+        }
+    }
+}
+
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::Io(error) => Some(error),
+            StreamError::Utf8(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+impl std::convert::From<std::io::Error> for StreamError {
+    fn from(error: std::io::Error) -> Self {
+        StreamError::Io(error)
+    }
+}
+
+impl std::convert::From<FromUtf8Error> for StreamError {
+    fn from(error: FromUtf8Error) -> Self {
+        StreamError::Utf8(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn keep_source() {
+        let error = StreamError::from(std::io::Error::new(std::io::ErrorKind::TimedOut, "too slow"));
+        assert!(error.is_timeout());
+        assert_eq!("too slow", error.source().unwrap().to_string());
+        assert_eq!("StreamError: IO Error: too slow", error.to_string());
+        assert!(StreamError::new("oops").source().is_none());
+    }
+}
@@ -0,0 +1,164 @@
+//! Communication between the server and the clients
+//!
+//! Messages are exchanged over any [`Transport`]; on the server side, each client is wrapped in a
+//! [`PlayerConnection`](connection::PlayerConnection) with its own reader and writer threads.
+
+use std::io::{ Read, Write };
+
+pub mod connection;
+pub mod encryption;
+pub mod error;
+pub use error::StreamError;
+pub use encryption::EncryptedStream;
+
+/// prefix of the message sent by a client to reconnect with a session token instead of a name
+///
+/// It starts with a control character, so that it can not be typed as a name.
+pub const SESSION_TOKEN_PREFIX: &str = "\u{1}session:";
+
+/// prefix of the name sent by a client to watch the game as a spectator
+///
+/// Like `SESSION_TOKEN_PREFIX`, it starts with a control character.
+pub const SPECTATOR_PREFIX: &str = "\u{1}spectator:";
+
+/// prefix of the message sent by a client, before its name, to join the game with a given code
+///
+/// Like `SESSION_TOKEN_PREFIX`, it starts with a control character.
+pub const GAME_CODE_PREFIX: &str = "\u{1}game:";
+
+/// confirmation sent by a client, instead of 0, when the state of the game it has been sent does
+/// not match its hash, to ask for the whole state
+pub const RESYNC_REQUEST: u8 = 1;
+
+/// separator between the identifier of a move and its content (see `tag_move`)
+///
+/// It is a control character, so that it can not be typed in a move.
+pub const MOVE_ID_SEPARATOR: char = '\u{1f}';
+
+/// tag a message sent by a client with the identifier of the move
+///
+/// A client which is not sure that a move has been received sends it again with the same
+/// identifier, so that the server can ignore it if it has already been played.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::net::{ tag_move, split_move_id };
+///
+/// let tagged = tag_move(42, "p 1 2 3");
+/// assert_eq!((Some(42), "p 1 2 3".as_bytes()), split_move_id(tagged.as_bytes()));
+/// assert_eq!((None, "p 1 2 3".as_bytes()), split_move_id(b"p 1 2 3"));
+/// ```
+pub fn tag_move(id: u64, message: &str) -> String {
+    format!("{}{}{}", id, MOVE_ID_SEPARATOR, message)
+}
+
+/// split a message tagged by `tag_move` into the identifier of the move and its content
+///
+/// Untagged messages are returned unchanged, without an identifier.
+pub fn split_move_id(message: &[u8]) -> (Option<u64>, &[u8]) {
+    let separator = MOVE_ID_SEPARATOR as u8;
+    if let Some(position) = message.iter().position(|&b| b == separator) {
+        let id = std::str::from_utf8(&message[..position]).ok().and_then(|s| s.parse::<u64>().ok());
+        if let Some(id) = id {
+            return (Some(id), &message[position + 1..]);
+        }
+    }
+    (None, message)
+}
+
+/// How often a client is sent the situation while it is not its turn
+///
+/// Each client chooses when the game starts or when it reconnects. The announcements (votes,
+/// forfeits, the end of the game...) are sent to all clients either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Updates {
+
+    /// after every move of the current player
+    #[default]
+    Full,
+
+    /// only when a turn starts
+    TurnBoundary
+}
+
+impl Updates {
+
+    /// Parse the name of a granularity, `full` or `turn`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::net::Updates;
+    ///
+    /// assert_eq!(Some(Updates::TurnBoundary), Updates::parse(" turn\n"));
+    /// assert_eq!(Some(Updates::Full), Updates::parse("full"));
+    /// assert_eq!(None, Updates::parse("sometimes"));
+    /// ```
+    pub fn parse(s: &str) -> Option<Updates> {
+        match s.trim() {
+            "full" => Some(Updates::Full),
+            "turn" => Some(Updates::TurnBoundary),
+            _ => None
+        }
+    }
+}
+
+impl std::fmt::Display for Updates {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Updates::Full => write!(f, "full"),
+            Updates::TurnBoundary => write!(f, "turn")
+        }
+    }
+}
+
+/// Bidirectional byte stream used to exchange messages
+///
+/// It is implemented for everything which can be read from and written to, such as `TcpStream` and
+/// `PlayerConnection`.
+pub trait Transport: Read + Write {}
+
+impl<T: Read + Write> Transport for T {}
+
+/// check if an IO error is due to a timeout
+pub fn is_timeout(error: &std::io::Error) -> bool {
+    matches!(error.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+}
+
+/// listener passed by systemd with socket activation, if any
+///
+/// The `LISTEN_PID` and `LISTEN_FDS` environment variables must be set for this process; the
+/// first descriptor passed (3) is then used as an already-bound TCP listener. The variables are
+/// removed, so that they are not inherited by child processes.
+#[cfg(unix)]
+pub fn listener_from_systemd() -> Option<std::net::TcpListener> {
+    use std::os::unix::io::FromRawFd;
+
+    // first file descriptor passed by systemd
+    const SD_LISTEN_FDS_START: i32 = 3;
+
+    let pid_matches = std::env::var("LISTEN_PID").ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        .map(|pid| pid == std::process::id())
+        .unwrap_or(false);
+    let n_fds = std::env::var("LISTEN_FDS").ok()
+        .and_then(|n| n.trim().parse::<i32>().ok())
+        .unwrap_or(0);
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if !pid_matches || n_fds < 1 {
+        return None;
+    }
+
+    // safety: systemd guarantees that this descriptor is open and owned by this process, and it is
+    // not used anywhere else
+    Some(unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) })
+}
+
+/// listener passed by systemd with socket activation (never available on this platform)
+#[cfg(not(unix))]
+pub fn listener_from_systemd() -> Option<std::net::TcpListener> {
+    None
+}
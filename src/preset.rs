@@ -0,0 +1,192 @@
+//! Named presets of game settings
+//!
+//! A preset keeps the table setup (`Config`) and the optional rules (`RuleSet`) of a game under a
+//! name, in the `presets` subdirectory of the user config directory. Each preset is a file in the
+//! format of the rules file, with the settings of the table added.
+
+use std::fmt;
+use std::path::PathBuf;
+use crate::{ Config, InvalidInputError, user_config_dir };
+use crate::rules::RuleSet;
+
+/// extension of the preset files
+const PRESET_EXTENSION: &str = ".preset";
+
+/// Table setup and optional rules saved under a name
+#[derive(Debug, PartialEq)]
+pub struct Preset {
+    pub config: Config,
+    pub rules: RuleSet,
+}
+
+impl Preset {
+
+    /// Read a preset from a string
+    ///
+    /// All the settings of the table must be given, except `initial_meld_points` (0 by default);
+    /// the rules which are not keep their default value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::preset::Preset;
+    ///
+    /// let preset = Preset::from_string("n_decks = 2\nn_jokers = 4\nn_cards_to_start = 13\n\
+    ///                                   jokers_must_be_played = no\nn_players = 3\n\
+    ///                                   allow_joining = yes\n").unwrap();
+    ///
+    /// assert_eq!(2, preset.config.n_decks);
+    /// assert!(preset.rules.allow_joining);
+    /// assert_eq!(preset, Preset::from_string(&preset.to_string()).unwrap());
+    /// assert!(Preset::from_string("n_decks = 2\n").is_err());
+    /// ```
+    pub fn from_string(s: &str) -> Result<Preset, InvalidInputError> {
+        let (mut n_decks, mut n_jokers, mut n_cards_to_start, mut custom_rule_jokers, mut n_players) =
+            (None, None, None, None, None);
+        let mut initial_meld_points = 0;
+        let mut rules = String::new();
+        for line in s.lines() {
+            let value = line.split_once('=').map(|(_, value)| value.trim()).unwrap_or("");
+            match line.split_once('=').map(|(name, _)| name.trim()) {
+                Some("n_decks") => n_decks = Some(value.parse::<u8>()?),
+                Some("n_jokers") => n_jokers = Some(value.parse::<u8>()?),
+                Some("n_cards_to_start") => n_cards_to_start = Some(value.parse::<u16>()?),
+                Some("jokers_must_be_played") => custom_rule_jokers = Some(matches!(value, "yes" | "y" | "1" | "true")),
+                Some("n_players") => n_players = Some(value.parse::<u8>()?),
+                Some("initial_meld_points") => initial_meld_points = value.parse::<u16>()?,
+                _ => {
+                    rules.push_str(line);
+                    rules.push('\n');
+                }
+            }
+        }
+        let config = Config {
+            n_decks: n_decks.ok_or(InvalidInputError {})?,
+            n_jokers: n_jokers.ok_or(InvalidInputError {})?,
+            n_cards_to_start: n_cards_to_start.ok_or(InvalidInputError {})?,
+            custom_rule_jokers: custom_rule_jokers.ok_or(InvalidInputError {})?,
+            n_players: n_players.ok_or(InvalidInputError {})?,
+            initial_meld_points,
+        };
+        if config.n_decks == 0 || config.n_players == 0 || config.n_cards_to_start == 0 {
+            return Err(InvalidInputError {});
+        }
+        Ok(Preset { config, rules: RuleSet::from_string(&rules)? })
+    }
+
+    /// Load the preset with the given name from the user config directory
+    pub fn load(name: &str) -> Result<Preset, InvalidInputError> {
+        Preset::from_string(&std::fs::read_to_string(preset_path(name)?)?)
+    }
+
+    /// Save the preset under the given name in the user config directory, replacing any preset
+    /// with the same name
+    pub fn save(&self, name: &str) -> Result<(), InvalidInputError> {
+        std::fs::write(preset_path(name)?, self.to_string())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "n_decks = {}", self.config.n_decks)?;
+        writeln!(f, "n_jokers = {}", self.config.n_jokers)?;
+        writeln!(f, "n_cards_to_start = {}", self.config.n_cards_to_start)?;
+        writeln!(f, "jokers_must_be_played = {}", if self.config.custom_rule_jokers { "yes" } else { "no" })?;
+        writeln!(f, "n_players = {}", self.config.n_players)?;
+        writeln!(f, "initial_meld_points = {}", self.config.initial_meld_points)?;
+        write!(f, "{}", self.rules)
+    }
+}
+
+/// check that a preset name can be used as a file name: letters, digits, `-` and `_` only
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::preset::is_valid_name;
+///
+/// assert!(is_valid_name("quick_2-players"));
+/// assert!(!is_valid_name("../rules"));
+/// assert!(!is_valid_name(""));
+/// ```
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+// directory of the presets, created if needed
+fn preset_dir() -> Result<PathBuf, InvalidInputError> {
+    let dir = user_config_dir().ok_or(InvalidInputError {})?.join("presets");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// path of the file of a preset
+fn preset_path(name: &str) -> Result<PathBuf, InvalidInputError> {
+    if !is_valid_name(name) {
+        return Err(InvalidInputError {});
+    }
+    Ok(preset_dir()?.join(format!("{}{}", name, PRESET_EXTENSION)))
+}
+
+/// names of the saved presets, in alphabetical order
+pub fn list_presets() -> Vec<String> {
+    let mut names: Vec<String> = preset_dir().ok()
+        .and_then(|dir| std::fs::read_dir(dir).ok())
+        .map(|entries| entries.filter_map(|entry| {
+            let fname = entry.ok()?.file_name().to_string_lossy().to_string();
+            fname.strip_suffix(PRESET_EXTENSION).map(String::from)
+        }).collect())
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+/// Command given with the `config` command-line argument
+#[derive(Debug, PartialEq)]
+pub enum ConfigCommand {
+    /// `config save <name>`: save the current settings as a preset
+    Save(String),
+    /// `config load <name>`: start a new game with the settings of a preset
+    Load(String),
+}
+
+/// parse the `config save <name>` or `config load <name>` command-line arguments
+///
+/// Return `None` if there is no `config` argument, and an error message if the command is not
+/// valid.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::preset::{ parse_config_command, ConfigCommand };
+///
+/// let args: Vec<String> = ["config", "load", "quick"].iter().map(|s| s.to_string()).collect();
+///
+/// assert_eq!(Some(Ok(ConfigCommand::Load("quick".to_string()))), parse_config_command(&args));
+/// assert!(parse_config_command(&args[..2]).unwrap().is_err());
+/// assert_eq!(None, parse_config_command(&[]));
+/// ```
+pub fn parse_config_command(args: &[String]) -> Option<Result<ConfigCommand, String>> {
+    let i = args.iter().position(|a| a == "config")?;
+    let name = args.get(i + 2).map(String::as_str).unwrap_or("").to_string();
+    let command = match args.get(i + 1).map(String::as_str) {
+        Some("save") => ConfigCommand::Save(name.clone()),
+        Some("load") => ConfigCommand::Load(name.clone()),
+        _ => return Some(Err("Usage: config save <name> or config load <name>".to_string()))
+    };
+    if !is_valid_name(&name) {
+        return Some(Err(format!("Invalid preset name '{}' (use letters, digits, '-' and '_')", name)));
+    }
+    Some(Ok(command))
+}
+
+/// message explaining why a preset could not be loaded, with the list of the saved presets
+pub fn load_error_message(name: &str) -> String {
+    let presets = list_presets();
+    if presets.is_empty() {
+        format!("Could not read the preset {} (no preset has been saved)", name)
+    } else {
+        format!("Could not read the preset {} (saved presets: {})", name, presets.join(", "))
+    }
+}
@@ -0,0 +1,282 @@
+//! Styled rendering of cards, sequences, and tables for the terminal
+//!
+//! The `Display` implementations of the core types are plain text; the functions of this module
+//! add the ANSI colours when they are enabled (see `colors_enabled`).
+
+use std::sync::atomic::{ AtomicBool, Ordering };
+use crate::{ Card, Sequence, Table, Suit, colors_enabled, style_code, bold, reset_style_string, next_player_in_direction };
+use crate::evaluation::card_points;
+
+/// What the players are shown of the state of the game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visibility {
+
+    /// number of cards left in the deck
+    pub deck_count: bool,
+
+    /// number of cards in the hands of the other players
+    pub hand_counts: bool
+}
+
+impl Default for Visibility {
+    fn default() -> Visibility {
+        Visibility { deck_count: true, hand_counts: true }
+    }
+}
+
+impl Visibility {
+
+    /// Visibility of the blind mode, where only the own hand of each player is counted
+    pub fn blind() -> Visibility {
+        Visibility { deck_count: false, hand_counts: false }
+    }
+}
+
+static SHOW_DECK_COUNT: AtomicBool = AtomicBool::new(true);
+static SHOW_HAND_COUNTS: AtomicBool = AtomicBool::new(true);
+
+/// set what the players are shown of the state of the game
+pub fn set_visibility(visibility: Visibility) {
+    SHOW_DECK_COUNT.store(visibility.deck_count, Ordering::Relaxed);
+    SHOW_HAND_COUNTS.store(visibility.hand_counts, Ordering::Relaxed);
+}
+
+/// what the players are shown of the state of the game
+pub fn visibility() -> Visibility {
+    Visibility {
+        deck_count: SHOW_DECK_COUNT.load(Ordering::Relaxed),
+        hand_counts: SHOW_HAND_COUNTS.load(Ordering::Relaxed)
+    }
+}
+
+/// Render a card, coloured by suit if colours are enabled
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// set_colors(true);
+/// assert_eq!("\u{1b}[1;31m3♦", render::card(&RegularCard(Diamond, 3)));
+/// set_colors(false);
+/// assert_eq!("3♦", render::card(&RegularCard(Diamond, 3)));
+/// ```
+pub fn card(card: &Card) -> String {
+    if !colors_enabled() {
+        return card.to_plain_string();
+    }
+    let color = match card.suit() {
+        Some(Suit::Heart) | Some(Suit::Diamond) => "31",
+        Some(Suit::Club) | Some(Suit::Spade) => "30",
+        None => "34"
+    };
+    format!("\x1b[1;{}m{}", color, card.to_plain_string())
+}
+
+/// Render a sequence, each card followed by a space
+pub fn sequence(sequence: &Sequence) -> String {
+    sequence.to_vec().iter().map(|c| card(c) + " ").collect()
+}
+
+/// Render a table, one numbered sequence per line, each tagged with its kind (`[run]` or `[set]`)
+pub fn table(table: &Table) -> String {
+    table.iter().enumerate()
+        .map(|(i, seq)| {
+            let kind = seq.meld_kind().map(|kind| format!("[{}]", kind)).unwrap_or_default();
+            format!("{}: {}{}{}\n", i + 1, sequence(seq), reset_style_string(), kind)
+        })
+        .collect()
+}
+
+/// Render a sequence above the indices of its cards, starting from `n + 1`
+///
+/// See `Sequence::show_indices_shifted` for the plain version.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// set_colors(true);
+/// let sequence = Sequence::from_cards(&[Joker, RegularCard(Club, 10)]);
+///
+/// assert_eq!(render::show_indices_shifted(&sequence, 0),
+///            ("\u{1b}[1;34m# \u{1b}[1;30m10♣".to_string(), "1 2".to_string()));
+/// ```
+pub fn show_indices_shifted(sequence: &Sequence, n: usize) -> (String, String) {
+    sequence.layout_indices(n, card)
+}
+
+/// Render the cards taken from the table above their indices, starting from `n + 1`
+///
+/// The indices follow those of the hand, so they are shown in a different colour to make the
+/// shared numbering stand out.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// set_colors(true);
+/// let sequence = Sequence::from_cards(&[Joker, RegularCard(Club, 10)]);
+///
+/// assert_eq!(render::show_indices_taken(&sequence, 5),
+///            ("\u{1b}[1;34m# \u{1b}[1;30m10♣".to_string(), "\u{1b}[1;35m6 7\u{1b}[0m".to_string()));
+/// set_colors(false);
+/// assert_eq!(render::show_indices_taken(&sequence, 5), ("# 10♣".to_string(), "6 7".to_string()));
+/// ```
+pub fn show_indices_taken(sequence: &Sequence, n: usize) -> (String, String) {
+    let (cards, indices) = sequence.layout_indices(n, card);
+    (cards, format!("{}{}{}", style_code("\x1b[1;35m"), indices, style_code("\x1b[0m")))
+}
+
+/// Render the number of cards in a group and their points (see `evaluation::card_points`)
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// let cards = Sequence::from_cards(&[Joker, RegularCard(Club, 12), RegularCard(Heart, 3)]);
+///
+/// assert_eq!("3 cards, 38 points", render::subtotal(&cards));
+/// assert_eq!("1 card, 3 points", render::subtotal(&Sequence::from_cards(&[RegularCard(Heart, 3)])));
+/// ```
+pub fn subtotal(cards: &Sequence) -> String {
+    let n = cards.number_cards();
+    let points: u32 = cards.to_vec().iter().map(card_points).sum();
+    format!("{} card{}, {} point{}", n, if n == 1 { "" } else { "s" }, 
+            points, if points == 1 { "" } else { "s" })
+}
+
+/// Render the number of cards of each player, as seen by the player `viewer`, with a note after
+/// each of them (for instance their connection status); the players who have forfeited are marked
+/// as such
+///
+/// The numbers hidden by the visibility (see `set_visibility`) are replaced by `?`.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::render::*;
+///
+/// let names = vec!["Alice".to_string(), "Bob".to_string()];
+/// let notes = vec!["", " (disconnected)"];
+///
+/// set_visibility(Visibility::default());
+/// assert_eq!("\nNumber of cards (40 remaining in the deck):\n  Alice: 3\n  Bob: 5 (disconnected)",
+///            card_counts(&names, &[3, 5], &notes, &[false, false], 0, 40));
+/// set_visibility(Visibility::blind());
+/// assert_eq!("\nNumber of cards:\n  Alice: 3\n  Bob: ? (disconnected)",
+///            card_counts(&names, &[3, 5], &notes, &[false, false], 0, 40));
+/// assert_eq!("\nNumber of cards:\n  Alice: forfeited\n  Bob: 5 (disconnected)",
+///            card_counts(&names, &[0, 5], &notes, &[true, false], 1, 40));
+/// set_visibility(Visibility::default());
+/// ```
+pub fn card_counts(player_names: &[String], hand_sizes: &[usize], notes: &[&str], 
+                   forfeited: &[bool], viewer: usize, deck_size: usize) -> String {
+    let visibility = visibility();
+    let mut s = if visibility.deck_count {
+        format!("\nNumber of cards ({} remaining in the deck):", deck_size)
+    } else {
+        "\nNumber of cards:".to_string()
+    };
+    for (i, name) in player_names.iter().enumerate() {
+        if forfeited[i] {
+            s += &format!("\n  {}: forfeited", name);
+        } else if visibility.hand_counts || i == viewer {
+            s += &format!("\n  {}: {}{}", name, hand_sizes[i], notes[i]);
+        } else {
+            s += &format!("\n  {}: ?{}", name, notes[i]);
+        }
+    }
+    s
+}
+
+/// Render the seating order, from the current player to the one playing after the last
+///
+/// The current player is shown in bold and the next one is marked, with arrows showing the
+/// direction of play (backwards if `reversed` is set); the players who have forfeited are left
+/// out.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// set_colors(false);
+/// let names = vec!["Alice".to_string(), "Bob".to_string(), "Carol".to_string()];
+///
+/// assert_eq!("Turn order: Bob → Carol (next) → Alice ↻", 
+///            render::turn_order(&names, 1, &[false, false, false], false));
+/// assert_eq!("Turn order: Bob → Alice (next) ↻", 
+///            render::turn_order(&names, 1, &[false, false, true], false));
+/// assert_eq!("Turn order: Bob → Alice (next) → Carol ↺", 
+///            render::turn_order(&names, 1, &[false, false, false], true));
+/// ```
+pub fn turn_order(player_names: &[String], current_player: usize, forfeited: &[bool], 
+                  reversed: bool) -> String {
+    let n_players = player_names.len();
+    let next = next_player_in_direction(current_player, forfeited, reversed);
+    let names: Vec<String> = (0..n_players)
+        .map(|i| if reversed { (current_player + n_players - i) % n_players } else { (current_player + i) % n_players })
+        .filter(|&i| i == current_player || !forfeited[i])
+        .map(|i| {
+            if i == current_player && colors_enabled() {
+                format!("{}{}", bold(&player_names[i]), reset_style_string())
+            } else if i == next && i != current_player {
+                format!("{} (next)", player_names[i])
+            } else {
+                player_names[i].clone()
+            }
+        })
+        .collect();
+    format!("Turn order: {} {}", names.join(" → "), if reversed { "↺" } else { "↻" })
+}
+
+/// Render an announcement of the administrator of the server, in reverse video so that it stands
+/// out from the messages of the game
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+///
+/// set_colors(false);
+/// assert_eq!("\n[Announcement] Server restart at 18:00\n\n", render::announcement("Server restart at 18:00"));
+/// ```
+pub fn announcement(text: &str) -> String {
+    if colors_enabled() {
+        format!("\n\x1b[1;7m Announcement {}\x1b[1m {}{}\n\n", reset_style_string(), text, reset_style_string())
+    } else {
+        format!("\n[Announcement] {}\n\n", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{ Card::*, Suit::*, set_colors, COLORS_LOCK };
+
+    #[test]
+    fn styled_table() {
+        let _lock = COLORS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let colors = colors_enabled();
+        set_colors(true);
+        let mut t = Table::new();
+        t.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Diamond, 5),
+                                 RegularCard(Heart, 6)]));
+        t.add(Sequence::from_cards(&[RegularCard(Club, 2), Joker, RegularCard(Diamond, 3),
+                                 RegularCard(Heart, 2)]));
+
+        assert_eq!("1: \u{1b}[1;30m2♣ \u{1b}[1;34m# \u{1b}[1;31m3♦ \u{1b}[1;31m2♥ \u{1b}[0m\u{1b}[30;47m\u{1b}[?25l\u{1b}[K\n2: \u{1b}[1;30m4♣ \u{1b}[1;31m5♦ \u{1b}[1;31m6♥ \u{1b}[0m\u{1b}[30;47m\u{1b}[?25l\u{1b}[K\n".to_string(), table(&t));
+
+        let mut t = Table::new();
+        t.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+        t.add(Sequence::from_cards(&[RegularCard(Club, 2), Joker, RegularCard(Heart, 2)]));
+        set_colors(false);
+        assert_eq!("1: 2♣ # 2♥ [set]\n2: 4♣ 5♣ 6♣ [run]\n", table(&t));
+        set_colors(colors);
+    }
+}
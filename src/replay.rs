@@ -0,0 +1,227 @@
+//! Replays of finished games
+//!
+//! A replay keeps the configuration and optional rules of a game, the seed used to deal the cards,
+//! and, for each turn, the hand of the player and the table before and after the turn. It can be
+//! analysed after the game to find the turns where a player could have done better.
+
+use std::fs;
+use std::path::Path;
+use crate::{ Config, Sequence, Table, LoadingError, render, reset_style_string };
+use crate::evaluation::evaluate_hand;
+use crate::rules::RuleSet;
+
+/// A turn of a game
+#[derive(Debug, Clone, PartialEq)]
+pub struct TurnRecord {
+    pub player: u8,
+    pub hand_before: Sequence,
+    pub table_before: Table,
+    pub hand_after: Sequence,
+    pub table_after: Table
+}
+
+/// Configuration, players, and turns of a game
+#[derive(Debug, PartialEq)]
+pub struct Replay {
+    pub config: Config,
+    pub rules: RuleSet,
+
+    /// seed used to shuffle the deck (0 if the game was loaded from a save file)
+    pub seed: u64,
+
+    pub player_names: Vec<String>,
+    pub turns: Vec<TurnRecord>
+}
+
+// append a block of bytes preceded by its length as 2 u8
+fn push_block(bytes: &mut Vec<u8>, block: &[u8]) {
+    bytes.push((block.len() >> 8) as u8);
+    bytes.push((block.len() & 255) as u8);
+    bytes.extend_from_slice(block);
+}
+
+impl Replay {
+
+    /// Create a replay with no turn
+    pub fn new(config: &Config, rules: &RuleSet, seed: u64, player_names: &[String]) -> Replay {
+        Replay {
+            config: config.clone(),
+            rules: rules.clone(),
+            seed,
+            player_names: player_names.to_vec(),
+            turns: Vec::new()
+        }
+    }
+
+    /// Record a turn
+    pub fn record(&mut self, player: usize, hand_before: &Sequence, table_before: &Table,
+                  hand_after: &Sequence, table_after: &Table) {
+        self.turns.push(TurnRecord {
+            player: player as u8,
+            hand_before: hand_before.clone(),
+            table_before: table_before.clone(),
+            hand_after: hand_after.clone(),
+            table_after: table_after.clone()
+        });
+    }
+
+    /// Convert the replay to a sequence of bytes
+    ///
+    /// The config comes first (without the minimum points of the first meld), then the seed as 8 u8, the rules as text (preceded by its length
+    /// as 2 u8), the player names (each preceded by its length), and the turns (the player, then
+    /// the hands and tables, each preceded by its length as 2 u8).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.config.to_bytes();
+        bytes.extend_from_slice(&self.seed.to_be_bytes());
+        push_block(&mut bytes, self.rules.to_string().as_bytes());
+        for name in &self.player_names {
+            bytes.push(name.len() as u8);
+            bytes.extend_from_slice(name.as_bytes());
+        }
+        for turn in &self.turns {
+            bytes.push(turn.player);
+            push_block(&mut bytes, &turn.hand_before.to_bytes());
+            push_block(&mut bytes, &turn.table_before.to_bytes());
+            push_block(&mut bytes, &turn.hand_after.to_bytes());
+            push_block(&mut bytes, &turn.table_after.to_bytes());
+        }
+        bytes
+    }
+
+    /// Get a replay from a sequence of bytes (see `to_bytes`)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::replay::Replay;
+    /// use machiavelli::rules::RuleSet;
+    ///
+    /// let mut replay = Replay::new(&Config::default(), &RuleSet::default(), 42,
+    ///                             &["Alice".to_string(), "Bob".to_string()]);
+    /// let hand = Sequence::from_cards(&[RegularCard(Heart, 3), Joker]);
+    /// replay.record(1, &hand, &Table::new(), &hand, &Table::new());
+    ///
+    /// assert_eq!(replay, Replay::from_bytes(&replay.to_bytes()).unwrap());
+    /// assert!(Replay::from_bytes(&replay.to_bytes()[..10]).is_err());
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Result<Replay, LoadingError> {
+        let mut i_byte: usize = 0;
+
+        // take the next `n` bytes, checking that they exist
+        let mut take = |n: usize| -> Result<&[u8], LoadingError> {
+            let slice = bytes.get(i_byte..i_byte+n).ok_or(LoadingError::Invalid)?;
+            i_byte += n;
+            Ok(slice)
+        };
+
+        let config = Config::from_bytes(take(6)?);
+        let mut seed = [0u8; 8];
+        seed.copy_from_slice(take(8)?);
+        let seed = u64::from_be_bytes(seed);
+        let n = take(2)?;
+        let n = ((n[0] as usize) << 8) + n[1] as usize;
+        let rules = RuleSet::from_string(&String::from_utf8_lossy(take(n)?))
+            .map_err(|_| LoadingError::Invalid)?;
+        let mut player_names = Vec::<String>::new();
+        for _ in 0..config.n_players {
+            let n = take(1)?[0] as usize;
+            player_names.push(String::from_utf8_lossy(take(n)?).to_string());
+        }
+
+        // each turn: the player, then the hands and tables
+        let mut turns = Vec::<TurnRecord>::new();
+        while let Ok(player) = take(1) {
+            let player = player[0];
+            if player >= config.n_players {
+                return Err(LoadingError::Invalid);
+            }
+            let mut blocks = Vec::<&[u8]>::new();
+            for _ in 0..4 {
+                let n = take(2)?;
+                let n = ((n[0] as usize) << 8) + n[1] as usize;
+                blocks.push(take(n)?);
+            }
+            turns.push(TurnRecord {
+                player,
+                hand_before: Sequence::from_bytes(blocks[0]),
+                table_before: Table::from_bytes(blocks[1]),
+                hand_after: Sequence::from_bytes(blocks[2]),
+                table_after: Table::from_bytes(blocks[3])
+            });
+        }
+        Ok(Replay { config, rules, seed, player_names, turns })
+    }
+
+    /// Write the replay to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    /// Read a replay from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Replay, LoadingError> {
+        let bytes = fs::read(path).map_err(|_| LoadingError::Invalid)?;
+        Replay::from_bytes(&bytes)
+    }
+
+    /// Transcript of the game, where each turn in which the player could have gone out or played
+    /// more cards is flagged
+    ///
+    /// What could have been played is found by `evaluation::evaluate_hand` with the rules of the
+    /// game: sequences formed with the hand alone, and single cards added to the sequences on the
+    /// table. Plays rearranging the table are not looked for, so a turn is never flagged wrongly,
+    /// but some missed plays may not be.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::replay::Replay;
+    /// use machiavelli::rules::RuleSet;
+    ///
+    /// set_colors(false);
+    /// let mut replay = Replay::new(&Config::default(), &RuleSet::default(), 42,
+    ///                             &["Alice".to_string(), "Bob".to_string()]);
+    /// let hand = Sequence::from_cards(&[RegularCard(Heart, 3), RegularCard(Heart, 4),
+    ///                                   RegularCard(Heart, 5)]);
+    /// let mut hand_after = hand.clone();
+    /// hand_after.add_card(RegularCard(Club, 1));
+    /// replay.record(0, &hand, &Table::new(), &hand_after, &Table::new());
+    ///
+    /// let transcript = replay.analyze();
+    /// assert!(transcript.contains("Turn 1 — Alice: drew a card"));
+    /// assert!(transcript.contains("could have gone out"));
+    /// ```
+    pub fn analyze(&self) -> String {
+        let mut transcript = String::new();
+        let mut n_flagged = 0;
+        for (i, turn) in self.turns.iter().enumerate() {
+            let name = self.player_names.get(turn.player as usize).map(String::as_str).unwrap_or("?");
+            let n_before = turn.hand_before.number_cards();
+            let n_after = turn.hand_after.number_cards();
+            let action = if n_after >= n_before {
+                "drew a card".to_string()
+            } else {
+                format!("played {} card{}", n_before - n_after, if n_before - n_after > 1 { "s" } else { "" })
+            };
+            transcript += &format!("Turn {} — {}: {}\n", i + 1, name, action);
+
+            let n_played = n_before.saturating_sub(n_after);
+            let evaluation = evaluate_hand(&turn.hand_before, &turn.table_before, &self.rules);
+            if evaluation.deadwood.is_empty() && n_before > 0 && n_after > 0 {
+                n_flagged += 1;
+                transcript += &format!("  ! could have gone out: {}{}\n",
+                                       render::sequence(&turn.hand_before), reset_style_string());
+            } else if evaluation.playable.len() > n_played {
+                n_flagged += 1;
+                transcript += &format!("  ! could have played {} cards: {}{}\n",
+                                       evaluation.playable.len(),
+                                       render::sequence(&Sequence::from_cards(&evaluation.playable)),
+                                       reset_style_string());
+            }
+        }
+        transcript += &format!("\n{} turn{} with missed plays out of {}\n", n_flagged,
+                               if n_flagged == 1 { "" } else { "s" }, self.turns.len());
+        transcript
+    }
+}
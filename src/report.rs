@@ -0,0 +1,277 @@
+//! Machine-readable reports on finished games
+//!
+//! If the server is given a destination in `Config/report_server.dat`, it sends a JSON report at
+//! the end of each game, with the players, the winner, the points left in the hands, the duration
+//! of the game, and a hash of its settings, so that league or tournament software can collect the
+//! results without reading the messages of the game. The destination is either a local file, to
+//! which each report is appended as one line, or an `http://` URL to which it is posted.
+
+use std::io::{ self, Read, Write };
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use sha2::{ Sha256, Digest };
+use crate::{ Config, Sequence };
+use crate::fairness::to_hex;
+use crate::preset::Preset;
+use crate::rules::RuleSet;
+use crate::scoring::round_scores;
+
+/// how long to wait for the server receiving the reports
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Result of a player at the end of a game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerResult {
+    pub name: String,
+    pub bot: bool,
+    pub forfeited: bool,
+    pub cards_left: usize,
+
+    /// points left in the hand (see the `scoring` module)
+    pub score: u32
+}
+
+/// Report on a finished game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameReport {
+    pub game_code: String,
+
+    /// time at which the game ended, in seconds since 1970
+    pub end_time: u64,
+
+    pub duration: Duration,
+    pub n_turns: usize,
+
+    /// hash of the settings and rules of the game (see `config_hash`)
+    pub config_hash: String,
+
+    pub players: Vec<PlayerResult>,
+
+    /// index of the winner in `players`, if the game has not ended in a draw
+    pub winner: Option<usize>
+}
+
+impl GameReport {
+
+    /// Report on a game with the given final hands
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(game_code: &str, config: &Config, rules: &RuleSet, names: &[String], bots: &[bool],
+               hands: &[Sequence], forfeited: &[bool], winner: Option<usize>, n_turns: usize,
+               duration: Duration, end_time: u64) -> GameReport {
+        let scores = round_scores(hands, forfeited, rules.joker_penalty);
+        let players = names.iter().enumerate()
+            .map(|(i, name)| PlayerResult {
+                name: name.clone(),
+                bot: bots[i],
+                forfeited: forfeited[i],
+                cards_left: hands[i].number_cards(),
+                score: scores[i].total()
+            })
+            .collect();
+        GameReport {
+            game_code: game_code.to_string(),
+            end_time,
+            duration,
+            n_turns,
+            config_hash: config_hash(config, rules),
+            players,
+            winner
+        }
+    }
+
+    /// Write the report as a JSON object, on a single line
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use machiavelli::report::{ GameReport, PlayerResult };
+    ///
+    /// let report = GameReport {
+    ///     game_code: "ABC-123".to_string(),
+    ///     end_time: 1700000000,
+    ///     duration: Duration::from_secs(754),
+    ///     n_turns: 31,
+    ///     config_hash: "0123abcd".to_string(),
+    ///     players: vec![
+    ///         PlayerResult { name: "Alice \"Ace\"".to_string(), bot: false, forfeited: false, cards_left: 0, score: 0 },
+    ///         PlayerResult { name: "Bot".to_string(), bot: true, forfeited: false, cards_left: 2, score: 35 },
+    ///     ],
+    ///     winner: Some(0)
+    /// };
+    ///
+    /// assert_eq!("{\"game_code\":\"ABC-123\",\"end_time\":1700000000,\"duration_s\":754,\"turns\":31,\
+    ///             \"config_hash\":\"0123abcd\",\"winner\":\"Alice \\\"Ace\\\"\",\"players\":[\
+    ///             {\"name\":\"Alice \\\"Ace\\\"\",\"bot\":false,\"forfeited\":false,\"cards_left\":0,\"score\":0},\
+    ///             {\"name\":\"Bot\",\"bot\":true,\"forfeited\":false,\"cards_left\":2,\"score\":35}]}",
+    ///            report.to_json());
+    /// ```
+    pub fn to_json(&self) -> String {
+        let players: Vec<String> = self.players.iter()
+            .map(|p| format!("{{\"name\":{},\"bot\":{},\"forfeited\":{},\"cards_left\":{},\"score\":{}}}",
+                             json_string(&p.name), p.bot, p.forfeited, p.cards_left, p.score))
+            .collect();
+        let winner = match self.winner.and_then(|i| self.players.get(i)) {
+            Some(player) => json_string(&player.name),
+            None => "null".to_string()
+        };
+        format!("{{\"game_code\":{},\"end_time\":{},\"duration_s\":{},\"turns\":{},\"config_hash\":{},\
+                 \"winner\":{},\"players\":[{}]}}",
+                json_string(&self.game_code), self.end_time, self.duration.as_secs(), self.n_turns,
+                json_string(&self.config_hash), winner, players.join(","))
+    }
+
+    /// Send the report to its destination
+    pub fn send(&self, target: &ReportTarget) -> io::Result<()> {
+        match target {
+            ReportTarget::File(path) => {
+                let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(file, "{}", self.to_json())
+            },
+            ReportTarget::Webhook { host, port, path } => post_json(host, *port, path, &self.to_json())
+        }
+    }
+}
+
+/// Destination of the reports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReportTarget {
+
+    /// local file, to which each report is appended as one line
+    File(PathBuf),
+
+    /// URL to which each report is posted (plain HTTP only)
+    Webhook { host: String, port: u16, path: String }
+}
+
+impl ReportTarget {
+
+    /// Read the destination of the reports: an URL starting with `http://`, or the path of a file
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::report::ReportTarget;
+    ///
+    /// assert_eq!(Some(ReportTarget::Webhook { host: "league.local".to_string(), port: 8000,
+    ///                                         path: "/results".to_string() }),
+    ///            ReportTarget::parse("http://league.local:8000/results\n"));
+    /// assert_eq!(Some(ReportTarget::Webhook { host: "league.local".to_string(), port: 80,
+    ///                                         path: "/".to_string() }),
+    ///            ReportTarget::parse("http://league.local"));
+    /// assert_eq!(Some(ReportTarget::File("results.jsonl".into())), ReportTarget::parse("results.jsonl"));
+    /// assert_eq!(None, ReportTarget::parse("https://league.local/results"));
+    /// assert_eq!(None, ReportTarget::parse(""));
+    /// ```
+    pub fn parse(s: &str) -> Option<ReportTarget> {
+        let s = s.trim();
+        if s.is_empty() || s.starts_with("https://") {
+            return None;
+        }
+        let url = match s.strip_prefix("http://") {
+            Some(url) => url,
+            None => return Some(ReportTarget::File(PathBuf::from(s)))
+        };
+        let (address, path) = match url.find('/') {
+            Some(i) => (&url[..i], &url[i..]),
+            None => (url, "/")
+        };
+        let (host, port) = match address.rfind(':') {
+            Some(i) => (&address[..i], address[i+1..].parse::<u16>().ok()?),
+            None => (address, 80)
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(ReportTarget::Webhook { host: host.to_string(), port, path: path.to_string() })
+    }
+}
+
+/// Hash of the settings and rules of a game, as hexadecimal digits
+///
+/// Two games have the same hash if and only if they are played with the same settings and rules,
+/// which lets a league check that all its games follow the same ones.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::Config;
+/// use machiavelli::rules::RuleSet;
+/// use machiavelli::report::config_hash;
+///
+/// let hash = config_hash(&Config::default(), &RuleSet::default());
+///
+/// assert_eq!(64, hash.len());
+/// assert_ne!(hash, config_hash(&Config::default(), &RuleSet::from_string("blind = yes").unwrap()));
+/// ```
+pub fn config_hash(config: &Config, rules: &RuleSet) -> String {
+    let settings = Preset { config: config.clone(), rules: rules.clone() }.to_string();
+    to_hex(&Sha256::digest(settings.as_bytes()))
+}
+
+// write a string as a JSON string, with the quotes
+fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c)
+        }
+    }
+    result.push('"');
+    result
+}
+
+// post a JSON document and check that the status of the response is 2xx
+fn post_json(host: &str, port: u16, path: &str, json: &str) -> io::Result<()> {
+    let mut stream = TcpStream::connect((host, port))?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+    stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+    let head = format!("POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\n\
+                        Content-Length: {}\r\nConnection: close\r\n\r\n",
+                       path, host, port, json.len());
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(json.as_bytes())?;
+    let mut response = Vec::<u8>::new();
+    stream.read_to_end(&mut response)?;
+    let status_line = String::from_utf8_lossy(&response).lines().next().unwrap_or("").to_string();
+    match status_line.split_whitespace().nth(1).and_then(|s| s.parse::<u16>().ok()) {
+        Some(200..=299) => Ok(()),
+        _ => Err(io::Error::new(io::ErrorKind::Other, format!("the report was refused: {}", status_line)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::<u8>::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"{}") {
+                let n = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..n]);
+            }
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        post_json("127.0.0.1", port, "/results", "{}").unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /results HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Type: application/json\r\n"));
+        assert!(request.contains("Content-Length: 2\r\n"));
+    }
+}
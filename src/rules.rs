@@ -0,0 +1,285 @@
+//! Optional rules
+//!
+//! The optional rules of a game are read from a file with one rule per line, in the form
+//! `name = value`; lines starting with `#` are ignored.
+
+use std::fmt;
+use crate::{ InvalidInputError, KING, PENALTY_RESET };
+use crate::evaluation::JOKER_POINTS;
+
+/// Set of optional rules
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuleSet {
+    
+    /// new players can join a game in progress
+    pub allow_joining: bool,
+
+    /// the cards of a player who forfeits are shuffled back into the deck (otherwise, they are 
+    /// removed from the game)
+    pub forfeited_cards_to_deck: bool,
+
+    /// a majority of the players is enough to save and quit the game (otherwise, all of them must
+    /// agree)
+    pub save_by_majority: bool,
+
+    /// the resets with a penalty set as many cards aside in a discard pool, reshuffled into a new
+    /// deck when the deck is empty, and a game without cards to draw ends when the table has not
+    /// changed for this number of full rounds (0 to end it as soon as the deck is empty)
+    pub graveyard_rounds: usize,
+
+    /// maximum number of cards in a hand; a player whose hand is full skips drawing at the end of
+    /// their turn (0 for no limit)
+    pub max_hand_size: usize,
+
+    /// the players can not take or modify the sequences on the table until they have completed
+    /// their first turn
+    pub no_table_on_first_turn: bool,
+
+    /// before the first turn, a player whose hand can not form any pair or run may ask for the 
+    /// cards to be dealt again, which happens if a majority of the players agree
+    pub redeal_vote: bool,
+
+    /// once per game, at the beginning of their turn, each player may exchange a card of their 
+    /// choice with a random card from the hand of the next player
+    pub card_exchange: bool,
+
+    /// playing a sequence with four cards of this rank reverses the turn order (0 for never)
+    pub reverse_rank: u8,
+
+    /// playing a sequence with four cards of this rank makes the next player skip their turn (0
+    /// for never)
+    pub skip_rank: u8,
+
+    /// the games are rounds of a match, which ends when the total of the points left in the hand
+    /// of a player at the end of the rounds goes over this number (0 for single games)
+    pub match_score: u32,
+
+    /// points scored for each joker left in a hand at the end of a round of a match
+    pub joker_penalty: u32,
+
+    /// the players are not told how many cards are left in the deck and in the hands of the
+    /// others
+    pub blind: bool,
+
+    /// giving up and resetting the table costs no card, for teaching games
+    pub free_reset: bool,
+
+    /// speed of the game (delays before the turns of the bots and between some messages)
+    pub speed: Speed
+}
+
+/// Speed of the game
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Speed {
+    /// no delay
+    Instant,
+    #[default]
+    Normal,
+    /// longer delays, to follow what the bots do
+    Relaxed
+}
+
+impl Speed {
+
+    /// Read a speed from its name
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::rules::Speed;
+    ///
+    /// assert_eq!(Some(Speed::Relaxed), Speed::from_name(" relaxed"));
+    /// assert_eq!(None, Speed::from_name("fast"));
+    /// ```
+    pub fn from_name(s: &str) -> Option<Speed> {
+        match s.trim() {
+            "instant" => Some(Speed::Instant),
+            "normal" => Some(Speed::Normal),
+            "relaxed" => Some(Speed::Relaxed),
+            _ => None
+        }
+    }
+
+    /// number of milliseconds of the short waits, used when polling
+    pub fn wait_milliseconds(self) -> u64 {
+        match self {
+            Speed::Instant => 1,
+            Speed::Normal | Speed::Relaxed => 10
+        }
+    }
+
+    /// number of milliseconds of the long waits, before the turns of the bots and between some
+    /// messages
+    pub fn long_wait_milliseconds(self) -> u64 {
+        match self {
+            Speed::Instant => 0,
+            Speed::Normal => 1000,
+            Speed::Relaxed => 2500
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Speed::Instant => "instant",
+            Speed::Normal => "normal",
+            Speed::Relaxed => "relaxed"
+        })
+    }
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet {
+            allow_joining: false,
+            forfeited_cards_to_deck: true,
+            save_by_majority: false,
+            graveyard_rounds: 0,
+            max_hand_size: 0,
+            no_table_on_first_turn: false,
+            redeal_vote: false,
+            card_exchange: false,
+            reverse_rank: 0,
+            skip_rank: 0,
+            match_score: 0,
+            joker_penalty: JOKER_POINTS,
+            blind: false,
+            free_reset: false,
+            speed: Speed::Normal
+        }
+    }
+}
+
+impl RuleSet {
+
+    /// Read a set of rules from a string
+    ///
+    /// The rules which are not given keep their default value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::rules::RuleSet;
+    ///
+    /// let rules = RuleSet::from_string("# house rules\nallow_joining = yes\n").unwrap();
+    ///
+    /// assert!(rules.allow_joining);
+    /// assert_eq!(rules, RuleSet::from_string(&rules.to_string()).unwrap());
+    /// assert!(RuleSet::from_string("allow_jumping = yes").is_err());
+    /// ```
+    pub fn from_string(s: &str) -> Result<RuleSet, InvalidInputError> {
+        let mut rules = RuleSet::default();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line.split_once('=').ok_or(InvalidInputError {})?;
+            match name.trim() {
+                "allow_joining" => rules.allow_joining = parse_bool(value)?,
+                "forfeited_cards_to_deck" => rules.forfeited_cards_to_deck = parse_bool(value)?,
+                "save_by_majority" => rules.save_by_majority = parse_bool(value)?,
+                "graveyard_rounds" => rules.graveyard_rounds = value.trim().parse()?,
+                "max_hand_size" => rules.max_hand_size = value.trim().parse()?,
+                "no_table_on_first_turn" => rules.no_table_on_first_turn = parse_bool(value)?,
+                "redeal_vote" => rules.redeal_vote = parse_bool(value)?,
+                "card_exchange" => rules.card_exchange = parse_bool(value)?,
+                "reverse_rank" => rules.reverse_rank = parse_rank(value)?,
+                "skip_rank" => rules.skip_rank = parse_rank(value)?,
+                "match_score" => rules.match_score = value.trim().parse()?,
+                "joker_penalty" => rules.joker_penalty = value.trim().parse()?,
+                "blind" => rules.blind = parse_bool(value)?,
+                "free_reset" => rules.free_reset = parse_bool(value)?,
+                "speed" => rules.speed = Speed::from_name(value).ok_or(InvalidInputError {})?,
+                _ => return Err(InvalidInputError {})
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Read a set of rules from a file
+    pub fn load(fname: &str) -> Result<RuleSet, InvalidInputError> {
+        RuleSet::from_string(&std::fs::read_to_string(fname)?)
+    }
+
+    /// Number of cards drawn by a player who gives up and resets the table
+    pub fn reset_penalty(&self) -> usize {
+        if self.free_reset { 0 } else { PENALTY_RESET }
+    }
+
+    /// Rules which differ from the default ones, as `name = value`
+    pub fn changed_rules(&self) -> Vec<String> {
+        let default_rules = RuleSet::default().to_string();
+        self.to_string().lines()
+            .filter(|line| !default_rules.lines().any(|l| l == *line))
+            .map(String::from)
+            .collect()
+    }
+
+    /// Summary of the rules shown to the players at the beginning of a game
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::rules::RuleSet;
+    ///
+    /// let rules = RuleSet::from_string("free_reset = yes").unwrap();
+    ///
+    /// assert_eq!("Optional rules of this game:\n  free_reset = yes\n", rules.summary());
+    /// assert_eq!("This game uses the standard rules.\n", RuleSet::default().summary());
+    /// ```
+    pub fn summary(&self) -> String {
+        let changed = self.changed_rules();
+        if changed.is_empty() {
+            "This game uses the standard rules.\n".to_string()
+        } else {
+            format!("Optional rules of this game:\n{}", 
+                    changed.iter().map(|rule| format!("  {}\n", rule)).collect::<String>())
+        }
+    }
+}
+
+impl fmt::Display for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "allow_joining = {}", yes_no(self.allow_joining))?;
+        writeln!(f, "forfeited_cards_to_deck = {}", yes_no(self.forfeited_cards_to_deck))?;
+        writeln!(f, "save_by_majority = {}", yes_no(self.save_by_majority))?;
+        writeln!(f, "graveyard_rounds = {}", self.graveyard_rounds)?;
+        writeln!(f, "max_hand_size = {}", self.max_hand_size)?;
+        writeln!(f, "no_table_on_first_turn = {}", yes_no(self.no_table_on_first_turn))?;
+        writeln!(f, "redeal_vote = {}", yes_no(self.redeal_vote))?;
+        writeln!(f, "card_exchange = {}", yes_no(self.card_exchange))?;
+        writeln!(f, "reverse_rank = {}", self.reverse_rank)?;
+        writeln!(f, "skip_rank = {}", self.skip_rank)?;
+        writeln!(f, "match_score = {}", self.match_score)?;
+        writeln!(f, "joker_penalty = {}", self.joker_penalty)?;
+        writeln!(f, "blind = {}", yes_no(self.blind))?;
+        writeln!(f, "free_reset = {}", yes_no(self.free_reset))?;
+        writeln!(f, "speed = {}", self.speed)
+    }
+}
+
+// value of a rule which can be on or off
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
+
+// parse a rank (1 for aces to 13 for kings), or 0 to disable a rule
+fn parse_rank(s: &str) -> Result<u8, InvalidInputError> {
+    let rank: u8 = s.trim().parse()?;
+    if rank > KING {
+        return Err(InvalidInputError {});
+    }
+    Ok(rank)
+}
+
+// parse the value of a rule which can be on or off
+fn parse_bool(s: &str) -> Result<bool, InvalidInputError> {
+    match s.trim() {
+        "yes" | "y" | "1" | "true" => Ok(true),
+        "no" | "n" | "0" | "false" => Ok(false),
+        _ => Err(InvalidInputError {})
+    }
+}
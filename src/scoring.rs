@@ -0,0 +1,100 @@
+//! Scores of the hands at the end of a round
+//!
+//! At the end of a round, each player scores the points of the cards left in their hand: the value
+//! of each card up to 10, 10 for the figures, and a penalty for each joker (`joker_penalty` in the
+//! rules). The lower the score, the better; the player who has emptied their hand scores 0.
+
+use std::fmt;
+use crate::{ Card, Sequence };
+
+/// Score of the cards left in a hand at the end of a round
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HandScore {
+
+    /// points of the regular cards
+    pub card_points: u32,
+
+    /// number of jokers
+    pub n_jokers: u32,
+
+    /// penalty of each joker
+    pub joker_penalty: u32
+}
+
+impl HandScore {
+
+    /// Total score of the hand
+    pub fn total(&self) -> u32 {
+        self.card_points + self.n_jokers * self.joker_penalty
+    }
+}
+
+impl fmt::Display for HandScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} point{}", self.total(), if self.total() == 1 { "" } else { "s" })?;
+        if self.n_jokers > 0 {
+            write!(f, " ({} for the cards, {} for {} joker{})", self.card_points,
+                   self.n_jokers * self.joker_penalty, self.n_jokers,
+                   if self.n_jokers > 1 { "s" } else { "" })?;
+        }
+        Ok(())
+    }
+}
+
+/// Score of the cards left in a hand
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::scoring::hand_score;
+///
+/// let hand = Sequence::from_cards(&[RegularCard(Heart, 12), RegularCard(Club, 3), Joker]);
+/// let score = hand_score(&hand, 25);
+///
+/// assert_eq!(38, score.total());
+/// assert_eq!("38 points (13 for the cards, 25 for 1 joker)", score.to_string());
+/// assert_eq!("0 points", hand_score(&Sequence::new(), 25).to_string());
+/// ```
+pub fn hand_score(hand: &Sequence, joker_penalty: u32) -> HandScore {
+    let mut score = HandScore { joker_penalty, ..HandScore::default() };
+    for card in hand {
+        match card {
+            Card::RegularCard(_, value) => score.card_points += (*value as u32).min(10),
+            Card::Joker => score.n_jokers += 1
+        }
+    }
+    score
+}
+
+/// Scores of all the players at the end of a round; the players who have forfeited score nothing
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::scoring::round_scores;
+///
+/// let hands = vec![Sequence::new(), Sequence::from_cards(&[Joker]), Sequence::from_cards(&[Joker])];
+/// let scores: Vec<u32> = round_scores(&hands, &[false, false, true], 50).iter()
+///     .map(|score| score.total())
+///     .collect();
+///
+/// assert_eq!(vec![0, 50, 0], scores);
+/// ```
+pub fn round_scores(hands: &[Sequence], forfeited: &[bool], joker_penalty: u32) -> Vec<HandScore> {
+    hands.iter().enumerate()
+        .map(|(i, hand)| if forfeited.get(i).copied().unwrap_or(false) {
+            HandScore { joker_penalty, ..HandScore::default() }
+        } else {
+            hand_score(hand, joker_penalty)
+        })
+        .collect()
+}
+
+/// Scores of a round, one line per player, from the lowest to the highest
+pub fn round_summary(names: &[String], scores: &[HandScore]) -> String {
+    let mut order: Vec<usize> = (0..scores.len().min(names.len())).collect();
+    order.sort_by_key(|&i| scores[i].total());
+    order.iter().map(|&i| format!("  {}: {}\n", names[i], scores[i])).collect()
+}
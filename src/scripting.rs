@@ -0,0 +1,220 @@
+//! House rules written in Rhai scripts (requires the `scripting` feature)
+//!
+//! A script implements some of the callbacks of [`RuleHook`] as functions with the same names:
+//!
+//! * `on_meld_played(meld)`, called for each sequence formed during a turn;
+//! * `on_turn_end()`, returning `#{ reverse: true/false, skip: true/false }` to change the turn
+//!   order (missing fields are `false`);
+//! * `check_end_of_turn(hand)`, returning a string with the reason why the player may not end
+//!   their turn, or `()` if they may;
+//! * `is_valid_meld(meld, valid)`, returning whether the sequence can be played given whether it is
+//!   valid for the standard rules.
+//!
+//! Sequences are given as arrays of cards, each card being a map `#{ rank, suit, joker }` where
+//! `rank` goes from 1 (ace) to 13 (king) and `suit` is `"heart"`, `"club"`, `"diamond"`, or
+//! `"spade"` (0 and `""` for jokers). The functions share an object map, `this`, where they can
+//! keep a state between two calls.
+//!
+//! The scripts are limited in the number of operations, the depth of the function calls, and the
+//! depth of the expressions. An error in a callback, including hitting one of these limits, counts
+//! as a rejected move: `check_end_of_turn` refuses to end the turn with the error as reason, and
+//! `is_valid_meld` rejects the sequence. An error in `on_meld_played` or `on_turn_end`, called once
+//! the turn is over, leaves the turn order unchanged.
+
+use std::fmt;
+use std::path::Path;
+use rhai::{ Engine, AST, Scope, Dynamic, Map, Array, CallFnOptions, FuncArgs };
+use crate::{ Sequence, Suit };
+use crate::hooks::{ RuleHook, TurnOrderChange };
+
+/// maximum number of operations of a call to a script
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// maximum depth of the function calls in a script
+const MAX_CALL_LEVELS: usize = 32;
+
+/// maximum depth of the expressions in a script, at the top level and in the functions
+const MAX_EXPR_DEPTH: usize = 64;
+
+/// Error while loading a script
+#[derive(Debug, Clone)]
+pub struct ScriptError(pub String);
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// House rule implemented by a Rhai script
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::hooks::RuleHook;
+/// use machiavelli::scripting::ScriptHook;
+///
+/// // aces can not be kept in hand at the end of a turn
+/// let hook = ScriptHook::from_source("no aces", r#"
+///     fn check_end_of_turn(hand) {
+///         if hand.some(|c| c.rank == 1) { "Aces must be played!" }
+///     }
+/// "#).unwrap();
+///
+/// assert!(hook.check_end_of_turn(&Sequence::from_cards(&[RegularCard(Heart, 1)])).is_err());
+/// assert!(hook.check_end_of_turn(&Sequence::from_cards(&[RegularCard(Heart, 2)])).is_ok());
+/// ```
+pub struct ScriptHook {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    state: Dynamic
+}
+
+impl ScriptHook {
+
+    /// Load a script from a string
+    ///
+    /// Only the functions of the script are used; its top-level statements are not run.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::hooks::RuleHook;
+    /// use machiavelli::scripting::ScriptHook;
+    ///
+    /// // a script which never ends counts as rejecting the move
+    /// let hook = ScriptHook::from_source("endless", r#"
+    ///     fn check_end_of_turn(hand) { loop {} }
+    ///     fn is_valid_meld(meld, valid) { loop {} }
+    /// "#).unwrap();
+    ///
+    /// assert!(hook.check_end_of_turn(&Sequence::new()).is_err());
+    /// assert!(!hook.is_valid_meld(&Sequence::from_cards(&[Joker]), true));
+    /// ```
+    pub fn from_source(name: &str, source: &str) -> Result<ScriptHook, ScriptError> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH);
+        let ast = engine.compile(source).map_err(|e| ScriptError(format!("{}: {}", name, e)))?;
+        Ok(ScriptHook { name: name.to_string(), engine, ast, state: Dynamic::from_map(Map::new()) })
+    }
+
+    /// Load a script from a file, named after the file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ScriptHook, ScriptError> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| ScriptError(format!("{}: {}", path.display(), e)))?;
+        ScriptHook::from_source(&path.display().to_string(), &source)
+    }
+
+    // call a function of the script which may change its state
+    fn call(&mut self, function: &str, args: impl FuncArgs) -> Result<Option<Dynamic>, ScriptError> {
+        let mut state = std::mem::take(&mut self.state);
+        let result = self.call_with_state(&mut state, function, args);
+        self.state = state;
+        result
+    }
+
+    // call a function of the script without changing its state
+    fn call_const(&self, function: &str, args: impl FuncArgs) -> Result<Option<Dynamic>, ScriptError> {
+        self.call_with_state(&mut self.state.clone(), function, args)
+    }
+
+    // call a function of the script with `state` as `this`, if it is defined
+    fn call_with_state(&self, state: &mut Dynamic, function: &str, args: impl FuncArgs) 
+        -> Result<Option<Dynamic>, ScriptError> 
+    {
+        if !self.ast.iter_functions().any(|f| f.name == function) {
+            return Ok(None);
+        }
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(state);
+        self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, function, args)
+            .map(Some)
+            .map_err(|e| ScriptError(format!("Error in {} ({}): {}", self.name, function, e)))
+    }
+}
+
+impl RuleHook for ScriptHook {
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn on_meld_played(&mut self, sequence: &Sequence) {
+        self.call("on_meld_played", (to_array(sequence),)).unwrap_or_default();
+    }
+
+    fn on_turn_end(&mut self) -> TurnOrderChange {
+        let map = match self.call("on_turn_end", ()).ok().flatten().and_then(|v| v.try_cast::<Map>()) {
+            Some(map) => map,
+            None => return TurnOrderChange::default()
+        };
+        let flag = |name: &str| map.get(name).and_then(|v| v.as_bool().ok()).unwrap_or(false);
+        TurnOrderChange { reverse: flag("reverse"), skip: flag("skip") }
+    }
+
+    fn check_end_of_turn(&self, hand: &Sequence) -> Result<(), String> {
+        match self.call_const("check_end_of_turn", (to_array(hand),)) {
+            Ok(Some(reason)) if reason.is_string() => Err(reason.to_string()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string())
+        }
+    }
+
+    fn is_valid_meld(&self, sequence: &Sequence, valid: bool) -> bool {
+        match self.call_const("is_valid_meld", (to_array(sequence), valid)) {
+            Ok(value) => value.and_then(|v| v.as_bool().ok()).unwrap_or(valid),
+            Err(_) => false
+        }
+    }
+}
+
+// convert a sequence to an array of cards for the scripts
+fn to_array(sequence: &Sequence) -> Array {
+    sequence.to_vec().iter().map(|card| {
+        let mut map = Map::new();
+        map.insert("rank".into(), Dynamic::from_int(card.rank().map(|r| r as i64).unwrap_or(0)));
+        map.insert("suit".into(), Dynamic::from(match card.suit() {
+            Some(Suit::Heart) => "heart",
+            Some(Suit::Club) => "club",
+            Some(Suit::Diamond) => "diamond",
+            Some(Suit::Spade) => "spade",
+            None => ""
+        }.to_string()));
+        map.insert("joker".into(), Dynamic::from_bool(card.suit().is_none()));
+        Dynamic::from_map(map)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{ Card::*, Suit::* };
+
+    #[test]
+    fn state_between_calls() {
+        let mut hook = ScriptHook::from_source("reverse on pairs of kings", r#"
+            fn on_meld_played(meld) {
+                if meld.filter(|c| c.rank == 13).len() >= 2 { this.reverse = true; }
+            }
+            fn on_turn_end() {
+                let change = #{ reverse: this.reverse ?? false };
+                this.reverse = false;
+                change
+            }
+        "#).unwrap();
+
+        hook.on_meld_played(&Sequence::from_cards(&[RegularCard(Heart, 13), RegularCard(Spade, 13),
+                                                    Joker]));
+        assert_eq!(TurnOrderChange { reverse: true, skip: false }, hook.on_turn_end());
+        assert_eq!(TurnOrderChange::default(), hook.on_turn_end());
+        assert!(hook.is_valid_meld(&Sequence::new(), true));
+    }
+}
@@ -1,16 +1,29 @@
 //! Define representations for cards and sequences of cards.
 
 use std::fmt;
+use std::cmp::Ordering;
+use std::iter::FromIterator;
 use std::collections::HashMap;
 use rand::seq::SliceRandom;
-use rand::rngs::ThreadRng;
+use rand::Rng;
 use crate::sort::sort;
 pub use Card::*;
 pub use Suit::*;
 
-static MAX_VAL: u8 = 13;
+/// value of an ace
+pub const ACE: u8 = Rank::Ace as u8;
+
+/// value of a jack
+pub const JACK: u8 = Rank::Jack as u8;
+
+/// value of a queen
+pub const QUEEN: u8 = Rank::Queen as u8;
+
+/// value of a king, the highest one
+pub const KING: u8 = Rank::King as u8;
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Suit {
     Heart,
     Diamond,
@@ -18,7 +31,54 @@ pub enum Suit {
     Spade
 }
 
+impl Suit {
+
+    /// all the suits, in the order used to sort the cards
+    pub const ALL: [Suit; 4] = [Heart, Club, Diamond, Spade];
+}
+
+/// Rank of a regular card, whose value is given by `as u8` (from 1 for an ace to 13 for a king)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Rank {
+    Ace = 1,
+    Two,
+    Three,
+    Four,
+    Five,
+    Six,
+    Seven,
+    Eight,
+    Nine,
+    Ten,
+    Jack,
+    Queen,
+    King
+}
+
+impl Rank {
+
+    /// all the ranks, from ace to king
+    pub const ALL: [Rank; 13] = [Rank::Ace, Rank::Two, Rank::Three, Rank::Four, Rank::Five, 
+        Rank::Six, Rank::Seven, Rank::Eight, Rank::Nine, Rank::Ten, Rank::Jack, Rank::Queen, 
+        Rank::King];
+
+    /// Get a rank from its value
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::{ Rank, QUEEN };
+    ///
+    /// assert_eq!(Some(Rank::Queen), Rank::from_value(QUEEN));
+    /// assert_eq!(None, Rank::from_value(14));
+    /// ```
+    pub fn from_value(value: u8) -> Option<Rank> {
+        Rank::ALL.get((value as usize).checked_sub(1)?).copied()
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Card {
     RegularCard(Suit, u8),
     Joker
@@ -49,32 +109,97 @@ impl Card {
         if x == 0 {
             return Some(Joker);
         }
-        let mut val = x % MAX_VAL;
+        let mut val = x % KING;
         if val == 0 {
-            val = MAX_VAL;
+            val = KING;
         }
-        int_to_suit((x-1) / MAX_VAL + 1).map(|suit| RegularCard(suit, val))
+        int_to_suit((x-1) / KING + 1).map(|suit| RegularCard(suit, val))
     }
 
     fn to_byte(&self) -> u8 {
         match self {
             Joker => 0,
-            RegularCard(suit, value) => (suit_to_int(*suit)-1) * MAX_VAL + value
+            RegularCard(suit, value) => (suit_to_int(*suit)-1) * KING + value
         }
     }
 
 }
 
-impl fmt::Display for Card {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Card {
+
+    /// Rank of the card (`None` for a joker)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::{ Card::*, Suit::*, Rank };
+    ///
+    /// assert_eq!(Some(Rank::Seven), RegularCard(Club, 7).rank());
+    /// assert_eq!(None, Joker.rank());
+    /// ```
+    pub fn rank(&self) -> Option<Rank> {
+        match self {
+            RegularCard(_, value) => Rank::from_value(*value),
+            Joker => None
+        }
+    }
+
+    /// Suit of the card (`None` for a joker)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::{ Card::*, Suit::* };
+    ///
+    /// assert_eq!(Some(Club), RegularCard(Club, 7).suit());
+    /// assert_eq!(None, Joker.suit());
+    /// ```
+    pub fn suit(&self) -> Option<Suit> {
+        match self {
+            RegularCard(suit, _) => Some(*suit),
+            Joker => None
+        }
+    }
+
+    /// Points of the card when played: its value up to 10, 10 for the figures, and 0 for a joker,
+    /// whose points depend on the sequence it is in (see `Sequence::meld_points`)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::{ Card::*, Suit::* };
+    ///
+    /// assert_eq!(1, RegularCard(Club, 1).points());
+    /// assert_eq!(7, RegularCard(Club, 7).points());
+    /// assert_eq!(10, RegularCard(Heart, 13).points());
+    /// assert_eq!(0, Joker.points());
+    /// ```
+    pub fn points(&self) -> u16 {
+        match self {
+            RegularCard(_, value) => rank_points(*value as u16),
+            Joker => 0
+        }
+    }
+
+    /// Text of the card, without any style: its value followed by its suit, or `#` for a joker
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::{ Card::*, Suit::* };
+    ///
+    /// assert_eq!("10♣", RegularCard(Club, 10).to_plain_string());
+    /// assert_eq!("Q♥", RegularCard(Heart, 12).to_plain_string());
+    /// assert_eq!("#", Joker.to_plain_string());
+    /// ```
+    pub fn to_plain_string(&self) -> String {
         match self {
             RegularCard(suit, val) => {
-                let str_val = match val {
-                    1 => "A".to_string(),
-                    11 => "J".to_string(),
-                    12 => "Q".to_string(),
-                    13 => "K".to_string(),
-                    10 => "10".to_string(),
+                let str_val = match *val {
+                    ACE => "A".to_string(),
+                    JACK => "J".to_string(),
+                    QUEEN => "Q".to_string(),
+                    KING => "K".to_string(),
                     _ => format!("{}", val)
                 };
                 let char_suit = match suit {
@@ -83,21 +208,125 @@ impl fmt::Display for Card {
                     Club => '♣',
                     Spade => '♠',
                 };
-                let color = match suit {
-                    Heart => "31",
-                    Diamond => "31",
-                    Club => "30",
-                    Spade => "30",
-                };
-                write!(f, "\x1b[1;{}m{}{}", color, str_val, char_suit)
+                format!("{}{}", str_val, char_suit)
             },
-            Joker => write!(f, "\x1b[1;34m#")
+            Joker => "#".to_string()
         }
     }
 }
 
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_plain_string())
+    }
+}
+
+/// Cards are ordered by rank, then by suit, with the jokers last (see `ByRank` and `BySuit` to
+/// choose the order explicitly)
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::sequence_cards::{ Card::* , Suit::*};
+///
+/// assert!(RegularCard(Spade, 2) < RegularCard(Heart, 3));
+/// assert!(RegularCard(Heart, 3) < RegularCard(Club, 3));
+/// assert!(RegularCard(Spade, 13) < Joker);
+/// ```
+impl Ord for Card {
+    fn cmp(&self, other: &Card) -> Ordering {
+        value_card_by_rank(self).cmp(&value_card_by_rank(other))
+    }
+}
+
+impl PartialOrd for Card {
+    fn partial_cmp(&self, other: &Card) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Card ordered by rank, then by suit, with the jokers last
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::sequence_cards::{ ByRank, Card::* , Suit::*};
+///
+/// let mut cards = vec![ByRank(Joker), ByRank(RegularCard(Club, 3)), ByRank(RegularCard(Heart, 5))];
+/// cards.sort();
+///
+/// assert_eq!(ByRank(RegularCard(Club, 3)), cards[0]);
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ByRank(pub Card);
+
+impl Ord for ByRank {
+    fn cmp(&self, other: &ByRank) -> Ordering {
+        value_card_by_rank(&self.0).cmp(&value_card_by_rank(&other.0))
+    }
+}
+
+impl PartialOrd for ByRank {
+    fn partial_cmp(&self, other: &ByRank) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Card ordered by suit, then by rank, with the jokers last
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::sequence_cards::{ BySuit, Card::* , Suit::*};
+///
+/// let mut cards = vec![BySuit(Joker), BySuit(RegularCard(Club, 3)), BySuit(RegularCard(Heart, 5))];
+/// cards.sort();
+///
+/// assert_eq!(BySuit(RegularCard(Heart, 5)), cards[0]);
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BySuit(pub Card);
+
+impl Ord for BySuit {
+    fn cmp(&self, other: &BySuit) -> Ordering {
+        value_card_by_suit(&self.0).cmp(&value_card_by_suit(&other.0))
+    }
+}
+
+impl PartialOrd for BySuit {
+    fn partial_cmp(&self, other: &BySuit) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// points of a card with the given rank (14 for an ace after a king)
+fn rank_points(rank: u16) -> u16 {
+    rank.min(10)
+}
+
+/// Kind of a valid sequence on the table
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MeldKind {
+
+    /// cards of the same suit with consecutive values
+    Run,
+
+    /// cards of the same value with different suits
+    Set
+}
+
+impl fmt::Display for MeldKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            MeldKind::Run => "run",
+            MeldKind::Set => "set"
+        })
+    }
+}
+
 /// Sequence of cards
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sequence(Vec<Card>);
 
 impl Default for Sequence {
@@ -123,6 +352,37 @@ impl Sequence {
         Sequence(Vec::<Card>::new())
     }
 
+    /// Create an empty sequence with room for `capacity` cards
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::{ Sequence, Card::* };
+    ///
+    /// let mut sequence = Sequence::with_capacity(2);
+    /// sequence.add_cards(&[Joker, Joker]);
+    ///
+    /// assert_eq!(2, sequence.number_cards());
+    /// ```
+    pub fn with_capacity(capacity: usize) -> Sequence {
+        Sequence(Vec::<Card>::with_capacity(capacity))
+    }
+
+    /// Start building a sequence
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::{ Sequence, Card::* , Suit::*};
+    ///
+    /// let deck = Sequence::builder().decks(2).jokers(4).card(RegularCard(Heart, 1)).build();
+    ///
+    /// assert_eq!(109, deck.number_cards());
+    /// ```
+    pub fn builder() -> SequenceBuilder {
+        SequenceBuilder { cards: Vec::<Card>::new() }
+    }
+
     /// Create a sequence from an array of cards
     ///
     /// # Example
@@ -246,52 +506,11 @@ impl Sequence {
     /// let sequence = Sequence::from_cards(&cards);
     ///
     /// assert_eq!(sequence.show_indices(), 
-    ///            ("\u{1b}[1;34m# \u{1b}[1;31mA♥ \u{1b}[1;31m3♥ \u{1b}[1;30m10♣ \u{1b}[1;34m# \u{1b}[1;30mA♣ \u{1b}[1;31m2♥ \u{1b}[1;30m3♣ \u{1b}[1;30m4♣ \u{1b}[1;30m10♣ \u{1b}[1;30m6♣ \u{1b}[1;30m10♣".to_string(),
+    ///            ("# A♥ 3♥ 10♣ # A♣ 2♥ 3♣ 4♣ 10♣ 6♣ 10♣".to_string(),
     ///             "1 2  3  4   5 6  7  8  9  10  11 12".to_string()));
     /// ```
     pub fn show_indices(&self) -> (String,String) {
-
-        let mut first_line = String::new();
-        let mut second_line = String::new();
-        let mut n_chars_1: usize;
-        let mut n_chars_2: usize = 2;
-        let mut power_of_ten: usize = 10;
-        for i in 1..=self.0.len() {
-            
-            // if i is a power of 10, increase the number of characters for the second line by 1
-            if i==power_of_ten {
-                n_chars_2 += 1;
-                power_of_ten *= 10;
-            }
-            
-            // print the current card with a space
-            let current_card = &self.0[i-1];
-            first_line.push_str(&format!("{} ", current_card));
-            
-            // see how many characters the current caerd take
-            match current_card {
-                Joker => n_chars_1 = 2,
-                RegularCard(_,10) => n_chars_1 = 4,
-                _ => n_chars_1 = 3
-            };
-
-            // print the index
-            second_line.push_str(&format!("{} ", i));
-
-            // pad the first line with spaces if necessary
-            for _ in n_chars_1..n_chars_2 {
-                first_line.push(' ');
-            }
-            
-            // pad the second line with spaces if necessary
-            for _ in n_chars_2..n_chars_1 {
-                second_line.push(' ');
-            }
-        }
-        
-        first_line = first_line.trim().to_string();
-        second_line = second_line.trim().to_string();
-        (first_line.to_string(), second_line.to_string())
+        self.show_indices_shifted(0)
     }
     
     /// Return a string with the indices shifted by `n`
@@ -318,10 +537,32 @@ impl Sequence {
     /// let sequence = Sequence::from_cards(&cards);
     ///
     /// assert_eq!(sequence.show_indices_shifted(1), 
-    ///            ("\u{1b}[1;34m# \u{1b}[1;31mA♥ \u{1b}[1;31m3♥ \u{1b}[1;30m10♣ \u{1b}[1;34m# \u{1b}[1;30mA♣ \u{1b}[1;31m2♥ \u{1b}[1;30m3♣ \u{1b}[1;30m4♣ \u{1b}[1;30m10♣ \u{1b}[1;30m6♣ \u{1b}[1;30m10♣".to_string(),
+    ///            ("# A♥ 3♥ 10♣ # A♣ 2♥ 3♣ 4♣ 10♣ 6♣ 10♣".to_string(),
     ///             "2 3  4  5   6 7  8  9  10 11  12 13".to_string()));
     /// ```
     pub fn show_indices_shifted(&self, n: usize) -> (String,String) {
+        self.layout_indices(n, Card::to_plain_string)
+    }
+
+    /// Text of the sequence without any style, each card followed by a space
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::{ Sequence, Card::* , Suit::*};
+    ///
+    /// let sequence = Sequence::from_cards(&[RegularCard(Club, 2), Joker]);
+    ///
+    /// assert_eq!("2♣ # ", sequence.to_plain_string());
+    /// assert_eq!(sequence.to_plain_string(), sequence.to_string());
+    /// ```
+    pub fn to_plain_string(&self) -> String {
+        self.0.iter().map(|card| card.to_plain_string() + " ").collect()
+    }
+
+    // write the cards with `format_card` above their indices, starting from `n + 1`
+    pub(crate) fn layout_indices<F: Fn(&Card) -> String>(&self, n: usize, format_card: F) 
+        -> (String,String) {
 
         let mut first_line = String::new();
         let mut second_line = String::new();
@@ -343,7 +584,7 @@ impl Sequence {
             
             // print the current card with a space
             let current_card = &self.0[i-n-1];
-            first_line.push_str(&format!("{} ", current_card));
+            first_line.push_str(&format!("{} ", format_card(current_card)));
             
             // see how many characters the current card takes
             match current_card {
@@ -519,24 +760,13 @@ impl Sequence {
     ///
     /// assert_eq!(162, sequence.number_cards());
     /// ```
-    pub fn multi_deck(n_decks: u8, n_jokers: u8, rng: &mut ThreadRng) -> Sequence {
+    pub fn multi_deck<R: Rng + ?Sized>(n_decks: u8, n_jokers: u8, rng: &mut R) -> Sequence {
         
-        let mut deck = Sequence::new();
-
-        for _i in 0..n_decks {
-
-            // add the regular cards
-            for val in 1..=MAX_VAL {
-                for suit in &[Heart, Diamond, Club, Spade] {
-                    deck.add_card(RegularCard(*suit, val));
-                }
-            }
-        }
-            
-        // add the jokers
-        for _j in 0..n_jokers {
-            deck.add_card(Joker);
-        }
+        let mut deck = Sequence::builder()
+            .capacity(52 * n_decks as usize + n_jokers as usize)
+            .decks(n_decks)
+            .jokers(n_jokers)
+            .build();
 
         // shuffle the deck
         deck.shuffle(rng);
@@ -566,6 +796,28 @@ impl Sequence {
     pub fn add_card(&mut self, card: Card) {
         self.0.push(card);
     }
+
+    /// Add several cards to a sequence
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::{ Sequence, Card::* , Suit::*};
+    ///
+    /// let mut sequence = Sequence::from_cards(&[Joker]);
+    /// sequence.add_cards(&[RegularCard(Heart, 1), RegularCard(Heart, 2)]);
+    ///
+    /// assert_eq!(Sequence::from_cards(&[Joker, RegularCard(Heart, 1), RegularCard(Heart, 2)]),
+    ///            sequence);
+    /// ```
+    pub fn add_cards(&mut self, cards: &[Card]) {
+        self.0.extend_from_slice(cards);
+    }
+
+    /// Free the memory left unused after taking many cards from the sequence
+    pub fn shrink_to_fit(&mut self) {
+        self.0.shrink_to_fit();
+    }
     
     /// Draw the top card from a sequence
     ///
@@ -620,6 +872,25 @@ impl Sequence {
         None
     }
     
+    /// Insert a card in a sequence, so that it can be taken back with `take_card(i)`
+    ///
+    /// The card is added at the end if `i` is larger than the number of cards.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::{ Sequence, Card::* , Suit::*};
+    ///
+    /// let mut sequence = Sequence::from_cards(&[Joker, RegularCard(Heart, 2)]);
+    /// sequence.insert_card(2, RegularCard(Club, 11));
+    ///
+    /// assert_eq!(Some(RegularCard(Club, 11)), sequence.take_card(2));
+    /// ```
+    pub fn insert_card(&mut self, i: usize, card: Card) {
+        let i = i.max(1).min(self.0.len() + 1);
+        self.0.insert(i-1, card);
+    }
+    
     /// Check if a sequence has a joker
     ///
     /// # Example
@@ -708,6 +979,74 @@ impl Sequence {
         false
     }
 
+    /// Kind of the sequence if it is valid, or `None` if it is not valid or could be either
+    /// (for instance if it contains only jokers)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::*;
+    ///
+    /// let set = Sequence::from_cards(&[RegularCard(Club, 4), Joker, RegularCard(Heart, 4)]);
+    /// let run = Sequence::from_cards(&[RegularCard(Club, 4), Joker, RegularCard(Club, 6)]);
+    /// let invalid = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Heart, 6)]);
+    ///
+    /// assert_eq!(Some(MeldKind::Set), set.meld_kind());
+    /// assert_eq!(Some(MeldKind::Run), run.meld_kind());
+    /// assert_eq!(None, invalid.meld_kind());
+    /// ```
+    pub fn meld_kind(&self) -> Option<MeldKind> {
+        if self.has_only_jokers() || !self.clone().is_valid() {
+            None
+        } else if self.is_valid_sequence_same_val() {
+            Some(MeldKind::Set)
+        } else {
+            Some(MeldKind::Run)
+        }
+    }
+
+    /// Points of a sequence played on the table, used for the minimum of the first meld
+    ///
+    /// Each card counts for its points (see `Card::points`), and each joker for the card it
+    /// replaces; a joker which can be placed at either end of a run is counted at the higher one,
+    /// and an ace after a king counts for 10. The jokers of an invalid sequence count for 0.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::*;
+    ///
+    /// let set = Sequence::from_cards(&[RegularCard(Club, 12), Joker, RegularCard(Heart, 12)]);
+    /// let run = Sequence::from_cards(&[RegularCard(Club, 4), Joker, RegularCard(Club, 6), Joker]);
+    /// let high = Sequence::from_cards(&[RegularCard(Spade, 1), RegularCard(Spade, 12), Joker]);
+    ///
+    /// assert_eq!(30, set.meld_points());
+    /// assert_eq!(22, run.meld_points());
+    /// assert_eq!(30, high.meld_points());
+    /// ```
+    pub fn meld_points(&self) -> u16 {
+        let n = self.0.len() as u16;
+        let mut values: Vec<u16> = self.0.iter()
+            .filter_map(|card| card.rank().map(|rank| rank as u16))
+            .collect();
+        match self.meld_kind() {
+            Some(MeldKind::Set) => rank_points(values[0]) * n,
+            Some(MeldKind::Run) => {
+                values.sort_unstable();
+
+                // an ace is after the king if the run can not start with it
+                if values[0] == 1 && values[values.len()-1] > n {
+                    values[0] = KING as u16 + 1;
+                    values.sort_unstable();
+                }
+                let (low, high) = (values[0], values[values.len()-1]);
+                let end = (low + n - 1).max(high).min(KING as u16 + 1);
+                (end + 1 - n..=end).map(rank_points).sum()
+            },
+            None => self.0.iter().map(Card::points).sum()
+        }
+    }
+
     /// return the vector of cards
     pub fn to_vec(&self) -> Vec<Card> {
         self.0.clone()
@@ -728,10 +1067,72 @@ impl Sequence {
         true
     }
 
-    // randomly shuffle the sequence
-    fn shuffle(&mut self, rng: &mut ThreadRng) {
+    /// Randomly shuffle the sequence
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rand::{ SeedableRng, rngs::StdRng };
+    /// use machiavelli::sequence_cards::Sequence;
+    ///
+    /// let mut deck_1 = Sequence::multi_deck(1, 2, &mut StdRng::seed_from_u64(42));
+    /// let mut deck_2 = deck_1.clone();
+    /// deck_1.shuffle(&mut StdRng::seed_from_u64(1));
+    /// deck_2.shuffle(&mut StdRng::seed_from_u64(1));
+    ///
+    /// assert_eq!(deck_1, deck_2);
+    /// ```
+    pub fn shuffle<R: Rng + ?Sized>(&mut self, rng: &mut R) {
         self.0.shuffle(rng);
     }
+
+    /// Cut the sequence after its first `at` cards, which are moved after the other ones
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::{ Sequence, Card::* , Suit::*};
+    ///
+    /// let mut sequence = Sequence::from_cards(&[RegularCard(Heart, 1), RegularCard(Heart, 2), 
+    ///                                           Joker]);
+    /// sequence.cut(2);
+    ///
+    /// assert_eq!(Sequence::from_cards(&[Joker, RegularCard(Heart, 1), RegularCard(Heart, 2)]), 
+    ///            sequence);
+    /// ```
+    pub fn cut(&mut self, at: usize) {
+        let at = at.min(self.0.len());
+        self.0.rotate_left(at);
+    }
+
+    /// Deal `n_cards` cards from the top of the sequence to each of `n_players` players
+    ///
+    /// Each player draws all their cards before the next one. If there are not enough cards, the
+    /// last hands are smaller.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::sequence_cards::Sequence;
+    ///
+    /// let mut deck = Sequence::multi_deck(1, 0, &mut rand::thread_rng());
+    /// let hands = deck.deal(3, 13);
+    ///
+    /// assert_eq!(3, hands.len());
+    /// assert_eq!(13, hands[2].number_cards());
+    /// assert_eq!(13, deck.number_cards());
+    /// assert_eq!(vec![13, 0], deck.deal(2, 13).iter().map(Sequence::number_cards).collect::<Vec<_>>());
+    /// ```
+    pub fn deal(&mut self, n_players: usize, n_cards: usize) -> Vec<Sequence> {
+        (0..n_players).map(|_| {
+            let n = n_cards.min(self.0.len());
+            let mut hand = Sequence::with_capacity(n);
+            for _ in 0..n {
+                hand.add_card(self.draw_card().unwrap());
+            }
+            hand
+        }).collect()
+    }
         
     // check if the sequence contains only jokers
     fn has_only_jokers(&self) -> bool {
@@ -796,10 +1197,10 @@ impl Sequence {
                         }
                         if (*value != current_value + 1)
                               &&
-                              ((current_value < MAX_VAL) || (*value != 1))
+                              ((current_value < KING) || (*value != 1))
                         {
                             let next_val = match *value {
-                                1 => MAX_VAL + 1,
+                                1 => KING + 1,
                                 n => n
                             };
                             if next_val < (current_value + 1) {
@@ -827,16 +1228,14 @@ impl Sequence {
         
         // avoid configurations like KA#..., K##..., which look wrong
         match self.0[0] {
-            RegularCard(_, val) if val == MAX_VAL => {
+            RegularCard(_, val) if val == KING => {
                 match self.0[1] {
                     Joker => { 
                         self.0.swap(0,1); 
                     },
-                    RegularCard(_, 1) => {
-                        if self.0[2] == Joker {
-                            self.0.swap(1,2);
-                            self.0.swap(0,1);
-                        }
+                    RegularCard(_, 1) if self.0[2] == Joker => {
+                        self.0.swap(1,2);
+                        self.0.swap(0,1);
                     },
                     _ => ()
                 }
@@ -861,7 +1260,7 @@ impl Sequence {
     }
 
     /// count the numbers of cards of each type and return them as a hashmap
-    fn count_cards(&self) -> HashMap<Card, u16> {
+    pub(crate) fn count_cards(&self) -> HashMap<Card, u16> {
         let mut res = HashMap::<Card, u16>::new();
         
         for card in &self.0 {
@@ -880,11 +1279,187 @@ impl Sequence {
 
 impl fmt::Display for Sequence {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for card in &self.0 {
-            card.fmt(f)?;
-            write!(f, " ")?;
+        write!(f, "{}", self.to_plain_string())
+    }
+}
+
+/// Difference between the cards of a game and the expected ones
+#[derive(Debug, PartialEq)]
+pub struct DeckAuditError {
+
+    /// cards which should be in the game but are not, once for each missing copy
+    pub missing: Vec<Card>,
+
+    /// cards which should not be in the game, once for each extra copy
+    pub extra: Vec<Card>
+}
+
+impl fmt::Display for DeckAuditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "missing cards: {}; extra cards: {}", Sequence(self.missing.clone()), 
+               Sequence(self.extra.clone()))
+    }
+}
+
+/// Check that the cards of a game are exactly those of `n_decks` decks with `n_jokers` jokers
+///
+/// The cards can come from several sequences (deck, hands, table, ...) chained together.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::sequence_cards::{ Sequence, Card::*, Suit::*, deck_audit };
+///
+/// let mut deck = Sequence::multi_deck(2, 3, &mut rand::thread_rng());
+/// let hands = deck.deal(2, 13);
+///
+/// assert!(deck_audit(hands.iter().chain(Some(&deck)).flatten(), 2, 3).is_ok());
+///
+/// deck.draw_card();
+/// deck.add_card(RegularCard(Heart, 14));
+/// let error = deck_audit(hands.iter().chain(Some(&deck)).flatten(), 2, 3).unwrap_err();
+/// assert_eq!(1, error.missing.len());
+/// assert_eq!(vec![RegularCard(Heart, 14)], error.extra);
+/// ```
+pub fn deck_audit<'a, I: IntoIterator<Item = &'a Card>>(cards: I, n_decks: u8, n_jokers: u8) 
+    -> Result<(), DeckAuditError> {
+    
+    // count the difference between the expected and actual numbers of each card
+    let mut counts = HashMap::<Card, i64>::new();
+    for card in Sequence::builder().decks(n_decks).jokers(n_jokers).build().0 {
+        *counts.entry(card).or_insert(0) += 1;
+    }
+    for card in cards {
+        *counts.entry(card.clone()).or_insert(0) -= 1;
+    }
+
+    let mut error = DeckAuditError { missing: Vec::new(), extra: Vec::new() };
+    for (card, count) in counts {
+        let list = if count > 0 { &mut error.missing } else { &mut error.extra };
+        list.extend(std::iter::repeat(card).take(count.unsigned_abs() as usize));
+    }
+    if error.missing.is_empty() && error.extra.is_empty() {
+        Ok(())
+    } else {
+        error.missing.sort();
+        error.extra.sort();
+        Err(error)
+    }
+}
+
+/// Builder for sequences, created by `Sequence::builder`
+#[derive(Debug, Clone)]
+pub struct SequenceBuilder {
+    cards: Vec<Card>
+}
+
+impl SequenceBuilder {
+
+    /// Reserve room for `capacity` cards in total
+    pub fn capacity(mut self, capacity: usize) -> SequenceBuilder {
+        self.cards.reserve(capacity.saturating_sub(self.cards.len()));
+        self
+    }
+
+    /// Add a card
+    pub fn card(mut self, card: Card) -> SequenceBuilder {
+        self.cards.push(card);
+        self
+    }
+
+    /// Add several cards
+    pub fn cards(mut self, cards: &[Card]) -> SequenceBuilder {
+        self.cards.extend_from_slice(cards);
+        self
+    }
+
+    /// Add `n_decks` decks of 52 cards, without jokers
+    pub fn decks(mut self, n_decks: u8) -> SequenceBuilder {
+        self.cards.reserve(52 * n_decks as usize);
+        for _ in 0..n_decks {
+            for rank in &Rank::ALL {
+                for suit in &[Heart, Diamond, Club, Spade] {
+                    self.cards.push(RegularCard(*suit, *rank as u8));
+                }
+            }
         }
-        write!(f, "")
+        self
+    }
+
+    /// Add `n_jokers` jokers
+    pub fn jokers(mut self, n_jokers: u8) -> SequenceBuilder {
+        self.cards.extend(std::iter::repeat(Joker).take(n_jokers as usize));
+        self
+    }
+
+    /// Get the sequence
+    pub fn build(self) -> Sequence {
+        Sequence(self.cards)
+    }
+}
+
+/// # Example
+///
+/// ```
+/// use machiavelli::sequence_cards::{ Sequence, Card::* , Suit::*};
+///
+/// let mut sequence: Sequence = (1..=3).map(|value| RegularCard(Heart, value)).collect();
+///
+/// assert!(sequence.is_valid());
+/// ```
+impl FromIterator<Card> for Sequence {
+    fn from_iter<I: IntoIterator<Item = Card>>(iter: I) -> Sequence {
+        Sequence(iter.into_iter().collect())
+    }
+}
+
+/// # Example
+///
+/// ```
+/// use machiavelli::sequence_cards::{ Sequence, Card::* , Suit::*};
+///
+/// let mut sequence = Sequence::from_cards(&[Joker]);
+/// sequence.extend(vec![RegularCard(Club, 5), RegularCard(Club, 6)]);
+///
+/// assert_eq!(3, sequence.number_cards());
+/// ```
+impl Extend<Card> for Sequence {
+    fn extend<I: IntoIterator<Item = Card>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+/// # Example
+///
+/// ```
+/// use machiavelli::sequence_cards::{ Sequence, Card::* , Suit::*};
+///
+/// let sequence = Sequence::from_cards(&[Joker, RegularCard(Club, 5)]);
+/// let mut n_jokers = 0;
+/// for card in &sequence {
+///     if *card == Joker {
+///         n_jokers += 1;
+///     }
+/// }
+///
+/// assert_eq!(1, n_jokers);
+/// assert_eq!(vec![Joker, RegularCard(Club, 5)], sequence.into_iter().collect::<Vec<_>>());
+/// ```
+impl IntoIterator for Sequence {
+    type Item = Card;
+    type IntoIter = std::vec::IntoIter<Card>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Sequence {
+    type Item = &'a Card;
+    type IntoIter = std::slice::Iter<'a, Card>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
     }
 }
 
@@ -893,7 +1468,7 @@ impl fmt::Display for Sequence {
 fn value_card_by_suit(card: &Card) -> u8 {
     match *card {
         Joker => 255,
-        RegularCard(suit, val) => (MAX_VAL + 1) * suit_to_int(suit) + val
+        RegularCard(suit, val) => (KING + 1) * suit_to_int(suit) + val
     }
 }
 
@@ -908,6 +1483,7 @@ fn value_card_by_rank(card: &Card) -> u8 {
 
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
 
     use super::*;
@@ -1395,7 +1971,7 @@ mod tests {
             RegularCard(Diamond, 3), 
             RegularCard(Heart, 2), 
         ]);
-        assert_eq!("\u{1b}[1;30m2♣ \u{1b}[1;34m# \u{1b}[1;31m3♦ \u{1b}[1;31m2♥ ".to_string(), format!("{}", &seq));
+        assert_eq!("2♣ # 3♦ 2♥ ".to_string(), format!("{}", &seq));
     }
 
     #[test]
@@ -1417,7 +1993,7 @@ mod tests {
         let sequence = Sequence::from_cards(&cards);
        
         assert_eq!(sequence.show_indices_shifted(1), 
-                   ("\u{1b}[1;34m# \u{1b}[1;31mA♥ \u{1b}[1;31m3♥ \u{1b}[1;30m10♣ \u{1b}[1;34m# \u{1b}[1;30mA♣ \u{1b}[1;31m2♥ \u{1b}[1;30m3♣ \u{1b}[1;30m4♣ \u{1b}[1;30m10♣ \u{1b}[1;30m6♣ \u{1b}[1;30m10♣".to_string(),
+                   ("# A♥ 3♥ 10♣ # A♣ 2♥ 3♣ 4♣ 10♣ 6♣ 10♣".to_string(),
                     "2 3  4  5   6 7  8  9  10 11  12 13".to_string()));
     }
     
@@ -1440,7 +2016,7 @@ mod tests {
         let sequence = Sequence::from_cards(&cards);
        
         assert_eq!(sequence.show_indices_shifted(11), 
-                   ("\u{1b}[1;34m#  \u{1b}[1;31mA♥ \u{1b}[1;31m3♥ \u{1b}[1;30m10♣ \u{1b}[1;34m#  \u{1b}[1;30mA♣ \u{1b}[1;31m2♥ \u{1b}[1;30m3♣ \u{1b}[1;30m4♣ \u{1b}[1;30m10♣ \u{1b}[1;30m6♣ \u{1b}[1;30m10♣".to_string(),
+                   ("#  A♥ 3♥ 10♣ #  A♣ 2♥ 3♣ 4♣ 10♣ 6♣ 10♣".to_string(),
                     "12 13 14 15  16 17 18 19 20 21  22 23".to_string()));
     }
     
@@ -1463,7 +2039,7 @@ mod tests {
         let sequence = Sequence::from_cards(&cards);
        
         assert_eq!(sequence.show_indices_shifted(11), 
-                   ("\u{1b}[1;34m#  \u{1b}[1;31mA♥ \u{1b}[1;31m3♥ \u{1b}[1;30m10♣ \u{1b}[1;34m#  \u{1b}[1;30mA♣ \u{1b}[1;31m2♥ \u{1b}[1;30m3♣ \u{1b}[1;30m4♣ \u{1b}[1;30m10♣ \u{1b}[1;34m#  \u{1b}[1;30m10♣".to_string(),
+                   ("#  A♥ 3♥ 10♣ #  A♣ 2♥ 3♣ 4♣ 10♣ #  10♣".to_string(),
                     "12 13 14 15  16 17 18 19 20 21  22 23".to_string()));
     }
 
@@ -0,0 +1,118 @@
+//! Monte-Carlo estimation of the chances of winning
+//!
+//! Each rollout deals the cards which are not on the table again, keeping the number of cards of
+//! each player, and plays the rest of the game with the bot policy: play every sequence found in
+//! the hand, or pick a card. Only public information is used, so the estimates can be shown to all
+//! the players.
+
+use rand::Rng;
+use crate::{ Sequence, Table, pick_a_card, next_player };
+use crate::bot::MeldCache;
+
+/// number of rollouts used by the servers to estimate the chances of winning
+pub const N_ROLLOUTS: usize = 50;
+
+/// maximum number of turns of a rollout, after which it counts as a draw
+const MAX_TURNS_ROLLOUT: usize = 300;
+
+/// number of hands kept in the cache of the solver during the rollouts
+const ROLLOUT_CACHE_CAPACITY: usize = 10_000;
+
+/// play a turn with the bot policy; return `true` if the player has emptied their hand
+fn play_policy_turn(hand: &mut Sequence, table: &mut Table, deck: &mut Sequence,
+                    cache: &mut MeldCache) -> bool {
+    let mut has_played = false;
+    while let Some((meld, indices)) = cache.find_meld(hand) {
+        for i in indices {
+            hand.take_card(i);
+        }
+        table.add(meld);
+        has_played = true;
+    }
+    if !has_played {
+        pick_a_card(hand, deck).ok();
+    }
+    hand.number_cards() == 0
+}
+
+/// play the rest of a game with the bot policy, starting with `player`
+///
+/// Return the index of the winner, or `None` if the game ends in a draw.
+pub fn rollout(hands: &mut [Sequence], table: &mut Table, deck: &mut Sequence, mut player: usize,
+               forfeited: &[bool], cache: &mut MeldCache) -> Option<usize> {
+    for _ in 0..MAX_TURNS_ROLLOUT {
+        if deck.number_cards() == 0 {
+            return None;
+        }
+        if play_policy_turn(&mut hands[player], table, deck, cache) {
+            return Some(player);
+        }
+        player = next_player(player, forfeited);
+    }
+    None
+}
+
+/// estimate the chances of winning of each player, from the one whose turn it is
+///
+/// The players who have forfeited have no chance; the chances do not add up to 1 if some rollouts
+/// end in a draw.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::simulation::estimate_win_chances;
+///
+/// let mut deck = Sequence::multi_deck(2, 2, &mut rand::thread_rng());
+/// let hands = deck.deal(3, 13);
+/// let chances = estimate_win_chances(&hands, &Table::new(), &deck, 0, &[false, true, false], 10,
+///                                    &mut rand::thread_rng());
+///
+/// assert_eq!(3, chances.len());
+/// assert_eq!(0., chances[1]);
+/// assert!(chances.iter().sum::<f64>() <= 1.);
+/// ```
+pub fn estimate_win_chances<R: Rng + ?Sized>(hands: &[Sequence], table: &Table, deck: &Sequence,
+                                             current_player: usize, forfeited: &[bool],
+                                             n_rollouts: usize, rng: &mut R) -> Vec<f64> {
+    let mut n_wins = vec![0; hands.len()];
+    let mut cache = MeldCache::new(ROLLOUT_CACHE_CAPACITY);
+
+    // cards whose position is unknown to the players
+    let mut unseen = deck.clone();
+    for hand in hands {
+        unseen.merge(hand.clone());
+    }
+
+    for _ in 0..n_rollouts {
+        let mut cards = unseen.clone();
+        cards.shuffle(rng);
+        let mut hands: Vec<Sequence> = hands.iter().map(|hand| {
+            (0..hand.number_cards()).filter_map(|_| cards.draw_card()).collect()
+        }).collect();
+        let mut table = table.clone();
+        if let Some(winner) = rollout(&mut hands, &mut table, &mut cards, current_player, forfeited,
+                                      &mut cache) {
+            n_wins[winner] += 1;
+        }
+    }
+    n_wins.iter().map(|&n| n as f64 / n_rollouts.max(1) as f64).collect()
+}
+
+/// chances of winning as percentages, one player per line
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::simulation::chances_summary;
+///
+/// let names = vec!["Alice".to_string(), "Bob".to_string()];
+///
+/// assert_eq!("  Alice: 75%\n  Bob: 20%\n", chances_summary(&names, &[0.75, 0.2], &[false, false]));
+/// ```
+pub fn chances_summary(player_names: &[String], chances: &[f64], forfeited: &[bool]) -> String {
+    player_names.iter().zip(chances).zip(forfeited)
+        .filter(|(_, &f)| !f)
+        .map(|((name, chance), _)| format!("  {}: {:.0}%\n", name, 100. * chance))
+        .collect()
+}
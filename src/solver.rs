@@ -0,0 +1,360 @@
+//! Search for the moves a player can make with their hand
+//!
+//! The solver looks for all the melds which can be formed with the cards of a hand, and for the
+//! cards which can be added on their own to the sequences of the table. The players can ask for
+//! one of these moves as a hint with the command `h`.
+//!
+//! The number of melds grows quickly with the size of the hand, so the search is limited (see
+//! [`SolverLimits`]): when it visits too many sets of cards, or keeps too many melds, it stops and
+//! the melds are looked for with the greedy heuristic of the bots instead.
+//!
+//! With several decks, the same cards can be chosen from different decks, which leads to the same
+//! sets of cards and the same cards left to choose from. The search keeps these states in a
+//! transposition table, keyed on the cards chosen and the cards left regardless of their decks,
+//! and does not visit them twice.
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt;
+use std::mem::size_of;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use crate::{ Card, Sequence, Table, render, reset_style_string };
+#[cfg(feature = "serde")]
+use crate::InvalidInputError;
+use crate::bot::find_meld;
+use crate::evaluation::card_points;
+use crate::tournament::Move;
+
+/// default maximum number of sets of cards visited by a search
+pub const DEFAULT_MAX_NODES: usize = 200_000;
+
+/// default maximum memory used by the melds found, in kilobytes
+pub const DEFAULT_MAX_MEMORY_KB: usize = 4096;
+
+static MAX_NODES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_NODES);
+static MAX_MEMORY_KB: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_MEMORY_KB);
+
+/// Limits of the search for melds
+///
+/// With the `serde` feature, they can be read from a TOML file (see
+/// `SolverLimits::from_string`). With `max_nodes = 0`, only the greedy heuristic is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SolverLimits {
+
+    /// maximum number of sets of cards visited
+    pub max_nodes: usize,
+
+    /// maximum memory used by the melds found, in kilobytes (an estimate)
+    pub max_memory_kb: usize
+}
+
+impl Default for SolverLimits {
+    fn default() -> Self {
+        SolverLimits { max_nodes: DEFAULT_MAX_NODES, max_memory_kb: DEFAULT_MAX_MEMORY_KB }
+    }
+}
+
+impl SolverLimits {
+
+    /// Read the limits from a TOML string
+    ///
+    /// The limits are non-negative integers given as top-level keys; those which are not given
+    /// keep their default value. The sections (tables) are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::solver::SolverLimits;
+    ///
+    /// let limits = SolverLimits::from_string("# small VPS\nmax_nodes = 5000 # nodes\n").unwrap();
+    ///
+    /// assert_eq!(5000, limits.max_nodes);
+    /// assert_eq!(SolverLimits::default().max_memory_kb, limits.max_memory_kb);
+    /// assert_eq!(limits, SolverLimits::from_string(&limits.to_string()).unwrap());
+    /// assert!(SolverLimits::from_string("max_nodes = -1").is_err());
+    /// assert!(SolverLimits::from_string("max_nodes = 5e3").is_err());
+    /// assert!(SolverLimits::from_string("max_depth = 3").is_err());
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn from_string(s: &str) -> Result<SolverLimits, InvalidInputError> {
+        use std::convert::TryFrom;
+        let mut limits = SolverLimits::default();
+        for (name, value) in toml::from_str::<toml::Table>(s)? {
+            let limit = match name.as_str() {
+                "max_nodes" => &mut limits.max_nodes,
+                "max_memory_kb" => &mut limits.max_memory_kb,
+                _ if value.is_table() => continue,
+                _ => return Err(InvalidInputError {})
+            };
+            *limit = usize::try_from(value.as_integer().ok_or(InvalidInputError {})?)?;
+        }
+        Ok(limits)
+    }
+
+    /// Read the limits from a TOML file
+    #[cfg(feature = "serde")]
+    pub fn load(fname: &str) -> Result<SolverLimits, InvalidInputError> {
+        SolverLimits::from_string(&std::fs::read_to_string(fname)?)
+    }
+}
+
+impl fmt::Display for SolverLimits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "max_nodes = {}", self.max_nodes)?;
+        writeln!(f, "max_memory_kb = {}", self.max_memory_kb)
+    }
+}
+
+/// set the limits of the searches made by `find_valid_melds`
+pub fn set_limits(limits: SolverLimits) {
+    MAX_NODES.store(limits.max_nodes, Ordering::Relaxed);
+    MAX_MEMORY_KB.store(limits.max_memory_kb, Ordering::Relaxed);
+}
+
+/// limits of the searches made by `find_valid_melds`
+pub fn limits() -> SolverLimits {
+    SolverLimits {
+        max_nodes: MAX_NODES.load(Ordering::Relaxed),
+        max_memory_kb: MAX_MEMORY_KB.load(Ordering::Relaxed)
+    }
+}
+
+/// Moves found by a search, and how they have been found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    pub moves: Vec<Move>,
+
+    /// number of sets of cards visited
+    pub nodes: usize,
+
+    /// number of sets of cards not visited because the same cards had already been reached from
+    /// other decks (see the transposition table of the search)
+    pub transpositions: usize,
+
+    /// `true` if the search has reached a limit, in which case the melds come from the greedy
+    /// heuristic
+    pub degraded: bool
+}
+
+/// Moves which can be made with the cards of a hand: the melds which can be formed with them,
+/// from the one with the most cards, then the cards which can be added on their own to the
+/// sequences of the table, from the one worth the most points
+///
+/// The moves are checked against the standard rules only. Melds made of the same cards (from
+/// different decks) are given once. The search is limited as set by `set_limits`; if it reaches
+/// a limit, the melds come from the greedy heuristic (see `find_valid_melds_within`).
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::solver::find_valid_melds;
+/// use machiavelli::tournament::Move;
+///
+/// let mut table = Table::new();
+/// table.add(Sequence::from_cards(&[RegularCard(Heart, 7), RegularCard(Spade, 7), RegularCard(Club, 7)]));
+/// let hand = Sequence::from_cards(&[
+///     RegularCard(Club, 4), RegularCard(Diamond, 7), RegularCard(Club, 6),
+///     RegularCard(Club, 5), RegularCard(Heart, 1),
+/// ]);
+///
+/// assert_eq!(vec![Move::Play(vec![1, 3, 4]), Move::Add(2, 1)], find_valid_melds(&hand, &table));
+/// assert!(find_valid_melds(&Sequence::from_cards(&[RegularCard(Heart, 1)]), &Table::new()).is_empty());
+/// ```
+pub fn find_valid_melds(hand: &Sequence, table: &Table) -> Vec<Move> {
+    find_valid_melds_within(hand, table, &limits()).moves
+}
+
+/// Same as `find_valid_melds`, with the given limits
+///
+/// If the search visits more than `limits.max_nodes` sets of cards, or if the melds it has found
+/// take more than `limits.max_memory_kb` kilobytes, the melds are replaced by the one found by
+/// the greedy heuristic of the bots (`bot::find_meld`), if any; the cards which can be added to
+/// the table are always all given.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::solver::{ find_valid_melds_within, SolverLimits };
+/// use machiavelli::tournament::Move;
+///
+/// let hand = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6),
+///                                   RegularCard(Club, 7)]);
+///
+/// let solution = find_valid_melds_within(&hand, &Table::new(), &SolverLimits::default());
+/// assert_eq!(3, solution.moves.len());
+/// assert_eq!(Move::Play(vec![1, 2, 3, 4]), solution.moves[0]);
+/// assert!(!solution.degraded);
+///
+/// let limits = SolverLimits { max_nodes: 5, ..SolverLimits::default() };
+/// let solution = find_valid_melds_within(&hand, &Table::new(), &limits);
+/// assert_eq!(vec![Move::Play(vec![1, 2, 3, 4])], solution.moves);
+/// assert!(solution.degraded);
+///
+/// // the second 4♣ leads to the same sets of cards as the first one
+/// let hand = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 4), RegularCard(Club, 5),
+///                                   RegularCard(Club, 6)]);
+/// let solution = find_valid_melds_within(&hand, &Table::new(), &SolverLimits::default());
+/// assert_eq!(vec![Move::Play(vec![1, 3, 4])], solution.moves);
+/// assert!(solution.transpositions > 0);
+/// ```
+pub fn find_valid_melds_within(hand: &Sequence, table: &Table, limits: &SolverLimits) -> Solution {
+    let cards = hand.to_vec();
+    let mut search = Search { cards: &cards, limits, melds: Vec::new(), nodes: 0, memory: 0,
+                              visited: HashSet::new(), transpositions: 0 };
+    let degraded = !search.visit(&mut Vec::new(), 0);
+    let (nodes, transpositions) = (search.nodes, search.transpositions);
+    let mut melds = search.melds;
+    if degraded {
+        melds = find_meld(hand).map(|(_, indices)| indices.into_iter().rev().map(|i| i - 1).collect())
+            .into_iter()
+            .collect();
+    }
+
+    // melds with the same cards, from different decks, are kept once
+    let mut seen = HashSet::<Vec<u8>>::new();
+    melds.retain(|meld| {
+        let mut key = Sequence::from_cards(&meld_cards(&cards, meld)).to_bytes();
+        key.sort_unstable();
+        seen.insert(key)
+    });
+    melds.sort_by_key(|meld| {
+        let points: u32 = meld.iter().map(|&i| card_points(&cards[i])).sum();
+        (Reverse(meld.len()), Reverse(points))
+    });
+    let mut moves: Vec<Move> = melds.into_iter()
+        .map(|meld| Move::Play(meld.into_iter().map(|i| i + 1).collect()))
+        .collect();
+
+    // cards added on their own to the sequences of the table
+    let mut additions = Vec::<(u32, Move)>::new();
+    for (i, seq) in table.to_vec().iter().enumerate() {
+        for (j, card) in cards.iter().enumerate() {
+            let mut longer = seq.clone();
+            longer.add_card(card.clone());
+            if longer.is_valid() {
+                additions.push((card_points(card), Move::Add(j + 1, i + 1)));
+            }
+        }
+    }
+    additions.sort_by_key(|(points, _)| Reverse(*points));
+    moves.extend(additions.into_iter().map(|(_, m)| m));
+    Solution { moves, nodes, transpositions, degraded }
+}
+
+// state of a search for melds
+struct Search<'a> {
+    cards: &'a [Card],
+    limits: &'a SolverLimits,
+    melds: Vec<Vec<usize>>,
+    nodes: usize,
+
+    // estimated memory used by the melds found, in bytes
+    memory: usize,
+
+    // transposition table: the cards chosen and the cards left to choose from, as sorted bytes
+    // separated by 255, of the states already visited (at most one per node, so its size is
+    // limited by `max_nodes`)
+    visited: HashSet<Vec<u8>>,
+    transpositions: usize
+}
+
+impl Search<'_> {
+
+    // add to `chosen` (indices of cards, in increasing order) the cards after `next` which may
+    // form a meld with them, keeping the sets of at least three cards which do; return `false` if
+    // a limit has been reached
+    fn visit(&mut self, chosen: &mut Vec<usize>, next: usize) -> bool {
+
+        // the melds found from a state already visited would only repeat the ones found then
+        if !self.visited.insert(self.canonical_state(chosen, next)) {
+            self.transpositions += 1;
+            return true;
+        }
+        self.nodes += 1;
+        if self.nodes > self.limits.max_nodes {
+            return false;
+        }
+        if chosen.len() >= 3 && Sequence::from_cards(&meld_cards(self.cards, chosen)).is_valid() {
+
+            // the meld, and its key when the duplicates are removed
+            self.memory += size_of::<Vec<usize>>() + chosen.len() * size_of::<usize>()
+                + size_of::<Vec<u8>>() + chosen.len();
+            if self.memory > self.limits.max_memory_kb * 1024 {
+                return false;
+            }
+            self.melds.push(chosen.clone());
+        }
+        for i in next..self.cards.len() {
+            chosen.push(i);
+            let complete = !may_form_meld(self.cards, chosen) || self.visit(chosen, i + 1);
+            chosen.pop();
+            if !complete {
+                return false;
+            }
+        }
+        true
+    }
+
+    // key of a state in the transposition table, which does not depend on the decks of the cards
+    fn canonical_state(&self, chosen: &[usize], next: usize) -> Vec<u8> {
+        let mut key = Sequence::from_cards(&meld_cards(self.cards, chosen)).to_bytes();
+        key.sort_unstable();
+        let mut left = Sequence::from_cards(&self.cards[next..]).to_bytes();
+        left.sort_unstable();
+        key.push(255);
+        key.extend(left);
+        key
+    }
+}
+
+// check if the cards with the given indices may be part of a meld: their regular cards must have
+// the same value and different suits, or the same suit and different values
+fn may_form_meld(cards: &[Card], indices: &[usize]) -> bool {
+    let regular: Vec<&Card> = indices.iter().map(|&i| &cards[i]).filter(|c| **c != Card::Joker).collect();
+    let mut group = true;
+    let mut run = true;
+    for (i, card_1) in regular.iter().enumerate() {
+        for card_2 in &regular[i+1..] {
+            group &= card_1.rank() == card_2.rank() && card_1.suit() != card_2.suit();
+            run &= card_1.suit() == card_2.suit() && card_1.rank() != card_2.rank();
+        }
+    }
+    group || run
+}
+
+// cards with the given indices
+fn meld_cards(cards: &[Card], indices: &[usize]) -> Vec<Card> {
+    indices.iter().map(|&i| cards[i].clone()).collect()
+}
+
+/// Description of a move made with the cards of `hand`
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::solver::describe_move;
+/// use machiavelli::tournament::Move;
+///
+/// set_colors(false);
+/// let hand = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Diamond, 7), RegularCard(Club, 6),
+///                                   RegularCard(Club, 5)]);
+///
+/// assert_eq!("play 4♣ 6♣ 5♣ (cards 1 3 4)", describe_move(&Move::Play(vec![1, 3, 4]), &hand));
+/// assert_eq!("add 7♦ (card 2) to sequence 1", describe_move(&Move::Add(2, 1), &hand));
+/// ```
+pub fn describe_move(m: &Move, hand: &Sequence) -> String {
+    let cards = hand.to_vec();
+    let card = |i: usize| cards.get(i.wrapping_sub(1))
+        .map(|card| format!("{}{}", render::card(card), reset_style_string()))
+        .unwrap_or_default();
+    match m {
+        Move::Play(indices) => format!("play {} (cards {})",
+                                       indices.iter().map(|&i| card(i)).collect::<Vec<String>>().join(" "),
+                                       indices.iter().map(usize::to_string).collect::<Vec<String>>().join(" ")),
+        Move::Add(i, n) => format!("add {} (card {}) to sequence {}", card(*i), i, n),
+        Move::Take(n) => format!("take sequence {}", n)
+    }
+}
@@ -0,0 +1,271 @@
+//! Records set during a game
+//!
+//! The records of a game are shown at the end of it and merged with the all-time records, which
+//! are stored in the user config directory. The time taken by each player is also shown at the end
+//! of the game.
+
+use std::fmt;
+use std::time::Duration;
+use crate::{ Sequence, Table, InvalidInputError, user_config_dir };
+
+/// name of the file, in the user config directory, storing the all-time records
+const RECORDS_FILE: &str = "records.dat";
+
+/// A record and the player who holds it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Record {
+    pub value: usize,
+    pub player: String
+}
+
+impl Record {
+
+    // beat the record if `value` is larger, returning whether it has been beaten
+    fn beat(&mut self, value: usize, player: &str) -> bool {
+        if value > self.value {
+            self.value = value;
+            self.player = player.to_string();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Records on the melds
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GameRecords {
+
+    /// longest run (cards of the same suit with consecutive values) on the table
+    pub longest_run: Record,
+
+    /// biggest set (cards with the same value) on the table
+    pub biggest_set: Record,
+
+    /// largest number of cards played from the hand in a single turn
+    pub largest_play: Record
+}
+
+/// determine whether a valid sequence is a run rather than a set
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::stats::is_run;
+///
+/// assert!(is_run(&Sequence::from_cards(&[RegularCard(Club, 4), Joker, RegularCard(Club, 6)])));
+/// assert!(!is_run(&Sequence::from_cards(&[RegularCard(Club, 4), Joker, RegularCard(Heart, 4)])));
+/// ```
+pub fn is_run(sequence: &Sequence) -> bool {
+    let mut values = sequence.to_vec().into_iter().filter_map(|card| card.rank());
+    match values.next() {
+        Some(first) => values.any(|value| value != first),
+        None => false
+    }
+}
+
+impl GameRecords {
+
+    /// Create empty records
+    pub fn new() -> GameRecords {
+        GameRecords::default()
+    }
+
+    /// Update the records after the turn of `player`, who has played `n_cards_played` cards
+    ///
+    /// Return the descriptions of the records which have been beaten.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::*;
+    /// use machiavelli::stats::GameRecords;
+    ///
+    /// let mut table = Table::new();
+    /// table.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+    /// let mut records = GameRecords::new();
+    ///
+    /// assert_eq!(2, records.update(&table, 3, "Alice").len());
+    /// assert_eq!(3, records.longest_run.value);
+    /// assert_eq!("Alice", records.largest_play.player);
+    /// assert!(records.update(&table, 2, "Bob").is_empty());
+    /// ```
+    pub fn update(&mut self, table: &Table, n_cards_played: usize, player: &str) -> Vec<String> {
+        let mut beaten = Vec::<String>::new();
+        let longest_run = table.iter().filter(|seq| is_run(seq))
+            .map(Sequence::number_cards).max().unwrap_or(0);
+        if self.longest_run.beat(longest_run, player) {
+            beaten.push(format!("longest run ({} cards)", longest_run));
+        }
+        let biggest_set = table.iter().filter(|seq| !is_run(seq))
+            .map(Sequence::number_cards).max().unwrap_or(0);
+        if self.biggest_set.beat(biggest_set, player) {
+            beaten.push(format!("biggest set ({} cards)", biggest_set));
+        }
+        if self.largest_play.beat(n_cards_played, player) {
+            beaten.push(format!("largest play ({} cards)", n_cards_played));
+        }
+        beaten
+    }
+
+    /// Keep the best of two sets of records
+    pub fn merge(&mut self, other: &GameRecords) {
+        self.longest_run.beat(other.longest_run.value, &other.longest_run.player);
+        self.biggest_set.beat(other.biggest_set.value, &other.biggest_set.player);
+        self.largest_play.beat(other.largest_play.value, &other.largest_play.player);
+    }
+
+    /// Summary of the records, to be shown at the end of a game
+    pub fn summary(&self) -> String {
+        let line = |name: &str, record: &Record| {
+            if record.value == 0 {
+                format!("{}: none\n", name)
+            } else {
+                format!("{}: {} cards, by {}\n", name, record.value, &record.player)
+            }
+        };
+        format!("{}{}{}",
+                line("Longest run", &self.longest_run),
+                line("Biggest set", &self.biggest_set),
+                line("Largest play in a turn", &self.largest_play))
+    }
+
+    /// Read the records from a string
+    ///
+    /// Each line has the form `name = value player`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::stats::GameRecords;
+    ///
+    /// let records = GameRecords::from_string("longest_run = 7 Alice B.\n").unwrap();
+    ///
+    /// assert_eq!(7, records.longest_run.value);
+    /// assert_eq!("Alice B.", records.longest_run.player);
+    /// assert_eq!(records, GameRecords::from_string(&records.to_string()).unwrap());
+    /// ```
+    pub fn from_string(s: &str) -> Result<GameRecords, InvalidInputError> {
+        let mut records = GameRecords::new();
+        for line in s.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let (name, value) = line.split_once('=').ok_or(InvalidInputError {})?;
+            let value = value.trim();
+            let (n, player) = value.split_once(' ').unwrap_or((value, ""));
+            let record = Record { value: n.parse()?, player: player.trim().to_string() };
+            match name.trim() {
+                "longest_run" => records.longest_run = record,
+                "biggest_set" => records.biggest_set = record,
+                "largest_play" => records.largest_play = record,
+                _ => return Err(InvalidInputError {})
+            }
+        }
+        Ok(records)
+    }
+
+    /// Load the all-time records from the user config directory (empty if there are none)
+    pub fn load() -> GameRecords {
+        user_config_dir()
+            .and_then(|dir| std::fs::read_to_string(dir.join(RECORDS_FILE)).ok())
+            .and_then(|s| GameRecords::from_string(&s).ok())
+            .unwrap_or_default()
+    }
+
+    /// Save the records in the user config directory
+    pub fn save(&self) -> Result<(), InvalidInputError> {
+        let dir = user_config_dir().ok_or(InvalidInputError {})?;
+        std::fs::write(dir.join(RECORDS_FILE), self.to_string())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for GameRecords {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "longest_run = {} {}", self.longest_run.value, &self.longest_run.player)?;
+        writeln!(f, "biggest_set = {} {}", self.biggest_set.value, &self.biggest_set.player)?;
+        writeln!(f, "largest_play = {} {}", self.largest_play.value, &self.largest_play.player)
+    }
+}
+
+/// write a duration in a short form, like `2m31s`
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use machiavelli::stats::format_duration;
+///
+/// assert_eq!("45s", format_duration(Duration::from_millis(45_300)));
+/// assert_eq!("2m31s", format_duration(Duration::from_secs(151)));
+/// assert_eq!("1h02m03s", format_duration(Duration::from_secs(3723)));
+/// ```
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Time taken by each player during their turns
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TurnTimes {
+    totals: Vec<Duration>,
+    n_turns: Vec<usize>
+}
+
+impl TurnTimes {
+
+    /// Create empty times for `n_players` players
+    pub fn new(n_players: usize) -> TurnTimes {
+        TurnTimes { totals: vec![Duration::ZERO; n_players], n_turns: vec![0; n_players] }
+    }
+
+    /// Add a player who has joined the game
+    pub fn add_player(&mut self) {
+        self.totals.push(Duration::ZERO);
+        self.n_turns.push(0);
+    }
+
+    /// Record the duration of a turn of `player`
+    pub fn record(&mut self, player: usize, duration: Duration) {
+        self.totals[player] += duration;
+        self.n_turns[player] += 1;
+    }
+
+    /// Average duration of the turns of `player` (`None` if they have not played yet)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use machiavelli::stats::TurnTimes;
+    ///
+    /// let mut times = TurnTimes::new(2);
+    /// times.record(0, Duration::from_secs(10));
+    /// times.record(0, Duration::from_secs(20));
+    ///
+    /// assert_eq!(Some(Duration::from_secs(15)), times.average(0));
+    /// assert_eq!(None, times.average(1));
+    /// ```
+    pub fn average(&self, player: usize) -> Option<Duration> {
+        match self.n_turns[player] {
+            0 => None,
+            n => Some(self.totals[player] / n as u32)
+        }
+    }
+
+    /// Summary of the average turn times, to be shown at the end of a game
+    pub fn summary(&self, player_names: &[String]) -> String {
+        (0..self.totals.len())
+            .filter_map(|i| self.average(i).map(|average| {
+                format!("{}: {} on average over {} turns\n", &player_names[i], 
+                        format_duration(average), self.n_turns[i])
+            }))
+            .collect()
+    }
+}
@@ -1,18 +1,19 @@
 //! Define the Table structure
 //!
-//! The Table is defined as a cons list of sequences of cards.
+//! The Table is a vector of sequences of cards, from the oldest to the most recent; the most
+//! recent one is shown first, with the index 1. The sequences are shared between the copies of a
+//! table, so that copying it (for instance to restore it at the end of a turn) only copies the
+//! pointers to them; a sequence is only copied when it is taken from a table sharing it.
 
 use std::fmt;
 use std::collections::HashMap;
-use core::mem::swap;
+use std::sync::Arc;
 use crate::sequence_cards::*;
-use super::reset_style_string;
-use SequenceList::*;
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Table {
-    number_sequences: usize, 
-    sequences: SequenceList
+    sequences: Vec<Arc<Sequence>>
 }
 
 impl Default for Table {
@@ -34,8 +35,7 @@ impl Table {
     /// ```
     pub fn new() -> Table {
         Table {
-            number_sequences: 0,
-            sequences: Nil
+            sequences: Vec::new()
         }
     }
     
@@ -67,13 +67,11 @@ impl Table {
     /// ```
     pub fn from_bytes(bytes: &[u8]) -> Table {
         let mut cur_seq = Vec::<u8>::new();
-        let mut sequences = Nil;
-        let mut number_sequences: usize = 0;
+        let mut sequences = Vec::<Arc<Sequence>>::new();
         for &b in bytes {
             match b {
                 255 => {
-                    number_sequences += 1;
-                    sequences = Cons(Sequence::from_bytes(&cur_seq), Box::new(sequences));
+                    sequences.push(Arc::new(Sequence::from_bytes(&cur_seq)));
                     cur_seq = Vec::<u8>::new();
                 },
                 n => {
@@ -82,7 +80,6 @@ impl Table {
             }
         }
         Table {
-            number_sequences,
             sequences
         }
     }
@@ -116,18 +113,9 @@ impl Table {
     /// ```
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut res = Vec::<u8>::new();
-        let mut cur_seq = &self.sequences;
-        for _i in 0..self.number_sequences {
-            match cur_seq {
-                Nil => (),
-                Cons(seq, box_list) => {
-                    let mut buffer = res;
-                    res = (*seq).to_bytes();
-                    res.push(255);
-                    res.append(&mut buffer);
-                    cur_seq = &**box_list;
-                }
-            }
+        for seq in self.sequences.iter() {
+            res.append(&mut seq.to_bytes());
+            res.push(255);
         }
         res
     }
@@ -152,13 +140,10 @@ impl Table {
     ///     RegularCard(Heart, 13), 
     /// ]));
     ///
-    /// assert_eq!("1: \u{1b}[1;31mJ♥ \u{1b}[1;31mQ♥ \u{1b}[1;31mK♥ \u{1b}[0m\u{1b}[30;47m\u{1b}[?25l\u{1b}[K\n2: \u{1b}[1;30m4♣ \u{1b}[1;30m5♣ \u{1b}[1;30m6♣ \u{1b}[0m\u{1b}[30;47m\u{1b}[?25l\u{1b}[K\n".to_string(), format!("{}", &table));
+    /// assert_eq!("1: J♥ Q♥ K♥ \n2: 4♣ 5♣ 6♣ \n".to_string(), format!("{}", &table));
     /// ```
     pub fn add(&mut self, sequence: Sequence) {
-        let mut buffer = Box::new(Nil);
-        swap(&mut self.sequences, &mut buffer);
-        self.sequences = SequenceList::Cons(sequence, buffer);
-        self.number_sequences += 1;
+        self.sequences.push(Arc::new(sequence));
     }
     
     /// Take a sequence from a table
@@ -193,7 +178,7 @@ impl Table {
     ///     RegularCard(Club, 5), 
     ///     RegularCard(Club, 6), 
     /// ]));
-    /// assert_eq!("1: \u{1b}[1;31mJ♥ \u{1b}[1;31mQ♥ \u{1b}[1;31mK♥ \u{1b}[0m\u{1b}[30;47m\u{1b}[?25l\u{1b}[K\n2: \u{1b}[1;30m7♠ \u{1b}[1;31m7♥ \u{1b}[1;31m7♦ \u{1b}[0m\u{1b}[30;47m\u{1b}[?25l\u{1b}[K\n".to_string(), format!("{}", &table));
+    /// assert_eq!("1: J♥ Q♥ K♥ \n2: 7♠ 7♥ 7♦ \n".to_string(), format!("{}", &table));
     ///
     /// seq = table.take(1).unwrap();
     ///
@@ -202,7 +187,7 @@ impl Table {
     ///     RegularCard(Heart, 12), 
     ///     RegularCard(Heart, 13), 
     /// ]));
-    /// assert_eq!("1: \u{1b}[1;30m7♠ \u{1b}[1;31m7♥ \u{1b}[1;31m7♦ \u{1b}[0m\u{1b}[30;47m\u{1b}[?25l\u{1b}[K\n".to_string(), format!("{}", &table));
+    /// assert_eq!("1: 7♠ 7♥ 7♦ \n".to_string(), format!("{}", &table));
     ///
     /// seq = table.take(1).unwrap();
     ///
@@ -214,51 +199,95 @@ impl Table {
     /// assert_eq!("".to_string(), format!("{}", &table));
     /// ```
     pub fn take(&mut self, n: usize) -> Option<Sequence> {
-        
-        if (n==0) || (n > self.number_sequences) {
-            return None;
-        }
+        let position = self.position(n)?;
+        let sequence = self.sequences.remove(position);
+        Some(Arc::try_unwrap(sequence).unwrap_or_else(|sequence| (*sequence).clone()))
+    }
 
-        let mut buffer = Box::new(Nil);
-        swap(&mut self.sequences, &mut buffer);
-        let res: Sequence;
+    /// Insert a sequence on the table, so that it can be taken back with `take(n)`
+    ///
+    /// The sequence is added at the end if `n` is larger than the number of sequences.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::table::*;
+    /// use machiavelli::sequence_cards::*;
+    ///
+    /// let mut table = Table::new();
+    /// let seq_1 = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]);
+    /// let seq_2 = Sequence::from_cards(&[RegularCard(Heart, 7), RegularCard(Spade, 7), RegularCard(Club, 7)]);
+    /// table.add(seq_1.clone());
+    /// table.insert(2, seq_2.clone());
+    ///
+    /// assert_eq!(vec![seq_1, seq_2.clone()], table.to_vec());
+    /// assert_eq!(Some(seq_2), table.take(2));
+    /// ```
+    pub fn insert(&mut self, n: usize, sequence: Sequence) {
+        let n = n.max(1).min(self.len() + 1);
+        let position = self.len() + 1 - n;
+        self.sequences.insert(position, Arc::new(sequence));
+    }
 
-        if n==1 {
-            res = match *buffer {
-                Cons(seq, box_sl) => {
-                    buffer = box_sl;
-                    seq
-                },
-                Nil => Sequence::new()
-            }
-        } else {
-            let mut current_item = &mut *buffer;
-            for _i in 2..n {
-                if let Cons(_, box_sl) = current_item {
-                    current_item = &mut *box_sl;
-                }
-            }
+    /// Sequence with the index `n` (starting from 1, as displayed), if any
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::table::*;
+    /// use machiavelli::sequence_cards::*;
+    ///
+    /// let mut table = Table::new();
+    /// let seq_1 = Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]);
+    /// let seq_2 = Sequence::from_cards(&[RegularCard(Heart, 7), RegularCard(Spade, 7), RegularCard(Club, 7)]);
+    /// table.add(seq_1.clone());
+    /// table.add(seq_2.clone());
+    ///
+    /// assert_eq!(Some(&seq_2), table.get(1));
+    /// assert_eq!(Some(&seq_1), table.get(2));
+    /// assert_eq!(None, table.get(0));
+    /// assert_eq!(None, table.get(3));
+    /// ```
+    pub fn get(&self, n: usize) -> Option<&Sequence> {
+        self.position(n).map(|position| &*self.sequences[position])
+    }
 
-            let mut tail = Box::new(Nil);
-            if let Cons(_, box_sl) = &mut current_item {
-                swap(box_sl, &mut tail);
-            };
-
-            res = match *tail {
-                Cons(s, mut box_sl) => {
-                    if let Cons(_, box_sl_prev) = &mut current_item {
-                        swap(&mut box_sl, box_sl_prev);
-                    }
-                    s
-                },
-                _ => Sequence::new()
-            };
-        }
+    /// Number of sequences on the table
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    /// Check if there is no sequence on the table
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
 
-        self.sequences = *buffer;
-        self.number_sequences -= 1;
+    /// Iterator over the sequences on the table, in the order in which they are displayed
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::table::*;
+    /// use machiavelli::sequence_cards::*;
+    ///
+    /// let mut table = Table::new();
+    /// table.add(Sequence::from_cards(&[RegularCard(Club, 4), RegularCard(Club, 5), RegularCard(Club, 6)]));
+    /// table.add(Sequence::from_cards(&[RegularCard(Heart, 7), RegularCard(Spade, 7), RegularCard(Club, 7)]));
+    ///
+    /// assert_eq!(vec![3, 3], table.iter().map(|seq| seq.number_cards()).collect::<Vec<usize>>());
+    /// assert_eq!(table.to_vec(), table.iter().cloned().collect::<Vec<Sequence>>());
+    /// ```
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(self.sequences.iter().rev())
+    }
 
-        Some(res)
+    // position in the vector of the sequence with the index `n` (starting from 1)
+    fn position(&self, n: usize) -> Option<usize> {
+        if n == 0 || n > self.len() {
+            None
+        } else {
+            Some(self.len() - n)
+        }
     }
 
     /// HashMap of the type and number of each card on the table
@@ -291,18 +320,9 @@ impl Table {
 
         let mut res = HashMap::<Card, u16>::new();
 
-        let mut current_sequence = &self.sequences;
-        while *current_sequence != Nil {
-            #[allow(clippy::map_entry)]
-            if let Cons(seq, box_sl) = current_sequence {
-                for card in seq.to_vec() {
-                    if res.contains_key(&card) {
-                        *res.get_mut(&card).unwrap() += 1;
-                    } else {
-                        res.insert(card, 1);
-                    }
-                }
-                current_sequence = box_sl;
+        for seq in self.iter() {
+            for card in seq.to_vec() {
+                *res.entry(card).or_insert(0) += 1;
             }
         }
         
@@ -363,33 +383,76 @@ impl Table {
          
         true
     }
+
+    /// Vector of the sequences on the table, in the order in which they are displayed
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::table::*;
+    /// use machiavelli::sequence_cards::*;
+    ///
+    /// let mut table = Table::new();
+    /// let seq_1 = Sequence::from_cards(&[
+    ///     RegularCard(Club, 4), 
+    ///     RegularCard(Club, 5), 
+    ///     RegularCard(Club, 6), 
+    /// ]);
+    /// let seq_2 = Sequence::from_cards(&[
+    ///     RegularCard(Heart, 7), 
+    ///     RegularCard(Spade, 7), 
+    ///     RegularCard(Diamond, 7), 
+    /// ]);
+    /// table.add(seq_1.clone());
+    /// table.add(seq_2.clone());
+    ///
+    /// assert_eq!(vec![seq_2, seq_1], table.to_vec());
+    /// ```
+    pub fn to_vec(&self) -> Vec<Sequence> {
+        self.iter().cloned().collect()
+    }
 }
 
-impl fmt::Display for Table {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut i_seq = 1;
-        let mut sl = &self.sequences;
-        while let Cons(seq, new_sl) = sl {
-            writeln!(f, "{}: {}{}", i_seq, seq, reset_style_string())?;
-            i_seq += 1;
-            sl = new_sl;
-        }
-        write!(f, "")
+/// Iterator over the sequences of a table, in the order in which they are displayed (see
+/// `Table::iter`)
+#[derive(Debug, Clone)]
+pub struct Iter<'a>(std::iter::Rev<std::slice::Iter<'a, Arc<Sequence>>>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Sequence;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|sequence| &**sequence)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|sequence| &**sequence)
     }
 }
 
-#[derive(Debug, PartialEq)]
-enum SequenceList {
-    Cons(Sequence, Box<SequenceList>),
-    Nil
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a Table {
+    type Item = &'a Sequence;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
 }
 
-impl Clone for SequenceList {
-    fn clone(&self) -> Self {
-        match self {
-            Nil => Nil,
-            Cons(seq, box_sl) => Cons(seq.clone(), Box::<SequenceList>::new((**box_sl).clone()))
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, seq) in self.iter().enumerate() {
+            writeln!(f, "{}: {}", i + 1, seq)?;
         }
+        Ok(())
     }
 }
 
@@ -413,11 +476,34 @@ mod tests {
         ]);
 
         let table = Table {
-            number_sequences: 2,
-            sequences: Cons(seq_1, Box::new(Cons(seq_2, Box::new(Nil))))
+            sequences: vec![Arc::new(seq_2), Arc::new(seq_1)]
         };
 
-        assert_eq!("1: \u{1b}[1;30m2♣ \u{1b}[1;34m# \u{1b}[1;31m3♦ \u{1b}[1;31m2♥ \u{1b}[0m\u{1b}[30;47m\u{1b}[?25l\u{1b}[K\n2: \u{1b}[1;30m4♣ \u{1b}[1;31m5♦ \u{1b}[1;31m6♥ \u{1b}[0m\u{1b}[30;47m\u{1b}[?25l\u{1b}[K\n".to_string(), format!("{}", &table));
+        assert_eq!("1: 2♣ # 3♦ 2♥ \n2: 4♣ 5♦ 6♥ \n".to_string(), format!("{}", &table));
+    }
+
+    #[test]
+    fn snapshot_shares_sequences() {
+        let mut table = Table::new();
+        for value in 1..=4 {
+            table.add(Sequence::from_cards(&[RegularCard(Club, value), RegularCard(Heart, value), 
+                                             RegularCard(Spade, value)]));
+        }
+        let snapshot = table.clone();
+        assert!(table.sequences.iter().zip(&snapshot.sequences).all(|(a, b)| Arc::ptr_eq(a, b)));
+
+        // taking a sequence copies it, and leaves the snapshot unchanged; the other sequences are
+        // still shared
+        let seq = table.take(2).unwrap();
+        assert!(Arc::ptr_eq(&table.sequences[2], &snapshot.sequences[3]));
+        assert!(Arc::ptr_eq(&table.sequences[0], &snapshot.sequences[0]));
+        assert_eq!(3, table.len());
+        assert_eq!(4, snapshot.len());
+        assert_eq!(snapshot.get(2), Some(&seq));
+
+        // the byte format lists the sequences from the oldest to the most recent
+        assert_eq!(snapshot, Table::from_bytes(&snapshot.to_bytes()));
+        assert_eq!(snapshot.get(4).unwrap().to_bytes()[..], snapshot.to_bytes()[..3]);
     }
 
 }
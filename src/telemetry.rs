@@ -0,0 +1,89 @@
+//! Anonymous statistics on the games played, kept only if the server operator opts in
+//!
+//! Each finished game adds a line to a local file in the user config directory, with the settings
+//! and optional rules of the game, its length, and the number of players, bots, and forfeits; no
+//! name or card is recorded. Nothing is ever sent over the network: the file is only shared if its
+//! owner decides to, for instance when asked which variants are played.
+
+use std::fmt;
+use std::io::Write;
+use std::time::Duration;
+use crate::{ Config, InvalidInputError, user_config_dir };
+use crate::rules::RuleSet;
+
+/// file where the statistics are appended
+pub const TELEMETRY_FILE: &str = "telemetry.dat";
+
+/// Anonymous summary of a finished game
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameUsage {
+    pub n_players: u8,
+    pub n_bots: usize,
+    pub n_forfeits: usize,
+    pub n_decks: u8,
+    pub n_jokers: u8,
+    pub n_cards_to_start: u16,
+    pub custom_rule_jokers: bool,
+
+    /// optional rules which differ from the default ones, as `name=value`
+    pub rules: Vec<String>,
+
+    pub n_turns: usize,
+    pub duration: Duration
+}
+
+impl GameUsage {
+
+    /// Summary of a game with the given settings and rules
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use machiavelli::*;
+    /// use machiavelli::rules::RuleSet;
+    /// use machiavelli::telemetry::GameUsage;
+    ///
+    /// let rules = RuleSet::from_string("max_hand_size = 20").unwrap();
+    /// let usage = GameUsage::new(&Config::default(), &rules, 1, 0, 42, Duration::from_secs(600));
+    ///
+    /// assert_eq!("players=2 bots=1 forfeits=0 decks=2 jokers=4 cards=13 joker_rule=0 \
+    ///             rules=max_hand_size=20 turns=42 duration_s=600",
+    ///            usage.to_string());
+    /// ```
+    pub fn new(config: &Config, rules: &RuleSet, n_bots: usize, n_forfeits: usize, n_turns: usize,
+               duration: Duration) -> GameUsage {
+        let rules = rules.changed_rules().iter().map(|line| line.replace(' ', "")).collect();
+        GameUsage {
+            n_players: config.n_players,
+            n_bots,
+            n_forfeits,
+            n_decks: config.n_decks,
+            n_jokers: config.n_jokers,
+            n_cards_to_start: config.n_cards_to_start,
+            custom_rule_jokers: config.custom_rule_jokers,
+            rules,
+            n_turns,
+            duration
+        }
+    }
+
+    /// Append the summary to the statistics file in the user config directory
+    pub fn record(&self) -> Result<(), InvalidInputError> {
+        let dir = user_config_dir().ok_or(InvalidInputError {})?;
+        let mut file = std::fs::OpenOptions::new().create(true).append(true)
+            .open(dir.join(TELEMETRY_FILE))?;
+        writeln!(file, "{}", self)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for GameUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "players={} bots={} forfeits={} decks={} jokers={} cards={} joker_rule={} rules={} turns={} duration_s={}",
+               self.n_players, self.n_bots, self.n_forfeits, self.n_decks, self.n_jokers,
+               self.n_cards_to_start, self.custom_rule_jokers as u8,
+               if self.rules.is_empty() { "default".to_string() } else { self.rules.join(",") },
+               self.n_turns, self.duration.as_secs())
+    }
+}
@@ -0,0 +1,185 @@
+//! Cross-platform terminal control
+//!
+//! All the cursor movements and screen clearing go through this module, which relies on crossterm
+//! to support the older Windows consoles. If the output is not an interactive terminal, it falls
+//! back to a plain mode where these operations are replaced by new lines or ignored.
+
+use std::io::{ stdout, Write, IsTerminal };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use crossterm::{ execute, cursor, style, terminal };
+use crate::{ colors_enabled, set_colors, detect_colors };
+
+/// whether the output is an interactive terminal which can be controlled
+static INTERACTIVE: AtomicBool = AtomicBool::new(true);
+
+/// check if the terminal can be controlled (clearing, moving the cursor, ...)
+pub fn is_interactive() -> bool {
+    INTERACTIVE.load(Ordering::Relaxed)
+}
+
+/// set-up the terminal
+///
+/// Colours are enabled only if the terminal supports them (see `detect_colors`) and, on Windows,
+/// if ANSI escape sequences can be processed by the console.
+pub fn init() {
+    INTERACTIVE.store(detect_interactive(), Ordering::Relaxed);
+    set_colors(detect_colors() && ansi_supported());
+}
+
+/// set-up the terminal control only, without changing whether colours are used
+///
+/// This is used by the server, whose coloured messages are meant for the clients.
+pub fn init_control_only() {
+    INTERACTIVE.store(detect_interactive(), Ordering::Relaxed);
+}
+
+// the terminal can be controlled if the standard output is a terminal which is not `dumb`
+fn detect_interactive() -> bool {
+    let dumb_term = std::env::var("TERM").map(|t| t == "dumb").unwrap_or(false);
+    stdout().is_terminal() && !dumb_term
+}
+
+#[cfg(windows)]
+fn ansi_supported() -> bool {
+    crossterm::ansi_support::supports_ansi()
+}
+
+#[cfg(not(windows))]
+fn ansi_supported() -> bool {
+    true
+}
+
+/// clear the terminal and move the cursor to the top-left corner
+pub fn clear() {
+    if is_interactive() {
+        execute!(stdout(), terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0))
+            .unwrap_or(());
+    } else {
+        println!();
+    }
+}
+
+/// move the cursor to the beginning of the line `row` (starting from 0)
+pub fn move_to_row(row: u16) {
+    if is_interactive() {
+        execute!(stdout(), cursor::MoveTo(0, row)).unwrap_or(());
+    }
+}
+
+/// clear the rest of the current line
+pub fn clear_line() {
+    if is_interactive() {
+        execute!(stdout(), terminal::Clear(terminal::ClearType::UntilNewLine)).unwrap_or(());
+    }
+}
+
+/// save the cursor position
+pub fn save_cursor() {
+    if is_interactive() {
+        execute!(stdout(), cursor::SavePosition).unwrap_or(());
+    }
+}
+
+/// restore the cursor position saved by `save_cursor`
+pub fn restore_cursor() {
+    if is_interactive() {
+        execute!(stdout(), cursor::RestorePosition).unwrap_or(());
+    }
+}
+
+/// hide the cursor
+pub fn hide_cursor() {
+    if is_interactive() {
+        execute!(stdout(), cursor::Hide).unwrap_or(());
+    }
+}
+
+/// show the cursor
+pub fn show_cursor() {
+    if is_interactive() {
+        execute!(stdout(), cursor::Show).unwrap_or(());
+    }
+}
+
+/// escape sequence of a crossterm command, for text sent to another terminal (such as a client)
+///
+/// # Example
+///
+/// ```
+/// use crossterm::cursor;
+/// use machiavelli::terminal;
+///
+/// assert_eq!("\u{1b}[?25l", terminal::ansi(cursor::Hide));
+/// ```
+pub fn ansi<C: crossterm::Command>(command: C) -> String {
+    let mut s = String::new();
+    command.write_ansi(&mut s).unwrap_or(());
+    s
+}
+
+/// restrict the scrolling to the lines below `top` (starting from 1)
+///
+/// crossterm has no command for this, so it is only done when escape sequences are supported.
+pub fn set_scroll_region(top: usize) {
+    if colors_enabled() {
+        print!("\x1b[{};r", top);
+        stdout().flush().unwrap_or(());
+    }
+}
+
+/// restore the terminal: default style and scrolling region, visible cursor
+pub fn restore() {
+    if colors_enabled() {
+        print!("\x1b[r");
+    }
+    if is_interactive() {
+        execute!(stdout(), style::SetAttribute(style::Attribute::Reset), style::ResetColor,
+                 cursor::Show).unwrap_or(());
+    }
+    stdout().flush().unwrap_or(());
+}
+
+/// restore the terminal and exit the process with the given status code
+///
+/// `process::exit` does not run the destructors, so this must be used instead of it where a
+/// `TerminalGuard` is alive.
+pub fn exit(code: i32) -> ! {
+    restore();
+    std::process::exit(code)
+}
+
+/// Guard restoring the terminal when it goes out of scope
+///
+/// The style, scrolling region, and cursor are restored on every exit path which unwinds the
+/// stack, including panics. If `clear_screen` is set, the screen is also cleared, except during a
+/// panic so that its message stays visible.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::terminal;
+///
+/// let _guard = terminal::TerminalGuard::new(false);
+/// terminal::hide_cursor();
+/// // the cursor is shown again when `_guard` is dropped
+/// ```
+pub struct TerminalGuard {
+    clear_screen: bool
+}
+
+impl TerminalGuard {
+
+    /// create a guard, clearing the screen when dropped if `clear_screen` is set
+    pub fn new(clear_screen: bool) -> TerminalGuard {
+        TerminalGuard { clear_screen }
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+        if self.clear_screen && !std::thread::panicking() {
+            clear();
+        }
+    }
+}
@@ -0,0 +1,563 @@
+//! Tournaments between bot strategies
+//!
+//! Each pair of registered strategies plays a number of two-player games against each other. The
+//! decks are shuffled from seeds, and each deal is played twice with the players swapping seats,
+//! so that a tournament can be run again with the same deals and no strategy is favoured by the
+//! cards. A strategy only sees the [`PlayerView`] of its player, and answers with the moves of its
+//! turn, which are then checked against the rules. When the deck is empty, the game is won by the
+//! player with the fewest points left in hand (see the `scoring` module).
+//!
+//! Each turn has a time limit. It is not enforced by interrupting the strategy: the moves chosen
+//! too late are ignored, and the player draws a card instead. The results are gathered in a
+//! [`CrossTable`].
+
+use std::fmt;
+use std::cmp::Ordering;
+use std::time::{ Duration, Instant };
+use rand::{ SeedableRng, rngs::StdRng, seq::SliceRandom };
+use crate::{ Card, Config, Sequence, Table, next_player, pick_a_card_end_of_turn };
+use crate::bot::{ BotWeights, MeldCache };
+use crate::evaluation::attachable_cards;
+use crate::game::{ GameState, PlayerView };
+use crate::hooks::RuleHooks;
+use crate::render::Visibility;
+use crate::rules::RuleSet;
+use crate::scoring::round_scores;
+use crate::simulation::rollout;
+
+/// configuration of the tournament games
+const TOURNAMENT_CONFIG: Config = Config {
+    n_decks: 2,
+    n_jokers: 4,
+    n_cards_to_start: 13,
+    custom_rule_jokers: false,
+    n_players: 2,
+    initial_meld_points: 0
+};
+
+/// maximum number of turns of a game, after which it counts as a draw
+const MAX_TURNS: usize = 1000;
+
+/// number of hands kept in the cache of the solver of each strategy
+const STRATEGY_CACHE_CAPACITY: usize = 10_000;
+
+/// number of rollouts used by the `montecarlo` strategy to compare two moves
+const N_ROLLOUTS_STRATEGY: usize = 10;
+
+/// names of the built-in strategies (see `builtin_strategy`)
+pub const BUILTIN_STRATEGIES: [&str; 4] = ["cautious", "greedy", "hoarder", "montecarlo"];
+
+/// A move of a strategy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Move {
+
+    /// play the cards of the hand with the given indices (starting from 1)
+    Play(Vec<usize>),
+
+    /// add the card of the hand with the first index to the sequence of the table with the second
+    /// one (both starting from 1)
+    Add(usize, usize),
+
+    /// take the sequence of the table with the given index (starting from 1)
+    Take(usize)
+}
+
+impl fmt::Display for Move {
+
+    /// Command making the move in a networked game
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use machiavelli::tournament::Move;
+    ///
+    /// assert_eq!("p 1 3 4", Move::Play(vec![1, 3, 4]).to_string());
+    /// assert_eq!("q 2 1", Move::Add(2, 1).to_string());
+    /// assert_eq!("t 3", Move::Take(3).to_string());
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Move::Play(indices) => write!(f, "p {}", indices.iter().map(usize::to_string)
+                                                         .collect::<Vec<String>>().join(" ")),
+            Move::Add(card, seq) => write!(f, "q {} {}", card, seq),
+            Move::Take(seq) => write!(f, "t {}", seq)
+        }
+    }
+}
+
+/// A bot strategy taking part in tournaments
+pub trait Strategy {
+
+    /// Moves of the turn of the player of `view`, in the order they are made
+    ///
+    /// The player draws a card if there is no move. A move refused by the rules is ignored, with
+    /// the ones after it; if the turn can not then be ended, it is given up and the player draws a
+    /// card.
+    fn choose_moves(&mut self, view: &PlayerView, hooks: &RuleHooks) -> Vec<Move>;
+}
+
+/// Strategy playing the melds of its hand and adding cards to the table as its weights tell
+///
+/// This is how the bots of the benchmark games play: a meld is played if its score (see
+/// `BotWeights::score`) is positive, and a card is added to the table if `BotWeights::adds_to_table`
+/// says so.
+///
+/// # Example
+///
+/// ```
+/// use machiavelli::*;
+/// use machiavelli::bot::BotWeights;
+/// use machiavelli::game::GameState;
+/// use machiavelli::hooks::RuleHooks;
+/// use machiavelli::render::Visibility;
+/// use machiavelli::rules::RuleSet;
+/// use machiavelli::tournament::{ Move, Strategy, WeightedStrategy };
+///
+/// let mut table = Table::new();
+/// table.add(Sequence::from_cards(&[RegularCard(Heart, 7), RegularCard(Spade, 7), RegularCard(Club, 7)]));
+/// let hand = Sequence::from_cards(&[
+///     RegularCard(Club, 4), RegularCard(Diamond, 7), RegularCard(Club, 6),
+///     RegularCard(Club, 5), RegularCard(Heart, 1),
+/// ]);
+/// let game = GameState::new(table, vec![hand], Sequence::new(), 0);
+/// let hooks = RuleHooks::from_rules(false, &RuleSet::default());
+/// let view = game.view(0, Visibility::default());
+///
+/// let mut cautious = WeightedStrategy::new(BotWeights::default());
+/// assert_eq!(vec![Move::Play(vec![4, 3, 1])], cautious.choose_moves(&view, &hooks));
+///
+/// let mut greedy = WeightedStrategy::new(BotWeights { table_aggressiveness: 1., ..BotWeights::default() });
+/// assert_eq!(vec![Move::Play(vec![4, 3, 1]), Move::Add(1, 2)], greedy.choose_moves(&view, &hooks));
+/// ```
+#[derive(Debug, Clone)]
+pub struct WeightedStrategy {
+    weights: BotWeights,
+    cache: MeldCache
+}
+
+impl WeightedStrategy {
+
+    /// Strategy with the given weights
+    pub fn new(weights: BotWeights) -> WeightedStrategy {
+        WeightedStrategy { weights, cache: MeldCache::new(STRATEGY_CACHE_CAPACITY) }
+    }
+}
+
+impl Strategy for WeightedStrategy {
+    fn choose_moves(&mut self, view: &PlayerView, hooks: &RuleHooks) -> Vec<Move> {
+        plan_moves(view, hooks, &self.weights, &mut self.cache).0
+    }
+}
+
+/// Strategy comparing the moves of the `greedy` strategy with drawing a card, by playing the rest
+/// of the game many times with the policy of the `simulation` module
+///
+/// For each rollout, the cards it can not see are dealt at random to the other players and the
+/// deck, from a random number generator with a fixed seed.
+#[derive(Debug, Clone)]
+pub struct MonteCarloStrategy {
+    n_decks: u8,
+    n_jokers: u8,
+    n_rollouts: usize,
+    rng: StdRng,
+    cache: MeldCache
+}
+
+impl MonteCarloStrategy {
+
+    /// Strategy for games with the given numbers of decks and jokers, using `n_rollouts` rollouts
+    /// for each move
+    pub fn new(n_decks: u8, n_jokers: u8, n_rollouts: usize, seed: u64) -> MonteCarloStrategy {
+        MonteCarloStrategy {
+            n_decks,
+            n_jokers,
+            n_rollouts,
+            rng: StdRng::seed_from_u64(seed),
+            cache: MeldCache::new(STRATEGY_CACHE_CAPACITY)
+        }
+    }
+
+    // number of rollouts won by the player after ending their turn with the given hand and table,
+    // drawing a card if `draws` is set
+    fn wins(&mut self, view: &PlayerView, hand: &Sequence, table: &Table, draws: bool) -> usize {
+        let player = view.player();
+        let forfeited = vec![false; view.hand_sizes().len()];
+        let mut wins = 0;
+        for _ in 0..self.n_rollouts {
+            let mut unseen = unseen_cards(self.n_decks, self.n_jokers, hand, table);
+            unseen.shuffle(&mut self.rng);
+            let mut hands = vec![Sequence::new(); forfeited.len()];
+            hands[player] = hand.clone();
+            if draws {
+                if let Some(card) = unseen.pop() {
+                    hands[player].add_card(card);
+                }
+            }
+
+            // the hidden numbers of cards are guessed from the hand of the player
+            for (i, size) in view.hand_sizes().iter().enumerate().filter(|(i, _)| *i != player) {
+                let n = size.unwrap_or(view.hand().number_cards()).min(unseen.len());
+                hands[i] = Sequence::from_cards(&unseen.split_off(unseen.len() - n));
+            }
+            let mut deck = Sequence::from_cards(&unseen);
+            let mut table = table.clone();
+            let first = next_player(player, &forfeited);
+            if rollout(&mut hands, &mut table, &mut deck, first, &forfeited, &mut self.cache) == Some(player) {
+                wins += 1;
+            }
+        }
+        wins
+    }
+}
+
+impl Strategy for MonteCarloStrategy {
+    fn choose_moves(&mut self, view: &PlayerView, hooks: &RuleHooks) -> Vec<Move> {
+        let weights = BotWeights { table_aggressiveness: 1., ..BotWeights::default() };
+        let (moves, hand, table) = plan_moves(view, hooks, &weights, &mut self.cache);
+        if moves.is_empty() || hand.number_cards() == 0 {
+            return moves;
+        }
+        if self.wins(view, &hand, &table, false) >= self.wins(view, view.hand(), view.table(), true) {
+            moves
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+// moves of a player with the given weights, with the hand and table they leave
+fn plan_moves(view: &PlayerView, hooks: &RuleHooks, weights: &BotWeights, cache: &mut MeldCache)
+    -> (Vec<Move>, Sequence, Table)
+{
+    let mut hand = view.hand().clone();
+    let mut table = view.table().clone();
+    let mut moves = Vec::<Move>::new();
+
+    // melds from the hand
+    while let Some((meld, indices)) = cache.find_meld(&hand) {
+        if weights.score(&meld.to_vec()) <= 0. {
+            break;
+        }
+        for &i in &indices {
+            hand.take_card(i);
+        }
+        table.add(meld);
+        moves.push(Move::Play(indices));
+    }
+
+    // cards added to the sequences on the table, until none can be
+    if weights.table_aggressiveness > 0. && view.has_opened(view.player()) {
+        let mut added = true;
+        while added {
+            added = false;
+            for (i, seq) in table.to_vec().into_iter().enumerate() {
+                let addition = attachable_cards(&seq, &hand, hooks).into_iter()
+                    .find(|(_, card)| weights.adds_to_table(card));
+                if let Some((j, card)) = addition {
+                    let mut longer = seq;
+                    longer.add_card(card);
+                    hand.take_card(j);
+                    table.take(i + 1);
+                    table.insert(i + 1, longer);
+                    moves.push(Move::Add(j, i + 1));
+                    added = true;
+                    break;
+                }
+            }
+        }
+    }
+    (moves, hand, table)
+}
+
+// cards of the decks which are neither in the hand nor on the table
+fn unseen_cards(n_decks: u8, n_jokers: u8, hand: &Sequence, table: &Table) -> Vec<Card> {
+    let mut unseen = Sequence::builder().decks(n_decks).jokers(n_jokers).build().to_vec();
+    let mut seen = hand.to_vec();
+    for seq in table.to_vec() {
+        seen.extend(seq.to_vec());
+    }
+    for card in seen {
+        if let Some(i) = unseen.iter().position(|c| *c == card) {
+            unseen.swap_remove(i);
+        }
+    }
+    unseen
+}
+
+/// Built-in strategy with the given name (see `BUILTIN_STRATEGIES`), for games with the given
+/// configuration
+///
+/// * `cautious` plays the melds of its hand and never touches the table,
+/// * `greedy` also adds its cards to the sequences on the table,
+/// * `hoarder` plays like `greedy`, but keeps its jokers for as long as it can,
+/// * `montecarlo` plays like `greedy` when rollouts tell it is better than drawing a card; its
+///   random number generator is seeded with `seed`.
+pub fn builtin_strategy(name: &str, config: &Config, seed: u64) -> Option<Box<dyn Strategy>> {
+    let greedy = BotWeights { table_aggressiveness: 1., ..BotWeights::default() };
+    Some(match name {
+        "cautious" => Box::new(WeightedStrategy::new(BotWeights::default())),
+        "greedy" => Box::new(WeightedStrategy::new(greedy)),
+        "hoarder" => Box::new(WeightedStrategy::new(BotWeights { keep_joker: 25., ..greedy })),
+        "montecarlo" => Box::new(MonteCarloStrategy::new(config.n_decks, config.n_jokers,
+                                                         N_ROLLOUTS_STRATEGY, seed)),
+        _ => return None
+    })
+}
+
+/// Results of a tournament
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrossTable {
+    names: Vec<String>,
+    wins: Vec<Vec<usize>>,
+    games: Vec<Vec<usize>>,
+    timeouts: Vec<usize>,
+    illegal_moves: Vec<usize>
+}
+
+impl CrossTable {
+
+    // empty results for the given strategies
+    fn new(names: Vec<String>) -> CrossTable {
+        let n = names.len();
+        CrossTable {
+            names,
+            wins: vec![vec![0; n]; n],
+            games: vec![vec![0; n]; n],
+            timeouts: vec![0; n],
+            illegal_moves: vec![0; n]
+        }
+    }
+
+    /// Names of the strategies, in the order they have been registered
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Number of games played between the strategies `i` and `j`
+    pub fn games(&self, i: usize, j: usize) -> usize {
+        self.games[i][j]
+    }
+
+    /// Number of games won by the strategy `i` against the strategy `j`
+    pub fn wins(&self, i: usize, j: usize) -> usize {
+        self.wins[i][j]
+    }
+
+    /// Number of games between the strategies `i` and `j` which have ended in a draw
+    pub fn draws(&self, i: usize, j: usize) -> usize {
+        self.games[i][j] - self.wins[i][j] - self.wins[j][i]
+    }
+
+    /// Points of the strategy `i`: 1 for each game won, and ½ for each draw
+    pub fn points(&self, i: usize) -> f64 {
+        (0..self.names.len())
+            .map(|j| self.wins[i][j] as f64 + 0.5 * self.draws(i, j) as f64)
+            .sum()
+    }
+
+    /// Number of turns in which the strategy `i` has exceeded the time limit
+    pub fn timeouts(&self, i: usize) -> usize {
+        self.timeouts[i]
+    }
+
+    /// Number of turns in which a move of the strategy `i` has been refused
+    pub fn illegal_moves(&self, i: usize) -> usize {
+        self.illegal_moves[i]
+    }
+}
+
+impl fmt::Display for CrossTable {
+
+    /// One line per strategy, with its wins and losses against each other one
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.names.iter().map(|name| name.chars().count()).max().unwrap_or(0).max(6);
+        write!(f, "{:width$}", "", width = width)?;
+        for name in &self.names {
+            write!(f, "  {:>width$}", name, width = width)?;
+        }
+        writeln!(f, "  {:>6}  {:>8}  {:>7}", "points", "timeouts", "illegal")?;
+        for (i, name) in self.names.iter().enumerate() {
+            write!(f, "{:width$}", name, width = width)?;
+            for j in 0..self.names.len() {
+                let cell = if i == j { "-".to_string() } else { format!("{}-{}", self.wins[i][j], self.wins[j][i]) };
+                write!(f, "  {:>width$}", cell, width = width)?;
+            }
+            writeln!(f, "  {:>6}  {:>8}  {:>7}", self.points(i), self.timeouts[i], self.illegal_moves[i])?;
+        }
+        Ok(())
+    }
+}
+
+/// Tournament between registered strategies
+pub struct Tournament {
+
+    /// settings of the games; there are always two players
+    pub config: Config,
+
+    pub rules: RuleSet,
+
+    /// number of games played by each pair of strategies
+    pub games_per_pair: usize,
+
+    /// time given to a strategy to choose the moves of a turn
+    pub time_limit: Duration,
+
+    /// seed of the first deal; the next ones use the following seeds
+    pub seed: u64,
+
+    strategies: Vec<(String, Box<dyn Strategy>)>
+}
+
+impl Tournament {
+
+    /// Tournament with no strategy, and the default settings of the tournament games
+    pub fn new(games_per_pair: usize, time_limit: Duration, seed: u64) -> Tournament {
+        Tournament {
+            config: TOURNAMENT_CONFIG,
+            rules: RuleSet::default(),
+            games_per_pair,
+            time_limit,
+            seed,
+            strategies: Vec::new()
+        }
+    }
+
+    /// Add a strategy to the tournament
+    pub fn register(&mut self, name: &str, strategy: Box<dyn Strategy>) {
+        self.strategies.push((name.to_string(), strategy));
+    }
+
+    /// Play all the games of the tournament
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use machiavelli::tournament::{ Tournament, builtin_strategy };
+    ///
+    /// let mut tournament = Tournament::new(2, Duration::from_secs(10), 42);
+    /// for name in ["cautious", "greedy"] {
+    ///     tournament.register(name, builtin_strategy(name, &tournament.config, 0).unwrap());
+    /// }
+    /// let results = tournament.run();
+    ///
+    /// assert_eq!(2, results.games(0, 1));
+    /// assert_eq!(2., results.points(0) + results.points(1));
+    /// assert_eq!(0, results.timeouts(0) + results.illegal_moves(1));
+    /// assert_eq!(results, tournament.run());
+    /// ```
+    pub fn run(&mut self) -> CrossTable {
+        let names = self.strategies.iter().map(|(name, _)| name.clone()).collect();
+        let mut results = CrossTable::new(names);
+        let n = self.strategies.len();
+        for i in 0..n {
+            for j in i+1..n {
+                for k in 0..self.games_per_pair {
+
+                    // each deal is played twice, with the players swapping seats
+                    let seed = self.seed.wrapping_add(k as u64 / 2);
+                    let seats = if k % 2 == 0 { [i, j] } else { [j, i] };
+                    let winner = self.play_game(seed, seats, &mut results);
+                    results.games[i][j] += 1;
+                    results.games[j][i] += 1;
+                    if let Some(seat) = winner {
+                        results.wins[seats[seat]][seats[1 - seat]] += 1;
+                    }
+                }
+            }
+        }
+        results
+    }
+
+    // play a game between the strategies with the given indices; return the seat of the winner
+    fn play_game(&mut self, seed: u64, seats: [usize; 2], results: &mut CrossTable) -> Option<usize> {
+        let hooks = RuleHooks::from_rules(false, &self.rules);
+        let mut deck = Sequence::multi_deck(self.config.n_decks, self.config.n_jokers,
+                                            &mut StdRng::seed_from_u64(seed));
+        let hands = deck.deal(2, self.config.n_cards_to_start as usize);
+        let mut game = GameState::new(Table::new(), hands, deck, 0);
+        game.initial_meld_points = self.config.initial_meld_points;
+        for _ in 0..MAX_TURNS {
+
+            // once the deck is empty, the player with the fewest points left in hand wins
+            if game.deck.number_cards() == 0 {
+                let scores = round_scores(&game.hands, &[false, false], self.rules.joker_penalty);
+                return match scores[0].total().cmp(&scores[1].total()) {
+                    Ordering::Less => Some(0),
+                    Ordering::Greater => Some(1),
+                    Ordering::Equal => None
+                };
+            }
+            let player = game.current_player;
+            let strategy = seats[player];
+            let view = game.view(player, Visibility::default());
+            let timer = Instant::now();
+            let mut moves = self.strategies[strategy].1.choose_moves(&view, &hooks);
+            if timer.elapsed() > self.time_limit {
+                results.timeouts[strategy] += 1;
+                moves.clear();
+            }
+            if !play_moves(&mut game, &moves, &hooks, &self.rules) {
+                results.illegal_moves[strategy] += 1;
+            }
+            if game.hands[player].number_cards() == 0 {
+                return Some(player);
+            }
+            game.start_turn(1 - player);
+        }
+        None
+    }
+}
+
+// make the moves of a turn and end it; return `false` if one of them has been refused
+fn play_moves(game: &mut GameState, moves: &[Move], hooks: &RuleHooks, rules: &RuleSet) -> bool {
+    let mut legal = true;
+    for m in moves {
+        let result = match m {
+            Move::Play(indices) => game.play_cards(indices, hooks),
+            Move::Add(card, seq) => game.add_to_sequence(*card, *seq, hooks),
+            Move::Take(seq) => game.take_from_table(*seq)
+        };
+        if result.is_err() {
+            legal = false;
+            break;
+        }
+    }
+    let end = if game.has_played() { game.pass(hooks) } else { game.draw(hooks) };
+
+    // a turn which can not be ended is given up without penalty
+    if end.is_err() {
+        game.give_up(0);
+        let player = game.current_player;
+        pick_a_card_end_of_turn(&mut game.hands[player], &mut game.deck, rules.max_hand_size);
+        return legal && moves.is_empty();
+    }
+    legal
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // strategy drawing a card every turn, too slowly
+    struct SlowStrategy;
+
+    impl Strategy for SlowStrategy {
+        fn choose_moves(&mut self, _view: &PlayerView, _hooks: &RuleHooks) -> Vec<Move> {
+            std::thread::sleep(Duration::from_millis(20));
+            vec![Move::Take(1)]
+        }
+    }
+
+    #[test]
+    fn time_limit() {
+        let mut tournament = Tournament::new(2, Duration::from_millis(5), 7);
+        tournament.register("slow", Box::new(SlowStrategy));
+        tournament.register("greedy", builtin_strategy("greedy", &tournament.config, 0).unwrap());
+        let results = tournament.run();
+        assert!(results.timeouts(0) > 0);
+        assert_eq!(0, results.illegal_moves(0));
+        assert_eq!(2, results.wins(1, 0));
+    }
+}
@@ -0,0 +1,100 @@
+//! Detection of stuck games
+//!
+//! The server tells the watchdog each time a turn starts. If no turn starts for longer than the
+//! limit, which usually means that the server and a client are both waiting for each other, the
+//! watchdog prints what the game is waiting for and writes the state of the game at the beginning
+//! of the current turn to the emergency save (see `save::set_emergency_state`). If asked to, it
+//! also closes the connection of the active player: their client reconnects, and the turn is sent
+//! to them again.
+
+use std::net::{ TcpStream, Shutdown };
+use std::path::PathBuf;
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::{ Duration, Instant };
+use crate::save;
+use crate::stats::format_duration;
+
+/// longest time between two checks of the watchdog
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// what the watchdog knows about the current turn
+struct TurnInfo {
+    start: Instant,
+    n_turns: usize,
+    player_name: String,
+    stream: Option<TcpStream>,
+    flagged: bool
+}
+
+/// Watchdog of a game, checking in its own thread that the turns go on
+pub struct Watchdog {
+    turn: Arc<Mutex<TurnInfo>>
+}
+
+impl Watchdog {
+
+    /// Start a watchdog flagging the game if no turn starts for `limit`
+    ///
+    /// `save_path` is the local file where the emergency save is written, and `restart` tells
+    /// whether the connection of the active player is closed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use machiavelli::watchdog::Watchdog;
+    ///
+    /// let watchdog = Watchdog::start(Duration::from_secs(1800), false, "game_emergency.sav".into());
+    /// watchdog.turn_started("Alice", None);
+    /// ```
+    pub fn start(limit: Duration, restart: bool, save_path: PathBuf) -> Watchdog {
+        let turn = Arc::new(Mutex::new(TurnInfo {
+            start: Instant::now(),
+            n_turns: 0,
+            player_name: String::new(),
+            stream: None,
+            flagged: false
+        }));
+        let turn_thread = turn.clone();
+        let check_interval = (limit / 4).clamp(Duration::from_millis(100), MAX_CHECK_INTERVAL);
+        thread::spawn(move || loop {
+            thread::sleep(check_interval);
+            let mut turn = turn_thread.lock().unwrap_or_else(|e| e.into_inner());
+            let elapsed = turn.start.elapsed();
+            if turn.flagged || elapsed < limit {
+                continue;
+            }
+            turn.flagged = true;
+            println!("Watchdog: no turn has started for {} (turn {}, waiting for {}, {})", 
+                     format_duration(elapsed), turn.n_turns, turn.player_name,
+                     if turn.stream.is_some() { "player" } else { "bot or unknown connection" });
+            match save::write_emergency_save(&save_path) {
+                Ok(true) => println!("Watchdog: the game at the beginning of the turn is saved in {}", 
+                                     save_path.display()),
+                Ok(false) => println!("Watchdog: there is no game state to save yet"),
+                Err(e) => println!("Watchdog: could not save the game in {}: {}", save_path.display(), e)
+            }
+            if restart {
+                if let Some(stream) = turn.stream.as_ref() {
+                    println!("Watchdog: closing the connection of {} so that the turn is sent again", 
+                             turn.player_name);
+                    stream.shutdown(Shutdown::Both).unwrap_or(());
+                }
+            }
+        });
+        Watchdog { turn }
+    }
+
+    /// Tell the watchdog that the turn of a player has started
+    ///
+    /// `stream` is a clone of the connection of the player, if they are not a bot.
+    pub fn turn_started(&self, player_name: &str, stream: Option<TcpStream>) {
+        let mut turn = self.turn.lock().unwrap_or_else(|e| e.into_inner());
+        turn.start = Instant::now();
+        turn.n_turns += 1;
+        turn.player_name = player_name.to_string();
+        turn.stream = stream;
+        turn.flagged = false;
+    }
+}